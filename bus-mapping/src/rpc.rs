@@ -7,8 +7,9 @@ use eth_types::{
     Transaction, Word, U64,
 };
 pub use ethers_core::types::BlockNumber;
+use ethers_core::types::DiffMode;
 use ethers_providers::JsonRpcClient;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// Serialize a type.
 ///
@@ -47,6 +48,11 @@ impl Default for GethLoggerConfig {
     }
 }
 
+#[derive(Deserialize)]
+struct ResultGethPrestateDiff {
+    result: DiffMode,
+}
+
 /// Placeholder structure designed to contain the methods that the BusMapping
 /// needs in order to enable Geth queries.
 pub struct GethClient<P: JsonRpcClient>(pub P);
@@ -132,6 +138,26 @@ impl<P: JsonRpcClient> GethClient<P> {
         Ok(resp.0.into_iter().map(|step| step.result).collect())
     }
 
+    /// Calls `debug_traceBlockByNumber` via JSON-RPC with the `prestateTracer`
+    /// in diff mode, returning a [`DiffMode`] for each transaction of the
+    /// block, see [`eth_types::state_diff::StateDiff`].
+    pub async fn trace_block_prestate_diff(
+        &self,
+        block_num: BlockNumber,
+    ) -> Result<Vec<DiffMode>, Error> {
+        let num = serialize(&block_num);
+        let cfg = serde_json::json!({
+            "tracer": "prestateTracer",
+            "tracerConfig": { "diffMode": true },
+        });
+        let resp: Vec<ResultGethPrestateDiff> = self
+            .0
+            .request("debug_traceBlockByNumber", [num, cfg])
+            .await
+            .map_err(|e| Error::JSONRpcError(e.into()))?;
+        Ok(resp.into_iter().map(|trace| trace.result).collect())
+    }
+
     /// Calls `eth_getCode` via JSON-RPC returning a contract code
     pub async fn get_code(
         &self,
@@ -5,6 +5,7 @@ mod tests {
     use ark_std::{end_timer, start_timer};
     use core::marker::PhantomData;
     use halo2_proofs::{
+        dev::MockProver,
         halo2curves::bn256::{Bn256, Fr, G1Affine},
         plonk::{create_proof, keygen_pk, keygen_vk, verify_proof},
         poly::{
@@ -21,8 +22,63 @@ mod tests {
     };
     use rand::SeedableRng;
     use rand_xorshift::XorShiftRng;
-    use std::{env::var, ops::Deref};
-    use zkevm_circuits::mpt_circuit::{load_proof, witness_row::Node, MPTCircuit};
+    use std::{env::var, ops::Deref, time::Instant};
+    use zkevm_circuits::mpt_circuit::{load_proof, witness_row::Node, KeyDerivation, MPTCircuit};
+
+    /// Times the assignment of all the MPT test witnesses, run it with and
+    /// without the `fast_assign` feature to compare
+    #[cfg_attr(not(feature = "benches"), ignore)]
+    #[test]
+    fn bench_mpt_circuit_assignment() {
+        const BENCHMARK_ID: &str = "MPT Circuit assignment";
+
+        let degree: u32 = var("DEGREE")
+            .expect("No DEGREE env var was provided")
+            .parse()
+            .expect("Cannot parse DEGREE env var as u32");
+
+        let mut paths: Vec<_> = std::fs::read_dir("../zkevm-circuits/src/mpt_circuit/tests")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let mut num_rows = 0;
+        let start = Instant::now();
+        for path in paths.iter() {
+            let nodes: Vec<Node> = load_proof(path.to_str().unwrap());
+            num_rows += nodes.iter().map(|node| node.values.len()).sum::<usize>();
+            let keccak_data = nodes
+                .iter()
+                .flat_map(|node| node.keccak_data.iter().map(|k| k.deref().clone()))
+                .collect();
+            let disable_preimage_check = nodes[0].start.clone().unwrap().disable_preimage_check;
+            let circuit = MPTCircuit::<Fr> {
+                nodes,
+                keccak_data,
+                degree: degree as usize,
+                disable_preimage_check,
+                strict_empty_accounts: false,
+                strict_codehash: false,
+                nonce_increment: false,
+                balance_delta: false,
+                key_derivation: KeyDerivation::Keccak,
+                max_nodes: 0,
+                single_pass: true,
+                _marker: PhantomData,
+            };
+            MockProver::<Fr>::run(degree, &circuit, vec![]).unwrap();
+        }
+        println!(
+            "{} of {} witnesses ({} rows) with fast_assign = {}: {:?}",
+            BENCHMARK_ID,
+            paths.len(),
+            num_rows,
+            cfg!(feature = "fast_assign"),
+            start.elapsed()
+        );
+    }
 
     #[cfg_attr(not(feature = "benches"), ignore)]
     #[test]
@@ -53,6 +109,13 @@ mod tests {
             keccak_data,
             degree: degree as usize,
             disable_preimage_check: false,
+            strict_empty_accounts: false,
+            strict_codehash: false,
+            nonce_increment: false,
+            balance_delta: false,
+            key_derivation: KeyDerivation::Keccak,
+            max_nodes: 0,
+            single_pass: true,
             _marker: PhantomData,
         };
 
@@ -25,7 +25,7 @@ mod tests {
     use rand::SeedableRng;
     use rand_chacha::ChaChaRng;
     use std::{collections::HashMap, env::var};
-    use zkevm_circuits::super_circuit::SuperCircuit;
+    use zkevm_circuits::{super_circuit::SuperCircuit, util::Randomness};
 
     #[cfg_attr(not(feature = "benches"), ignore)]
     #[test]
@@ -91,7 +91,7 @@ mod tests {
             max_keccak_rows: 0,
         };
         let (_, circuit, instance, _) =
-            SuperCircuit::build(block, circuits_params, Fr::from(0x100)).unwrap();
+            SuperCircuit::build(block, circuits_params, Randomness::challenge()).unwrap();
         let instance_refs: Vec<&[Fr]> = instance.iter().map(|v| &v[..]).collect();
 
         // Bench setup generation
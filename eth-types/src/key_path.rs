@@ -0,0 +1,186 @@
+//! Path of a key through a Merkle Patricia Trie
+
+use crate::{Endianness, Field, ToWord, H256};
+
+/// Number of nibbles of a key in the trie.
+pub const KEY_NUM_NIBBLES: usize = 64;
+
+/// The 64 nibble path of a key in the trie together with the number of
+/// nibbles already taken by the nodes above the current one. Iterating over
+/// the path consumes its nibbles one by one, like branches do.
+///
+/// The RLC of a path follows the MPT circuit: the nibbles are paired into
+/// bytes, so a nibble at an even position is multiplied by 16 and the
+/// multiplier only advances after a nibble at an odd position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyPath {
+    nibbles: [u8; KEY_NUM_NIBBLES],
+    consumed: usize,
+}
+
+impl KeyPath {
+    /// Path of `key`, which is already hashed for the secure tries.
+    pub fn new(key: H256) -> Self {
+        Self {
+            nibbles: key.to_nibbles(Endianness::Big),
+            consumed: 0,
+        }
+    }
+
+    /// Takes the next `n` nibbles of the path and returns them, panics when
+    /// fewer than `n` nibbles remain.
+    pub fn consume(&mut self, n: usize) -> &[u8] {
+        assert!(
+            n <= self.remaining().len(),
+            "cannot consume {} nibbles, only {} remain",
+            n,
+            self.remaining().len()
+        );
+        self.consumed += n;
+        &self.nibbles[self.consumed - n..self.consumed]
+    }
+
+    /// Nibbles that have not been consumed yet.
+    pub fn remaining(&self) -> &[u8] {
+        &self.nibbles[self.consumed..]
+    }
+
+    /// Nibbles that have been consumed.
+    pub fn consumed(&self) -> &[u8] {
+        &self.nibbles[..self.consumed]
+    }
+
+    /// If an odd number of nibbles has been consumed, the next nibble is then
+    /// the low nibble of a key byte.
+    pub fn parity(&self) -> bool {
+        self.consumed % 2 == 1
+    }
+
+    /// RLC of the consumed nibbles.
+    pub fn rlc<F: Field>(&self, r: F) -> F {
+        Self::rlc_chain(0, self.consumed(), (F::ZERO, F::ONE), r).0
+    }
+
+    /// Adds `nibbles`, found at position `pos` of a path, to the RLC `rlc` of
+    /// the nibbles before them, `mult` being the multiplier of the next nibble.
+    /// Returns the new RLC and multiplier.
+    pub fn rlc_chain<F: Field>(pos: usize, nibbles: &[u8], (rlc, mult): (F, F), r: F) -> (F, F) {
+        nibbles
+            .iter()
+            .enumerate()
+            .fold((rlc, mult), |(rlc, mult), (idx, nibble)| {
+                let (nibble_mult, mult_step) = Self::nibble_mults(pos + idx, r);
+                (
+                    rlc + F::from(*nibble as u64) * nibble_mult * mult,
+                    mult * mult_step,
+                )
+            })
+    }
+
+    /// Multiplier of the next nibble added to the RLC, `r` to the power of the
+    /// number of completed key bytes.
+    pub fn mult<F: Field>(&self, r: F) -> F {
+        r.pow([(self.consumed / 2) as u64])
+    }
+
+    /// Multiplier of the nibble at position `pos` on top of the multiplier of
+    /// the key, and the factor the multiplier of the key is advanced with
+    /// after adding the nibble.
+    pub fn nibble_mults<F: Field>(pos: usize, r: F) -> (F, F) {
+        if pos % 2 == 0 {
+            // Most significant nibble of a byte, the multiplier stays the same
+            (F::from(16), F::ONE)
+        } else {
+            // Least significant nibble of a byte, the multiplier needs to advance
+            (F::ONE, r)
+        }
+    }
+
+    /// Nibbles of a part of a path in the compact encoding of the leaf and
+    /// extension keys. The first nibble holds the flags, it is followed by a
+    /// padding nibble when the number of nibbles is even.
+    pub fn compact_nibbles(compact: &[u8]) -> Vec<u8> {
+        let Some((first, rest)) = compact.split_first() else {
+            return vec![];
+        };
+        let mut nibbles = Vec::with_capacity(2 * compact.len());
+        if (first >> 4) & 1 == 1 {
+            nibbles.push(first & 0xf);
+        }
+        nibbles.extend(rest.iter().flat_map(|byte| [byte >> 4, byte & 0xf]));
+        nibbles
+    }
+}
+
+impl Iterator for KeyPath {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let nibble = *self.remaining().first()?;
+        self.consumed += 1;
+        Some(nibble)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::halo2curves::{bn256::Fr, ff::Field as _};
+
+    #[test]
+    fn consume_path() {
+        let key = H256::from_slice(&(0..32).map(|byte| byte * 17).collect::<Vec<u8>>());
+        let mut path = KeyPath::new(key);
+        assert_eq!(path.next(), Some(0));
+        assert!(path.parity());
+        assert_eq!(path.consume(3), &[0, 1, 1]);
+        assert!(!path.parity());
+        assert_eq!(path.remaining().len(), 60);
+        assert_eq!(path.remaining()[..2], [2, 2]);
+        assert_eq!(path.by_ref().count(), 60);
+        assert_eq!(path.next(), None);
+        assert_eq!(path.consumed(), &key.to_nibbles(Endianness::Big)[..]);
+    }
+
+    #[test]
+    fn path_rlc() {
+        let key = H256::from_slice(&(1..=32).collect::<Vec<u8>>());
+        let r = Fr::from(7);
+        let mut path = KeyPath::new(key);
+
+        // A full path is the RLC of the key bytes
+        path.consume(KEY_NUM_NIBBLES);
+        assert_eq!(path.rlc(r), key.to_rlc(r, Endianness::Big));
+        assert_eq!(path.mult(r), r.pow([32]));
+
+        // Half a byte
+        let mut path = KeyPath::new(key);
+        path.consume(3);
+        assert_eq!(path.rlc(r), Fr::from(1) + Fr::from(0) * r);
+        assert_eq!(path.mult(r), r);
+        path.consume(1);
+        assert_eq!(path.rlc(r), Fr::from(1) + Fr::from(2) * r);
+        assert_eq!(path.mult(r), r * r);
+
+        // The RLC of a path is the RLC of its parts
+        let mut path = KeyPath::new(key);
+        let (rlc, mult) = KeyPath::rlc_chain(0, path.consume(3), (Fr::ZERO, Fr::ONE), r);
+        let (rlc, mult) = KeyPath::rlc_chain(3, path.consume(58), (rlc, mult), r);
+        assert_eq!((rlc, mult), (path.rlc(r), path.mult(r)));
+    }
+
+    #[test]
+    fn compact_nibbles() {
+        // Leaf and extension keys, odd and even
+        assert_eq!(KeyPath::compact_nibbles(&[0x3a, 0xbc]), [0xa, 0xb, 0xc]);
+        assert_eq!(KeyPath::compact_nibbles(&[0x1a]), [0xa]);
+        assert_eq!(KeyPath::compact_nibbles(&[0x20, 0xab]), [0xa, 0xb]);
+        assert_eq!(
+            KeyPath::compact_nibbles(&[0x00, 0xab, 0xcd]),
+            [0xa, 0xb, 0xc, 0xd]
+        );
+        // No nibbles
+        assert!(KeyPath::compact_nibbles(&[0x20]).is_empty());
+        assert!(KeyPath::compact_nibbles(&[]).is_empty());
+    }
+}
@@ -19,8 +19,11 @@ pub mod bytecode;
 pub mod evm_types;
 pub mod geth_types;
 pub mod keccak;
+pub mod key_path;
 pub mod sign_types;
+pub mod state_diff;
 pub use keccak::{keccak256, Keccak};
+pub use key_path::KeyPath;
 
 pub use bytecode::Bytecode;
 pub use error::Error;
@@ -79,10 +82,80 @@ pub trait ToScalar<F> {
     fn to_scalar(&self) -> Option<F>;
 }
 
+/// Byte order of the bytes of a [`Word`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    /// Most significant byte first, the order of the EVM.
+    Big,
+    /// Least significant byte first, the order of the field elements.
+    Little,
+}
+
 /// Trait used to convert a type to a [`Word`].
 pub trait ToWord {
     /// Convert the type to a [`Word`].
     fn to_word(&self) -> Word;
+
+    /// Convert the type to the bytes of its [`Word`] in the given order.
+    fn to_word_bytes(&self, endianness: Endianness) -> [u8; 32] {
+        let word = self.to_word();
+        match endianness {
+            Endianness::Big => word.to_be_bytes(),
+            Endianness::Little => word.to_le_bytes(),
+        }
+    }
+
+    /// Convert the type to the lo (least significant 128 bits) and hi halves of
+    /// its [`Word`].
+    fn to_lo_hi<F: Field>(&self) -> [F; 2] {
+        let bytes = self.to_word_bytes(Endianness::Little);
+        let half = |bytes: &[u8]| F::from_u128(u128::from_le_bytes(bytes.try_into().unwrap()));
+        [half(&bytes[..16]), half(&bytes[16..])]
+    }
+
+    /// Convert the type to the 64 nibbles of its [`Word`], the bytes are in the
+    /// given order and the high nibble of every byte comes first.
+    fn to_nibbles(&self, endianness: Endianness) -> [u8; 64] {
+        let mut nibbles = [0u8; 64];
+        for (idx, byte) in self.to_word_bytes(endianness).iter().enumerate() {
+            nibbles[2 * idx] = byte >> 4;
+            nibbles[2 * idx + 1] = byte & 0xf;
+        }
+        nibbles
+    }
+
+    /// Random linear combination of the bytes of the [`Word`] in the given
+    /// order, the first byte is multiplied by `r^0`.
+    fn to_rlc<F: Field>(&self, r: F, endianness: Endianness) -> F {
+        self.to_word_bytes(endianness)
+            .iter()
+            .rev()
+            .fold(F::ZERO, |acc, byte| acc * r + F::from(*byte as u64))
+    }
+}
+
+/// Trait used to convert a [`Word`] to a type, the inverse of [`ToWord`].
+pub trait FromWord: Sized {
+    /// Convert a [`Word`] to the type, the most significant bytes that do not
+    /// fit in the type are dropped.
+    fn from_word(word: Word) -> Self;
+
+    /// Convert the bytes of a [`Word`] in the given order to the type.
+    fn from_word_bytes(bytes: &[u8; 32], endianness: Endianness) -> Self {
+        Self::from_word(match endianness {
+            Endianness::Big => Word::from_big_endian(bytes),
+            Endianness::Little => Word::from_little_endian(bytes),
+        })
+    }
+
+    /// Convert the lo and hi halves of a [`Word`] to the type, see
+    /// [`ToWord::to_lo_hi`]. Both halves need to fit in 128 bits.
+    fn from_lo_hi<F: Field>(lo_hi: [F; 2]) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[..16].copy_from_slice(&lo_hi[0].get_lower_128().to_le_bytes());
+        bytes[16..].copy_from_slice(&lo_hi[1].get_lower_128().to_le_bytes());
+        Self::from_word_bytes(&bytes, Endianness::Little)
+    }
 }
 
 /// Trait used to convert a type to a [`Address`].
@@ -177,6 +250,12 @@ impl<F: Field> ToScalar<F> for U256 {
     }
 }
 
+impl FromWord for Word {
+    fn from_word(word: Word) -> Self {
+        word
+    }
+}
+
 impl ToAddress for U256 {
     fn to_address(&self) -> Address {
         Address::from_slice(&self.to_be_bytes()[12..])
@@ -192,6 +271,12 @@ impl ToWord for Hash {
     }
 }
 
+impl FromWord for Hash {
+    fn from_word(word: Word) -> Self {
+        Hash::from(word.to_be_bytes())
+    }
+}
+
 impl ToWord for Address {
     fn to_word(&self) -> Word {
         let mut bytes = [0u8; 32];
@@ -200,6 +285,12 @@ impl ToWord for Address {
     }
 }
 
+impl FromWord for Address {
+    fn from_word(word: Word) -> Self {
+        word.to_address()
+    }
+}
+
 impl ToWord for bool {
     fn to_word(&self) -> Word {
         if *self {
@@ -716,6 +807,50 @@ mod eth_types_test {
         Ok(())
     }
 
+    #[test]
+    fn word_conversions() {
+        let word = Word::from_big_endian(&(1..=32).collect::<Vec<u8>>());
+
+        let lo_hi = word.to_lo_hi::<Fr>();
+        assert_eq!(lo_hi[0], Fr::from_u128(word.low_u128()));
+        assert_eq!(lo_hi[1], Fr::from_u128((word >> 128).low_u128()));
+        assert_eq!(Word::from_lo_hi(lo_hi), word);
+
+        let nibbles = word.to_nibbles(Endianness::Big);
+        assert_eq!(nibbles[..4], [0, 1, 0, 2]);
+        assert_eq!(nibbles[60..], [1, 15, 2, 0]);
+        assert_eq!(word.to_nibbles(Endianness::Little)[..4], [2, 0, 1, 15]);
+
+        let r = Fr::from(7u64);
+        let rlc = word.to_rlc(r, Endianness::Little);
+        let expected = word
+            .to_le_bytes()
+            .iter()
+            .enumerate()
+            .fold(Fr::ZERO, |acc, (idx, byte)| {
+                acc + Fr::from(*byte as u64) * r.pow([idx as u64])
+            });
+        assert_eq!(rlc, expected);
+        assert_eq!(
+            word.to_rlc(Fr::from(256u64), Endianness::Little),
+            word.to_scalar().unwrap()
+        );
+
+        for endianness in [Endianness::Big, Endianness::Little] {
+            let bytes = word.to_word_bytes(endianness);
+            assert_eq!(Word::from_word_bytes(&bytes, endianness), word);
+            assert_eq!(
+                H256::from_word_bytes(&bytes, endianness),
+                H256::from(word.to_be_bytes())
+            );
+        }
+
+        let address = Address::from_slice(&(1..=20).collect::<Vec<u8>>());
+        assert_eq!(Address::from_word(address.to_word()), address);
+        assert_eq!(Address::from_lo_hi(address.to_lo_hi::<Fr>()), address);
+        assert_eq!(H256::from_word(word).to_word(), word);
+    }
+
     #[test]
     fn creation_tx_into_tx_req() -> Result<(), Error> {
         let tx = &geth_types::Transaction {
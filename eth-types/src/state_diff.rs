@@ -0,0 +1,349 @@
+//! State change of a block, built from the `prestateTracer` traces of its
+//! transactions in diff mode, and the trie updates proving it.
+
+use crate::{Address, Word, H256, U64};
+use ethers_core::{
+    types::{
+        transaction::eip2930::{AccessList, AccessListItem},
+        AccountState as GethAccountState, DiffMode,
+    },
+    utils::keccak256,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Fields of an existing account
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountState {
+    /// Nonce
+    pub nonce: U64,
+    /// Balance
+    pub balance: Word,
+    /// Code hash
+    pub code_hash: H256,
+}
+
+impl AccountState {
+    /// Account without nonce, balance or code
+    pub fn empty() -> Self {
+        Self {
+            nonce: U64::zero(),
+            balance: Word::zero(),
+            code_hash: H256(keccak256(&[] as &[u8])),
+        }
+    }
+
+    // In `pre` a missing field is empty
+    fn from_pre(state: &GethAccountState) -> Self {
+        Self::empty().apply_post(state)
+    }
+
+    // In `post` a missing field is unchanged
+    fn apply_post(mut self, state: &GethAccountState) -> Self {
+        if let Some(nonce) = state.nonce {
+            self.nonce = nonce.as_u64().into();
+        }
+        if let Some(balance) = state.balance {
+            self.balance = balance;
+        }
+        if let Some(code) = &state.code {
+            let code = hex::decode(code.trim_start_matches("0x")).expect("hex encoded code");
+            self.code_hash = H256(keccak256(code));
+        }
+        self
+    }
+}
+
+/// Value at the start and at the end of a block
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueDiff {
+    /// Value at the start of the block
+    pub old: Word,
+    /// Value at the end of the block
+    pub new: Word,
+}
+
+/// Change of an account in a block
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountDiff {
+    /// Account at the start of the block, `None` if it does not exist
+    pub old: Option<AccountState>,
+    /// Account at the end of the block, `None` if it does not exist
+    pub new: Option<AccountState>,
+    /// True if the account was removed, with its storage, within the block
+    pub destructed: bool,
+    /// Changed storage slots
+    pub storage: BTreeMap<H256, ValueDiff>,
+}
+
+/// Accounts and slots changed by a block
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDiff {
+    /// Changed accounts
+    pub accounts: BTreeMap<Address, AccountDiff>,
+}
+
+/// Kind of trie update, one for each MPT proof type changing the state
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrieUpdateKind {
+    /// Nonce update, creating the account if needed
+    Nonce,
+    /// Balance update
+    Balance,
+    /// Code hash update
+    CodeHash,
+    /// Removal of the account with its storage
+    AccountDestructed,
+    /// Storage slot update
+    Storage(H256),
+}
+
+/// Update of the state trie between the start and the end of a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrieUpdate {
+    /// Account address
+    pub address: Address,
+    /// Updated field
+    pub kind: TrieUpdateKind,
+    /// Value at the start of the block, or zero for a re-created account
+    pub old_value: Word,
+    /// Value at the end of the block
+    pub new_value: Word,
+}
+
+impl StateDiff {
+    /// Merges the `prestateTracer` diff traces of the transactions of a block,
+    /// in order.
+    ///
+    /// In a trace `pre` contains the accounts changed by the transaction as
+    /// they were before it, with only the slots that had a non-zero value.
+    /// `post` contains the fields that changed, a slot set to zero is omitted.
+    /// An account only in `pre` was removed, an account only in `post` was
+    /// created.
+    pub fn from_traces(traces: &[DiffMode]) -> Self {
+        let mut accounts: BTreeMap<Address, AccountDiff> = BTreeMap::new();
+        for trace in traces {
+            let addresses = trace.pre.keys().chain(trace.post.keys());
+            for address in addresses.collect::<BTreeSet<_>>() {
+                let pre = trace.pre.get(address);
+                let post = trace.post.get(address);
+                let before = pre.map(AccountState::from_pre);
+                let diff = accounts.entry(*address).or_insert_with(|| AccountDiff {
+                    old: before,
+                    new: before,
+                    destructed: false,
+                    storage: BTreeMap::new(),
+                });
+                diff.new = match (pre, post) {
+                    (_, Some(post)) => {
+                        Some(before.unwrap_or_else(AccountState::empty).apply_post(post))
+                    }
+                    (Some(_), None) => {
+                        diff.destructed = true;
+                        for value in diff.storage.values_mut() {
+                            value.new = Word::zero();
+                        }
+                        None
+                    }
+                    (None, None) => unreachable!(),
+                };
+                let Some(post) = post else {
+                    continue;
+                };
+                let pre_storage = pre.and_then(|pre| pre.storage.as_ref());
+                let post_storage = post.storage.as_ref();
+                let slots = pre_storage
+                    .into_iter()
+                    .chain(post_storage)
+                    .flat_map(|storage| storage.keys());
+                for slot in slots {
+                    let value_of = |storage: Option<&BTreeMap<H256, H256>>| {
+                        storage
+                            .and_then(|storage| storage.get(slot))
+                            .map_or_else(Word::zero, |value| {
+                                Word::from_big_endian(value.as_bytes())
+                            })
+                    };
+                    let new = value_of(post_storage);
+                    diff.storage
+                        .entry(*slot)
+                        .or_insert_with(|| ValueDiff {
+                            old: value_of(pre_storage),
+                            new,
+                        })
+                        .new = new;
+                }
+            }
+        }
+        Self { accounts }
+    }
+
+    /// Updates turning the state trie at the start of the block into the
+    /// state trie at the end of the block. Accounts are sorted by address. The
+    /// removal of an account comes before its re-creation, and the nonce is
+    /// set first so that the account exists before its other fields change.
+    pub fn updates(&self) -> Vec<TrieUpdate> {
+        let mut updates = Vec::new();
+        for (address, diff) in self.accounts.iter() {
+            let destructed = TrieUpdate {
+                address: *address,
+                kind: TrieUpdateKind::AccountDestructed,
+                old_value: Word::zero(),
+                new_value: Word::zero(),
+            };
+            let (old, new) = match (diff.old, diff.new) {
+                // Created and removed within the block
+                (None, None) => continue,
+                (Some(_), None) => {
+                    updates.push(destructed);
+                    continue;
+                }
+                (Some(_), Some(new)) if diff.destructed => {
+                    updates.push(destructed);
+                    (None, new)
+                }
+                (old, Some(new)) => (old, new),
+            };
+            let created = old.is_none();
+            let old = old.unwrap_or_else(AccountState::empty);
+            let fields = [
+                (
+                    TrieUpdateKind::Nonce,
+                    old.nonce.as_u64().into(),
+                    new.nonce.as_u64().into(),
+                ),
+                (TrieUpdateKind::Balance, old.balance, new.balance),
+                (
+                    TrieUpdateKind::CodeHash,
+                    Word::from_big_endian(old.code_hash.as_bytes()),
+                    Word::from_big_endian(new.code_hash.as_bytes()),
+                ),
+            ];
+            for (kind, old_value, new_value) in fields {
+                if old_value != new_value || (created && kind == TrieUpdateKind::Nonce) {
+                    updates.push(TrieUpdate {
+                        address: *address,
+                        kind,
+                        old_value,
+                        new_value,
+                    });
+                }
+            }
+            for (slot, value) in diff.storage.iter() {
+                // The storage of a created account starts empty
+                let old_value = if created { Word::zero() } else { value.old };
+                if old_value != value.new {
+                    updates.push(TrieUpdate {
+                        address: *address,
+                        kind: TrieUpdateKind::Storage(*slot),
+                        old_value,
+                        new_value: value.new,
+                    });
+                }
+            }
+        }
+        updates
+    }
+
+    /// Accounts and slots changed by the block
+    pub fn access_list(&self) -> AccessList {
+        AccessList(
+            self.accounts
+                .iter()
+                .map(|(address, diff)| AccessListItem {
+                    address: *address,
+                    storage_keys: diff.storage.keys().copied().collect(),
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACES: &str = r#"[
+        {
+            "pre": {
+                "0x0000000000000000000000000000000000000001": {
+                    "balance": "0x10", "nonce": 1
+                },
+                "0x0000000000000000000000000000000000000002": {
+                    "balance": "0x0", "code": "0x6000",
+                    "storage": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000001":
+                        "0x0000000000000000000000000000000000000000000000000000000000000005"
+                    }
+                }
+            },
+            "post": {
+                "0x0000000000000000000000000000000000000001": {
+                    "balance": "0x8", "nonce": 2
+                },
+                "0x0000000000000000000000000000000000000002": {
+                    "balance": "0x8",
+                    "storage": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000002":
+                        "0x0000000000000000000000000000000000000000000000000000000000000007"
+                    }
+                },
+                "0x0000000000000000000000000000000000000003": {
+                    "balance": "0x0", "nonce": 1, "code": "0x00"
+                }
+            }
+        },
+        {
+            "pre": {
+                "0x0000000000000000000000000000000000000001": {
+                    "balance": "0x8", "nonce": 2
+                },
+                "0x0000000000000000000000000000000000000003": {
+                    "balance": "0x0", "nonce": 1, "code": "0x00"
+                }
+            },
+            "post": {
+                "0x0000000000000000000000000000000000000001": {
+                    "nonce": 3
+                }
+            }
+        }
+    ]"#;
+
+    #[test]
+    fn state_diff_updates() {
+        let traces: Vec<DiffMode> = serde_json::from_str(TRACES).unwrap();
+        let diff = StateDiff::from_traces(&traces);
+        let sender = crate::address!("0x0000000000000000000000000000000000000001");
+        let contract = crate::address!("0x0000000000000000000000000000000000000002");
+        let slot = |n: u64| H256::from_low_u64_be(n);
+
+        let update = |address, kind, old_value: u64, new_value: u64| TrieUpdate {
+            address,
+            kind,
+            old_value: old_value.into(),
+            new_value: new_value.into(),
+        };
+        // The account created and removed in the block is not changed
+        assert_eq!(
+            diff.updates(),
+            vec![
+                update(sender, TrieUpdateKind::Nonce, 1, 3),
+                update(sender, TrieUpdateKind::Balance, 0x10, 8),
+                update(contract, TrieUpdateKind::Balance, 0, 8),
+                update(contract, TrieUpdateKind::Storage(slot(1)), 5, 0),
+                update(contract, TrieUpdateKind::Storage(slot(2)), 0, 7),
+            ]
+        );
+        assert_eq!(
+            diff.access_list().0[1],
+            AccessListItem {
+                address: contract,
+                storage_keys: vec![slot(1), slot(2)],
+            }
+        );
+
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(serde_json::from_str::<StateDiff>(&json).unwrap(), diff);
+    }
+}
@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: Unlicense
+pragma solidity ^0.8.5;
+
+// Example verifier for the proofs generated by `prove_state_root_in_beacon_root`.
+//
+// The beacon block root is read from the EIP-4788 contract, so no header chain
+// needs to be maintained. The contract keeps the roots of roughly the last day.
+contract BeaconStateRootVerifier {
+    address constant BEACON_ROOTS = 0x000F3df6D732807Ef1319fB7B8bB8522d0Beac02;
+    // execution_payload.state_root in the beacon block
+    uint256 constant STATE_ROOT_GINDEX = 6434;
+    uint256 constant STATE_ROOT_DEPTH = 12;
+
+    // `timestamp` is the timestamp of the execution block following the block
+    // with state root `stateRoot`
+    function verifyStateRoot(uint256 timestamp, bytes32 stateRoot, bytes32[] calldata branch)
+        external
+        view
+        returns (bool)
+    {
+        (bool success, bytes memory data) = BEACON_ROOTS.staticcall(abi.encode(timestamp));
+        require(success && data.length == 32, "beacon root not available");
+        bytes32 beaconRoot = abi.decode(data, (bytes32));
+
+        require(branch.length == STATE_ROOT_DEPTH, "invalid branch length");
+        bytes32 root = stateRoot;
+        uint256 index = STATE_ROOT_GINDEX;
+        for (uint256 i = 0; i < branch.length; i++) {
+            if (index & 1 == 1) {
+                root = sha256(abi.encodePacked(branch[i], root));
+            } else {
+                root = sha256(abi.encodePacked(root, branch[i]));
+            }
+            index >>= 1;
+        }
+        return root == beaconRoot;
+    }
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: Unlicense
+pragma solidity ^0.8.5;
+
+// Example verifier for the slot proofs generated by `prove_slot_at_block`.
+//
+// The header is trusted through `blockhash`, so only the last 256 blocks can be
+// verified directly. For older blocks the block hash needs to be made available
+// to the contract by other means (e.g. a relayed header chain).
+contract SlotProofVerifier {
+    function verifySlot(
+        uint256 blockNumber,
+        bytes calldata header,
+        address account,
+        bytes32 slot,
+        bytes[] calldata accountProof,
+        bytes[] calldata storageProof
+    ) external view returns (uint256) {
+        bytes32 blockHash = blockhash(blockNumber);
+        require(blockHash != bytes32(0), "block hash not available");
+        return verifySlotWithHash(blockHash, header, account, slot, accountProof, storageProof);
+    }
+
+    function verifySlotWithHash(
+        bytes32 blockHash,
+        bytes calldata header,
+        address account,
+        bytes32 slot,
+        bytes[] calldata accountProof,
+        bytes[] calldata storageProof
+    ) public pure returns (uint256) {
+        require(keccak256(header) == blockHash, "header does not match the block hash");
+
+        bytes memory headerMem = header;
+        bytes32 stateRoot = bytes32(itemPayload(headerMem, listItems(headerMem)[3]));
+
+        bytes memory accountRlp = verifyProof(stateRoot, abi.encodePacked(account), accountProof);
+        // Non existing accounts have an empty storage
+        if (accountRlp.length == 0) {
+            return 0;
+        }
+        bytes32 storageRoot = bytes32(itemPayload(accountRlp, listItems(accountRlp)[2]));
+
+        bytes memory valueRlp = verifyProof(storageRoot, abi.encodePacked(slot), storageProof);
+        if (valueRlp.length == 0) {
+            return 0;
+        }
+        bytes memory value = itemPayload(valueRlp, 0);
+        return toUint(value, 0, value.length);
+    }
+
+    // Returns the value stored at `key` in the secure trie with root `root`, or
+    // an empty value when the proof shows that `key` is not in the trie.
+    function verifyProof(bytes32 root, bytes memory key, bytes[] calldata proof)
+        internal
+        pure
+        returns (bytes memory)
+    {
+        bytes32 keyHash = keccak256(key);
+        uint256 pos = 0;
+        uint256 proofIdx = 0;
+        bytes32 expectedHash = root;
+        bytes memory node;
+        bool isInline = false;
+        while (true) {
+            if (!isInline) {
+                require(proofIdx < proof.length, "proof is too short");
+                node = proof[proofIdx++];
+                require(keccak256(node) == expectedHash, "invalid node hash in proof");
+            }
+            uint256[] memory items = listItems(node);
+            uint256 child;
+            if (items.length == 17) {
+                require(pos < 64, "proof is too long");
+                child = items[nibble(keyHash, pos++)];
+            } else if (items.length == 2) {
+                bytes memory path = itemPayload(node, items[0]);
+                uint8 flag = uint8(path[0]) >> 4;
+                uint256 start = (flag & 1) == 1 ? 1 : 2;
+                uint256 pathLen = path.length * 2 - start;
+                if (pos + pathLen > 64) {
+                    return "";
+                }
+                for (uint256 i = 0; i < pathLen; i++) {
+                    uint256 p = start + i;
+                    uint8 pathNibble = (p % 2 == 0) ? uint8(path[p / 2]) >> 4 : uint8(path[p / 2]) & 0xf;
+                    if (pathNibble != nibble(keyHash, pos + i)) {
+                        return "";
+                    }
+                }
+                pos += pathLen;
+                if (flag >= 2) {
+                    // Leaf
+                    return pos == 64 ? itemPayload(node, items[1]) : bytes("");
+                }
+                child = items[1];
+            } else {
+                revert("invalid trie node");
+            }
+
+            (uint256 payloadOffset, uint256 payloadLen, bool isList) = decodeLength(node, child);
+            if (isList) {
+                // Nodes shorter than 32 bytes are embedded in their parent
+                node = slice(node, child, payloadOffset + payloadLen - child);
+                isInline = true;
+            } else if (payloadLen == 0) {
+                return "";
+            } else {
+                require(payloadLen == 32, "invalid child reference");
+                expectedHash = bytes32(itemPayload(node, child));
+                isInline = false;
+            }
+        }
+        return "";
+    }
+
+    function nibble(bytes32 data, uint256 idx) internal pure returns (uint8) {
+        uint8 b = uint8(data[idx / 2]);
+        return idx % 2 == 0 ? b >> 4 : b & 0xf;
+    }
+
+    // RLP decoding
+
+    function decodeLength(bytes memory data, uint256 offset)
+        internal
+        pure
+        returns (uint256 payloadOffset, uint256 payloadLen, bool isList)
+    {
+        uint8 prefix = uint8(data[offset]);
+        if (prefix < 0x80) {
+            return (offset, 1, false);
+        } else if (prefix < 0xb8) {
+            return (offset + 1, prefix - 0x80, false);
+        } else if (prefix < 0xc0) {
+            uint256 lenLen = prefix - 0xb7;
+            return (offset + 1 + lenLen, toUint(data, offset + 1, lenLen), false);
+        } else if (prefix < 0xf8) {
+            return (offset + 1, prefix - 0xc0, true);
+        } else {
+            uint256 lenLen = prefix - 0xf7;
+            return (offset + 1 + lenLen, toUint(data, offset + 1, lenLen), true);
+        }
+    }
+
+    // Returns the offsets of the items of the RLP list `data`
+    function listItems(bytes memory data) internal pure returns (uint256[] memory items) {
+        (uint256 offset, uint256 len, bool isList) = decodeLength(data, 0);
+        require(isList, "not a list");
+        uint256 end = offset + len;
+        uint256 count = 0;
+        for (uint256 p = offset; p < end; count++) {
+            (uint256 itemOffset, uint256 itemLen, ) = decodeLength(data, p);
+            p = itemOffset + itemLen;
+        }
+        items = new uint256[](count);
+        uint256 q = offset;
+        for (uint256 i = 0; i < count; i++) {
+            items[i] = q;
+            (uint256 itemOffset, uint256 itemLen, ) = decodeLength(data, q);
+            q = itemOffset + itemLen;
+        }
+    }
+
+    function itemPayload(bytes memory data, uint256 offset) internal pure returns (bytes memory) {
+        (uint256 payloadOffset, uint256 payloadLen, ) = decodeLength(data, offset);
+        return slice(data, payloadOffset, payloadLen);
+    }
+
+    function slice(bytes memory data, uint256 offset, uint256 len) internal pure returns (bytes memory out) {
+        out = new bytes(len);
+        for (uint256 i = 0; i < len; i++) {
+            out[i] = data[offset + i];
+        }
+    }
+
+    function toUint(bytes memory data, uint256 offset, uint256 len) internal pure returns (uint256 value) {
+        require(len <= 32, "integer too large");
+        for (uint256 i = 0; i < len; i++) {
+            value = (value << 8) | uint8(data[offset + i]);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Proves the price of the USDC/WETH Uniswap V2 pair at a mainnet block.
+//!
+//! The state update of the block is proven and verified, then the storage
+//! slots of the pair (and the slot of the factory registering the pair) are
+//! read from the verified state. Slots not modified by the block are fetched
+//! together with `eth_getProof` and checked against the proven state root.
+//!
+//! ```text
+//! PROVIDER_URL=<node> cargo run --release --example uniswap_v2_price -- <block>
+//! ```
+//!
+//! The degree of the circuit and the maximum number of state changes can be
+//! given after the block number.
+//!
+//! Pick a block with a swap on the pair to have the reserves proven by the
+//! state update proof itself.
+
+use eth_types::{Address, H256, U256, U64};
+use ethers::types::transaction::eip2930::{AccessList, AccessListItem};
+use eyre::{ensure, eyre, Result};
+use light_client::{
+    circuit::{ChainConfig, ProofBundle, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT},
+    logging,
+    slot_of::{address_key, mapping, slot},
+    BlockProof, LightClient,
+};
+use mpt_witness_generator::ProofType;
+use std::str::FromStr;
+
+const PVK: &str = "7ccb34dc5fd31fd0aa7860de89a4adc37ccb34dc5fd31fd0aa7860de89a4adc3";
+
+const FACTORY: &str = "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f";
+const USDC: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+const WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+
+// Storage layout of UniswapV2Factory
+const FACTORY_GET_PAIR_SLOT: u64 = 2;
+// Storage layout of UniswapV2Pair, after the slots of UniswapV2ERC20
+const PAIR_TOKEN0_SLOT: u64 = 6;
+const PAIR_TOKEN1_SLOT: u64 = 7;
+// reserve0 (uint112), reserve1 (uint112) and blockTimestampLast (uint32)
+const PAIR_RESERVES_SLOT: u64 = 8;
+const PAIR_PRICE0_CUMULATIVE_SLOT: u64 = 9;
+const PAIR_PRICE1_CUMULATIVE_SLOT: u64 = 10;
+
+/// Splits the packed reserves slot into reserve0, reserve1 and the timestamp
+fn unpack_reserves(value: U256) -> (U256, U256, u32) {
+    let mask = (U256::one() << 112) - 1;
+    (value & mask, (value >> 112) & mask, (value >> 224).as_u32())
+}
+
+fn address_of(value: U256) -> Address {
+    let mut word = [0u8; 32];
+    value.to_big_endian(&mut word);
+    Address::from_slice(&word[12..])
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init();
+    let args: Vec<String> = std::env::args().collect();
+    let usage = "usage: uniswap_v2_price <block> [degree] [max-proof-count]";
+    let block: U64 = args
+        .get(1)
+        .ok_or_else(|| eyre!(usage))?
+        .parse::<u64>()?
+        .into();
+    let degree = match args.get(2) {
+        Some(degree) => degree.parse()?,
+        None => DEFAULT_CIRCUIT_DEGREE,
+    };
+    let max_proof_count = match args.get(3) {
+        Some(count) => count.parse()?,
+        None => DEFAULT_MAX_PROOF_COUNT,
+    };
+    let provider_url = std::env::var("PROVIDER_URL")
+        .map_err(|_| eyre!("PROVIDER_URL should be set to a mainnet node"))?;
+
+    let factory = Address::from_str(FACTORY)?;
+    let (usdc, weth) = (Address::from_str(USDC)?, Address::from_str(WETH)?);
+
+    // Slot of getPair[token0][token1], the factory registers the pair in both orders
+    let get_pair_slot = mapping(
+        mapping(slot(FACTORY_GET_PAIR_SLOT), address_key(usdc)),
+        address_key(weth),
+    );
+
+    let mut client = LightClient::new(&provider_url, PVK, Some(ChainConfig::mainnet()))
+        .await?
+        .with_circuit_params(degree, max_proof_count)
+        .with_auto_fetch(true);
+
+    // Prove the state update of the block
+    let proof = client
+        .prove_block(block, None)
+        .await?
+        .ok_or_else(|| eyre!("block {} does not change the state", block))?;
+    println!(
+        "block {} proven, {} state changes",
+        block,
+        proof.transforms.trie_modifications.len()
+    );
+
+    // The bundle is what gets shipped to a verifier, check the round trip
+    let bytes = proof.bundle.to_bytes()?;
+    let proof = BlockProof {
+        bundle: ProofBundle::from_bytes(&bytes)?,
+        transforms: proof.transforms,
+    };
+    println!("proof bundle of {} bytes", bytes.len());
+    client.verify(&proof)?;
+    println!(
+        "proof verified, state root {:?}",
+        client.state().latest_root
+    );
+
+    // The pair address is read from the factory, the slots of the pair are
+    // fetched together
+    let pair = address_of(client.storage(factory, get_pair_slot).await?);
+    ensure!(
+        !pair.is_zero(),
+        "no USDC/WETH pair registered in the factory"
+    );
+    let pair_slots = [
+        PAIR_TOKEN0_SLOT,
+        PAIR_TOKEN1_SLOT,
+        PAIR_RESERVES_SLOT,
+        PAIR_PRICE0_CUMULATIVE_SLOT,
+        PAIR_PRICE1_CUMULATIVE_SLOT,
+    ]
+    .map(slot);
+    let missing: Vec<H256> = pair_slots
+        .iter()
+        .filter(|key| client.state().storage(&pair, key).is_err())
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        client
+            .fetch(&AccessList(vec![AccessListItem {
+                address: pair,
+                storage_keys: missing,
+            }]))
+            .await?;
+    }
+
+    let token0 = address_of(client.storage(pair, slot(PAIR_TOKEN0_SLOT)).await?);
+    let token1 = address_of(client.storage(pair, slot(PAIR_TOKEN1_SLOT)).await?);
+    ensure!(
+        (token0, token1) == (usdc, weth),
+        "pair {:?} holds {:?}/{:?} instead of USDC/WETH",
+        pair,
+        token0,
+        token1
+    );
+
+    let reserves_slot = slot(PAIR_RESERVES_SLOT);
+    let (reserve0, reserve1, timestamp) =
+        unpack_reserves(client.storage(pair, reserves_slot).await?);
+    let price0_cumulative = client
+        .storage(pair, slot(PAIR_PRICE0_CUMULATIVE_SLOT))
+        .await?;
+    let price1_cumulative = client
+        .storage(pair, slot(PAIR_PRICE1_CUMULATIVE_SLOT))
+        .await?;
+    let swapped = proof.transforms.trie_modifications.iter().any(|m| {
+        matches!(m.typ, ProofType::StorageChanged) && m.address == pair && m.key == reserves_slot
+    });
+
+    // USDC has 6 decimals and WETH 18
+    let usdc_reserve = reserve0.as_u128() as f64 / 1e6;
+    let weth_reserve = reserve1.as_u128() as f64 / 1e18;
+    println!("pair {:?} at block {}", pair, block);
+    println!(
+        "reserves {} USDC / {} WETH, last updated at {}{}",
+        usdc_reserve,
+        weth_reserve,
+        timestamp,
+        if swapped {
+            ", proven by the state update of the block"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "cumulative prices {} / {}",
+        price0_cumulative, price1_cumulative
+    );
+    println!("price {:.2} USDC/WETH", usdc_reserve / weth_reserve);
+
+    Ok(())
+}
@@ -0,0 +1,398 @@
+use eth_types::{Address, H256, U64};
+use ethers::types::{
+    transaction::eip2930::{AccessList, AccessListItem},
+    Block, Transaction,
+};
+use eyre::{ensure, eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+    path::Path,
+};
+
+use crate::slot_of;
+
+/// Accounts and slots touched by a block, stored as TOML or JSON so that test
+/// cases can be shared with the Go witness generator tooling:
+///
+/// ```toml
+/// block = 2000070
+///
+/// [[accounts]]
+/// address = "0xbb9bc244d798123fde783fcc1c72d3bb8c189413"
+/// slots = ["0x4312ad16021fb135960665020d410e3ca0e42488b684d61315e73d368c7182ad"]
+///
+/// # balanceOf(0x1a06...58f1) of a token with the balances at slot 3
+/// [[accounts.mappings]]
+/// slot = 3
+/// keys = ["0x0000000000000000000000001a060b0604883a99809eb3f798df71bef6c358f1"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessListFile {
+    pub block: u64,
+    pub accounts: Vec<AccountAccess>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountAccess {
+    pub address: Address,
+    #[serde(default)]
+    pub slots: Vec<H256>,
+    /// Values of mappings, their slots are derived with `slot_of::mapping`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mappings: Vec<MappingAccess>,
+}
+
+/// Keys of a mapping declared at storage slot `slot`, padded to a word
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MappingAccess {
+    pub slot: u64,
+    pub keys: Vec<H256>,
+}
+
+impl AccountAccess {
+    /// The listed slots followed by the slots of the mapping values
+    pub fn all_slots(&self) -> Vec<H256> {
+        let mapping_slots = self.mappings.iter().flat_map(|mapping| {
+            let slot = slot_of::slot(mapping.slot);
+            mapping
+                .keys
+                .iter()
+                .map(move |key| slot_of::mapping(slot, *key))
+        });
+        self.slots.iter().copied().chain(mapping_slots).collect()
+    }
+}
+
+impl AccessListFile {
+    /// Loads and validates `path`, the format is selected by the extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let file: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content)?,
+            Some("json") => serde_json::from_str(&content)?,
+            _ => eyre::bail!("{}: expected a .toml or .json file", path.display()),
+        };
+        file.validate()
+            .map_err(|e| eyre!("{}: {}", path.display(), e))?;
+        Ok(file)
+    }
+
+    /// Saves to `path`, the format is selected by the extension
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let content = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::to_string(self)?,
+            Some("json") => serde_json::to_string_pretty(self)?,
+            _ => eyre::bail!("{}: expected a .toml or .json file", path.display()),
+        };
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Loads all the files in `dir`, sorted by block number
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("toml" | "json")
+            ) {
+                files.push(Self::load(path)?);
+            }
+        }
+        files.sort_by_key(|file| file.block);
+        Ok(files)
+    }
+
+    /// Checks the constraints the parser can not express: the genesis block
+    /// has no parent to prove a transition from, and every account and slot
+    /// is listed only once.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.block > 0, "block must be greater than 0");
+        let mut addresses = HashSet::new();
+        for account in self.accounts.iter() {
+            ensure!(
+                addresses.insert(account.address),
+                "account {:?} is listed more than once",
+                account.address
+            );
+            let mut slots = HashSet::new();
+            for slot in account.all_slots() {
+                ensure!(
+                    slots.insert(slot),
+                    "slot {:?} of account {:?} is listed more than once",
+                    slot,
+                    account.address
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn block_no(&self) -> U64 {
+        self.block.into()
+    }
+
+    pub fn access_list(&self) -> AccessList {
+        AccessList(
+            self.accounts
+                .iter()
+                .map(|account| AccessListItem {
+                    address: account.address,
+                    storage_keys: account.all_slots(),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Source of the entries of a merged access list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessListSource {
+    /// Given by the user, e.g. in an access list file
+    User,
+    /// Access list of a typed transaction (EIP-2930)
+    Transaction(H256),
+    /// State diff of the block from the node traces
+    Trace,
+}
+
+impl fmt::Display for AccessListSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessListSource::User => write!(f, "user"),
+            AccessListSource::Transaction(hash) => write!(f, "tx {:?}", hash),
+            AccessListSource::Trace => write!(f, "trace"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct MergedAccount {
+    sources: BTreeSet<AccessListSource>,
+    slots: BTreeMap<H256, BTreeSet<AccessListSource>>,
+}
+
+/// Access list assembled from partial sources, for when the node cannot trace
+/// the block or the traces are not trusted. Every account and slot is listed
+/// once, with all the sources that listed it, so the final list can be audited
+/// before it is used to fetch the transforms.
+#[derive(Debug, Clone, Default)]
+pub struct MergedAccessList {
+    accounts: BTreeMap<Address, MergedAccount>,
+    has_trace: bool,
+}
+
+impl MergedAccessList {
+    /// Adds the accounts and slots of `access_list`, the ones already listed by
+    /// another source are not duplicated
+    pub fn add(&mut self, source: AccessListSource, access_list: &AccessList) {
+        self.has_trace |= source == AccessListSource::Trace;
+        for item in access_list.0.iter() {
+            let account = self.accounts.entry(item.address).or_default();
+            account.sources.insert(source);
+            for slot in item.storage_keys.iter() {
+                account.slots.entry(*slot).or_default().insert(source);
+            }
+        }
+    }
+
+    /// Adds the access lists of the typed transactions of `block`
+    pub fn add_transactions(&mut self, block: &Block<Transaction>) {
+        for tx in block.transactions.iter() {
+            if let Some(access_list) = &tx.access_list {
+                self.add(AccessListSource::Transaction(tx.hash), access_list);
+            }
+        }
+    }
+
+    pub fn access_list(&self) -> AccessList {
+        AccessList(
+            self.accounts
+                .iter()
+                .map(|(address, account)| AccessListItem {
+                    address: *address,
+                    storage_keys: account.slots.keys().copied().collect(),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn to_file(&self, block: u64) -> AccessListFile {
+        AccessListFile {
+            block,
+            accounts: self
+                .accounts
+                .iter()
+                .map(|(address, account)| AccountAccess {
+                    address: *address,
+                    slots: account.slots.keys().copied().collect(),
+                    mappings: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Accounts and slots missing from the trace, `None` for the slot of an
+    /// account. The trace lists every change of the block, so these are left
+    /// unchanged and only add proofs of unchanged values. Empty when no trace
+    /// was added.
+    pub fn not_in_trace(&self) -> Vec<(Address, Option<H256>)> {
+        if !self.has_trace {
+            return Vec::new();
+        }
+        let mut entries = Vec::new();
+        for (address, account) in self.accounts.iter() {
+            if !account.sources.contains(&AccessListSource::Trace) {
+                entries.push((*address, None));
+            }
+            for (slot, sources) in account.slots.iter() {
+                if !sources.contains(&AccessListSource::Trace) {
+                    entries.push((*address, Some(*slot)));
+                }
+            }
+        }
+        entries
+    }
+}
+
+impl fmt::Display for MergedAccessList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sources = |sources: &BTreeSet<AccessListSource>| {
+            let unchanged = self.has_trace && !sources.contains(&AccessListSource::Trace);
+            let sources: Vec<_> = sources.iter().map(|s| s.to_string()).collect();
+            format!(
+                "[{}]{}",
+                sources.join(", "),
+                if unchanged { " not in trace" } else { "" }
+            )
+        };
+        for (address, account) in self.accounts.iter() {
+            writeln!(f, "{:?} {}", address, sources(&account.sources))?;
+            for (slot, slot_sources) in account.slots.iter() {
+                writeln!(f, "  {:?} {}", slot, sources(slot_sources))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+block = 2000070
+
+[[accounts]]
+address = "0x1a060B0604883A99809eB3F798DF71BEf6c358f1"
+
+[[accounts]]
+address = "0xbb9bc244d798123fde783fcc1c72d3bb8c189413"
+slots = ["0x4312ad16021fb135960665020d410e3ca0e42488b684d61315e73d368c7182ad"]
+"#;
+
+    #[test]
+    fn toml_and_json_are_equivalent() {
+        let from_toml: AccessListFile = toml::from_str(TOML).unwrap();
+        let json = serde_json::to_string(&from_toml).unwrap();
+        let from_json: AccessListFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(from_toml, from_json);
+        assert_eq!(from_toml.accounts.len(), 2);
+        assert!(from_toml.accounts[0].slots.is_empty());
+        assert_eq!(from_toml.access_list().0[1].storage_keys.len(), 1);
+        from_toml.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(toml::from_str::<AccessListFile>("block = 1\naccounts = []\nslots = []").is_err());
+
+        let mut file: AccessListFile = toml::from_str(TOML).unwrap();
+        let slot = file.accounts[1].slots[0];
+        file.accounts[1].slots.push(slot);
+        assert!(file.validate().is_err());
+
+        let mut file: AccessListFile = toml::from_str(TOML).unwrap();
+        let account = file.accounts[0].clone();
+        file.accounts.push(account);
+        assert!(file.validate().is_err());
+
+        let mut file: AccessListFile = toml::from_str(TOML).unwrap();
+        file.block = 0;
+        assert!(file.validate().is_err());
+    }
+
+    #[test]
+    fn mapping_slots() {
+        let mut file: AccessListFile = toml::from_str(
+            r#"
+block = 10
+
+[[accounts]]
+address = "0x1a060B0604883A99809eB3F798DF71BEf6c358f1"
+
+[[accounts.mappings]]
+slot = 3
+keys = ["0x0000000000000000000000001111111111111111111111111111111111111111"]
+"#,
+        )
+        .unwrap();
+        file.validate().unwrap();
+        let slot = crate::bridge::erc20_balance_slot(Address::repeat_byte(0x11), 3.into());
+        assert_eq!(file.access_list().0[0].storage_keys, vec![slot]);
+
+        // The derived slots can not be listed again
+        file.accounts[0].slots.push(slot);
+        assert!(file.validate().is_err());
+    }
+
+    #[test]
+    fn merge_access_lists() {
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let (x, y) = (H256::repeat_byte(1), H256::repeat_byte(2));
+        let item = |address, storage_keys| AccessListItem {
+            address,
+            storage_keys,
+        };
+        let tx = H256::repeat_byte(0xaa);
+
+        let mut merged = MergedAccessList::default();
+        merged.add(
+            AccessListSource::User,
+            &AccessList(vec![item(a, vec![x]), item(b, vec![])]),
+        );
+        merged.add(
+            AccessListSource::Transaction(tx),
+            &AccessList(vec![item(a, vec![x, y])]),
+        );
+        assert!(merged.not_in_trace().is_empty());
+        merged.add(AccessListSource::Trace, &AccessList(vec![item(a, vec![y])]));
+
+        // Every account and slot is listed once
+        let file = merged.to_file(10);
+        assert_eq!(file.accounts.len(), 2);
+        assert_eq!(file.accounts[0].slots, vec![x, y]);
+        file.validate().unwrap();
+        assert_eq!(file.access_list(), merged.access_list());
+
+        assert_eq!(merged.not_in_trace(), vec![(a, Some(x)), (b, None)]);
+        let audit = merged.to_string();
+        assert!(audit.contains(&format!("{:?} [user] not in trace", b)));
+        assert!(audit.contains(&format!("{:?} [tx {:?}, trace]", y, tx)));
+    }
+
+    #[test]
+    fn access_lists_are_valid() {
+        let files =
+            AccessListFile::load_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/access_lists")).unwrap();
+        assert!(!files.is_empty());
+    }
+}
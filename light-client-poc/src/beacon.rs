@@ -0,0 +1,602 @@
+use eth_types::{H256, U256, U64};
+use ethers::providers::Middleware;
+use eyre::{bail, ensure, eyre, Result};
+use halo2_proofs::halo2curves::bn256::Fr;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use zkevm_circuits::util::word::Word;
+
+use crate::{circuit::PublicInputs, utils::MM};
+
+/// EIP-4788 contract storing the parent beacon block root of every execution
+/// block, indexed by the timestamp of the execution block
+pub const BEACON_ROOTS_ADDRESS: &str = "0x000F3df6D732807Ef1319fB7B8bB8522d0Beac02";
+
+/// Generalized index of `execution_payload.state_root` in a beacon block:
+/// `body_root` (index 4 of 5 header fields), `execution_payload` (index 9 of the
+/// body fields) and `state_root` (index 2 of 17 payload fields)
+pub const STATE_ROOT_GINDEX: u64 = ((8 + 4) * 16 + 9) * 32 + 2;
+
+/// Beacon block layouts supported to build the branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fork {
+    Deneb,
+    Electra,
+}
+
+type Root = [u8; 32];
+
+/// SSZ Merkle branch from the beacon block root made available on-chain by
+/// EIP-4788 to the execution state root of block `block_number`, so a verifier
+/// contract can check the state root without maintaining a header chain.
+///
+/// The root of the beacon block containing block `block_number` is stored by
+/// the next execution block, so it is read from the EIP-4788 contract with
+/// `timestamp`, the timestamp of block `block_number + 1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BeaconStateRootProof {
+    pub block_number: U64,
+    pub state_root: H256,
+    pub beacon_root: H256,
+    pub timestamp: U64,
+    /// Siblings from the state root up to the beacon block root
+    pub branch: Vec<H256>,
+}
+
+/// Builds the proof that the state root of block `block_no` is committed to by
+/// a beacon block root available through EIP-4788. `beacon_url` is the URL of
+/// a beacon node API.
+pub async fn prove_state_root_in_beacon_root(
+    client: &MM,
+    beacon_url: &str,
+    block_no: U64,
+) -> Result<BeaconStateRootProof> {
+    let block = get_execution_block(client, block_no).await?;
+    let next_block = get_execution_block(client, block_no + 1).await?;
+    let state_root = H256::from_slice(&hex_bytes(get(&block, "stateRoot")?)?);
+    let timestamp = U64::from_str_radix(
+        get(&next_block, "timestamp")?
+            .as_str()
+            .ok_or_else(|| eyre!("invalid timestamp"))?
+            .trim_start_matches("0x"),
+        16,
+    )?;
+    let beacon_root = H256::from_slice(&hex_bytes(
+        next_block
+            .get("parentBeaconBlockRoot")
+            .ok_or_else(|| eyre!("block {} has no parent beacon block root", block_no + 1))?,
+    )?);
+
+    let url = format!(
+        "{}/eth/v2/beacon/blocks/0x{}",
+        beacon_url.trim_end_matches('/'),
+        hex::encode(beacon_root)
+    );
+    let response: Value =
+        serde_json::from_str(&reqwest::get(url).await?.error_for_status()?.text().await?)?;
+    let fork = match response["version"].as_str() {
+        Some("deneb") => Fork::Deneb,
+        // The block body is unchanged in fulu
+        Some("electra") | Some("fulu") => Fork::Electra,
+        version => bail!("unsupported beacon block version {:?}", version),
+    };
+    let message = get(get(&response, "data")?, "message")?;
+    let body = get(message, "body")?;
+    let payload = get(body, "execution_payload")?;
+
+    ensure!(
+        uint64_value(get(payload, "block_number")?)? == block_no.as_u64(),
+        "beacon block {:?} does not contain block {}",
+        beacon_root,
+        block_no
+    );
+    ensure!(
+        byte_vector(get(payload, "state_root")?)? == state_root.0,
+        "execution payload state root does not match block {}",
+        block_no
+    );
+
+    let payload_fields = execution_payload_fields(payload)?;
+    let body_fields = beacon_block_body_fields(body, fork)?;
+    let header_fields = vec![
+        uint64(get(message, "slot")?)?,
+        uint64(get(message, "proposer_index")?)?,
+        byte_vector(get(message, "parent_root")?)?,
+        byte_vector(get(message, "state_root")?)?,
+        merkleize(body_fields.clone(), body_fields.len()),
+    ];
+
+    let mut branch = merkle_branch(&payload_fields, 2);
+    branch.extend(merkle_branch(&body_fields, 9));
+    branch.extend(merkle_branch(&header_fields, 4));
+
+    let proof = BeaconStateRootProof {
+        block_number: block_no,
+        state_root,
+        beacon_root,
+        timestamp,
+        branch: branch.into_iter().map(H256).collect(),
+    };
+
+    // Catches any difference between the layout used here and the actual one
+    proof.verify(beacon_root)?;
+
+    Ok(proof)
+}
+
+impl BeaconStateRootProof {
+    /// Verifies the branch against the trusted beacon block root
+    pub fn verify(&self, trusted_beacon_root: H256) -> Result<()> {
+        ensure!(
+            self.beacon_root == trusted_beacon_root,
+            "beacon root does not match the trusted beacon root"
+        );
+        ensure!(
+            H256(branch_root(
+                self.state_root.0,
+                &self.branch,
+                STATE_ROOT_GINDEX
+            )?) == self.beacon_root,
+            "invalid state root branch"
+        );
+        Ok(())
+    }
+
+    /// Checks that a state update proof for block `block_number` ends in the
+    /// anchored state root
+    pub fn check_public_inputs(&self, public_inputs: &PublicInputs<Fr>) -> Result<()> {
+        let state_root = Word::<Fr>::from(self.state_root);
+        ensure!(
+            public_inputs.curr_state_root() == [state_root.lo(), state_root.hi()],
+            "new state root does not match block {}",
+            self.block_number
+        );
+        ensure!(
+            public_inputs.block_number() == Fr::from(self.block_number.as_u64()),
+            "the proof is not for block {}",
+            self.block_number
+        );
+        Ok(())
+    }
+}
+
+async fn get_execution_block(client: &MM, block_no: U64) -> Result<Value> {
+    let block: Value = client
+        .provider()
+        .request("eth_getBlockByNumber", (format!("0x{:x}", block_no), false))
+        .await?;
+    ensure!(!block.is_null(), "block {} not found", block_no);
+    Ok(block)
+}
+
+fn branch_root(leaf: Root, branch: &[H256], gindex: u64) -> Result<Root> {
+    ensure!(
+        branch.len() == (63 - gindex.leading_zeros()) as usize,
+        "invalid branch length"
+    );
+    let mut root = leaf;
+    for (depth, sibling) in branch.iter().enumerate() {
+        root = if (gindex >> depth) & 1 == 1 {
+            hash(&sibling.0, &root)
+        } else {
+            hash(&root, &sibling.0)
+        };
+    }
+    Ok(root)
+}
+
+// SSZ merkleization
+
+fn hash(left: &Root, right: &Root) -> Root {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn zero_hash(depth: usize) -> Root {
+    let mut root = [0u8; 32];
+    for _ in 0..depth {
+        root = hash(&root, &root);
+    }
+    root
+}
+
+fn merkleize(chunks: Vec<Root>, limit: usize) -> Root {
+    assert!(chunks.len() <= limit.max(1));
+    let depth = limit.max(1).next_power_of_two().trailing_zeros() as usize;
+    let mut layer = chunks;
+    for level in 0..depth {
+        if layer.len() % 2 == 1 {
+            layer.push(zero_hash(level));
+        }
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash(&pair[0], &pair[1]))
+            .collect();
+    }
+    layer.first().copied().unwrap_or_else(|| zero_hash(depth))
+}
+
+/// Siblings of leaf `index` of the container with fields `leaves`, bottom up
+fn merkle_branch(leaves: &[Root], index: usize) -> Vec<Root> {
+    let mut layer = leaves.to_vec();
+    layer.resize(leaves.len().next_power_of_two(), [0u8; 32]);
+    let mut index = index;
+    let mut branch = Vec::new();
+    while layer.len() > 1 {
+        branch.push(layer[index ^ 1]);
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash(&pair[0], &pair[1]))
+            .collect();
+        index /= 2;
+    }
+    branch
+}
+
+fn mix_in_length(root: Root, len: usize) -> Root {
+    let mut length = [0u8; 32];
+    length[..8].copy_from_slice(&(len as u64).to_le_bytes());
+    hash(&root, &length)
+}
+
+fn pack(bytes: &[u8]) -> Vec<Root> {
+    bytes
+        .chunks(32)
+        .map(|chunk| {
+            let mut root = [0u8; 32];
+            root[..chunk.len()].copy_from_slice(chunk);
+            root
+        })
+        .collect()
+}
+
+// SSZ values from their beacon API JSON representation
+
+fn get<'a>(value: &'a Value, key: &str) -> Result<&'a Value> {
+    value
+        .get(key)
+        .ok_or_else(|| eyre!("missing field `{}`", key))
+}
+
+fn items<'a>(value: &'a Value, key: &str) -> Result<&'a Vec<Value>> {
+    get(value, key)?
+        .as_array()
+        .ok_or_else(|| eyre!("field `{}` is not a list", key))
+}
+
+fn hex_bytes(value: &Value) -> Result<Vec<u8>> {
+    let value = value.as_str().ok_or_else(|| eyre!("invalid hex value"))?;
+    Ok(hex::decode(value.trim_start_matches("0x"))?)
+}
+
+fn uint64_value(value: &Value) -> Result<u64> {
+    Ok(value
+        .as_str()
+        .ok_or_else(|| eyre!("invalid integer"))?
+        .parse()?)
+}
+
+fn uint64(value: &Value) -> Result<Root> {
+    Ok(pack(&uint64_value(value)?.to_le_bytes())[0])
+}
+
+fn uint256(value: &Value) -> Result<Root> {
+    let value = U256::from_dec_str(value.as_str().ok_or_else(|| eyre!("invalid integer"))?)?;
+    let mut root = [0u8; 32];
+    value.to_little_endian(&mut root);
+    Ok(root)
+}
+
+fn byte_vector(value: &Value) -> Result<Root> {
+    let chunks = pack(&hex_bytes(value)?);
+    let len = chunks.len();
+    Ok(merkleize(chunks, len))
+}
+
+fn byte_list(value: &Value, limit: usize) -> Result<Root> {
+    let bytes = hex_bytes(value)?;
+    ensure!(
+        bytes.len() <= limit,
+        "byte list longer than {} bytes",
+        limit
+    );
+    Ok(mix_in_length(
+        merkleize(pack(&bytes), (limit + 31) / 32),
+        bytes.len(),
+    ))
+}
+
+fn bitvector(value: &Value, len: usize) -> Result<Root> {
+    Ok(merkleize(pack(&hex_bytes(value)?), (len + 255) / 256))
+}
+
+fn bitlist(value: &Value, limit: usize) -> Result<Root> {
+    let mut bytes = hex_bytes(value)?;
+    // Remove the delimiting bit marking the length of the list
+    let last = *bytes.last().ok_or_else(|| eyre!("empty bitlist"))?;
+    ensure!(last != 0, "bitlist without delimiting bit");
+    let len = (bytes.len() - 1) * 8 + (7 - last.leading_zeros() as usize);
+    *bytes.last_mut().unwrap() ^= 1 << (len % 8);
+    bytes.truncate((len + 7) / 8);
+    Ok(mix_in_length(
+        merkleize(pack(&bytes), (limit + 255) / 256),
+        len,
+    ))
+}
+
+fn uint64_list(values: &[Value], limit: usize) -> Result<Root> {
+    let mut bytes = Vec::new();
+    for value in values {
+        bytes.extend_from_slice(&uint64_value(value)?.to_le_bytes());
+    }
+    Ok(mix_in_length(
+        merkleize(pack(&bytes), (limit * 8 + 31) / 32),
+        values.len(),
+    ))
+}
+
+fn list(values: &[Value], limit: usize, root: impl Fn(&Value) -> Result<Root>) -> Result<Root> {
+    ensure!(values.len() <= limit, "list longer than {} items", limit);
+    let roots = values.iter().map(root).collect::<Result<Vec<_>>>()?;
+    Ok(mix_in_length(merkleize(roots, limit), values.len()))
+}
+
+fn container(fields: Vec<Root>) -> Root {
+    let len = fields.len();
+    merkleize(fields, len)
+}
+
+// Beacon chain containers
+
+fn execution_payload_fields(payload: &Value) -> Result<Vec<Root>> {
+    Ok(vec![
+        byte_vector(get(payload, "parent_hash")?)?,
+        byte_vector(get(payload, "fee_recipient")?)?,
+        byte_vector(get(payload, "state_root")?)?,
+        byte_vector(get(payload, "receipts_root")?)?,
+        byte_vector(get(payload, "logs_bloom")?)?,
+        byte_vector(get(payload, "prev_randao")?)?,
+        uint64(get(payload, "block_number")?)?,
+        uint64(get(payload, "gas_limit")?)?,
+        uint64(get(payload, "gas_used")?)?,
+        uint64(get(payload, "timestamp")?)?,
+        byte_list(get(payload, "extra_data")?, 32)?,
+        uint256(get(payload, "base_fee_per_gas")?)?,
+        byte_vector(get(payload, "block_hash")?)?,
+        list(items(payload, "transactions")?, 1 << 20, |tx| {
+            byte_list(tx, 1 << 30)
+        })?,
+        list(items(payload, "withdrawals")?, 16, |withdrawal| {
+            Ok(container(vec![
+                uint64(get(withdrawal, "index")?)?,
+                uint64(get(withdrawal, "validator_index")?)?,
+                byte_vector(get(withdrawal, "address")?)?,
+                uint64(get(withdrawal, "amount")?)?,
+            ]))
+        })?,
+        uint64(get(payload, "blob_gas_used")?)?,
+        uint64(get(payload, "excess_blob_gas")?)?,
+    ])
+}
+
+fn beacon_block_body_fields(body: &Value, fork: Fork) -> Result<Vec<Root>> {
+    let (max_attester_slashings, max_attestations, max_attesting_indices) = match fork {
+        Fork::Deneb => (2, 128, 2048),
+        Fork::Electra => (1, 8, 2048 * 64),
+    };
+
+    let eth1_data = get(body, "eth1_data")?;
+    let sync_aggregate = get(body, "sync_aggregate")?;
+    let mut fields = vec![
+        byte_vector(get(body, "randao_reveal")?)?,
+        container(vec![
+            byte_vector(get(eth1_data, "deposit_root")?)?,
+            uint64(get(eth1_data, "deposit_count")?)?,
+            byte_vector(get(eth1_data, "block_hash")?)?,
+        ]),
+        byte_vector(get(body, "graffiti")?)?,
+        list(items(body, "proposer_slashings")?, 16, |slashing| {
+            Ok(container(vec![
+                signed_beacon_block_header(get(slashing, "signed_header_1")?)?,
+                signed_beacon_block_header(get(slashing, "signed_header_2")?)?,
+            ]))
+        })?,
+        list(
+            items(body, "attester_slashings")?,
+            max_attester_slashings,
+            |slashing| {
+                let indexed_attestation = |attestation: &Value| -> Result<Root> {
+                    Ok(container(vec![
+                        uint64_list(
+                            items(attestation, "attesting_indices")?,
+                            max_attesting_indices,
+                        )?,
+                        attestation_data(get(attestation, "data")?)?,
+                        byte_vector(get(attestation, "signature")?)?,
+                    ]))
+                };
+                Ok(container(vec![
+                    indexed_attestation(get(slashing, "attestation_1")?)?,
+                    indexed_attestation(get(slashing, "attestation_2")?)?,
+                ]))
+            },
+        )?,
+        list(
+            items(body, "attestations")?,
+            max_attestations,
+            |attestation| {
+                let mut fields = vec![
+                    bitlist(get(attestation, "aggregation_bits")?, max_attesting_indices)?,
+                    attestation_data(get(attestation, "data")?)?,
+                    byte_vector(get(attestation, "signature")?)?,
+                ];
+                if fork == Fork::Electra {
+                    fields.push(bitvector(get(attestation, "committee_bits")?, 64)?);
+                }
+                Ok(container(fields))
+            },
+        )?,
+        list(items(body, "deposits")?, 16, |deposit| {
+            let data = get(deposit, "data")?;
+            Ok(container(vec![
+                merkleize(
+                    items(deposit, "proof")?
+                        .iter()
+                        .map(byte_vector)
+                        .collect::<Result<_>>()?,
+                    33,
+                ),
+                container(vec![
+                    byte_vector(get(data, "pubkey")?)?,
+                    byte_vector(get(data, "withdrawal_credentials")?)?,
+                    uint64(get(data, "amount")?)?,
+                    byte_vector(get(data, "signature")?)?,
+                ]),
+            ]))
+        })?,
+        list(items(body, "voluntary_exits")?, 16, |exit| {
+            let message = get(exit, "message")?;
+            Ok(container(vec![
+                container(vec![
+                    uint64(get(message, "epoch")?)?,
+                    uint64(get(message, "validator_index")?)?,
+                ]),
+                byte_vector(get(exit, "signature")?)?,
+            ]))
+        })?,
+        container(vec![
+            bitvector(get(sync_aggregate, "sync_committee_bits")?, 512)?,
+            byte_vector(get(sync_aggregate, "sync_committee_signature")?)?,
+        ]),
+        container(execution_payload_fields(get(body, "execution_payload")?)?),
+        list(items(body, "bls_to_execution_changes")?, 16, |change| {
+            let message = get(change, "message")?;
+            Ok(container(vec![
+                container(vec![
+                    uint64(get(message, "validator_index")?)?,
+                    byte_vector(get(message, "from_bls_pubkey")?)?,
+                    byte_vector(get(message, "to_execution_address")?)?,
+                ]),
+                byte_vector(get(change, "signature")?)?,
+            ]))
+        })?,
+        list(items(body, "blob_kzg_commitments")?, 4096, byte_vector)?,
+    ];
+
+    if fork == Fork::Electra {
+        let requests = get(body, "execution_requests")?;
+        fields.push(container(vec![
+            list(items(requests, "deposits")?, 8192, |request| {
+                Ok(container(vec![
+                    byte_vector(get(request, "pubkey")?)?,
+                    byte_vector(get(request, "withdrawal_credentials")?)?,
+                    uint64(get(request, "amount")?)?,
+                    byte_vector(get(request, "signature")?)?,
+                    uint64(get(request, "index")?)?,
+                ]))
+            })?,
+            list(items(requests, "withdrawals")?, 16, |request| {
+                Ok(container(vec![
+                    byte_vector(get(request, "source_address")?)?,
+                    byte_vector(get(request, "validator_pubkey")?)?,
+                    uint64(get(request, "amount")?)?,
+                ]))
+            })?,
+            list(items(requests, "consolidations")?, 2, |request| {
+                Ok(container(vec![
+                    byte_vector(get(request, "source_address")?)?,
+                    byte_vector(get(request, "source_pubkey")?)?,
+                    byte_vector(get(request, "target_pubkey")?)?,
+                ]))
+            })?,
+        ]));
+    }
+
+    Ok(fields)
+}
+
+fn signed_beacon_block_header(header: &Value) -> Result<Root> {
+    let message = get(header, "message")?;
+    Ok(container(vec![
+        container(vec![
+            uint64(get(message, "slot")?)?,
+            uint64(get(message, "proposer_index")?)?,
+            byte_vector(get(message, "parent_root")?)?,
+            byte_vector(get(message, "state_root")?)?,
+            byte_vector(get(message, "body_root")?)?,
+        ]),
+        byte_vector(get(header, "signature")?)?,
+    ]))
+}
+
+fn attestation_data(data: &Value) -> Result<Root> {
+    let checkpoint = |checkpoint: &Value| -> Result<Root> {
+        Ok(container(vec![
+            uint64(get(checkpoint, "epoch")?)?,
+            byte_vector(get(checkpoint, "root")?)?,
+        ]))
+    };
+    Ok(container(vec![
+        uint64(get(data, "slot")?)?,
+        uint64(get(data, "index")?)?,
+        byte_vector(get(data, "beacon_block_root")?)?,
+        checkpoint(get(data, "source")?)?,
+        checkpoint(get(data, "target")?)?,
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beacon_state_root_branch() {
+        let leaf = |idx: u8| [idx; 32];
+        let payload_fields: Vec<Root> = (0..17).map(leaf).collect();
+        let body_fields: Vec<Root> = (0..12)
+            .map(|idx| {
+                if idx == 9 {
+                    container(payload_fields.clone())
+                } else {
+                    leaf(idx)
+                }
+            })
+            .collect();
+        let header_fields: Vec<Root> = (0..5)
+            .map(|idx| {
+                if idx == 4 {
+                    container(body_fields.clone())
+                } else {
+                    leaf(idx)
+                }
+            })
+            .collect();
+
+        let mut branch = merkle_branch(&payload_fields, 2);
+        branch.extend(merkle_branch(&body_fields, 9));
+        branch.extend(merkle_branch(&header_fields, 4));
+
+        let beacon_root = H256(container(header_fields));
+        let proof = BeaconStateRootProof {
+            block_number: U64::one(),
+            state_root: H256(payload_fields[2]),
+            beacon_root,
+            timestamp: U64::one(),
+            branch: branch.into_iter().map(H256).collect(),
+        };
+        proof.verify(beacon_root).unwrap();
+
+        let mut wrong_proof = proof.clone();
+        wrong_proof.state_root = H256(leaf(3));
+        assert!(wrong_proof.verify(beacon_root).is_err());
+    }
+
+    #[test]
+    fn ssz_bitlist() {
+        // 0b1101 with the delimiting bit at position 4
+        let root = bitlist(&Value::String("0x1d".to_string()), 2048).unwrap();
+        assert_eq!(root, mix_in_length(merkleize(vec![pack(&[0x0d])[0]], 8), 4));
+    }
+}
@@ -0,0 +1,254 @@
+use eth_types::{Address, Bytes, KeyPath, H256, U256, U64};
+use ethers::{
+    providers::Middleware,
+    types::{BlockId, BlockNumber, TransactionRequest},
+    utils::{keccak256, rlp::Rlp},
+};
+use eyre::{bail, ensure, eyre, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    header_chain::{get_raw_header, Header, HeaderField},
+    slot_of,
+    utils::MM,
+};
+
+/// Solidity verifier consuming `SlotProof`s, see `examples/SlotProofVerifier.sol`
+pub const SLOT_PROOF_VERIFIER_SOL: &str = include_str!("../examples/SlotProofVerifier.sol");
+
+/// Self-contained proof of the value of a storage slot at a given block, meant
+/// to be relayed to a foreign chain.
+///
+/// The header is only trusted through its hash: the verifier needs to know the
+/// hash of block `block_number` (e.g. using `blockhash` on-chain for the last 256
+/// blocks, or a relayed header chain for older blocks, see `HeaderChain`). From the header the
+/// state root is read, the account proof links the state root to the storage
+/// root of `address`, and the storage proof links the storage root to `value`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotProof {
+    pub block_number: U64,
+    pub block_hash: H256,
+    pub header: Bytes,
+    pub address: Address,
+    pub slot: H256,
+    pub value: U256,
+    pub account_proof: Vec<Bytes>,
+    pub storage_proof: Vec<Bytes>,
+}
+
+/// Builds the proof of the value of `slot` of `address` at block `block_no`
+pub async fn prove_slot_at_block(
+    client: &MM,
+    address: Address,
+    slot: H256,
+    block_no: U64,
+) -> Result<SlotProof> {
+    let block_id = BlockId::Number(BlockNumber::Number(block_no));
+    let block = client
+        .get_block(block_id)
+        .await?
+        .ok_or_else(|| eyre!("block {} not found", block_no))?;
+    let header = get_raw_header(client, block_no).await?;
+    let proof = client
+        .get_proof(address, vec![slot], Some(block_id))
+        .await?;
+    let storage_proof = proof
+        .storage_proof
+        .into_iter()
+        .find(|p| p.key == slot)
+        .ok_or_else(|| eyre!("no storage proof returned for slot {:?}", slot))?;
+
+    let slot_proof = SlotProof {
+        block_number: block_no,
+        block_hash: block
+            .hash
+            .ok_or_else(|| eyre!("block {} is pending", block_no))?,
+        header,
+        address,
+        slot,
+        value: storage_proof.value,
+        account_proof: proof.account_proof,
+        storage_proof: storage_proof.proof,
+    };
+
+    // Never hand out a proof that does not verify
+    slot_proof.verify(slot_proof.block_hash)?;
+
+    Ok(slot_proof)
+}
+
+/// Selector of `balanceOf(address)`
+const BALANCE_OF_SELECTOR: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+
+/// Storage slot of `balanceOf(holder)` of an ERC-20 token keeping the balances
+/// in a `mapping(address => uint256)` declared at storage slot `slot_index`,
+/// e.g. 0 for the OpenZeppelin ERC20 and 3 for WETH9
+pub fn erc20_balance_slot(holder: Address, slot_index: U256) -> H256 {
+    slot_of::mapping(slot_of::uint_key(slot_index), slot_of::address_key(holder))
+}
+
+/// Builds the proof of the `balanceOf(holder)` of the ERC-20 `token` at block
+/// `block_no`, see `erc20_balance_slot`. The proven value is checked against
+/// `balanceOf` so that a wrong `slot_index` is not silently proven as a zero
+/// balance.
+pub async fn prove_erc20_balance(
+    client: &MM,
+    token: Address,
+    holder: Address,
+    slot_index: U256,
+    block_no: U64,
+) -> Result<SlotProof> {
+    let slot = erc20_balance_slot(holder, slot_index);
+    let slot_proof = prove_slot_at_block(client, token, slot, block_no).await?;
+
+    let mut data = BALANCE_OF_SELECTOR.to_vec();
+    data.extend_from_slice(H256::from(holder).as_bytes());
+    let call = TransactionRequest::new().to(token).data(data).into();
+    let balance = client
+        .call(&call, Some(BlockId::Number(BlockNumber::Number(block_no))))
+        .await?;
+    ensure!(
+        balance.len() == 32 && U256::from_big_endian(&balance) == slot_proof.value,
+        "balanceOf({:?}) is not in slot {:?}, check the slot index {} of the balances",
+        holder,
+        slot,
+        slot_index
+    );
+
+    Ok(slot_proof)
+}
+
+impl SlotProof {
+    /// Verifies the proof against the trusted hash of block `block_number`
+    pub fn verify(&self, trusted_block_hash: H256) -> Result<()> {
+        ensure!(
+            self.block_hash == trusted_block_hash,
+            "block hash does not match the trusted block hash"
+        );
+        ensure!(
+            H256(keccak256(&self.header)) == self.block_hash,
+            "header does not match the block hash"
+        );
+
+        let state_root = Header::decode(&self.header)?
+            .h256(HeaderField::StateRoot)?
+            .unwrap();
+
+        let account = verify_mpt_proof(state_root, self.address.as_bytes(), &self.account_proof)?;
+        let value = match account {
+            Some(account) => {
+                let storage_root = H256::from_slice(Rlp::new(&account).at(2)?.data()?);
+                let value =
+                    verify_mpt_proof(storage_root, self.slot.as_bytes(), &self.storage_proof)?;
+                match value {
+                    Some(value) => U256::from_big_endian(Rlp::new(&value).data()?),
+                    None => U256::zero(),
+                }
+            }
+            // Non existing accounts have an empty storage
+            None => U256::zero(),
+        };
+
+        ensure!(
+            value == self.value,
+            "proven value {} does not match the claimed value {}",
+            value,
+            self.value
+        );
+        Ok(())
+    }
+}
+
+enum NodeRef {
+    Hash(H256),
+    Inline(Vec<u8>),
+}
+
+fn child_ref(child: &Rlp) -> Result<Option<NodeRef>> {
+    if child.is_list() {
+        // Nodes shorter than 32 bytes are embedded in their parent
+        Ok(Some(NodeRef::Inline(child.as_raw().to_vec())))
+    } else {
+        let data = child.data()?;
+        match data.len() {
+            0 => Ok(None),
+            32 => Ok(Some(NodeRef::Hash(H256::from_slice(data)))),
+            _ => bail!("invalid child reference"),
+        }
+    }
+}
+
+/// Decodes the hex prefix encoded path of a leaf/extension node, returns if the
+/// node is a leaf and the nibbles of the path
+fn decode_path(path: &[u8]) -> Result<(bool, Vec<u8>)> {
+    ensure!(!path.is_empty(), "empty node path");
+    let flag = path[0] >> 4;
+    ensure!(flag <= 3, "invalid node path flag");
+    Ok((flag >= 2, KeyPath::compact_nibbles(path)))
+}
+
+/// Verifies a proof as returned by `eth_getProof` for `key` in the secure trie
+/// with root `root`. Returns the value stored at `key`, or `None` when the proof
+/// shows that `key` is not in the trie.
+pub fn verify_mpt_proof(root: H256, key: &[u8], proof: &[Bytes]) -> Result<Option<Vec<u8>>> {
+    let mut path = KeyPath::new(H256(keccak256(key)));
+
+    let mut proof = proof.iter();
+    let mut node_ref = NodeRef::Hash(root);
+    loop {
+        let node = match node_ref {
+            NodeRef::Hash(hash) => {
+                let node = proof.next().ok_or_else(|| eyre!("proof is too short"))?;
+                ensure!(H256(keccak256(node)) == hash, "invalid node hash in proof");
+                node.to_vec()
+            }
+            NodeRef::Inline(node) => node,
+        };
+        let rlp = Rlp::new(&node);
+        let child = match rlp.item_count()? {
+            17 => {
+                // Values are only stored in leaves in secure tries
+                let nibble = path.next().ok_or_else(|| eyre!("proof is too long"))?;
+                rlp.at(nibble as usize)?
+            }
+            2 => {
+                let (is_leaf, node_path) = decode_path(rlp.at(0)?.data()?)?;
+                if is_leaf {
+                    if path.remaining() != node_path.as_slice() {
+                        return Ok(None);
+                    }
+                    return Ok(Some(rlp.at(1)?.data()?.to_vec()));
+                }
+                if !path.remaining().starts_with(&node_path) {
+                    return Ok(None);
+                }
+                path.consume(node_path.len());
+                rlp.at(1)?
+            }
+            _ => bail!("invalid trie node"),
+        };
+        node_ref = match child_ref(&child)? {
+            Some(node_ref) => node_ref,
+            None => return Ok(None),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erc20_balance_slots() {
+        let slot = |hex: &str| H256::from_slice(&hex::decode(hex).unwrap());
+        let holder = Address::repeat_byte(0x11);
+        assert_eq!(
+            erc20_balance_slot(holder, U256::zero()),
+            slot("f043c50fe795c69f30b8ff78b84032dc53a9d87ca283ae10a1dacfbb648e83ef")
+        );
+        assert_eq!(
+            erc20_balance_slot(holder, 3.into()),
+            slot("fc40ea33816453f766ebc0872d4b5152b468882abe7b6b35528069db4d6e41c4")
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use eyre::Result;
+use halo2_proofs::{
+    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    plonk::{
+        create_proof, keygen_pk, keygen_vk, verify_proof, Circuit, ProvingKey,
+        VerificationStrategy, VerifyingKey,
+    },
+    poly::{
+        commitment::ParamsProver,
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG, ParamsVerifierKZG},
+            multiopen::{ProverSHPLONK, VerifierSHPLONK},
+            strategy::{AccumulatorStrategy, SingleStrategy},
+        },
+    },
+    transcript::{
+        Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
+    },
+    SerdeFormat,
+};
+use rand::RngCore;
+use std::io::{Read, Write};
+
+/// Proof system the circuits are proven with. The circuits only depend on the
+/// halo2 frontend (`Circuit`), so another commitment scheme, transcript or
+/// halo2 fork only needs an implementation of this trait, the gadgets are left
+/// untouched.
+///
+/// The instances are given by column, the state update circuit has one
+/// instance column and the MPT circuit none.
+pub trait ProvingBackend {
+    /// Parameters of the commitment scheme
+    type Params: Clone;
+    /// Part of the parameters needed to verify
+    type VerifierParams: Clone;
+    type ProvingKey: Clone;
+    type VerifyingKey;
+
+    /// Parameters for circuits of `2^k` rows
+    fn setup(k: u32, rng: impl RngCore) -> Self::Params;
+
+    fn verifier_params(params: &Self::Params) -> Self::VerifierParams;
+
+    fn keygen<C: Circuit<Fr>>(params: &Self::Params, circuit: &C) -> Result<Self::ProvingKey>;
+
+    fn verifying_key(pk: &Self::ProvingKey) -> &Self::VerifyingKey;
+
+    fn prove<C: Circuit<Fr>>(
+        params: &Self::Params,
+        pk: &Self::ProvingKey,
+        circuit: C,
+        instances: &[&[Fr]],
+        rng: impl RngCore,
+    ) -> Result<Vec<u8>>;
+
+    fn verify(
+        params: &Self::VerifierParams,
+        vk: &Self::VerifyingKey,
+        proof: &[u8],
+        instances: &[&[Fr]],
+    ) -> Result<()>;
+
+    /// Verifies many proofs of the same circuit. Backends that can share work
+    /// between the proofs override it, by default they are verified one by one.
+    fn verify_batch(
+        params: &Self::VerifierParams,
+        vk: &Self::VerifyingKey,
+        proofs: &[(&[u8], &[&[Fr]])],
+    ) -> Result<()> {
+        for (proof, instances) in proofs {
+            Self::verify(params, vk, proof, instances)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the parameters and the proving key, read back by `read_keys`
+    fn write_keys(
+        params: &Self::Params,
+        verifier_params: &Self::VerifierParams,
+        pk: &Self::ProvingKey,
+        writer: &mut impl Write,
+    ) -> Result<()>;
+
+    /// Reads the keys written by `write_keys`. The proving key embeds the
+    /// constraint system, which is rebuilt from `circuit_params`.
+    fn read_keys<C: Circuit<Fr>>(
+        reader: &mut impl Read,
+        circuit_params: C::Params,
+    ) -> Result<(Self::Params, Self::VerifierParams, Self::ProvingKey)>;
+
+    /// Serialized verifying key, hashed to identify the keys a proof was
+    /// generated with
+    fn vk_bytes(vk: &Self::VerifyingKey) -> Vec<u8>;
+}
+
+/// KZG commitments on BN254 opened with SHPLONK, Blake2b transcript. The
+/// default backend of `StateUpdateCircuitKeys`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct KzgShplonk;
+
+impl ProvingBackend for KzgShplonk {
+    type Params = ParamsKZG<Bn256>;
+    type VerifierParams = ParamsVerifierKZG<Bn256>;
+    type ProvingKey = ProvingKey<G1Affine>;
+    type VerifyingKey = VerifyingKey<G1Affine>;
+
+    fn setup(k: u32, rng: impl RngCore) -> Self::Params {
+        ParamsKZG::<Bn256>::setup(k, rng)
+    }
+
+    fn verifier_params(params: &Self::Params) -> Self::VerifierParams {
+        params.verifier_params().clone()
+    }
+
+    fn keygen<C: Circuit<Fr>>(params: &Self::Params, circuit: &C) -> Result<Self::ProvingKey> {
+        let vk = keygen_vk(params, circuit)?;
+        Ok(keygen_pk(params, vk, circuit)?)
+    }
+
+    fn verifying_key(pk: &Self::ProvingKey) -> &Self::VerifyingKey {
+        pk.get_vk()
+    }
+
+    fn prove<C: Circuit<Fr>>(
+        params: &Self::Params,
+        pk: &Self::ProvingKey,
+        circuit: C,
+        instances: &[&[Fr]],
+        rng: impl RngCore,
+    ) -> Result<Vec<u8>> {
+        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+        create_proof::<
+            KZGCommitmentScheme<Bn256>,
+            ProverSHPLONK<'_, Bn256>,
+            Challenge255<G1Affine>,
+            _,
+            Blake2bWrite<Vec<u8>, G1Affine, Challenge255<G1Affine>>,
+            C,
+        >(params, pk, &[circuit], &[instances], rng, &mut transcript)?;
+        Ok(transcript.finalize())
+    }
+
+    fn verify(
+        params: &Self::VerifierParams,
+        vk: &Self::VerifyingKey,
+        proof: &[u8],
+        instances: &[&[Fr]],
+    ) -> Result<()> {
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(proof);
+        let strategy = SingleStrategy::new(params);
+        verify_proof::<
+            KZGCommitmentScheme<Bn256>,
+            VerifierSHPLONK<'_, Bn256>,
+            Challenge255<G1Affine>,
+            Blake2bRead<&[u8], G1Affine, Challenge255<G1Affine>>,
+            SingleStrategy<'_, Bn256>,
+        >(params, vk, strategy, &[instances], &mut transcript)?;
+        Ok(())
+    }
+
+    /// The pairing checks of all the proofs are accumulated and only done once
+    /// at the end, which is much faster than verifying the proofs one by one.
+    fn verify_batch(
+        params: &Self::VerifierParams,
+        vk: &Self::VerifyingKey,
+        proofs: &[(&[u8], &[&[Fr]])],
+    ) -> Result<()> {
+        let mut strategy = AccumulatorStrategy::new(params);
+        for (proof, instances) in proofs {
+            let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(*proof);
+            strategy = verify_proof::<
+                KZGCommitmentScheme<Bn256>,
+                VerifierSHPLONK<'_, Bn256>,
+                Challenge255<G1Affine>,
+                Blake2bRead<&[u8], G1Affine, Challenge255<G1Affine>>,
+                AccumulatorStrategy<'_, Bn256>,
+            >(params, vk, strategy, &[instances], &mut transcript)?;
+        }
+        eyre::ensure!(strategy.finalize(), "batch verification failed");
+        Ok(())
+    }
+
+    fn write_keys(
+        params: &Self::Params,
+        verifier_params: &Self::VerifierParams,
+        pk: &Self::ProvingKey,
+        writer: &mut impl Write,
+    ) -> Result<()> {
+        params.write_custom(writer, SerdeFormat::RawBytes)?;
+        verifier_params.write_custom(writer, SerdeFormat::RawBytes)?;
+        pk.write(writer, SerdeFormat::RawBytes)?;
+        Ok(())
+    }
+
+    fn read_keys<C: Circuit<Fr>>(
+        reader: &mut impl Read,
+        circuit_params: C::Params,
+    ) -> Result<(Self::Params, Self::VerifierParams, Self::ProvingKey)> {
+        let params = ParamsKZG::<Bn256>::read_custom(reader, SerdeFormat::RawBytes)?;
+        let verifier_params =
+            ParamsVerifierKZG::<Bn256>::read_custom(reader, SerdeFormat::RawBytes)?;
+        let pk =
+            ProvingKey::<G1Affine>::read::<_, C>(reader, SerdeFormat::RawBytes, circuit_params)?;
+        Ok((params, verifier_params, pk))
+    }
+
+    fn vk_bytes(vk: &Self::VerifyingKey) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        vk.write(&mut buffer, SerdeFormat::RawBytes)
+            .expect("writing to a vec should not fail");
+        buffer
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner, Value},
+        plonk::{Advice, Column, ConstraintSystem, Error, Instance},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    /// Exposes a private value as its only instance
+    #[derive(Clone, Default)]
+    pub(crate) struct PublicValue(pub Value<Fr>);
+
+    impl Circuit<Fr> for PublicValue {
+        type Config = (Column<Advice>, Column<Instance>);
+        type FloorPlanner = SimpleFloorPlanner;
+        type Params = ();
+
+        fn without_witnesses(&self) -> Self {
+            Self::default()
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let advice = meta.advice_column();
+            let instance = meta.instance_column();
+            meta.enable_equality(advice);
+            meta.enable_equality(instance);
+            (advice, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (advice, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let cell = layouter.assign_region(
+                || "value",
+                |mut region| region.assign_advice(|| "value", advice, 0, || self.0),
+            )?;
+            layouter.constrain_instance(cell.cell(), instance, 0)
+        }
+    }
+
+    /// Proves and verifies `PublicValue` only through `ProvingBackend`,
+    /// including keys written and read back
+    pub(crate) fn prove_and_verify<B: ProvingBackend>() {
+        let mut rng = ChaCha20Rng::seed_from_u64(42);
+        let seven: &[&[Fr]] = &[&[Fr::from(7)]];
+        let nine: &[&[Fr]] = &[&[Fr::from(9)]];
+
+        let params = B::setup(4, &mut rng);
+        let verifier_params = B::verifier_params(&params);
+        let pk = B::keygen(&params, &PublicValue::default()).unwrap();
+        let vk = B::verifying_key(&pk);
+
+        let proof = B::prove(
+            &params,
+            &pk,
+            PublicValue(Value::known(Fr::from(7))),
+            seven,
+            &mut rng,
+        )
+        .unwrap();
+        B::verify(&verifier_params, vk, &proof, seven).unwrap();
+        assert!(B::verify(&verifier_params, vk, &proof, nine).is_err());
+
+        let other = B::prove(
+            &params,
+            &pk,
+            PublicValue(Value::known(Fr::from(9))),
+            nine,
+            &mut rng,
+        )
+        .unwrap();
+        B::verify_batch(&verifier_params, vk, &[(&proof, seven), (&other, nine)]).unwrap();
+        assert!(
+            B::verify_batch(&verifier_params, vk, &[(&proof, seven), (&other, seven)]).is_err()
+        );
+
+        let mut buffer = Vec::new();
+        B::write_keys(&params, &verifier_params, &pk, &mut buffer).unwrap();
+        let (_, verifier_params, pk) =
+            B::read_keys::<PublicValue>(&mut buffer.as_slice(), ()).unwrap();
+        assert_eq!(B::vk_bytes(B::verifying_key(&pk)), B::vk_bytes(vk));
+        B::verify(&verifier_params, B::verifying_key(&pk), &proof, seven).unwrap();
+    }
+
+    #[test]
+    fn kzg_shplonk() {
+        prove_and_verify::<KzgShplonk>();
+    }
+}
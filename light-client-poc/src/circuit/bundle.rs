@@ -0,0 +1,268 @@
+use ethers::utils::keccak256;
+use eyre::{bail, ensure, eyre, Result};
+use halo2_proofs::{
+    halo2curves::{bn256::Fr, ff::PrimeField},
+    plonk::Circuit,
+};
+use std::io::{Read, Write};
+use zkevm_circuits::{mpt_circuit::KeyDerivation, util::circuit_fingerprint};
+
+use super::{
+    backend::ProvingBackend, prover::StateUpdateCircuitKeys, state_update::StateUpdateCircuit,
+    PublicInputs,
+};
+
+/// Magic bytes at the start of every serialized proof bundle
+const PROOF_BUNDLE_MAGIC: &[u8; 4] = b"LCPB";
+/// Current version of the proof bundle format. Bump it when the layout changes,
+/// older versions that can still be read are listed in `read`.
+pub const PROOF_BUNDLE_VERSION: u16 = 2;
+
+/// A state update proof together with everything needed to check that it can
+/// be verified with a given set of keys.
+///
+/// Layout (all integers big endian):
+///   magic (4 bytes) | version (u16) | params hash (32 bytes) | vk hash (32 bytes)
+///   | circuit fingerprint (32 bytes, since version 2)
+///   | instance count (u32) | instances (32 bytes each, field repr)
+///   | proof length (u32) | proof
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofBundle {
+    pub version: u16,
+    pub params_hash: [u8; 32],
+    pub vk_hash: [u8; 32],
+    /// Not available for bundles written with version 1
+    pub circuit_fingerprint: Option<[u8; 32]>,
+    pub instances: Vec<Fr>,
+    pub proof: Vec<u8>,
+}
+
+impl StateUpdateCircuit<Fr> {
+    /// Hash of the parameters the circuit layout depends on
+    pub fn params_hash(&self) -> [u8; 32] {
+        let params = self.params().mpt;
+        let mut data = Vec::new();
+        data.extend_from_slice(&(params.degree as u64).to_be_bytes());
+        data.push(params.disable_preimage_check as u8);
+        data.push(params.strict_empty_accounts as u8);
+        // Left out for keccak so the hash of existing bundles does not change
+        if params.key_derivation != KeyDerivation::Keccak {
+            data.push(params.key_derivation.into());
+        }
+        data.extend_from_slice(&(self.max_proof_count as u64).to_be_bytes());
+        data.push((cfg!(feature = "disable-keccak") || self.keccak_oracle) as u8);
+        // Only added when enabled so the hash of existing bundles does not change
+        if self.mpt_digest {
+            data.push(1);
+        }
+        keccak256(data)
+    }
+
+    /// Fingerprint of the gate and lookup structure of the circuit
+    pub fn circuit_fingerprint(&self) -> [u8; 32] {
+        circuit_fingerprint::<Fr, StateUpdateCircuit<Fr>>(self.params())
+    }
+}
+
+impl ProofBundle {
+    pub fn new<B: ProvingBackend>(
+        proof: Vec<u8>,
+        public_inputs: &PublicInputs<Fr>,
+        circuit: &StateUpdateCircuit<Fr>,
+        keys: &StateUpdateCircuitKeys<B>,
+    ) -> Self {
+        Self {
+            version: PROOF_BUNDLE_VERSION,
+            params_hash: circuit.params_hash(),
+            vk_hash: keys.vk_hash(),
+            circuit_fingerprint: Some(circuit.circuit_fingerprint()),
+            instances: public_inputs.0.clone(),
+            proof,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(PROOF_BUNDLE_MAGIC)?;
+        writer.write_all(&PROOF_BUNDLE_VERSION.to_be_bytes())?;
+        writer.write_all(&self.params_hash)?;
+        writer.write_all(&self.vk_hash)?;
+        let circuit_fingerprint = self
+            .circuit_fingerprint
+            .ok_or_else(|| eyre!("only bundles with a circuit fingerprint can be written"))?;
+        writer.write_all(&circuit_fingerprint)?;
+        writer.write_all(&(self.instances.len() as u32).to_be_bytes())?;
+        for instance in self.instances.iter() {
+            writer.write_all(&instance.to_repr())?;
+        }
+        writer.write_all(&(self.proof.len() as u32).to_be_bytes())?;
+        writer.write_all(&self.proof)?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == PROOF_BUNDLE_MAGIC, "not a proof bundle");
+
+        let version = u16::from_be_bytes(read_array(reader)?);
+        match version {
+            1 | PROOF_BUNDLE_VERSION => {}
+            _ => bail!(
+                "unsupported proof bundle version {} (supported: 1..={})",
+                version,
+                PROOF_BUNDLE_VERSION
+            ),
+        }
+
+        let params_hash = read_array(reader)?;
+        let vk_hash = read_array(reader)?;
+        let circuit_fingerprint = if version >= 2 {
+            Some(read_array(reader)?)
+        } else {
+            None
+        };
+
+        let num_instances = u32::from_be_bytes(read_array(reader)?);
+        let instances = (0..num_instances)
+            .map(|idx| {
+                let repr = read_array(reader)?;
+                Option::from(Fr::from_repr(repr))
+                    .ok_or_else(|| eyre!("instance {} is not a valid field element", idx))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let proof_len = u32::from_be_bytes(read_array(reader)?);
+        let mut proof = vec![0u8; proof_len as usize];
+        reader.read_exact(&mut proof)?;
+
+        Ok(Self {
+            version,
+            params_hash,
+            vk_hash,
+            circuit_fingerprint,
+            instances,
+            proof,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.write(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        Self::read(&mut bytes)
+    }
+
+    /// Checks that the proof was generated for the given circuit and keys, so a
+    /// circuit change is reported as such instead of as a failed verification.
+    pub fn check_compatible<B: ProvingBackend>(
+        &self,
+        circuit: &StateUpdateCircuit<Fr>,
+        keys: &StateUpdateCircuitKeys<B>,
+    ) -> Result<()> {
+        if let Some(fingerprint) = self.circuit_fingerprint {
+            ensure!(
+                fingerprint == circuit.circuit_fingerprint(),
+                "proof was generated by a different circuit (fingerprint 0x{}, expected 0x{})",
+                hex::encode(fingerprint),
+                hex::encode(circuit.circuit_fingerprint())
+            );
+        }
+        ensure!(
+            self.params_hash == circuit.params_hash(),
+            "proof was generated with different circuit parameters (params hash 0x{}, expected 0x{})",
+            hex::encode(self.params_hash),
+            hex::encode(circuit.params_hash())
+        );
+        ensure!(
+            self.vk_hash == keys.vk_hash(),
+            "proof was generated with a different verifying key (vk hash 0x{}, expected 0x{})",
+            hex::encode(self.vk_hash),
+            hex::encode(keys.vk_hash())
+        );
+        Ok(())
+    }
+
+    pub fn verify<B: ProvingBackend>(
+        &self,
+        circuit: &StateUpdateCircuit<Fr>,
+        keys: &StateUpdateCircuitKeys<B>,
+    ) -> Result<()> {
+        self.check_compatible(circuit, keys)?;
+        StateUpdateCircuit::verify(&self.proof, &self.instances, keys)
+    }
+
+    /// Verifies many bundles at once, see `StateUpdateCircuit::verify_batch`
+    pub fn verify_batch<B: ProvingBackend>(
+        bundles: &[ProofBundle],
+        circuit: &StateUpdateCircuit<Fr>,
+        keys: &StateUpdateCircuitKeys<B>,
+    ) -> Result<()> {
+        for bundle in bundles {
+            bundle.check_compatible(circuit, keys)?;
+        }
+        let proofs: Vec<_> = bundles
+            .iter()
+            .map(|bundle| (bundle.proof.as_slice(), bundle.instances.as_slice()))
+            .collect();
+        StateUpdateCircuit::verify_batch(&proofs, keys)
+    }
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle() -> ProofBundle {
+        ProofBundle {
+            version: PROOF_BUNDLE_VERSION,
+            params_hash: [1u8; 32],
+            vk_hash: [2u8; 32],
+            circuit_fingerprint: Some([4u8; 32]),
+            instances: vec![Fr::from(1), Fr::from(2), -Fr::from(3)],
+            proof: vec![3u8; 100],
+        }
+    }
+
+    #[test]
+    fn proof_bundle_roundtrip() {
+        let bundle = bundle();
+        assert_eq!(
+            ProofBundle::from_bytes(&bundle.to_bytes().unwrap()).unwrap(),
+            bundle
+        );
+    }
+
+    #[test]
+    fn proof_bundle_version_1() {
+        // Version 1 bundles don't have a circuit fingerprint
+        let mut bytes = bundle().to_bytes().unwrap();
+        bytes[4..6].copy_from_slice(&1u16.to_be_bytes());
+        bytes.drain(4 + 2 + 32 + 32..4 + 2 + 32 + 32 + 32);
+        let read = ProofBundle::from_bytes(&bytes).unwrap();
+        assert_eq!(read.version, 1);
+        assert_eq!(read.circuit_fingerprint, None);
+        assert_eq!(read.proof, bundle().proof);
+    }
+
+    #[test]
+    fn proof_bundle_unknown_version() {
+        let mut bytes = bundle().to_bytes().unwrap();
+        bytes[4..6].copy_from_slice(&(PROOF_BUNDLE_VERSION + 1).to_be_bytes());
+        assert!(ProofBundle::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn proof_bundle_truncated() {
+        let bytes = bundle().to_bytes().unwrap();
+        assert!(ProofBundle::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use ethers::{providers::Middleware, types::U64};
+use eyre::{ensure, eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::utils::MM;
+
+/// Chain dependent rules needed to generate and prove the witness, selected
+/// with `--chain` as one of the built-in profiles (`mainnet`, `sepolia`,
+/// `polygon`) or a custom profile stored as TOML or JSON:
+///
+/// ```toml
+/// name = "devnet"
+/// chain_id = 1337
+/// eip161_block = 0
+/// rpc_url = "http://localhost:8545"
+/// ```
+///
+/// All the supported chains keep their state in a secure trie, so the keys are
+/// always derived with keccak.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainConfig {
+    pub name: String,
+    pub chain_id: u64,
+    /// First block with the EIP-161 state clearing rules (Spurious Dragon), empty
+    /// accounts can exist in the trie before it
+    #[serde(default)]
+    pub eip161_block: u64,
+    /// Node used when `PROVIDER_URL` is not set, only set by custom profiles
+    #[serde(default)]
+    pub rpc_url: Option<String>,
+}
+
+impl ChainConfig {
+    pub fn mainnet() -> Self {
+        Self {
+            name: "mainnet".to_string(),
+            chain_id: 1,
+            eip161_block: 2_675_000,
+            rpc_url: None,
+        }
+    }
+
+    pub fn sepolia() -> Self {
+        Self {
+            name: "sepolia".to_string(),
+            chain_id: 11_155_111,
+            eip161_block: 0,
+            rpc_url: None,
+        }
+    }
+
+    /// Polygon PoS, Bor has the EIP-161 rules from genesis
+    pub fn polygon() -> Self {
+        Self {
+            name: "polygon".to_string(),
+            chain_id: 137,
+            eip161_block: 0,
+            rpc_url: None,
+        }
+    }
+
+    /// The built-in profile of `chain_id`, other chains are assumed to have all
+    /// the forks active from genesis
+    pub fn from_chain_id(chain_id: u64) -> Self {
+        [Self::mainnet(), Self::sepolia(), Self::polygon()]
+            .into_iter()
+            .find(|config| config.chain_id == chain_id)
+            .unwrap_or_else(|| Self {
+                name: format!("chain {}", chain_id),
+                chain_id,
+                eip161_block: 0,
+                rpc_url: None,
+            })
+    }
+
+    /// The built-in profile called `name`, or the custom profile stored in the
+    /// file `name`, the format is selected by the extension
+    pub fn load(name: &str) -> Result<Self> {
+        match name {
+            "mainnet" => return Ok(Self::mainnet()),
+            "sepolia" => return Ok(Self::sepolia()),
+            "polygon" => return Ok(Self::polygon()),
+            _ => {}
+        }
+        let path = Path::new(name);
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("{}: not a built-in chain or a chain file: {}", name, e))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(toml::from_str(&content)?),
+            Some("json") => Ok(serde_json::from_str(&content)?),
+            _ => eyre::bail!("{}: expected a .toml or .json file", path.display()),
+        }
+    }
+
+    /// Checks that the node serves this chain, the rules of another chain would
+    /// produce a witness the circuit rejects
+    pub async fn check_chain_id(&self, client: &MM) -> Result<()> {
+        let chain_id = client.get_chainid().await?.as_u64();
+        ensure!(
+            chain_id == self.chain_id,
+            "the node serves chain {}, not {} ({})",
+            chain_id,
+            self.name,
+            self.chain_id
+        );
+        Ok(())
+    }
+
+    /// Strict rules do not allow empty accounts, legacy rules do
+    pub fn strict_empty_accounts(&self, block_no: U64) -> bool {
+        block_no.as_u64() >= self.eip161_block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mainnet_empty_account_rules() {
+        let config = ChainConfig::from_chain_id(1);
+        assert!(!config.strict_empty_accounts(436_875.into()));
+        assert!(config.strict_empty_accounts(2_675_000.into()));
+        assert!(ChainConfig::from_chain_id(31337).strict_empty_accounts(1.into()));
+    }
+
+    #[test]
+    fn chain_profiles() {
+        for name in ["mainnet", "sepolia", "polygon"] {
+            let config = ChainConfig::load(name).unwrap();
+            assert_eq!(config.name, name);
+            assert_eq!(ChainConfig::from_chain_id(config.chain_id), config);
+        }
+        assert!(ChainConfig::load("goerli").is_err());
+
+        let custom: ChainConfig = toml::from_str("name = \"devnet\"\nchain_id = 1337").unwrap();
+        assert_eq!(custom.eip161_block, 0);
+        assert_eq!(custom.rpc_url, None);
+        assert!(toml::from_str::<ChainConfig>("name = \"devnet\"\nchain = 1337").is_err());
+    }
+}
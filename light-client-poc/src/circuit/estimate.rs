@@ -0,0 +1,134 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::Path};
+
+/// Proving cost measured on the prover machine for one circuit degree
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationPoint {
+    pub degree: usize,
+    /// Key generation and proof generation
+    pub prove_secs: f64,
+    /// Peak resident memory of the prover process
+    pub peak_memory_bytes: u64,
+}
+
+/// Proving costs measured on the prover machine, stored as JSON. The `prove`
+/// stage of the pipeline records its measurement in the file given by
+/// `CALIBRATION_FILE`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub points: Vec<CalibrationPoint>,
+}
+
+/// Proving cost extrapolated from the calibration data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProvingEstimate {
+    pub degree: usize,
+    pub prove_secs: f64,
+    pub peak_memory_bytes: u64,
+    /// Degree of the measurement the estimate is extrapolated from
+    pub calibrated_degree: usize,
+}
+
+impl Calibration {
+    /// Loads `path`, a missing file is an empty calibration
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Adds `point`, replacing the previous measurement of the same degree
+    pub fn record(&mut self, point: CalibrationPoint) {
+        self.points.retain(|p| p.degree != point.degree);
+        self.points.push(point);
+        self.points.sort_by_key(|p| p.degree);
+    }
+
+    /// Extrapolates the cost of proving at `degree` from the measurement with
+    /// the closest degree. Proving is dominated by FFTs and MSMs over the 2^k
+    /// rows, so the time is scaled by k * 2^k and the memory by 2^k.
+    pub fn estimate(&self, degree: usize) -> Option<ProvingEstimate> {
+        let point = self
+            .points
+            .iter()
+            .min_by_key(|p| p.degree.abs_diff(degree))?;
+        let rows_ratio = 2f64.powi(degree as i32 - point.degree as i32);
+        Some(ProvingEstimate {
+            degree,
+            prove_secs: point.prove_secs * rows_ratio * degree as f64 / point.degree as f64,
+            peak_memory_bytes: (point.peak_memory_bytes as f64 * rows_ratio) as u64,
+            calibrated_degree: point.degree,
+        })
+    }
+}
+
+impl fmt::Display for ProvingEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<20}~{:.0} s, ~{:.1} GiB peak memory (calibrated at k={})",
+            format!("proving at k={}:", self.degree),
+            self.prove_secs,
+            self.peak_memory_bytes as f64 / (1u64 << 30) as f64,
+            self.calibrated_degree
+        )
+    }
+}
+
+/// Peak resident memory of the process, only available on Linux
+pub fn peak_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_from_closest_degree() {
+        let mut calibration = Calibration::default();
+        assert_eq!(calibration.estimate(14), None);
+
+        calibration.record(CalibrationPoint {
+            degree: 10,
+            prove_secs: 1.0,
+            peak_memory_bytes: 1000,
+        });
+        calibration.record(CalibrationPoint {
+            degree: 14,
+            prove_secs: 20.0,
+            peak_memory_bytes: 16_000,
+        });
+        // Measuring a degree again replaces the previous measurement
+        calibration.record(CalibrationPoint {
+            degree: 14,
+            prove_secs: 28.0,
+            peak_memory_bytes: 16_000,
+        });
+        assert_eq!(calibration.points.len(), 2);
+
+        let estimate = calibration.estimate(14).unwrap();
+        assert_eq!(estimate.prove_secs, 28.0);
+        assert_eq!(estimate.calibrated_degree, 14);
+
+        let estimate = calibration.estimate(16).unwrap();
+        assert_eq!(estimate.calibrated_degree, 14);
+        assert_eq!(estimate.prove_secs, 28.0 * 4.0 * 16.0 / 14.0);
+        assert_eq!(estimate.peak_memory_bytes, 64_000);
+
+        let estimate = calibration.estimate(9).unwrap();
+        assert_eq!(estimate.calibrated_degree, 10);
+        assert_eq!(estimate.peak_memory_bytes, 500);
+    }
+}
@@ -0,0 +1,248 @@
+use eyre::{ensure, eyre, Result};
+use halo2_proofs::{
+    halo2curves::{
+        bn256::{Bn256, Fq, Fr, G1Affine},
+        ff::Field,
+    },
+    plonk::{create_proof, verify_proof, Circuit, ProvingKey, VerifyingKey},
+    poly::{
+        commitment::ParamsProver,
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG, ParamsVerifierKZG},
+            multiopen::{ProverSHPLONK, VerifierSHPLONK},
+            strategy::SingleStrategy,
+        },
+    },
+};
+use rand::RngCore;
+use snark_verifier::{
+    loader::{
+        evm::{self, encode_calldata, Address, EvmLoader, ExecutorBuilder},
+        native::NativeLoader,
+    },
+    pcs::kzg::{Bdfg21, KzgAs},
+    system::halo2::{compile, transcript::evm::EvmTranscript, Config},
+    verifier::{self, SnarkVerifier},
+};
+use std::{
+    io::{Read, Write},
+    rc::Rc,
+};
+
+use super::{
+    backend::{KzgShplonk, ProvingBackend},
+    prover::StateUpdateCircuitKeys,
+    InstanceLayout,
+};
+
+type PlonkVerifier = verifier::plonk::PlonkVerifier<KzgAs<Bn256, Bdfg21>>;
+
+/// KZG commitments on BN254 opened with SHPLONK, like `KzgShplonk`, but with
+/// the keccak transcript of the verifier contracts generated by
+/// `verifier_yul`. The keys are the ones of `KzgShplonk`, only the proofs
+/// differ.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct KzgShplonkEvm;
+
+impl ProvingBackend for KzgShplonkEvm {
+    type Params = ParamsKZG<Bn256>;
+    type VerifierParams = ParamsVerifierKZG<Bn256>;
+    type ProvingKey = ProvingKey<G1Affine>;
+    type VerifyingKey = VerifyingKey<G1Affine>;
+
+    fn setup(k: u32, rng: impl RngCore) -> Self::Params {
+        KzgShplonk::setup(k, rng)
+    }
+
+    fn verifier_params(params: &Self::Params) -> Self::VerifierParams {
+        KzgShplonk::verifier_params(params)
+    }
+
+    fn keygen<C: Circuit<Fr>>(params: &Self::Params, circuit: &C) -> Result<Self::ProvingKey> {
+        KzgShplonk::keygen(params, circuit)
+    }
+
+    fn verifying_key(pk: &Self::ProvingKey) -> &Self::VerifyingKey {
+        pk.get_vk()
+    }
+
+    fn prove<C: Circuit<Fr>>(
+        params: &Self::Params,
+        pk: &Self::ProvingKey,
+        circuit: C,
+        instances: &[&[Fr]],
+        rng: impl RngCore,
+    ) -> Result<Vec<u8>> {
+        let mut transcript = EvmTranscript::<_, NativeLoader, _, _>::new(vec![]);
+        create_proof::<KZGCommitmentScheme<Bn256>, ProverSHPLONK<'_, Bn256>, _, _, _, _>(
+            params,
+            pk,
+            &[circuit],
+            &[instances],
+            rng,
+            &mut transcript,
+        )?;
+        Ok(transcript.finalize())
+    }
+
+    fn verify(
+        params: &Self::VerifierParams,
+        vk: &Self::VerifyingKey,
+        proof: &[u8],
+        instances: &[&[Fr]],
+    ) -> Result<()> {
+        let mut transcript = EvmTranscript::<_, NativeLoader, _, _>::new(proof);
+        let strategy = SingleStrategy::new(params);
+        verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<'_, Bn256>, _, _, _>(
+            params,
+            vk,
+            strategy,
+            &[instances],
+            &mut transcript,
+        )?;
+        Ok(())
+    }
+
+    fn write_keys(
+        params: &Self::Params,
+        verifier_params: &Self::VerifierParams,
+        pk: &Self::ProvingKey,
+        writer: &mut impl Write,
+    ) -> Result<()> {
+        KzgShplonk::write_keys(params, verifier_params, pk, writer)
+    }
+
+    fn read_keys<C: Circuit<Fr>>(
+        reader: &mut impl Read,
+        circuit_params: C::Params,
+    ) -> Result<(Self::Params, Self::VerifierParams, Self::ProvingKey)> {
+        KzgShplonk::read_keys::<C>(reader, circuit_params)
+    }
+
+    fn vk_bytes(vk: &Self::VerifyingKey) -> Vec<u8> {
+        KzgShplonk::vk_bytes(vk)
+    }
+}
+
+/// Yul source of a contract verifying the proofs made with `params` and `vk`
+/// of a circuit with one instance column of `num_instances` cells. The
+/// contract takes the calldata of `verifier_calldata`, it reverts when the
+/// proof is invalid and returns nothing otherwise.
+pub fn verifier_yul(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    num_instances: usize,
+) -> Result<String> {
+    let protocol = compile(
+        params,
+        vk,
+        Config::kzg().with_num_instance(vec![num_instances]),
+    );
+    let vk = (params.get_g()[0], params.g2(), params.s_g2()).into();
+
+    let loader = EvmLoader::new::<Fq, Fr>();
+    let protocol = protocol.loaded(&loader);
+    let mut transcript = EvmTranscript::<_, Rc<EvmLoader>, _, _>::new(&loader);
+
+    let instances = transcript.load_instances(vec![num_instances]);
+    let proof = PlonkVerifier::read_proof(&vk, &protocol, &instances, &mut transcript)
+        .map_err(|err| eyre!("cannot generate the verifier: {:?}", err))?;
+    PlonkVerifier::verify(&vk, &protocol, &instances, &proof)
+        .map_err(|err| eyre!("cannot generate the verifier: {:?}", err))?;
+
+    Ok(loader.yul_code())
+}
+
+/// Yul source of the verifier contract of the state update proofs made with
+/// `keys`, the instances are in the order of `layout`
+pub fn state_update_verifier_yul(
+    keys: &StateUpdateCircuitKeys<KzgShplonkEvm>,
+    layout: InstanceLayout,
+) -> Result<String> {
+    verifier_yul(keys.params(), keys.verifying_key(), layout.len())
+}
+
+/// Deployment bytecode of a verifier contract, compiled with the `solc` found
+/// in the path
+pub fn compile_verifier(yul: &str) -> Vec<u8> {
+    evm::compile_yul(yul)
+}
+
+/// Calldata of a verifier contract generated by `verifier_yul`: the instances
+/// as 32 bytes big endian words followed by the proof. The instances a proof
+/// leaves out are zero, they are padded to `num_instances`.
+pub fn verifier_calldata(instances: &[Fr], proof: &[u8], num_instances: usize) -> Vec<u8> {
+    let mut instances = instances.to_vec();
+    instances.resize(num_instances, Fr::ZERO);
+    encode_calldata(&[instances], proof)
+}
+
+/// Deploys `deployment_code` in an in-memory EVM and calls it with
+/// `calldata`, returns the gas used by the call when it does not revert
+pub fn evm_call(deployment_code: Vec<u8>, calldata: Vec<u8>) -> Result<u64> {
+    let mut evm = ExecutorBuilder::default()
+        .with_gas_limit(u64::MAX.into())
+        .build();
+
+    let caller = Address::from_low_u64_be(0xfe);
+    let verifier = evm
+        .deploy(caller, deployment_code.into(), 0.into())
+        .address
+        .ok_or_else(|| eyre!("the verifier cannot be deployed"))?;
+    let result = evm.call_raw(caller, verifier, calldata.into(), 0.into());
+    ensure!(!result.reverted, "the verifier rejects the proof");
+    Ok(result.gas_used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::backend::tests::{prove_and_verify, PublicValue};
+    use halo2_proofs::circuit::Value;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn kzg_shplonk_evm() {
+        prove_and_verify::<KzgShplonkEvm>();
+    }
+
+    // Needs `solc` in the path
+    #[test]
+    fn submit_to_local_verifier() {
+        let mut rng = ChaCha20Rng::seed_from_u64(42);
+        let params = KzgShplonkEvm::setup(4, &mut rng);
+        let pk = KzgShplonkEvm::keygen(&params, &PublicValue::default()).unwrap();
+        let proof = KzgShplonkEvm::prove(
+            &params,
+            &pk,
+            PublicValue(Value::known(Fr::from(7))),
+            &[&[Fr::from(7)]],
+            &mut rng,
+        )
+        .unwrap();
+
+        let yul = verifier_yul(&params, pk.get_vk(), 1).unwrap();
+        let deployment_code = compile_verifier(&yul);
+
+        evm_call(
+            deployment_code.clone(),
+            verifier_calldata(&[Fr::from(7)], &proof, 1),
+        )
+        .unwrap();
+        // Wrong instance
+        assert!(evm_call(
+            deployment_code.clone(),
+            verifier_calldata(&[Fr::from(8)], &proof, 1),
+        )
+        .is_err());
+        // Tampered proof
+        let mut tampered = proof.clone();
+        tampered[40] ^= 1;
+        assert!(evm_call(
+            deployment_code,
+            verifier_calldata(&[Fr::from(7)], &tampered, 1)
+        )
+        .is_err());
+    }
+}
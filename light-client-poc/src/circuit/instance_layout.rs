@@ -0,0 +1,204 @@
+use std::fmt::Write;
+
+/// Order and meaning of the instance cells of the state update circuit. The
+/// public inputs are built (`PublicInputs`), constrained (`PublicData`) and
+/// checked by verifiers with these positions, see `solidity` for contracts.
+///
+/// The header comes first, followed by the changes of the `max_proof_count`
+/// rows, or by their digest (lo, hi) when `mpt_digest` is set. Rows without a
+/// change have zero public inputs, so the instances of a proof can stop after
+/// its last change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InstanceLayout {
+    pub max_proof_count: usize,
+    pub mpt_digest: bool,
+}
+
+impl InstanceLayout {
+    /// State root before the first change (lo, hi)
+    pub const PREV_STATE_ROOT: [usize; 2] = [0, 1];
+    /// State root after the last change (lo, hi)
+    pub const CURR_STATE_ROOT: [usize; 2] = [2, 3];
+    /// Number of changes
+    pub const CHANGE_COUNT: usize = 4;
+    pub const CHAIN_ID: usize = 5;
+    pub const BLOCK_NUMBER: usize = 6;
+    /// Block hash (lo, hi)
+    pub const BLOCK_HASH: [usize; 2] = [7, 8];
+    /// Number of instances before the changes
+    pub const HEADER_LEN: usize = 9;
+
+    /// Offsets of the fields in the instances of a change
+    pub const CHANGE_PROOF_TYPE: usize = 0;
+    pub const CHANGE_ADDRESS: usize = 1;
+    /// Value (lo, hi)
+    pub const CHANGE_VALUE: [usize; 2] = [2, 3];
+    /// Storage key (lo, hi)
+    pub const CHANGE_KEY: [usize; 2] = [4, 5];
+    /// Number of instances of a change
+    pub const CHANGE_LEN: usize = 6;
+
+    const HEADER_NAMES: [&'static str; Self::HEADER_LEN] = [
+        "PREV_STATE_ROOT_LO",
+        "PREV_STATE_ROOT_HI",
+        "CURR_STATE_ROOT_LO",
+        "CURR_STATE_ROOT_HI",
+        "CHANGE_COUNT",
+        "CHAIN_ID",
+        "BLOCK_NUMBER",
+        "BLOCK_HASH_LO",
+        "BLOCK_HASH_HI",
+    ];
+    const CHANGE_NAMES: [&'static str; Self::CHANGE_LEN] = [
+        "PROOF_TYPE",
+        "ADDRESS",
+        "VALUE_LO",
+        "VALUE_HI",
+        "KEY_LO",
+        "KEY_HI",
+    ];
+
+    pub fn new(max_proof_count: usize, mpt_digest: bool) -> Self {
+        Self {
+            max_proof_count,
+            mpt_digest,
+        }
+    }
+
+    /// Position of the first instance of the change of row `idx`, `None` when
+    /// the changes are replaced by their digest
+    pub fn change(&self, idx: usize) -> Option<usize> {
+        assert!(idx < self.max_proof_count, "no row {} in the circuit", idx);
+        (!self.mpt_digest).then_some(Self::HEADER_LEN + idx * Self::CHANGE_LEN)
+    }
+
+    /// Position of the digest of the changes (lo, hi), when enabled
+    pub fn mpt_digest(&self) -> Option<[usize; 2]> {
+        self.mpt_digest
+            .then_some([Self::HEADER_LEN, Self::HEADER_LEN + 1])
+    }
+
+    /// Number of instances of a proof with changes in all the rows
+    pub fn len(&self) -> usize {
+        Self::HEADER_LEN
+            + if self.mpt_digest {
+                2
+            } else {
+                self.max_proof_count * Self::CHANGE_LEN
+            }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of every instance, in order
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Self::HEADER_NAMES.iter().map(|s| s.to_string()).collect();
+        if self.mpt_digest {
+            names.extend(["MPT_DIGEST_LO".to_string(), "MPT_DIGEST_HI".to_string()]);
+        } else {
+            for idx in 0..self.max_proof_count {
+                names.extend(
+                    Self::CHANGE_NAMES
+                        .iter()
+                        .map(|name| format!("CHANGE_{}_{}", idx, name)),
+                );
+            }
+        }
+        names
+    }
+
+    /// Solidity library with the positions of the instances, for the contracts
+    /// consuming the public inputs of a generated verifier. The changes are
+    /// given by their offsets in a change and by `change(idx)`.
+    pub fn solidity(&self) -> String {
+        let mut sol = String::new();
+        writeln!(sol, "// SPDX-License-Identifier: Unlicense").unwrap();
+        writeln!(sol, "pragma solidity ^0.8.5;").unwrap();
+        writeln!(sol).unwrap();
+        writeln!(
+            sol,
+            "// Generated by `InstanceLayout::solidity`, do not edit. Positions of the"
+        )
+        .unwrap();
+        writeln!(
+            sol,
+            "// public inputs of the state update circuit (max_proof_count = {}, mpt_digest = {}).",
+            self.max_proof_count, self.mpt_digest
+        )
+        .unwrap();
+        writeln!(sol, "library StateUpdateInstances {{").unwrap();
+        writeln!(sol, "    uint256 constant NUM_INSTANCES = {};", self.len()).unwrap();
+        for (idx, name) in Self::HEADER_NAMES.iter().enumerate() {
+            writeln!(sol, "    uint256 constant {} = {};", name, idx).unwrap();
+        }
+        if let Some([lo, hi]) = self.mpt_digest() {
+            writeln!(sol, "    uint256 constant MPT_DIGEST_LO = {};", lo).unwrap();
+            writeln!(sol, "    uint256 constant MPT_DIGEST_HI = {};", hi).unwrap();
+        } else {
+            writeln!(
+                sol,
+                "    uint256 constant MAX_PROOF_COUNT = {};",
+                self.max_proof_count
+            )
+            .unwrap();
+            for (idx, name) in Self::CHANGE_NAMES.iter().enumerate() {
+                writeln!(sol, "    uint256 constant CHANGE_{} = {};", name, idx).unwrap();
+            }
+            writeln!(sol).unwrap();
+            writeln!(
+                sol,
+                "    function change(uint256 idx) internal pure returns (uint256) {{"
+            )
+            .unwrap();
+            writeln!(
+                sol,
+                "        require(idx < MAX_PROOF_COUNT, \"no such row\");"
+            )
+            .unwrap();
+            writeln!(
+                sol,
+                "        return {} + idx * {};",
+                Self::HEADER_LEN,
+                Self::CHANGE_LEN
+            )
+            .unwrap();
+            writeln!(sol, "    }}").unwrap();
+        }
+        writeln!(sol, "}}").unwrap();
+        sol
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_positions() {
+        let layout = InstanceLayout::new(3, false);
+        let names = layout.names();
+        assert_eq!(names.len(), layout.len());
+        assert_eq!(
+            names[InstanceLayout::CURR_STATE_ROOT[1]],
+            "CURR_STATE_ROOT_HI"
+        );
+        assert_eq!(names[InstanceLayout::BLOCK_NUMBER], "BLOCK_NUMBER");
+        assert_eq!(
+            names[layout.change(2).unwrap() + InstanceLayout::CHANGE_KEY[0]],
+            "CHANGE_2_KEY_LO"
+        );
+        assert!(layout
+            .solidity()
+            .contains("uint256 constant NUM_INSTANCES = 27;"));
+
+        let layout = InstanceLayout::new(3, true);
+        assert_eq!(layout.change(0), None);
+        assert_eq!(layout.len(), InstanceLayout::HEADER_LEN + 2);
+        assert_eq!(
+            layout.names()[layout.mpt_digest().unwrap()[1]],
+            "MPT_DIGEST_HI"
+        );
+    }
+}
@@ -1,9 +1,39 @@
+mod backend;
+mod bundle;
+mod chain_config;
 mod equal_words;
+mod estimate;
+mod evm;
+mod instance_layout;
+mod mpt_digest;
+mod node_cache;
 mod prover;
+mod report;
 mod state_update;
+mod stats;
 mod witness;
 
+pub use backend::{KzgShplonk, ProvingBackend};
+pub use bundle::{ProofBundle, PROOF_BUNDLE_VERSION};
+pub use chain_config::ChainConfig;
+pub use estimate::{peak_memory_bytes, Calibration, CalibrationPoint, ProvingEstimate};
+pub use evm::{
+    compile_verifier, evm_call, state_update_verifier_yul, verifier_calldata, verifier_yul,
+    KzgShplonkEvm,
+};
+pub use instance_layout::InstanceLayout;
+pub use node_cache::NodeCache;
 pub use prover::StateUpdateCircuitKeys;
+pub use report::CircuitReport;
+pub use stats::WitnessStats;
 
-pub use state_update::{StateUpdateCircuit, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT};
-pub use witness::{PublicInputs, StateUpdateWitness};
+pub use state_update::{
+    advice_commitment_index, PublicData, StateUpdateCircuit, StateUpdateCircuitConfig,
+    StateUpdateCircuitParams, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT,
+};
+pub use witness::{
+    check_canonical_order, sort_trie_modifications, BlockMetadata, PublicInputs,
+    StateUpdateWitness, Transforms,
+};
+
+pub(crate) use witness::{fetch_state_diff, TrieModificationBuilder};
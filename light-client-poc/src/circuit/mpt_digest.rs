@@ -0,0 +1,413 @@
+use super::InstanceLayout;
+use eth_types::Field;
+use ethers::{types::H256, utils::keccak256};
+use gadgets::util::Expr;
+use halo2_proofs::{
+    circuit::{AssignedCell, Layouter, Value},
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Fixed, SecondPhase},
+    poly::Rotation,
+};
+use zkevm_circuits::{
+    table::{KeccakTable, LookupTable},
+    util::{word::Word, Challenges},
+};
+
+// The digest is the keccak hash of the public changes of all the rows of the
+// light client table, padding rows included, so that its input has a fixed
+// length. Every change is serialized big endian as
+//
+//   proof type (1 byte) | address (20) | value (32) | key (32)
+//
+// Every input byte takes one row: the bytes are range checked, accumulated
+// into the value of their field (copy constrained to the cell of the change)
+// and into the RLC looked up in the keccak table on the last row.
+
+/// Public inputs of a change, in the `InstanceLayout` order
+pub const CHANGE_PUBLIC_INPUTS: usize = InstanceLayout::CHANGE_LEN;
+
+/// Fields of a change in the digest input: the index of the field in the
+/// public inputs of the change and its number of bytes
+const CHUNKS: [(usize, usize); 6] = [
+    (InstanceLayout::CHANGE_PROOF_TYPE, 1),
+    (InstanceLayout::CHANGE_ADDRESS, 20),
+    (InstanceLayout::CHANGE_VALUE[1], 16),
+    (InstanceLayout::CHANGE_VALUE[0], 16),
+    (InstanceLayout::CHANGE_KEY[1], 16),
+    (InstanceLayout::CHANGE_KEY[0], 16),
+];
+
+/// Number of bytes of a change in the digest input
+pub const CHANGE_BYTES: usize = 85;
+
+fn be_bytes<F: Field>(value: F, len: usize) -> impl Iterator<Item = u8> {
+    let repr = value.to_repr();
+    (0..len).rev().map(move |idx| repr[idx])
+}
+
+/// Input of the digest of `changes` (public inputs of the changes), padded
+/// with empty changes to `max_proof_count` changes
+pub fn digest_input<F: Field>(changes: &[F], max_proof_count: usize) -> Vec<u8> {
+    let mut changes = changes.to_vec();
+    changes.resize(max_proof_count * CHANGE_PUBLIC_INPUTS, F::ZERO);
+    changes
+        .chunks(CHANGE_PUBLIC_INPUTS)
+        .flat_map(|change| {
+            CHUNKS
+                .iter()
+                .flat_map(|(idx, len)| be_bytes(change[*idx], *len))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Digest (lo, hi) of `changes` (public inputs of the changes)
+pub fn digest<F: Field>(changes: &[F], max_proof_count: usize) -> Word<F> {
+    Word::from(H256(keccak256(digest_input(changes, max_proof_count))))
+}
+
+#[derive(Clone, Debug)]
+pub struct MptDigestConfig {
+    q_enable: Column<Fixed>,
+    q_first: Column<Fixed>,
+    q_chunk_start: Column<Fixed>,
+    q_last: Column<Fixed>,
+    /// Length of the input on the last row
+    input_len: Column<Fixed>,
+    byte_table: Column<Fixed>,
+    byte: Column<Advice>,
+    value: Column<Advice>,
+    rlc: Column<Advice>,
+    digest: Word<Column<Advice>>,
+}
+
+impl MptDigestConfig {
+    pub fn configure<F: Field>(
+        meta: &mut ConstraintSystem<F>,
+        keccak_table: &KeccakTable,
+        challenges: &Challenges<Expression<F>>,
+    ) -> Self {
+        let q_enable = meta.fixed_column();
+        let q_first = meta.fixed_column();
+        let q_chunk_start = meta.fixed_column();
+        let q_last = meta.fixed_column();
+        let input_len = meta.fixed_column();
+        let byte_table = meta.fixed_column();
+        let byte = meta.advice_column();
+        let value = meta.advice_column();
+        let rlc = meta.advice_column_in(SecondPhase);
+        let digest = Word::new([meta.advice_column(), meta.advice_column()]);
+
+        meta.enable_equality(value);
+        meta.enable_equality(digest.lo());
+        meta.enable_equality(digest.hi());
+
+        meta.create_gate("mpt digest accumulation", |meta| {
+            let q_enable = meta.query_fixed(q_enable, Rotation::cur());
+            let q_first = meta.query_fixed(q_first, Rotation::cur());
+            let q_chunk_start = meta.query_fixed(q_chunk_start, Rotation::cur());
+            let byte = meta.query_advice(byte, Rotation::cur());
+            let value_prev = meta.query_advice(value, Rotation::prev());
+            let value = meta.query_advice(value, Rotation::cur());
+            let rlc_prev = meta.query_advice(rlc, Rotation::prev());
+            let rlc = meta.query_advice(rlc, Rotation::cur());
+            vec![
+                // The bytes of a field are big endian
+                q_enable.clone()
+                    * (value - byte.clone() - (1.expr() - q_chunk_start) * value_prev * 256.expr()),
+                // RLC of the input, the first byte has the highest power
+                q_enable
+                    * (rlc - byte - (1.expr() - q_first) * rlc_prev * challenges.keccak_input()),
+            ]
+        });
+
+        meta.lookup_any("mpt digest byte range", |meta| {
+            let q_enable = meta.query_fixed(q_enable, Rotation::cur());
+            vec![(
+                q_enable * meta.query_advice(byte, Rotation::cur()),
+                meta.query_fixed(byte_table, Rotation::cur()),
+            )]
+        });
+
+        meta.lookup_any("mpt digest keccak", |meta| {
+            let q_last = meta.query_fixed(q_last, Rotation::cur());
+            let input = [
+                1.expr(),
+                meta.query_advice(rlc, Rotation::cur()),
+                meta.query_fixed(input_len, Rotation::cur()),
+                meta.query_advice(digest.lo(), Rotation::cur()),
+                meta.query_advice(digest.hi(), Rotation::cur()),
+            ];
+            input
+                .into_iter()
+                .zip(keccak_table.table_exprs(meta))
+                .map(|(input, table)| (q_last.clone() * input, table))
+                .collect()
+        });
+
+        Self {
+            q_enable,
+            q_first,
+            q_chunk_start,
+            q_last,
+            input_len,
+            byte_table,
+            byte,
+            value,
+            rlc,
+            digest,
+        }
+    }
+
+    pub fn load_byte_table<F: Field>(&self, layouter: &mut impl Layouter<F>) -> Result<(), Error> {
+        layouter.assign_region(
+            || "mpt digest byte table",
+            |mut region| {
+                for byte in 0..256 {
+                    region.assign_fixed(
+                        || "byte",
+                        self.byte_table,
+                        byte,
+                        || Value::known(F::from(byte as u64)),
+                    )?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Assigns the digest of `changes`, the cells of the public inputs of the
+    /// changes of all the rows, and returns the cells of the digest (lo, hi)
+    pub fn assign<F: Field>(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        changes: &[AssignedCell<F, F>],
+        max_proof_count: usize,
+        challenges: &Challenges<Value<F>>,
+    ) -> Result<[AssignedCell<F, F>; 2], Error> {
+        assert_eq!(changes.len(), max_proof_count * CHANGE_PUBLIC_INPUTS);
+        layouter.assign_region(
+            || "mpt digest",
+            |mut region| {
+                region.name_column(|| "MPT_digest_byte", self.byte);
+                region.name_column(|| "MPT_digest_value", self.value);
+                region.name_column(|| "MPT_digest_rlc", self.rlc);
+
+                // The values are unknown during key generation, only the
+                // layout matters then
+                let values: Vec<F> = changes
+                    .iter()
+                    .map(|cell| {
+                        let mut value = F::ZERO;
+                        cell.value().map(|v| value = *v);
+                        value
+                    })
+                    .collect();
+                let input = digest_input(&values, max_proof_count);
+
+                let mut offset = 0;
+                let mut rlc = Value::known(F::ZERO);
+                for change in changes.chunks(CHANGE_PUBLIC_INPUTS) {
+                    for (idx, len) in CHUNKS {
+                        let mut value = F::ZERO;
+                        let mut value_cell = None;
+                        for (pos, byte) in input[offset..offset + len].iter().enumerate() {
+                            let byte = F::from(*byte as u64);
+                            value = value * F::from(256) + byte;
+                            rlc = rlc * challenges.keccak_input() + Value::known(byte);
+                            for (column, enabled) in [
+                                (self.q_enable, true),
+                                (self.q_first, offset + pos == 0),
+                                (self.q_chunk_start, pos == 0),
+                            ] {
+                                region.assign_fixed(
+                                    || "",
+                                    column,
+                                    offset + pos,
+                                    || Value::known(F::from(enabled as u64)),
+                                )?;
+                            }
+                            region.assign_advice(
+                                || "",
+                                self.byte,
+                                offset + pos,
+                                || Value::known(byte),
+                            )?;
+                            value_cell = Some(region.assign_advice(
+                                || "",
+                                self.value,
+                                offset + pos,
+                                || Value::known(value),
+                            )?);
+                            region.assign_advice(|| "", self.rlc, offset + pos, || rlc)?;
+                        }
+                        // The bytes are the ones of the change
+                        region.constrain_equal(value_cell.unwrap().cell(), change[idx].cell())?;
+                        offset += len;
+                    }
+                }
+
+                let last = offset - 1;
+                let digest = Word::<F>::from(H256(keccak256(&input)));
+                region.assign_fixed(|| "", self.q_last, last, || Value::known(F::ONE))?;
+                region.assign_fixed(
+                    || "",
+                    self.input_len,
+                    last,
+                    || Value::known(F::from(input.len() as u64)),
+                )?;
+                let lo = region.assign_advice(
+                    || "digest lo",
+                    self.digest.lo(),
+                    last,
+                    || Value::known(digest.lo()),
+                )?;
+                let hi = region.assign_advice(
+                    || "digest hi",
+                    self.digest.hi(),
+                    last,
+                    || Value::known(digest.hi()),
+                )?;
+                Ok([lo, hi])
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::{
+        circuit::SimpleFloorPlanner,
+        dev::{MockProver, VerifyFailure},
+        halo2curves::bn256::Fr,
+        plonk::{Circuit, Instance},
+    };
+
+    /// Digest of `changes` exposed as the instances (lo, hi). The keccak table
+    /// holds the digest of `hashed`, which is `changes` for an honest prover.
+    #[derive(Clone, Default)]
+    struct DigestCircuit {
+        changes: Vec<Fr>,
+        hashed: Vec<Fr>,
+        max_proof_count: usize,
+    }
+
+    impl Circuit<Fr> for DigestCircuit {
+        type Config = (
+            MptDigestConfig,
+            KeccakTable,
+            Challenges,
+            Column<Advice>,
+            Column<Instance>,
+        );
+        type FloorPlanner = SimpleFloorPlanner;
+        type Params = ();
+
+        fn without_witnesses(&self) -> Self {
+            self.clone()
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+            let challenges = Challenges::construct(meta);
+            let challenges_expr = challenges.exprs(meta);
+            let keccak_table = KeccakTable::construct(meta);
+            let digest = MptDigestConfig::configure(meta, &keccak_table, &challenges_expr);
+            let changes = meta.advice_column();
+            let instance = meta.instance_column();
+            meta.enable_equality(changes);
+            meta.enable_equality(instance);
+            (digest, keccak_table, challenges, changes, instance)
+        }
+
+        fn synthesize(
+            &self,
+            (digest, keccak_table, challenges, changes, instance): Self::Config,
+            mut layouter: impl Layouter<Fr>,
+        ) -> Result<(), Error> {
+            let challenges = challenges.values(&mut layouter);
+            let cells = layouter.assign_region(
+                || "changes",
+                |mut region| {
+                    let mut values = self.changes.clone();
+                    values.resize(self.max_proof_count * CHANGE_PUBLIC_INPUTS, Fr::from(0));
+                    values
+                        .iter()
+                        .enumerate()
+                        .map(|(offset, value)| {
+                            region.assign_advice(|| "", changes, offset, || Value::known(*value))
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                },
+            )?;
+            keccak_table.dev_load(
+                &mut layouter,
+                &[digest_input(&self.hashed, self.max_proof_count)],
+                &challenges,
+            )?;
+            digest.load_byte_table(&mut layouter)?;
+            let [lo, hi] =
+                digest.assign(&mut layouter, &cells, self.max_proof_count, &challenges)?;
+            layouter.constrain_instance(lo.cell(), instance, 0)?;
+            layouter.constrain_instance(hi.cell(), instance, 1)
+        }
+    }
+
+    fn changes() -> Vec<Fr> {
+        [1, 0xaa, 2, 3, 4, 5, 2, 0xbb, 6, 7, 0, 0]
+            .map(|value| Fr::from(value as u64))
+            .to_vec()
+    }
+
+    fn verify(hashed: Vec<Fr>) -> Result<(), Vec<VerifyFailure>> {
+        let circuit = DigestCircuit {
+            changes: changes(),
+            hashed: hashed.clone(),
+            max_proof_count: 3,
+        };
+        let digest = digest(&hashed, 3);
+        MockProver::run(12, &circuit, vec![vec![digest.lo(), digest.hi()]])
+            .unwrap()
+            .verify()
+    }
+
+    #[test]
+    fn mpt_digest_circuit() {
+        assert_eq!(verify(changes()), Ok(()));
+    }
+
+    #[test]
+    fn mpt_digest_circuit_tampered_byte() {
+        // The digest of the changes with one byte of the address of the
+        // second change flipped
+        let mut hashed = changes();
+        hashed[CHANGE_PUBLIC_INPUTS + InstanceLayout::CHANGE_ADDRESS] = Fr::from(0xba);
+        let failures = verify(hashed).unwrap_err();
+        assert!(
+            failures.iter().any(|failure| matches!(
+                failure,
+                VerifyFailure::Lookup { name, .. } if *name == "mpt digest keccak"
+            )),
+            "{:?}",
+            failures
+        );
+    }
+
+    #[test]
+    fn digest_input_layout() {
+        let changes = [1, 0xaa, 2, 3, 4, 5].map(|value| Fr::from(value as u64));
+        let input = digest_input(&changes, 2);
+        assert_eq!(input.len(), 2 * CHANGE_BYTES);
+        assert_eq!(
+            CHUNKS.iter().map(|(_, len)| len).sum::<usize>(),
+            CHANGE_BYTES
+        );
+        // proof type, address, value hi/lo and key hi/lo
+        assert_eq!(input[0], 1);
+        assert_eq!(input[20], 0xaa);
+        assert_eq!(input[21 + 31], 2);
+        assert_eq!(input[21 + 15], 3);
+        assert_eq!(input[53 + 31], 4);
+        // The padding change is empty
+        assert!(input[CHANGE_BYTES..].iter().all(|byte| *byte == 0));
+    }
+}
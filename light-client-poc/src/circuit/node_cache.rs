@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use ethers::{
+    types::{Bytes, H256},
+    utils::keccak256,
+};
+use zkevm_circuits::mpt_circuit::witness_row::Node;
+
+/// Trie nodes by hash. The proofs of different accounts share their upper
+/// branches, and the S and C proofs of consecutive modifications share most of
+/// their nodes, so every node only needs to be kept once.
+#[derive(Debug, Default, Clone)]
+pub struct NodeCache {
+    nodes: HashMap<H256, Vec<u8>>,
+    /// Number of nodes inserted, including the duplicates
+    inserted: usize,
+}
+
+impl NodeCache {
+    /// Returns `true` if the node was not in the cache yet
+    pub fn insert(&mut self, node: &[u8]) -> bool {
+        self.inserted += 1;
+        let hash = H256(keccak256(node));
+        if self.nodes.contains_key(&hash) {
+            return false;
+        }
+        self.nodes.insert(hash, node.to_vec());
+        true
+    }
+
+    pub fn insert_proof(&mut self, proof: &[Bytes]) {
+        for node in proof {
+            self.insert(node);
+        }
+    }
+
+    pub fn get(&self, hash: &H256) -> Option<&[u8]> {
+        self.nodes.get(hash).map(|node| node.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn inserted(&self) -> usize {
+        self.inserted
+    }
+
+    /// Removes the keccak data already given by a previous node of the witness,
+    /// the keccak table only needs every preimage once
+    pub fn dedup_keccak_data(&mut self, nodes: &mut [Node]) {
+        for node in nodes.iter_mut() {
+            node.keccak_data.retain(|data| self.insert(data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedup_keccak_data() {
+        let node = |data: &[&[u8]]| Node {
+            keccak_data: data.iter().map(|d| d.to_vec().into()).collect(),
+            ..Default::default()
+        };
+        let mut nodes = vec![node(&[b"a", b"b"]), node(&[b"b", b"c", b"a"])];
+
+        let mut cache = NodeCache::default();
+        cache.dedup_keccak_data(&mut nodes);
+
+        assert_eq!(nodes[0].keccak_data.len(), 2);
+        assert_eq!(nodes[1].keccak_data.len(), 1);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.inserted(), 5);
+        assert_eq!(cache.get(&H256(keccak256(b"c"))), Some(&b"c"[..]));
+    }
+}
@@ -1,83 +1,109 @@
 use eyre::Result;
-use halo2_proofs::{dev::MockProver, halo2curves::bn256::Fr, plonk::Circuit, SerdeFormat};
+use halo2_proofs::{dev::MockProver, halo2curves::bn256::Fr, plonk::Circuit};
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
 use std::time::Instant;
+use zkevm_circuits::mpt_circuit::KeyDerivation;
 
-use super::witness::PublicInputs;
-use halo2_proofs::{
-    halo2curves::bn256::{Bn256, G1Affine},
-    plonk::{create_proof, keygen_pk, keygen_vk, verify_proof, ProvingKey},
-    poly::{
-        commitment::ParamsProver,
-        kzg::{
-            commitment::{KZGCommitmentScheme, ParamsKZG, ParamsVerifierKZG},
-            multiopen::{ProverSHPLONK, VerifierSHPLONK},
-            strategy::SingleStrategy,
-        },
-    },
-    transcript::{
-        Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
-    },
+use super::{
+    backend::{KzgShplonk, ProvingBackend},
+    state_update::{StateUpdateCircuit, StateUpdateCircuitParams},
 };
 
-use super::state_update::StateUpdateCircuit;
-
+/// Keys of the state update circuit for the proving backend `B`
 #[derive(Clone)]
-pub struct StateUpdateCircuitKeys {
-    general_params: ParamsKZG<Bn256>,
-    verifier_params: ParamsVerifierKZG<Bn256>,
-    pk: ProvingKey<G1Affine>,
+pub struct StateUpdateCircuitKeys<B: ProvingBackend = KzgShplonk> {
+    general_params: B::Params,
+    verifier_params: B::VerifierParams,
+    pk: B::ProvingKey,
+    /// The constraint system depends on them, needed to read back the proving key
+    strict_empty_accounts: bool,
+    key_derivation: KeyDerivation,
+    keccak_oracle: bool,
+    mpt_digest: bool,
 }
 
-impl StateUpdateCircuitKeys {
-    pub fn new(circuit: &StateUpdateCircuit<Fr>) -> StateUpdateCircuitKeys {
+impl<B: ProvingBackend> StateUpdateCircuitKeys<B> {
+    pub fn new(circuit: &StateUpdateCircuit<Fr>) -> Self {
         let mut rng = ChaCha20Rng::seed_from_u64(42);
 
         let start = Instant::now();
 
-        // let circuit = StateUpdateCircuit::default();
-
-        let general_params = ParamsKZG::<Bn256>::setup(circuit.degree as u32, &mut rng);
-        let verifier_params: ParamsVerifierKZG<Bn256> = general_params.verifier_params().clone();
+        let general_params = B::setup(circuit.degree as u32, &mut rng);
+        let verifier_params = B::verifier_params(&general_params);
 
         // Initialize the proving key
-        let vk = keygen_vk(&general_params, circuit).expect("keygen_vk should not fail");
-        let pk = keygen_pk(&general_params, vk, circuit).expect("keygen_pk should not fail");
+        let pk = B::keygen(&general_params, circuit).expect("keygen should not fail");
 
-        println!("key generation time: {:?}", start.elapsed());
+        tracing::info!(elapsed = ?start.elapsed(), "keys generated");
 
         StateUpdateCircuitKeys {
             general_params,
             verifier_params,
             pk,
+            strict_empty_accounts: circuit.params().mpt.strict_empty_accounts,
+            key_derivation: circuit.params().mpt.key_derivation,
+            keccak_oracle: circuit.keccak_oracle,
+            mpt_digest: circuit.mpt_digest,
         }
     }
 
+    /// Parameters of the commitment scheme the keys were generated with
+    pub fn params(&self) -> &B::Params {
+        &self.general_params
+    }
+
+    pub fn verifying_key(&self) -> &B::VerifyingKey {
+        B::verifying_key(&self.pk)
+    }
+
+    /// Hash of the serialized verifying key
+    pub fn vk_hash(&self) -> [u8; 32] {
+        ethers::utils::keccak256(B::vk_bytes(self.verifying_key()))
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>> {
-        let mut buffer = Vec::new();
-        self.general_params
-            .write_custom(&mut buffer, SerdeFormat::RawBytes)?;
-        self.verifier_params
-            .write_custom(&mut buffer, SerdeFormat::RawBytes)?;
-        self.pk.write(&mut buffer, SerdeFormat::RawBytes).unwrap();
+        // Keys written before the key derivation was added have it set to 0 (keccak)
+        let flags = self.strict_empty_accounts as u8
+            | (u8::from(self.key_derivation) << 1)
+            | ((self.mpt_digest as u8) << 6)
+            | ((self.keccak_oracle as u8) << 7);
+        let mut buffer = vec![flags];
+        B::write_keys(
+            &self.general_params,
+            &self.verifier_params,
+            &self.pk,
+            &mut buffer,
+        )?;
         Ok(buffer)
     }
 
     pub fn unserialize(mut bytes: &[u8]) -> Result<Self> {
-        let general_params = ParamsKZG::<Bn256>::read_custom(&mut bytes, SerdeFormat::RawBytes)?;
-        let verifier_params =
-            ParamsVerifierKZG::<Bn256>::read_custom(&mut bytes, SerdeFormat::RawBytes)?;
-        let circuit_params = StateUpdateCircuit::<Fr>::default().params();
-        let pk = ProvingKey::<G1Affine>::read::<_, StateUpdateCircuit<Fr>>(
-            &mut bytes,
-            SerdeFormat::RawBytes,
-            circuit_params,
-        )?;
+        let (flags, rest) = bytes
+            .split_first()
+            .ok_or_else(|| eyre::eyre!("empty keys"))?;
+        let strict_empty_accounts = flags & 1 != 0;
+        let keccak_oracle = flags & 0x80 != 0;
+        let mpt_digest = flags & 0x40 != 0;
+        let key_derivation = KeyDerivation::try_from((flags & 0x3f) >> 1)
+            .map_err(|value| eyre::eyre!("unknown key derivation {}", value))?;
+        bytes = rest;
+        let mut circuit_params: StateUpdateCircuitParams =
+            StateUpdateCircuit::<Fr>::default().params();
+        circuit_params.mpt.strict_empty_accounts = strict_empty_accounts;
+        circuit_params.mpt.key_derivation = key_derivation;
+        circuit_params.keccak_oracle = keccak_oracle;
+        circuit_params.mpt_digest = mpt_digest;
+        let (general_params, verifier_params, pk) =
+            B::read_keys::<StateUpdateCircuit<Fr>>(&mut bytes, circuit_params)?;
         Ok(Self {
             general_params,
             verifier_params,
             pk,
+            strict_empty_accounts,
+            key_derivation,
+            keccak_oracle,
+            mpt_digest,
         })
     }
 }
@@ -91,71 +117,67 @@ impl StateUpdateCircuit<Fr> {
             .map(|node| node.values.len())
             .sum();
 
-        let public_inputs: PublicInputs<Fr> = (&self.lc_witness).into();
+        let public_inputs = self.public_inputs();
 
-        for (i, input) in public_inputs.iter().enumerate() {
-            println!("input[{i:}]: {input:?}");
-        }
+        tracing::debug!(inputs = ?public_inputs.0, "public inputs");
 
         let prover =
             MockProver::<Fr>::run(self.degree as u32, self, vec![public_inputs.0]).unwrap();
         prover.assert_satisfied_at_rows_par(0..num_rows, 0..num_rows);
     }
 
-    pub fn prove(self, keys: &StateUpdateCircuitKeys) -> Result<Vec<u8>> {
+    pub fn prove<B: ProvingBackend>(self, keys: &StateUpdateCircuitKeys<B>) -> Result<Vec<u8>> {
         let rng = ChaCha20Rng::seed_from_u64(42);
 
-        // Create a proof
-        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
-
-        let public_inputs: PublicInputs<Fr> = (&self.lc_witness).into();
+        let public_inputs = self.public_inputs();
 
         // Bench proof generation time
         let start = Instant::now();
-        create_proof::<
-            KZGCommitmentScheme<Bn256>,
-            ProverSHPLONK<'_, Bn256>,
-            Challenge255<G1Affine>,
-            ChaCha20Rng,
-            Blake2bWrite<Vec<u8>, G1Affine, Challenge255<G1Affine>>,
-            StateUpdateCircuit<Fr>,
-        >(
-            &keys.general_params,
-            &keys.pk,
-            &[self],
-            &[&[&public_inputs]],
-            rng,
-            &mut transcript,
-        )?;
+        let proof = B::prove(&keys.general_params, &keys.pk, self, &[&public_inputs], rng)?;
 
-        let proof = transcript.finalize();
-
-        println!("proof generation time: {:?}", start.elapsed());
+        tracing::info!(elapsed = ?start.elapsed(), bytes = proof.len(), "proof generated");
 
         Ok(proof)
     }
 
-    pub fn verify(proof: &[u8], public_inputs: &[Fr], keys: &StateUpdateCircuitKeys) -> Result<()> {
+    pub fn verify<B: ProvingBackend>(
+        proof: &[u8],
+        public_inputs: &[Fr],
+        keys: &StateUpdateCircuitKeys<B>,
+    ) -> Result<()> {
         // Bench verification time
         let start = Instant::now();
-        let mut verifier_transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(proof);
-        let strategy = SingleStrategy::new(&keys.general_params);
-
-        verify_proof::<
-            KZGCommitmentScheme<Bn256>,
-            VerifierSHPLONK<'_, Bn256>,
-            Challenge255<G1Affine>,
-            Blake2bRead<&[u8], G1Affine, Challenge255<G1Affine>>,
-            SingleStrategy<'_, Bn256>,
-        >(
+        B::verify(
             &keys.verifier_params,
-            keys.pk.get_vk(),
-            strategy,
-            &[&[public_inputs]],
-            &mut verifier_transcript,
+            keys.verifying_key(),
+            proof,
+            &[public_inputs],
         )?;
 
-        println!("verification time: {:?}", start.elapsed());
+        tracing::info!(elapsed = ?start.elapsed(), "proof verified");
+
+        Ok(())
+    }
+
+    /// Verifies many proofs at once, see `ProvingBackend::verify_batch`
+    pub fn verify_batch<B: ProvingBackend>(
+        proofs: &[(&[u8], &[Fr])],
+        keys: &StateUpdateCircuitKeys<B>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let instances: Vec<[&[Fr]; 1]> = proofs.iter().map(|(_, inputs)| [*inputs]).collect();
+        let proofs: Vec<(&[u8], &[&[Fr]])> = proofs
+            .iter()
+            .zip(instances.iter())
+            .map(|((proof, _), instances)| (*proof, instances.as_slice()))
+            .collect();
+        B::verify_batch(&keys.verifier_params, keys.verifying_key(), &proofs)?;
+
+        tracing::info!(
+            elapsed = ?start.elapsed(),
+            proofs = proofs.len(),
+            "batch of proofs verified"
+        );
 
         Ok(())
     }
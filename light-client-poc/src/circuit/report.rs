@@ -0,0 +1,186 @@
+use eth_types::Field;
+use halo2_proofs::{
+    halo2curves::bn256::Fr,
+    plonk::{Circuit, ConstraintSystem, Expression},
+};
+use std::fmt;
+
+use super::{state_update::StateUpdateCircuit, InstanceLayout};
+
+// Sizes of the BN254 elements in a proof
+const G1_SIZE: usize = 32;
+const FR_SIZE: usize = 32;
+
+// Gas costs used for the on-chain verification estimate
+const TX_GAS: usize = 21_000;
+const CALLDATA_GAS_PER_BYTE: usize = 16;
+// Two pairings (EIP-1108)
+const PAIRING_GAS: usize = 45_000 + 2 * 34_000;
+const EC_MUL_GAS: usize = 6_000;
+const EC_ADD_GAS: usize = 150;
+// Field operations and memory accesses of a generated verifier, per node of
+// the gate expressions and per public input (Lagrange evaluation)
+const EXPRESSION_NODE_GAS: usize = 30;
+const INSTANCE_GAS: usize = 200;
+
+/// Size of a proof and cost of verifying it on-chain for given circuit
+/// parameters. Only the constraint system is needed, nothing is proven.
+///
+/// The proof size is exact for the KZG/SHPLONK prover in `prover.rs`. The gas
+/// is an estimate of a generated Solidity verifier: calldata, the MSM of all
+/// the commitments, the final pairing and the evaluation of the constraints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitReport {
+    pub degree: usize,
+    pub max_proof_count: usize,
+    pub public_inputs: usize,
+    pub advice_columns: usize,
+    pub fixed_columns: usize,
+    pub lookups: usize,
+    pub constraint_degree: usize,
+    pub proof_commitments: usize,
+    pub proof_evaluations: usize,
+    pub proof_size: usize,
+    pub verification_gas: usize,
+}
+
+impl CircuitReport {
+    pub fn new(degree: usize, max_proof_count: usize) -> Self {
+        let mut params = StateUpdateCircuit::<Fr>::default().params();
+        params.mpt.degree = degree;
+        let mut meta = ConstraintSystem::<Fr>::default();
+        StateUpdateCircuit::<Fr>::configure_with_params(&mut meta, params);
+        Self::from_constraint_system(&meta, degree, max_proof_count)
+    }
+
+    fn from_constraint_system<F: Field>(
+        meta: &ConstraintSystem<F>,
+        degree: usize,
+        max_proof_count: usize,
+    ) -> Self {
+        let constraint_degree = meta.degree();
+        let permutation_columns = meta.permutation().get_columns().len();
+        let permutation_chunks = if permutation_columns == 0 {
+            0
+        } else {
+            (permutation_columns + constraint_degree - 3) / (constraint_degree - 2)
+        };
+        let lookups = meta.lookups().len();
+
+        // Advice, lookup permuted input/table and product, permutation products,
+        // vanishing random polynomial and quotient pieces, SHPLONK openings
+        let proof_commitments = meta.num_advice_columns()
+            + 3 * lookups
+            + permutation_chunks
+            + 1
+            + (constraint_degree - 1)
+            + 2;
+        // Instance columns are not queried by the KZG verifier
+        let proof_evaluations = meta.advice_queries().len()
+            + meta.fixed_queries().len()
+            + 1
+            + permutation_columns
+            + (3 * permutation_chunks).saturating_sub(1)
+            + 5 * lookups;
+        let proof_size = proof_commitments * G1_SIZE + proof_evaluations * FR_SIZE;
+
+        let public_inputs = InstanceLayout::new(max_proof_count, false).len();
+
+        // The verifying key commitments are part of the MSM too
+        let msm_points = proof_commitments + meta.num_fixed_columns() + permutation_columns;
+        let expression_nodes: usize = meta
+            .gates()
+            .iter()
+            .flat_map(|gate| gate.polynomials())
+            .chain(
+                meta.lookups()
+                    .iter()
+                    .flat_map(|lookup| lookup.input_expressions()),
+            )
+            .map(expression_nodes)
+            .sum();
+        let verification_gas = TX_GAS
+            + (proof_size + public_inputs * FR_SIZE) * CALLDATA_GAS_PER_BYTE
+            + msm_points * (EC_MUL_GAS + EC_ADD_GAS)
+            + PAIRING_GAS
+            + expression_nodes * EXPRESSION_NODE_GAS
+            + public_inputs * INSTANCE_GAS;
+
+        Self {
+            degree,
+            max_proof_count,
+            public_inputs,
+            advice_columns: meta.num_advice_columns(),
+            fixed_columns: meta.num_fixed_columns(),
+            lookups,
+            constraint_degree,
+            proof_commitments,
+            proof_evaluations,
+            proof_size,
+            verification_gas,
+        }
+    }
+}
+
+fn expression_nodes<F: Field>(expr: &Expression<F>) -> usize {
+    expr.evaluate(
+        &|_| 1,
+        &|_| 1,
+        &|_| 1,
+        &|_| 1,
+        &|_| 1,
+        &|_| 1,
+        &|a| a + 1,
+        &|a, b| a + b + 1,
+        &|a, b| a + b + 1,
+        &|a, _| a + 1,
+    )
+}
+
+impl fmt::Display for CircuitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "degree:             {}", self.degree)?;
+        writeln!(f, "max proof count:    {}", self.max_proof_count)?;
+        writeln!(f, "public inputs:      {}", self.public_inputs)?;
+        writeln!(f, "advice columns:     {}", self.advice_columns)?;
+        writeln!(f, "fixed columns:      {}", self.fixed_columns)?;
+        writeln!(f, "lookups:            {}", self.lookups)?;
+        writeln!(f, "constraint degree:  {}", self.constraint_degree)?;
+        writeln!(
+            f,
+            "proof size:         {} bytes ({} commitments, {} evaluations)",
+            self.proof_size, self.proof_commitments, self.proof_evaluations
+        )?;
+        write!(f, "verification gas:   ~{}", self.verification_gas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::{
+        halo2curves::bn256::Fr,
+        plonk::{Advice, Column},
+        poly::Rotation,
+    };
+
+    #[test]
+    fn proof_size_of_single_gate() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let a: Column<Advice> = meta.advice_column();
+        let q = meta.fixed_column();
+        meta.create_gate("square", |meta| {
+            let a = meta.query_advice(a, Rotation::cur());
+            let q = meta.query_fixed(q, Rotation::cur());
+            vec![q * (a.clone() * a.clone() - a)]
+        });
+        let report = CircuitReport::from_constraint_system(&meta, 4, 1);
+        assert_eq!(report.constraint_degree, 3);
+        // advice, random polynomial, 2 quotient pieces, 2 openings
+        assert_eq!(report.proof_commitments, 6);
+        // advice, fixed, random polynomial
+        assert_eq!(report.proof_evaluations, 3);
+        assert_eq!(report.proof_size, 9 * 32);
+        assert_eq!(report.public_inputs, 15);
+    }
+}
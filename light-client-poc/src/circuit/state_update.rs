@@ -1,4 +1,4 @@
-use super::equal_words::EqualWordsConfig;
+use super::{equal_words::EqualWordsConfig, mpt_digest::MptDigestConfig};
 use eth_types::Field;
 use eyre::Result;
 use gadgets::{
@@ -10,7 +10,7 @@ use gadgets::{
     },
 };
 use halo2_proofs::{
-    circuit::{Layouter, SimpleFloorPlanner, Value},
+    circuit::{AssignedCell, Layouter, SimpleFloorPlanner, Value},
     halo2curves::bn256::Fr,
     plonk::{
         Advice, Circuit, Column, ConstraintSystem, Error, Expression, Fixed, Instance, Selector,
@@ -19,13 +19,18 @@ use halo2_proofs::{
 };
 
 use zkevm_circuits::{
-    mpt_circuit::{MPTCircuit, MPTCircuitParams, MPTConfig},
-    table::{KeccakTable, MptTable},
+    mpt_circuit::{KeyDerivation, MPTCircuit, MPTCircuitParams, MPTConfig},
+    table::{KeccakTable, LookupTable, MptTable},
     util::{word, Challenges},
 };
 
-use super::witness::{
-    SingleTrieModification, SingleTrieModifications, StateUpdateWitness, Transforms,
+use super::{
+    mpt_digest,
+    witness::{
+        PublicInputs, SingleTrieModification, SingleTrieModifications, StateUpdateWitness,
+        Transforms,
+    },
+    InstanceLayout,
 };
 
 #[cfg(not(feature = "disable-keccak"))]
@@ -37,6 +42,19 @@ use zkevm_circuits::{
 pub const DEFAULT_MAX_PROOF_COUNT: usize = 20;
 pub const DEFAULT_CIRCUIT_DEGREE: usize = 14;
 
+/// Parameters of the state update circuit
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StateUpdateCircuitParams {
+    pub mpt: MPTCircuitParams,
+    /// Loads the keccak table from the keccak data of the witness instead of
+    /// proving the hashes with the keccak circuit, so that tests only need the
+    /// rows of the MPT circuit. ONLY ENABLE FOR TESTS!
+    pub keccak_oracle: bool,
+    /// Exposes the keccak digest of the changes instead of the changes
+    /// themselves, see `PublicInputs::into_mpt_digest`
+    pub mpt_digest: bool,
+}
+
 // A=>B  eq ~(A & ~B) (it is not the case that A is true and B is false)
 fn xif<F: Field>(a: Expression<F>, b: Expression<F>) -> Expression<F> {
     and::expr([a, not::expr(b)])
@@ -45,12 +63,20 @@ fn xif<F: Field>(a: Expression<F>, b: Expression<F>) -> Expression<F> {
 ///
 #[derive(Clone)]
 pub struct StateUpdateCircuitConfig<F: Field> {
+    /// `None` when the keccak table is loaded from the witness
     #[cfg(not(feature = "disable-keccak"))]
-    pub keccak_config: KeccakCircuitConfig<F>,
+    pub keccak_config: Option<KeccakCircuitConfig<F>>,
+    /// The keccak table is loaded from the witness, always set with the
+    /// `disable-keccak` feature
+    pub keccak_oracle: bool,
     pub mpt_config: MPTConfig<F>,
+    /// `None` when the changes are exposed in the public inputs
+    pub mpt_digest: Option<MptDigestConfig>,
 
     pub pi_mpt: MptTable,
     pub pi_instance: Column<Instance>,
+    /// Chain id, block number and block hash (lo, hi), in the first rows
+    pub pi_metadata: Column<Advice>,
 
     pub is_first: Column<Fixed>,
     pub is_padding: IsZeroConfig<F>,
@@ -65,6 +91,56 @@ pub struct StateUpdateCircuitConfig<F: Field> {
     pub q_enable: Selector,
 }
 
+impl<F: Field> StateUpdateCircuitConfig<F> {
+    /// Advice columns of the MPT table
+    pub fn mpt_table_columns(&self) -> Vec<Column<Advice>> {
+        LookupTable::<F>::advice_columns(&self.mpt_config.mpt_table)
+    }
+
+    /// Positions of the commitments of the MPT table columns in the advice
+    /// commitments of a proof, so that an aggregating verifier can open them
+    /// directly instead of re-proving the roots
+    pub fn mpt_table_commitment_indices(&self, meta: &ConstraintSystem<F>) -> Vec<usize> {
+        self.mpt_table_columns()
+            .into_iter()
+            .map(|column| advice_commitment_index(meta, column))
+            .collect()
+    }
+}
+
+/// Position of the commitment of `column` in the advice commitments of a proof,
+/// which are ordered by phase and then by column index
+pub fn advice_commitment_index<F: Field>(
+    meta: &ConstraintSystem<F>,
+    column: Column<Advice>,
+) -> usize {
+    let phases = meta.advice_column_phase();
+    let phase = phases[column.index()];
+    phases
+        .iter()
+        .enumerate()
+        .filter(|(idx, p)| **p < phase || (**p == phase && *idx < column.index()))
+        .count()
+}
+
+/// Assigned public values of the state update circuit, an outer (aggregation)
+/// circuit can connect to these with copy constraints
+#[derive(Clone, Debug)]
+pub struct PublicData<F: Field> {
+    /// Root before the first modification (lo, hi)
+    pub old_root: [AssignedCell<F, F>; 2],
+    /// Root after the last modification (lo, hi)
+    pub new_root: [AssignedCell<F, F>; 2],
+    /// Number of proven modifications
+    pub count: AssignedCell<F, F>,
+    /// Chain id, block number and block hash (lo, hi), see `BlockMetadata`
+    pub metadata: [AssignedCell<F, F>; 4],
+    /// Digest of the changes (lo, hi), when enabled
+    pub mpt_digest: Option<[AssignedCell<F, F>; 2]>,
+    /// All cells constrained to the instance column, in the `InstanceLayout` order
+    pub instances: Vec<AssignedCell<F, F>>,
+}
+
 /// MPT Circuit for proving the storage modification is valid.
 #[derive(Default)]
 pub struct StateUpdateCircuit<F: Field> {
@@ -75,44 +151,66 @@ pub struct StateUpdateCircuit<F: Field> {
     pub lc_witness: SingleTrieModifications<F>,
     pub degree: usize,
     pub max_proof_count: usize,
+    /// See `StateUpdateCircuitParams::keccak_oracle`. ONLY ENABLE FOR TESTS!
+    pub keccak_oracle: bool,
+    /// See `StateUpdateCircuitParams::mpt_digest`
+    pub mpt_digest: bool,
 }
 
 impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
     type Config = (StateUpdateCircuitConfig<F>, Challenges);
     type FloorPlanner = SimpleFloorPlanner;
-    type Params = MPTCircuitParams;
+    type Params = StateUpdateCircuitParams;
 
     fn without_witnesses(&self) -> Self {
         Self::default()
     }
 
     fn params(&self) -> Self::Params {
-        MPTCircuitParams {
-            degree: self.mpt_circuit.degree,
-            disable_preimage_check: self.mpt_circuit.disable_preimage_check,
+        StateUpdateCircuitParams {
+            mpt: MPTCircuitParams {
+                degree: self.mpt_circuit.degree,
+                disable_preimage_check: self.mpt_circuit.disable_preimage_check,
+                strict_empty_accounts: self.mpt_circuit.strict_empty_accounts,
+                strict_codehash: self.mpt_circuit.strict_codehash,
+                nonce_increment: self.mpt_circuit.nonce_increment,
+                balance_delta: self.mpt_circuit.balance_delta,
+                key_derivation: self.mpt_circuit.key_derivation,
+                max_nodes: self.mpt_circuit.max_nodes,
+                single_pass: self.mpt_circuit.single_pass,
+            },
+            keccak_oracle: self.keccak_oracle,
+            mpt_digest: self.mpt_digest,
         }
     }
 
     fn configure_with_params(meta: &mut ConstraintSystem<F>, params: Self::Params) -> Self::Config {
-        let challenges = Challenges::construct(meta);
+        let challenges = Challenges::construct_with_key_input(meta);
         let challenges_expr = challenges.exprs(meta);
 
         let keccak_table = KeccakTable::construct(meta);
 
+        let keccak_oracle = params.keccak_oracle || cfg!(feature = "disable-keccak");
         #[cfg(not(feature = "disable-keccak"))]
-        let keccak_config = KeccakCircuitConfig::new(
-            meta,
-            KeccakCircuitConfigArgs {
-                keccak_table: keccak_table.clone(),
-                challenges: challenges_expr.clone(),
-            },
-        );
-        let mpt_config = MPTConfig::new(meta, challenges_expr, keccak_table, params);
+        let keccak_config = (!keccak_oracle).then(|| {
+            KeccakCircuitConfig::new(
+                meta,
+                KeccakCircuitConfigArgs {
+                    keccak_table: keccak_table.clone(),
+                    challenges: challenges_expr.clone(),
+                },
+            )
+        });
+        let mpt_digest = params
+            .mpt_digest
+            .then(|| MptDigestConfig::configure(meta, &keccak_table, &challenges_expr));
+        let mpt_config = MPTConfig::new(meta, challenges_expr, keccak_table, params.mpt);
 
         let is_first = meta.fixed_column();
         let count = meta.advice_column();
         let q_enable = meta.complex_selector();
         let pi_instance = meta.instance_column();
+        let pi_metadata = meta.advice_column();
         let pi_mpt = MptTable {
             address: meta.advice_column(),
             storage_key: word::Word::new([meta.advice_column(), meta.advice_column()]),
@@ -121,6 +219,9 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
             old_root: word::Word::new([meta.advice_column(), meta.advice_column()]),
             new_value: word::Word::new([meta.advice_column(), meta.advice_column()]),
             old_value: word::Word::new([meta.advice_column(), meta.advice_column()]),
+            trie_id: meta.advice_column(),
+            q_lookup: meta.advice_column(),
+            balance_delta: None,
         };
 
         for col in [
@@ -143,6 +244,7 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
         }
 
         meta.enable_equality(pi_instance);
+        meta.enable_equality(pi_metadata);
         meta.enable_equality(count);
 
         let is_padding_inv = meta.advice_column();
@@ -220,6 +322,11 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
             let is_not_padding = 1.expr() - is_padding.expr();
 
             let lookups = vec![
+                // Only match the rows holding an MPT update
+                (
+                    1.expr(),
+                    meta.query_advice(mpt_config.mpt_table.q_lookup, Rotation::cur()),
+                ),
                 (
                     meta.query_advice(pi_mpt.proof_type, Rotation::cur()),
                     meta.query_advice(mpt_config.mpt_table.proof_type, Rotation::cur()),
@@ -272,7 +379,9 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
         let config = StateUpdateCircuitConfig {
             #[cfg(not(feature = "disable-keccak"))]
             keccak_config,
+            keccak_oracle,
             mpt_config,
+            mpt_digest,
             is_first,
             count,
             is_padding,
@@ -282,6 +391,7 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
             root_chained,
             q_enable,
             pi_instance,
+            pi_metadata,
             pi_mpt,
         };
 
@@ -299,27 +409,48 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
     ) -> Result<(), Error> {
         let challenges = _challenges.values(&mut layouter);
 
+        let public_data = self.synthesize_sub(&config, &challenges, &mut layouter)?;
+
+        // check that state updates to lookup are the same that the specified in the public inputs
+        for (n, cell) in public_data.instances.iter().enumerate() {
+            layouter.constrain_instance(cell.cell(), config.pi_instance, n)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: Field> StateUpdateCircuit<F> {
+    /// Assigns the circuit and returns the cells of the public inputs, so that
+    /// the circuit can also be embedded in a circuit that connects them with
+    /// copy constraints instead of instances
+    pub fn synthesize_sub(
+        &self,
+        config: &StateUpdateCircuitConfig<F>,
+        challenges: &Challenges<Value<F>>,
+        layouter: &mut impl Layouter<F>,
+    ) -> Result<PublicData<F>, Error> {
         // assign MPT witness
 
-        let height =
-            config
-                .mpt_config
-                .assign(&mut layouter, &self.mpt_circuit.nodes, &challenges)?;
-        config.mpt_config.load_fixed_table(&mut layouter)?;
         config
             .mpt_config
-            .load_mult_table(&mut layouter, &challenges, height)?;
-
-        #[cfg(feature = "disable-keccak")]
-        config.mpt_config.keccak_table.dev_load(
-            &mut layouter,
-            &self.mpt_circuit.keccak_data,
-            &challenges,
-        )?;
+            .assign(layouter, &self.mpt_circuit.nodes, challenges)?;
+        config.mpt_config.load_fixed_table(layouter)?;
+        config.mpt_config.load_mult_table(layouter, challenges)?;
+
+        if config.keccak_oracle {
+            config.mpt_config.keccak_table.dev_load(
+                layouter,
+                &self.mpt_circuit.keccak_data,
+                challenges,
+            )?;
+        }
 
         #[cfg(not(feature = "disable-keccak"))]
-        self.keccak_circuit
-            .synthesize_sub(&config.keccak_config, &challenges, &mut layouter)?;
+        if let Some(keccak_config) = &config.keccak_config {
+            self.keccak_circuit
+                .synthesize_sub(keccak_config, challenges, layouter)?;
+        }
 
         // assign LC witness
 
@@ -344,10 +475,13 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
                     config.count_decrement.value_inv,
                 );
                 region.name_column(|| "LC_pi_instance", config.pi_instance);
+                region.name_column(|| "LC_pi_metadata", config.pi_metadata);
 
                 region.assign_fixed(|| "", config.is_first, 0, || Value::known(F::ONE))?;
 
-                let mut pi = Vec::new();
+                // The changes of all the rows, the digest replaces them below
+                let layout = InstanceLayout::new(self.max_proof_count, false);
+                let mut pi = vec![None; layout.len()];
 
                 for offset in 0..self.max_proof_count {
 
@@ -429,23 +563,49 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
                                 ).unwrap()
                             );
 
-                    // at beggining, set the old root and number of proofs
+                    // at beggining, set the old root, number of proofs and block metadata
 
                     if offset == 0 {
-                        pi.push(Some(old_root_lo));
-                        pi.push(Some(old_root_hi));
-                        pi.push(None);
-                        pi.push(None);
-                        pi.push(Some(count_cell));
+                        pi[InstanceLayout::PREV_STATE_ROOT[0]] = Some(old_root_lo);
+                        pi[InstanceLayout::PREV_STATE_ROOT[1]] = Some(old_root_hi);
+                        pi[InstanceLayout::CHANGE_COUNT] = Some(count_cell);
+
+                        let metadata = &self.lc_witness.metadata;
+                        for (row, (idx, value)) in [
+                            (InstanceLayout::CHAIN_ID, metadata.chain_id),
+                            (InstanceLayout::BLOCK_NUMBER, metadata.block_number),
+                            (InstanceLayout::BLOCK_HASH[0], metadata.block_hash.lo()),
+                            (InstanceLayout::BLOCK_HASH[1], metadata.block_hash.hi()),
+                        ]
+                        .into_iter()
+                        .enumerate()
+                        {
+                            pi[idx] = Some(region.assign_advice(
+                                || "",
+                                config.pi_metadata,
+                                row,
+                                || Value::known(value),
+                            )?);
+                        }
                     }
 
-                    pi.append(vec![Some(typ), Some(addr), Some(value_lo), Some(value_hi), Some(key_lo), Some(key_hi)].as_mut());
+                    let change = layout.change(offset).unwrap();
+                    for (idx, cell) in [
+                        (InstanceLayout::CHANGE_PROOF_TYPE, typ),
+                        (InstanceLayout::CHANGE_ADDRESS, addr),
+                        (InstanceLayout::CHANGE_VALUE[0], value_lo),
+                        (InstanceLayout::CHANGE_VALUE[1], value_hi),
+                        (InstanceLayout::CHANGE_KEY[0], key_lo),
+                        (InstanceLayout::CHANGE_KEY[1], key_hi),
+                    ] {
+                        pi[change + idx] = Some(cell);
+                    }
 
                     // at ending, set the last root in the last row (valid since we are propagating it)
 
                     if offset == self.max_proof_count -1 {
-                        pi[2] = Some(new_root_lo);
-                        pi[3] = Some(new_root_hi);
+                        pi[InstanceLayout::CURR_STATE_ROOT[0]] = Some(new_root_lo);
+                        pi[InstanceLayout::CURR_STATE_ROOT[1]] = Some(new_root_hi);
                     }
 
                 }
@@ -453,12 +613,52 @@ impl<F: Field> Circuit<F> for StateUpdateCircuit<F> {
             },
         )?;
 
-        // check that state updates to lookup are the same that the specified in the public inputs
-        for (n, value) in pi.into_iter().enumerate() {
-            layouter.constrain_instance(value.unwrap().cell(), config.pi_instance, n)?;
-        }
+        let mut instances: Vec<_> = pi.into_iter().map(|cell| cell.unwrap()).collect();
+
+        // The changes of all the rows are replaced by their digest
+        let mpt_digest = match &config.mpt_digest {
+            Some(digest_config) => {
+                digest_config.load_byte_table(layouter)?;
+                let changes = instances.split_off(PublicInputs::<F>::HEADER_LEN);
+                let digest =
+                    digest_config.assign(layouter, &changes, self.max_proof_count, challenges)?;
+                instances.extend(digest.iter().cloned());
+                Some(digest)
+            }
+            None => None,
+        };
+        assert_eq!(instances.len(), self.instance_layout().len());
+
+        Ok(PublicData {
+            old_root: InstanceLayout::PREV_STATE_ROOT.map(|idx| instances[idx].clone()),
+            new_root: InstanceLayout::CURR_STATE_ROOT.map(|idx| instances[idx].clone()),
+            count: instances[InstanceLayout::CHANGE_COUNT].clone(),
+            metadata: [
+                InstanceLayout::CHAIN_ID,
+                InstanceLayout::BLOCK_NUMBER,
+                InstanceLayout::BLOCK_HASH[0],
+                InstanceLayout::BLOCK_HASH[1],
+            ]
+            .map(|idx| instances[idx].clone()),
+            mpt_digest,
+            instances,
+        })
+    }
 
-        Ok(())
+    /// Order of the public inputs of the circuit
+    pub fn instance_layout(&self) -> InstanceLayout {
+        InstanceLayout::new(self.max_proof_count, self.mpt_digest)
+    }
+
+    /// Public inputs of the circuit, with the digest of the changes when
+    /// enabled
+    pub fn public_inputs(&self) -> PublicInputs<F> {
+        let public_inputs = PublicInputs::from(&self.lc_witness);
+        if self.mpt_digest {
+            public_inputs.into_mpt_digest(self.max_proof_count)
+        } else {
+            public_inputs
+        }
     }
 }
 
@@ -484,12 +684,23 @@ impl StateUpdateCircuit<Fr> {
 
         // verify the circuit
         let disable_preimage_check = mpt_witness[0].start.clone().unwrap().disable_preimage_check;
+        let strict_empty_accounts = transforms.strict_empty_accounts;
 
         let mpt_circuit = zkevm_circuits::mpt_circuit::MPTCircuit::<Fr> {
             nodes: mpt_witness,
             keccak_data: keccak_data.clone(),
             degree,
             disable_preimage_check,
+            strict_empty_accounts,
+            strict_codehash: false,
+            // A block can bump the nonce of a sender more than once
+            nonce_increment: false,
+            balance_delta: false,
+            // The witness is generated from the secure trie of geth
+            key_derivation: KeyDerivation::Keccak,
+            max_nodes: 0,
+            // Laid out with `SimpleFloorPlanner`
+            single_pass: true,
             _marker: std::marker::PhantomData,
         };
 
@@ -504,8 +715,47 @@ impl StateUpdateCircuit<Fr> {
             lc_witness,
             degree,
             max_proof_count,
+            keccak_oracle: false,
+            mpt_digest: false,
         };
 
         Ok(lc_circuit)
     }
+
+    /// Exposes the digest of the changes instead of the changes, see
+    /// `StateUpdateCircuitParams::mpt_digest`. The input of the digest is
+    /// hashed by the keccak circuit together with the MPT nodes.
+    pub fn with_mpt_digest(mut self) -> Self {
+        let public_inputs = PublicInputs::from(&self.lc_witness);
+        let changes = &public_inputs[PublicInputs::<Fr>::HEADER_LEN..];
+        self.mpt_circuit
+            .keccak_data
+            .push(mpt_digest::digest_input(changes, self.max_proof_count));
+        #[cfg(not(feature = "disable-keccak"))]
+        {
+            self.keccak_circuit = KeccakCircuit::<Fr>::new(
+                2usize.pow(self.degree as u32),
+                self.mpt_circuit.keccak_data.clone(),
+            );
+        }
+        self.mpt_digest = true;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::plonk::{FirstPhase, SecondPhase};
+
+    #[test]
+    fn commitment_index_orders_by_phase() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let a = meta.advice_column_in(FirstPhase);
+        let b = meta.advice_column_in(SecondPhase);
+        let c = meta.advice_column_in(FirstPhase);
+        assert_eq!(advice_commitment_index(&meta, a), 0);
+        assert_eq!(advice_commitment_index(&meta, c), 1);
+        assert_eq!(advice_commitment_index(&meta, b), 2);
+    }
 }
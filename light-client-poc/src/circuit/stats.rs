@@ -0,0 +1,183 @@
+use halo2_proofs::halo2curves::bn256::Fr;
+use std::fmt;
+use zkevm_circuits::{
+    keccak_circuit::KeccakCircuit,
+    mpt_circuit::{witness_row::Node, MPTCircuitParams},
+    table::mpt_table::MPTProofType,
+};
+
+// Bytes absorbed by a single keccak_f
+const KECCAK_RATE: usize = 136;
+// Rows the prover can not use
+const UNUSABLE_ROWS: usize = 10;
+
+/// Shape of an MPT witness, used to choose the circuit parameters for a block
+/// and to spot blocks with unusually deep or large proofs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WitnessStats {
+    pub proofs: usize,
+    pub branches: usize,
+    pub extensions: usize,
+    pub account_leaves: usize,
+    pub storage_leaves: usize,
+    /// Branch and extension nodes above an account leaf
+    pub max_account_depth: usize,
+    /// Branch and extension nodes above a storage leaf
+    pub max_storage_depth: usize,
+    pub keccak_inputs: usize,
+    pub keccak_bytes: usize,
+    /// Number of keccak_f permutations needed to hash all the keccak inputs
+    pub keccak_permutations: usize,
+    /// Rows used by the MPT circuit
+    pub mpt_rows: usize,
+    /// Rows of `mpt_rows` used by storage leaves, an upper bound of the rows a
+    /// packed storage leaf layout could save on the block
+    pub storage_rows: usize,
+}
+
+impl From<&[Node]> for WitnessStats {
+    fn from(nodes: &[Node]) -> Self {
+        let mut stats = Self::default();
+        let mut depth = 0;
+        for node in nodes {
+            if let Some(start) = &node.start {
+                if start.proof_type != MPTProofType::Disabled {
+                    stats.proofs += 1;
+                }
+                depth = 0;
+            }
+            if let Some(extension_branch) = &node.extension_branch {
+                if extension_branch.is_extension {
+                    stats.extensions += 1;
+                    depth += 1;
+                }
+                stats.branches += 1;
+                depth += 1;
+            }
+            if node.account.is_some() {
+                stats.account_leaves += 1;
+                stats.max_account_depth = stats.max_account_depth.max(depth);
+                // The storage trie starts below the account
+                depth = 0;
+            }
+            if node.storage.is_some() {
+                stats.storage_leaves += 1;
+                stats.max_storage_depth = stats.max_storage_depth.max(depth);
+                stats.storage_rows += node.values.len();
+            }
+            for data in node.keccak_data.iter() {
+                stats.keccak_inputs += 1;
+                stats.keccak_bytes += data.len();
+                stats.keccak_permutations += data.len() / KECCAK_RATE + 1;
+            }
+            stats.mpt_rows += node.values.len();
+        }
+        stats
+    }
+}
+
+impl WitnessStats {
+    /// Rows used by the keccak circuit for all the keccak inputs
+    pub fn keccak_rows(&self) -> usize {
+        // Derived from the capacity, the rows per permutation are not exported
+        let num_rows = 1 << 20;
+        let capacity = KeccakCircuit::<Fr>::new(num_rows, vec![])
+            .capacity()
+            .unwrap();
+        let rows_per_permutation = num_rows / (capacity + 2);
+        (self.keccak_permutations + 2) * rows_per_permutation
+    }
+
+    /// Estimated number of rows needed by the state update circuit
+    pub fn estimated_rows(&self) -> usize {
+        self.mpt_rows.max(self.keccak_rows()) + UNUSABLE_ROWS
+    }
+
+    /// Estimated number of rows needed when the keccak table is loaded from
+    /// the witness, see `StateUpdateCircuitParams::keccak_oracle`. The tables
+    /// of the MPT circuit, mostly the byte range checks of the RLP items in
+    /// the fixed table, are then often larger than the witness.
+    pub fn estimated_oracle_rows(&self) -> usize {
+        let params = MPTCircuitParams::default();
+        self.mpt_rows
+            .max(params.fixed_table_len())
+            .max(params.mult_table_len())
+            + UNUSABLE_ROWS
+    }
+
+    /// Smallest circuit degree that fits the witness
+    pub fn min_degree(&self) -> usize {
+        degree_for(self.estimated_rows())
+    }
+
+    /// Smallest circuit degree that fits the witness with the keccak table
+    /// loaded from the witness
+    pub fn min_oracle_degree(&self) -> usize {
+        degree_for(self.estimated_oracle_rows())
+    }
+}
+
+fn degree_for(rows: usize) -> usize {
+    let mut degree = 1;
+    while (1 << degree) < rows {
+        degree += 1;
+    }
+    degree
+}
+
+impl fmt::Display for WitnessStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "proofs:             {}", self.proofs)?;
+        writeln!(
+            f,
+            "nodes:              {} branches ({} with extension), {} accounts, {} storage",
+            self.branches, self.extensions, self.account_leaves, self.storage_leaves
+        )?;
+        writeln!(
+            f,
+            "max depth:          {} account trie, {} storage trie",
+            self.max_account_depth, self.max_storage_depth
+        )?;
+        writeln!(
+            f,
+            "keccak:             {} inputs, {} bytes, {} permutations",
+            self.keccak_inputs, self.keccak_bytes, self.keccak_permutations
+        )?;
+        writeln!(
+            f,
+            "rows:               {} mpt ({} storage leaves), {} keccak",
+            self.mpt_rows,
+            self.storage_rows,
+            self.keccak_rows()
+        )?;
+        write!(f, "min degree:         {}", self.min_degree())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkevm_circuits::mpt_circuit::load_proof;
+
+    #[test]
+    fn stats_of_storage_proof() {
+        let nodes = load_proof("../zkevm-circuits/src/mpt_circuit/tests/AddBranch.json");
+        let stats = WitnessStats::from(nodes.as_slice());
+        assert_eq!(stats.proofs, 1);
+        assert_eq!(stats.branches, 9);
+        assert_eq!(stats.extensions, 0);
+        assert_eq!(stats.account_leaves, 1);
+        assert_eq!(stats.storage_leaves, 1);
+        assert_eq!(stats.max_account_depth, 7);
+        assert_eq!(stats.max_storage_depth, 2);
+        assert_eq!(stats.keccak_inputs, 25);
+        assert_eq!(stats.keccak_bytes, 7697);
+        // The address and the key are added to the leaves by `load_proof`
+        assert_eq!(stats.mpt_rows, 211 + 4);
+        assert_eq!(stats.storage_rows, 8);
+        assert!(stats.estimated_rows() <= 1 << stats.min_degree());
+        // Without the keccak circuit the fixed table of the MPT circuit is the
+        // largest
+        assert_eq!(stats.min_oracle_degree(), 15);
+    }
+}
@@ -1,6 +1,14 @@
-use std::{collections::HashSet, ops::Deref, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    sync::Arc,
+};
 
-use eth_types::{Field, ToScalar};
+use bus_mapping::rpc::GethClient;
+use eth_types::{
+    state_diff::{AccountState, StateDiff, TrieUpdate, TrieUpdateKind},
+    Field, ToScalar, ToWord,
+};
 use ethers::{
     abi::Address,
     prelude::{k256::ecdsa::SigningKey, SignerMiddleware, *},
@@ -10,8 +18,10 @@ use ethers::{
         transaction::eip2930::{AccessList, AccessListItem},
         BlockId, BlockNumber, H256, U256, U64,
     },
+    utils::{keccak256, rlp::Rlp},
 };
-use eyre::Result;
+use eyre::{ensure, eyre, Result};
+use serde::{Deserialize, Serialize};
 
 use mpt_witness_generator::{ProofType, TrieModification};
 use zkevm_circuits::{
@@ -20,19 +30,37 @@ use zkevm_circuits::{
     util::word::{self, Word},
 };
 
-#[derive(Default, Debug, Clone)]
+use super::{ChainConfig, InstanceLayout, NodeCache};
+use crate::bridge::verify_mpt_proof;
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Transforms {
     pub block_no: U64,
+    /// Chain and hash of the block, part of the public inputs so that a proof
+    /// can not be replayed for another chain or block
+    #[serde(default)]
+    pub chain_id: u64,
+    #[serde(default)]
+    pub block_hash: H256,
     pub prev_state_root: H256,
     pub curr_state_root: H256,
+    /// In the canonical order, see `sort_trie_modifications`
     pub trie_modifications: Vec<TrieModification>,
+    /// EIP-161 rules apply, see `ChainConfig`
+    #[serde(default)]
+    pub strict_empty_accounts: bool,
+    /// State change of the block from the node traces, when no access list
+    /// was given
+    #[serde(default)]
+    pub state_diff: Option<StateDiff>,
 }
 
-trait TrieModificationBuilder {
+pub(crate) trait TrieModificationBuilder {
     fn balance(address: Address, balance: U256) -> Self;
     fn nonce(address: Address, nonce: U64) -> Self;
     fn codehash(address: Address, code_hash: H256) -> Self;
     fn storage(address: Address, key: H256, value: U256) -> Self;
+    fn destructed(address: Address) -> Self;
 }
 
 impl TrieModificationBuilder for TrieModification {
@@ -69,6 +97,21 @@ impl TrieModificationBuilder for TrieModification {
             ..Default::default()
         }
     }
+    fn destructed(address: Address) -> Self {
+        Self {
+            typ: ProofType::AccountDestructed,
+            address,
+            ..Default::default()
+        }
+    }
+}
+
+/// The code of a contract never changes, a different code hash at both ends of
+/// the block means that the contract self destructed and was created again at
+/// the same address (CREATE2). The same code being deployed again can not be
+/// detected from the proofs alone.
+fn is_recreated(old_code_hash: H256, new_code_hash: H256) -> bool {
+    old_code_hash != H256(keccak256([])) && old_code_hash != new_code_hash
 }
 
 #[derive(Default)]
@@ -88,14 +131,27 @@ pub struct SingleTrieModification<F: Field> {
     pub new_root: word::Word<F>,
 }
 
+/// Chain and block of a state update, the state roots alone do not identify
+/// them. The block hash is not checked by the circuit, the verifier checks it
+/// against the chain, see `HeaderChain::check_public_inputs`.
+#[derive(Default, Clone)]
+pub struct BlockMetadata<F: Field> {
+    pub chain_id: F,
+    pub block_number: F,
+    pub block_hash: word::Word<F>,
+}
+
 #[derive(Default, Clone)]
-pub struct SingleTrieModifications<F: Field>(pub Vec<SingleTrieModification<F>>);
+pub struct SingleTrieModifications<F: Field> {
+    pub metadata: BlockMetadata<F>,
+    pub proofs: Vec<SingleTrieModification<F>>,
+}
 
 impl<F: Field> Deref for SingleTrieModifications<F> {
     type Target = Vec<SingleTrieModification<F>>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.proofs
     }
 }
 
@@ -108,57 +164,201 @@ impl<F: Field> Deref for PublicInputs<F> {
     }
 }
 
-impl<F: Field> From<&SingleTrieModifications<F>> for PublicInputs<F> {
-    fn from(stm: &SingleTrieModifications<F>) -> Self {
-        let mut inputs = vec![
-            stm.0[0].old_root.lo(),
-            stm.0[0].old_root.hi(),
-            stm.0.last().unwrap().new_root.lo(),
-            stm.0.last().unwrap().new_root.hi(),
-            F::from(stm.0.len() as u64),
-        ];
+impl<F: Field> PublicInputs<F> {
+    /// Number of public inputs before the trie modifications: the roots, the
+    /// number of modifications and the block metadata
+    pub const HEADER_LEN: usize = InstanceLayout::HEADER_LEN;
 
-        for proof in &stm.0 {
-            inputs.push(proof.typ);
-            inputs.push(proof.address);
-            inputs.push(proof.value.lo());
-            inputs.push(proof.value.hi());
-            inputs.push(proof.key.lo());
-            inputs.push(proof.key.hi());
-        }
+    /// State root before the first change (lo, hi)
+    pub fn prev_state_root(&self) -> [F; 2] {
+        InstanceLayout::PREV_STATE_ROOT.map(|idx| self.0[idx])
+    }
+
+    /// State root after the last change (lo, hi)
+    pub fn curr_state_root(&self) -> [F; 2] {
+        InstanceLayout::CURR_STATE_ROOT.map(|idx| self.0[idx])
+    }
+
+    pub fn chain_id(&self) -> F {
+        self.0[InstanceLayout::CHAIN_ID]
+    }
+
+    pub fn block_number(&self) -> F {
+        self.0[InstanceLayout::BLOCK_NUMBER]
+    }
 
+    /// Block hash (lo, hi)
+    pub fn block_hash(&self) -> [F; 2] {
+        InstanceLayout::BLOCK_HASH.map(|idx| self.0[idx])
+    }
+
+    /// Replaces the changes by the keccak digest (lo, hi) of the changes of
+    /// the `max_proof_count` rows of the circuit, the rows without a change
+    /// are hashed as empty changes. An outer circuit can then consume the
+    /// changes through the digest, see `StateUpdateCircuitParams::mpt_digest`.
+    pub fn into_mpt_digest(mut self, max_proof_count: usize) -> Self {
+        let changes = self.0.split_off(Self::HEADER_LEN);
+        let digest = super::mpt_digest::digest(&changes, max_proof_count);
+        self.0.extend([digest.lo(), digest.hi()]);
+        self
+    }
+}
+
+impl<F: Field> PublicInputs<F> {
+    /// Public inputs of the state update proof of `transforms`. They only
+    /// depend on the changes of the block, not on the MPT witness, so the
+    /// changes claimed for a proof can be checked against its public inputs.
+    pub fn from_transforms(transforms: &Transforms) -> Result<Self> {
+        check_canonical_order(&transforms.trie_modifications)?;
+        let changes = transforms
+            .trie_modifications
+            .iter()
+            .map(|m| {
+                let (proof_type, address, value, key) = public_change(m)
+                    .ok_or_else(|| eyre!("trie modification type {:?} unimplemented", m.typ))?;
+                Ok(SingleTrieModification {
+                    typ: F::from(proof_type as u64),
+                    address: address.to_scalar().unwrap(),
+                    value: Word::from(value),
+                    key: Word::from(key),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let metadata = BlockMetadata {
+            chain_id: F::from(transforms.chain_id),
+            block_number: F::from(transforms.block_no.as_u64()),
+            block_hash: Word::from(transforms.block_hash),
+        };
+        Ok(Self::with_layout(
+            Word::from(transforms.prev_state_root),
+            Word::from(transforms.curr_state_root),
+            &metadata,
+            &changes,
+        ))
+    }
+
+    /// Public inputs at the positions of `InstanceLayout`: the state roots
+    /// before and after `changes`, the block and the changes, whose roots are
+    /// not used
+    fn with_layout(
+        prev_state_root: Word<F>,
+        curr_state_root: Word<F>,
+        metadata: &BlockMetadata<F>,
+        changes: &[SingleTrieModification<F>],
+    ) -> Self {
+        let layout = InstanceLayout::new(changes.len(), false);
+        let mut inputs = vec![F::ZERO; layout.len()];
+        for (idx, value) in [
+            (InstanceLayout::PREV_STATE_ROOT[0], prev_state_root.lo()),
+            (InstanceLayout::PREV_STATE_ROOT[1], prev_state_root.hi()),
+            (InstanceLayout::CURR_STATE_ROOT[0], curr_state_root.lo()),
+            (InstanceLayout::CURR_STATE_ROOT[1], curr_state_root.hi()),
+            (InstanceLayout::CHANGE_COUNT, F::from(changes.len() as u64)),
+            (InstanceLayout::CHAIN_ID, metadata.chain_id),
+            (InstanceLayout::BLOCK_NUMBER, metadata.block_number),
+            (InstanceLayout::BLOCK_HASH[0], metadata.block_hash.lo()),
+            (InstanceLayout::BLOCK_HASH[1], metadata.block_hash.hi()),
+        ] {
+            inputs[idx] = value;
+        }
+        for (row, change) in changes.iter().enumerate() {
+            let offset = layout.change(row).unwrap();
+            for (idx, value) in [
+                (InstanceLayout::CHANGE_PROOF_TYPE, change.typ),
+                (InstanceLayout::CHANGE_ADDRESS, change.address),
+                (InstanceLayout::CHANGE_VALUE[0], change.value.lo()),
+                (InstanceLayout::CHANGE_VALUE[1], change.value.hi()),
+                (InstanceLayout::CHANGE_KEY[0], change.key.lo()),
+                (InstanceLayout::CHANGE_KEY[1], change.key.hi()),
+            ] {
+                inputs[offset + idx] = value;
+            }
+        }
         PublicInputs(inputs)
     }
 }
 
+impl<F: Field> From<&SingleTrieModifications<F>> for PublicInputs<F> {
+    fn from(stm: &SingleTrieModifications<F>) -> Self {
+        Self::with_layout(
+            stm[0].old_root,
+            stm.last().unwrap().new_root,
+            &stm.metadata,
+            stm,
+        )
+    }
+}
+
 impl<F: Field> StateUpdateWitness<F> {
     pub async fn build(
         client: Arc<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>,
         provider: &str,
         block_no: U64,
         access_list: Option<AccessList>,
+        chain: Option<&ChainConfig>,
     ) -> Result<Option<Self>> {
-        let transforms = Self::get_transforms(client, block_no, access_list).await?;
-        println!("### trns : {:#?}", transforms);
+        let transforms = Self::get_transforms(client, block_no, access_list, chain).await?;
+        tracing::debug!(block = %block_no, ?transforms, "block transforms");
+        Self::from_transforms(transforms, provider)
+    }
+
+    /// Generates the MPT witness of already fetched transforms
+    pub fn from_transforms(transforms: Transforms, provider: &str) -> Result<Option<Self>> {
+        check_canonical_order(&transforms.trie_modifications)?;
         if transforms.prev_state_root == transforms.curr_state_root {
             Ok(None)
         } else {
-            let (mpt_witness, lc_witness) = Self::mpt_witness(&transforms, provider)?;
-            Ok(Some(Self {
-                transforms,
-                mpt_witness,
-                lc_witness,
-            }))
+            let mut mpt_witness = mpt_witness_generator::get_witness(
+                transforms.block_no.as_u64() - 1,
+                &transforms.trie_modifications,
+                provider,
+            );
+
+            let mut node_cache = NodeCache::default();
+            node_cache.dedup_keccak_data(&mut mpt_witness);
+            tracing::debug!(
+                unique = node_cache.len(),
+                total = node_cache.inserted(),
+                "keccak preimages"
+            );
+            Self::from_parts(transforms, mpt_witness).map(Some)
         }
     }
 
-    async fn get_transforms(
+    /// Rebuilds the witness from the transforms and their MPT witness, no
+    /// access to a node is needed
+    pub fn from_parts(transforms: Transforms, mpt_witness: Vec<Node>) -> Result<Self> {
+        check_canonical_order(&transforms.trie_modifications)?;
+        let lc_witness = Self::lc_witness(&transforms, &mpt_witness)?;
+        Ok(Self {
+            transforms,
+            mpt_witness,
+            lc_witness,
+        })
+    }
+
+    /// Fetches the changes of the block, with the state rules of `chain`, or of
+    /// the chain served by the node when no chain is selected
+    pub async fn get_transforms(
         client: Arc<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>,
         block_no: U64,
         access_list: Option<AccessList>,
+        chain: Option<&ChainConfig>,
     ) -> Result<Transforms> {
         let mut trie_modifications = Vec::new();
 
+        let mut node_cache = NodeCache::default();
+
+        let chain_config = match chain {
+            Some(chain) => {
+                chain.check_chain_id(&client).await?;
+                chain.clone()
+            }
+            None => ChainConfig::from_chain_id(client.get_chainid().await?.as_u64()),
+        };
+        let strict_empty_accounts = chain_config.strict_empty_accounts(block_no);
+
         // get previous block and this block
         let prev_block = client
             .get_block(BlockId::Number(BlockNumber::Number(block_no - 1)))
@@ -169,43 +369,28 @@ impl<F: Field> StateUpdateWitness<F> {
             .await?
             .unwrap();
 
-        println!(
-            "Checking transition from\n  block {} {:?}\n  to {} {:?}",
-            block_no - 1,
-            prev_block.state_root,
-            block_no,
-            curr_block.state_root
+        tracing::info!(
+            block = %block_no,
+            prev_state_root = ?prev_block.state_root,
+            state_root = ?curr_block.state_root,
+            "checking state transition"
         );
 
-        let mut access_list = if let Some(access_list) = access_list {
-            access_list
+        // Without an access list the accounts and slots are taken from the
+        // state diff of the block, which also gives the expected new values
+        let (mut access_list, state_diff) = if let Some(access_list) = access_list {
+            (access_list, None)
         } else {
-            client
-                .provider()
-                .request(
-                    "eth_getAccessListByNumber",
-                    vec![format!("0x{:x}", block_no)],
-                )
-                .await?
+            let state_diff = fetch_state_diff(&client, block_no).await?;
+            (state_diff.access_list(), Some(state_diff))
         };
 
-        // add coinbase to the access list
-        let mut extra_addrs = HashSet::from([curr_block.author.unwrap()]);
-        for tx in curr_block.transactions {
-            extra_addrs.insert(tx.from);
-            if let Some(addr) = tx.to {
-                extra_addrs.insert(addr);
-            }
-        }
-
-        for addr in extra_addrs {
-            if !access_list.0.iter().any(|a| a.address == addr) {
-                access_list.0.push(AccessListItem {
-                    address: addr,
-                    storage_keys: Vec::new(),
-                });
-            }
-        }
+        // Computed before the block accounts are added, these are not touched by
+        // the execution of the transactions
+        let withdrawals = withdrawal_only_deltas(&access_list, &curr_block);
+        let sent = sent_transactions(&curr_block);
+        let expected = state_diff.as_ref().map(|state_diff| state_diff.updates());
+        add_block_accounts(&mut access_list, &curr_block);
 
         for entry in access_list.0 {
             let AccessListItem {
@@ -229,13 +414,110 @@ impl<F: Field> StateUpdateWitness<F> {
                 )
                 .await?;
 
+            if let Some(delta) = withdrawals.get(&address) {
+                ensure!(
+                    new.balance == old.balance + delta,
+                    "withdrawals to {:?} add {} wei but the balance changed from {} to {}",
+                    address,
+                    delta,
+                    old.balance,
+                    new.balance
+                );
+            }
+
+            node_cache.insert_proof(&old.account_proof);
+            node_cache.insert_proof(&new.account_proof);
+            for proof in old.storage_proof.iter().chain(new.storage_proof.iter()) {
+                node_cache.insert_proof(&proof.proof);
+            }
+
+            // The account can appear and disappear within the block (e.g. created
+            // and self destructed in the same transaction), so only the existence
+            // at both ends of the block matters
+            let old_account = verify_mpt_proof(
+                prev_block.state_root,
+                address.as_bytes(),
+                &old.account_proof,
+            )?;
+            let new_account = verify_mpt_proof(
+                curr_block.state_root,
+                address.as_bytes(),
+                &new.account_proof,
+            )?;
+            match (old_account.is_some(), new_account.is_some()) {
+                (false, false) => {
+                    tracing::debug!(?address, "skipping account, it does not exist");
+                    continue;
+                }
+                (true, false) => {
+                    // The storage is removed with the account
+                    trie_modifications.push(TrieModification::destructed(address));
+                    continue;
+                }
+                (false, true) if !strict_empty_accounts => {
+                    // Before EIP-161 touching an account creates it even when it stays
+                    // empty, in that case there is no field change creating the account
+                    if old.balance == new.balance
+                        && old.nonce == new.nonce
+                        && new.storage_proof.iter().all(|p| p.value.is_zero())
+                    {
+                        trie_modifications.push(TrieModification::nonce(address, new.nonce));
+                        continue;
+                    }
+                }
+                (true, true) if is_recreated(old.code_hash, new.code_hash) => {
+                    // The old account, including its storage, is removed and the
+                    // new one is built from an empty account
+                    trie_modifications.push(TrieModification::destructed(address));
+                    trie_modifications.push(TrieModification::nonce(address, new.nonce));
+                    if !new.balance.is_zero() {
+                        trie_modifications.push(TrieModification::balance(address, new.balance));
+                    }
+                    trie_modifications.push(TrieModification::codehash(address, new.code_hash));
+                    for proof in new.storage_proof.iter().filter(|p| !p.value.is_zero()) {
+                        trie_modifications.push(TrieModification::storage(
+                            address,
+                            proof.key,
+                            proof.value,
+                        ));
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            // The account fields of the responses are not covered by the proofs,
+            // the transition is checked on the proven ones
+            let proven = |account: Option<Vec<u8>>, proof: &EIP1186ProofResponse| -> Result<_> {
+                let Some(account) = account else {
+                    return Ok(AccountState::empty());
+                };
+                let account = proven_account(&account)?;
+                ensure!(
+                    account.nonce == proof.nonce
+                        && account.balance == proof.balance
+                        && account.code_hash == proof.code_hash,
+                    "the fields of {:?} do not match its account proof",
+                    address
+                );
+                Ok(account)
+            };
+            check_account_transition(
+                address,
+                &proven(old_account, &old)?,
+                &proven(new_account, &new)?,
+                sent.as_ref()
+                    .map(|sent| sent.get(&address).copied().unwrap_or(0)),
+                expected.as_deref(),
+            )?;
+
             // if nothing changed, skip
             if old.balance == new.balance
                 && old.nonce == new.nonce
                 && old.code_hash == new.code_hash
                 && old.storage_hash == new.storage_hash
             {
-                println!("Skipping {:?} as nothing changed", address);
+                tracing::debug!(?address, "skipping account, nothing changed");
                 continue;
             }
 
@@ -256,24 +538,35 @@ impl<F: Field> StateUpdateWitness<F> {
             }
         }
 
+        tracing::debug!(
+            unique = node_cache.len(),
+            total = node_cache.inserted(),
+            "trie nodes fetched"
+        );
+
+        // The access list order is up to the caller, the same block always
+        // gives the same witness
+        sort_trie_modifications(&mut trie_modifications);
+
+        if let Some(state_diff) = &state_diff {
+            check_expected_values(&trie_modifications, &state_diff.updates())?;
+        }
+
         Ok(Transforms {
             block_no,
+            chain_id: chain_config.chain_id,
+            block_hash: curr_block
+                .hash
+                .ok_or_else(|| eyre!("block {} is pending", block_no))?,
             curr_state_root: curr_block.state_root,
             prev_state_root: prev_block.state_root,
             trie_modifications,
+            strict_empty_accounts,
+            state_diff,
         })
     }
 
-    fn mpt_witness(
-        trns: &Transforms,
-        provider: &str,
-    ) -> Result<(Vec<Node>, SingleTrieModifications<F>)> {
-        let nodes = mpt_witness_generator::get_witness(
-            trns.block_no.as_u64() - 1,
-            &trns.trie_modifications,
-            provider,
-        );
-
+    fn lc_witness(trns: &Transforms, nodes: &[Node]) -> Result<SingleTrieModifications<F>> {
         let witness_previous_state_root = H256::from_slice(&nodes[0].values[0][1..33]);
         let non_disabled_node = |n: &&Node| {
             n.start
@@ -316,32 +609,10 @@ impl<F: Field> StateUpdateWitness<F> {
 
             let m = &trns.trie_modifications[lc_proofs.len()];
 
-            let changes = match m.typ {
-                ProofType::BalanceChanged => vec![(
-                    ProofType::BalanceChanged,
-                    m.address,
-                    m.balance,
-                    H256::zero(),
-                )],
-                ProofType::NonceChanged => vec![(
-                    ProofType::NonceChanged,
-                    m.address,
-                    U256::from(m.nonce.0[0]),
-                    H256::zero(),
-                )],
-                ProofType::StorageChanged => {
-                    vec![(ProofType::StorageChanged, m.address, m.value, m.key)]
-                }
-                ProofType::CodeHashChanged => {
-                    vec![(
-                        ProofType::CodeHashChanged,
-                        m.address,
-                        U256::from_big_endian(&m.code_hash.0),
-                        H256::zero(),
-                    )]
-                }
-                _ => {
-                    println!("type unimplemented: {:?}", m.typ);
+            let changes = match public_change(m) {
+                Some(change) => vec![change],
+                None => {
+                    tracing::error!(typ = ?m.typ, "trie modification type unimplemented");
                     unimplemented!()
                 }
             };
@@ -359,6 +630,547 @@ impl<F: Field> StateUpdateWitness<F> {
             }
         }
 
-        Ok((nodes, SingleTrieModifications(lc_proofs)))
+        Ok(SingleTrieModifications {
+            metadata: BlockMetadata {
+                chain_id: F::from(trns.chain_id),
+                block_number: F::from(trns.block_no.as_u64()),
+                block_hash: Word::<F>::from(trns.block_hash),
+            },
+            proofs: lc_proofs,
+        })
+    }
+}
+
+/// The change of a trie modification in the public inputs: the proof type, the
+/// address, the new value and the storage key. `None` for the modifications
+/// that can not be proven yet.
+fn public_change(m: &TrieModification) -> Option<(ProofType, Address, U256, H256)> {
+    let value = match m.typ {
+        ProofType::BalanceChanged => m.balance,
+        ProofType::NonceChanged => U256::from(m.nonce.0[0]),
+        ProofType::StorageChanged => m.value,
+        ProofType::CodeHashChanged => m.code_hash.to_word(),
+        ProofType::AccountDestructed => U256::zero(),
+        _ => return None,
+    };
+    let key = match m.typ {
+        ProofType::StorageChanged => m.key,
+        _ => H256::zero(),
+    };
+    Some((m.typ, m.address, value, key))
+}
+
+/// Position of a trie modification in the canonical order: by address, then
+/// by field, then by storage slot. A destructed account comes before the
+/// fields of its recreation, the account fields before its storage.
+fn canonical_order(m: &TrieModification) -> (Address, u8, H256) {
+    let field = match m.typ {
+        ProofType::AccountDestructed => 0,
+        ProofType::NonceChanged => 1,
+        ProofType::BalanceChanged => 2,
+        ProofType::CodeHashChanged => 3,
+        ProofType::StorageChanged => 4,
+        _ => 5,
+    };
+    (m.address, field, m.key)
+}
+
+/// Sorts the trie modifications in the canonical order, so that the witness
+/// of a block does not depend on the order of its access list. The sort is
+/// stable, modifications of the same field keep their order.
+pub fn sort_trie_modifications(trie_modifications: &mut [TrieModification]) {
+    trie_modifications.sort_by_key(canonical_order);
+}
+
+/// Checks that the trie modifications are in the canonical order, the
+/// transforms of another order are rejected instead of giving another witness
+/// and other public inputs for the same block
+pub fn check_canonical_order(trie_modifications: &[TrieModification]) -> Result<()> {
+    for (idx, pair) in trie_modifications.windows(2).enumerate() {
+        ensure!(
+            canonical_order(&pair[0]) <= canonical_order(&pair[1]),
+            "trie modification {} ({:?} of {:?}) is out of order, see `sort_trie_modifications`",
+            idx + 1,
+            pair[1].typ,
+            pair[1].address
+        );
+    }
+    Ok(())
+}
+
+/// State diff of `block_no` from the `prestateTracer` of the node, needs the
+/// `debug` namespace
+pub(crate) async fn fetch_state_diff(
+    client: &SignerMiddleware<Provider<Http>, Wallet<SigningKey>>,
+    block_no: U64,
+) -> Result<StateDiff> {
+    let http: &Http = client.provider().as_ref();
+    let geth = GethClient::new(http.clone());
+    let traces = geth
+        .trace_block_prestate_diff(BlockNumber::Number(block_no))
+        .await
+        .map_err(|e| eyre!("prestate trace of block {}: {}", block_no, e))?;
+    Ok(StateDiff::from_traces(&traces))
+}
+
+/// Checks the values read from the proofs against the values of the state
+/// diff. Only the changes in the diff can be checked, the proofs also update
+/// the unchanged slots and the nonce of the created accounts.
+fn check_expected_values(
+    trie_modifications: &[TrieModification],
+    expected: &[TrieUpdate],
+) -> Result<()> {
+    for m in trie_modifications {
+        let (kind, value) = match m.typ {
+            ProofType::NonceChanged => (TrieUpdateKind::Nonce, U256::from(m.nonce.as_u64())),
+            ProofType::BalanceChanged => (TrieUpdateKind::Balance, m.balance),
+            ProofType::CodeHashChanged => (TrieUpdateKind::CodeHash, m.code_hash.to_word()),
+            ProofType::StorageChanged => (TrieUpdateKind::Storage(m.key), m.value),
+            _ => continue,
+        };
+        if let Some(update) = expected
+            .iter()
+            .find(|update| update.address == m.address && update.kind == kind)
+        {
+            ensure!(
+                update.new_value == value,
+                "{:?} of {:?} is {} in the proofs but {} in the state diff",
+                kind,
+                m.address,
+                value,
+                update.new_value
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Account encoded in the leaf `value` of an account proof
+fn proven_account(value: &[u8]) -> Result<AccountState> {
+    let rlp = Rlp::new(value);
+    Ok(AccountState {
+        nonce: U64::from_big_endian(rlp.at(0)?.data()?),
+        balance: U256::from_big_endian(rlp.at(1)?.data()?),
+        code_hash: H256::from_slice(rlp.at(3)?.data()?),
+    })
+}
+
+/// Checks that an account can go from the proven `old` to the proven `new` in
+/// the block, `old` being empty for a created account. A witness failing these checks comes from a
+/// wrong access list or node, and would otherwise only fail in the circuit or
+/// against the public inputs:
+/// - the nonce never decreases, only a re-created account starts over
+/// - the nonce of an account without code only increases with the `sent` transactions, when known
+/// - the balance of the `expected` state diff starts from the proven balance, so that it never
+///   spends more than the account held
+fn check_account_transition(
+    address: Address,
+    old: &AccountState,
+    new: &AccountState,
+    sent: Option<u64>,
+    expected: Option<&[TrieUpdate]>,
+) -> Result<()> {
+    ensure!(
+        new.nonce >= old.nonce,
+        "nonce of {:?} decreases from {} to {}",
+        address,
+        old.nonce,
+        new.nonce
+    );
+    // Created accounts start with a nonce of one without sending anything,
+    // the nonce of an account with code is increased by CREATE
+    let no_code = H256(keccak256([]));
+    if let Some(sent) = sent
+        .filter(|_| !old.nonce.is_zero() && old.code_hash == no_code && new.code_hash == no_code)
+    {
+        ensure!(
+            new.nonce - old.nonce == U64::from(sent),
+            "nonce of {:?} goes from {} to {} but it sends {} transactions",
+            address,
+            old.nonce,
+            new.nonce,
+            sent
+        );
+    }
+    if let Some(update) = expected
+        .into_iter()
+        .flatten()
+        .find(|update| update.address == address && update.kind == TrieUpdateKind::Balance)
+    {
+        ensure!(
+            update.old_value == old.balance,
+            "balance of {:?} starts from {} in the state diff but it holds {}",
+            address,
+            update.old_value,
+            old.balance
+        );
+    }
+    Ok(())
+}
+
+/// Number of transactions sent by every sender of `block`, `None` when the
+/// block has EIP-7702 authorizations, they also increase the nonce of their
+/// signers
+fn sent_transactions(block: &Block<Transaction>) -> Option<HashMap<Address, u64>> {
+    let mut sent = HashMap::new();
+    for tx in block.transactions.iter() {
+        if tx.transaction_type == Some(U64::from(4)) {
+            return None;
+        }
+        *sent.entry(tx.from).or_insert(0) += 1;
+    }
+    Some(sent)
+}
+
+/// Adds the accounts every block changes to `access_list`, the access list of a
+/// block only contains the accounts touched by the execution of the
+/// transactions:
+/// - the coinbase, receiving the priority fees (the base fee is burned since London and does not
+///   credit any account)
+/// - the senders, paying the fees and increasing their nonce
+/// - the recipients and the created contracts, receiving the value
+/// - the recipients of the withdrawals (EIP-4895)
+pub(crate) fn add_block_accounts(access_list: &mut AccessList, block: &Block<Transaction>) {
+    let addresses = transaction_accounts(block)
+        .into_iter()
+        .chain(block.withdrawals.iter().flatten().map(|w| w.address));
+
+    let mut known: HashSet<Address> = access_list.0.iter().map(|item| item.address).collect();
+    for address in addresses {
+        if known.insert(address) {
+            access_list.0.push(AccessListItem {
+                address,
+                storage_keys: Vec::new(),
+            });
+        }
+    }
+}
+
+/// The coinbase and the accounts of the transactions of `block`
+fn transaction_accounts(block: &Block<Transaction>) -> Vec<Address> {
+    let mut addresses: Vec<Address> = block.author.into_iter().collect();
+    for tx in block.transactions.iter() {
+        addresses.push(tx.from);
+        addresses.push(
+            tx.to
+                .unwrap_or_else(|| ethers::utils::get_contract_address(tx.from, tx.nonce)),
+        );
+    }
+    addresses
+}
+
+/// Balance increase (in wei) of the accounts only changed by the withdrawals of
+/// `block`. Accounts in `access_list` or touched by a transaction can change for
+/// other reasons, so their balance can not be checked.
+pub(crate) fn withdrawal_only_deltas(
+    access_list: &AccessList,
+    block: &Block<Transaction>,
+) -> HashMap<Address, U256> {
+    let touched: HashSet<Address> = access_list
+        .0
+        .iter()
+        .map(|item| item.address)
+        .chain(transaction_accounts(block))
+        .collect();
+    let mut deltas = HashMap::new();
+    for withdrawal in block.withdrawals.iter().flatten() {
+        if !touched.contains(&withdrawal.address) {
+            // Withdrawal amounts are in gwei
+            *deltas.entry(withdrawal.address).or_insert_with(U256::zero) +=
+                withdrawal.amount * U256::exp10(9);
+        }
+    }
+    deltas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_accounts_are_added_once() {
+        let coinbase = Address::from_low_u64_be(1);
+        let sender = Address::from_low_u64_be(2);
+        let recipient = Address::from_low_u64_be(3);
+        let slot = H256::from_low_u64_be(4);
+        let block = Block {
+            author: Some(coinbase),
+            transactions: vec![
+                Transaction {
+                    from: sender,
+                    to: Some(recipient),
+                    ..Default::default()
+                },
+                // Contract creation
+                Transaction {
+                    from: sender,
+                    nonce: 1.into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let mut access_list = AccessList(vec![AccessListItem {
+            address: recipient,
+            storage_keys: vec![slot],
+        }]);
+
+        add_block_accounts(&mut access_list, &block);
+
+        let created = ethers::utils::get_contract_address(sender, 1);
+        assert_eq!(
+            access_list
+                .0
+                .iter()
+                .map(|item| item.address)
+                .collect::<Vec<_>>(),
+            vec![recipient, coinbase, sender, created]
+        );
+        // The slots of the user supplied access list are kept
+        assert_eq!(access_list.0[0].storage_keys, vec![slot]);
+    }
+
+    #[test]
+    fn withdrawal_deltas() {
+        let coinbase = Address::from_low_u64_be(1);
+        let validator = Address::from_low_u64_be(2);
+        let withdrawal = |address, amount: u64| Withdrawal {
+            address,
+            amount: amount.into(),
+            ..Default::default()
+        };
+        let block = Block {
+            author: Some(coinbase),
+            withdrawals: Some(vec![
+                withdrawal(validator, 1),
+                withdrawal(coinbase, 2),
+                withdrawal(validator, 3),
+            ]),
+            ..Default::default()
+        };
+        let mut access_list = AccessList::default();
+
+        // The coinbase also receives the fees
+        let deltas = withdrawal_only_deltas(&access_list, &block);
+        assert_eq!(
+            deltas,
+            HashMap::from([(validator, U256::from(4_000_000_000u64))])
+        );
+
+        add_block_accounts(&mut access_list, &block);
+        assert_eq!(
+            access_list
+                .0
+                .iter()
+                .map(|item| item.address)
+                .collect::<Vec<_>>(),
+            vec![coinbase, validator]
+        );
+    }
+
+    #[test]
+    fn account_transitions() {
+        let address = Address::from_low_u64_be(1);
+        let account = |nonce: u64, balance: u64| AccountState {
+            nonce: nonce.into(),
+            balance: balance.into(),
+            ..AccountState::empty()
+        };
+        let balance_update = |old: u64, new: u64| TrieUpdate {
+            address,
+            kind: TrieUpdateKind::Balance,
+            old_value: old.into(),
+            new_value: new.into(),
+        };
+
+        assert!(
+            check_account_transition(address, &account(2, 10), &account(4, 5), Some(2), None)
+                .is_ok()
+        );
+        // Nonce decreasing or skipping transactions
+        assert!(
+            check_account_transition(address, &account(2, 10), &account(1, 10), None, None)
+                .is_err()
+        );
+        assert!(
+            check_account_transition(address, &account(2, 10), &account(5, 10), Some(2), None)
+                .is_err()
+        );
+        // A created account starts at nonce one
+        assert!(
+            check_account_transition(address, &account(0, 0), &account(1, 10), Some(0), None)
+                .is_ok()
+        );
+        // The state diff spends more than the proven balance
+        assert!(check_account_transition(
+            address,
+            &account(1, 10),
+            &account(1, 0),
+            None,
+            Some(&[balance_update(10, 0)])
+        )
+        .is_ok());
+        assert!(check_account_transition(
+            address,
+            &account(1, 10),
+            &account(1, 0),
+            None,
+            Some(&[balance_update(20, 0)])
+        )
+        .is_err());
+        // The state diff starts from another balance than the proven one
+        assert!(check_account_transition(
+            address,
+            &account(1, 10),
+            &account(1, 5),
+            None,
+            Some(&[balance_update(8, 5)])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn public_inputs_layout() {
+        use halo2_proofs::halo2curves::bn256::Fr;
+
+        let block_hash = H256::repeat_byte(0xab);
+        let modification = SingleTrieModification::<Fr> {
+            typ: Fr::from(ProofType::StorageChanged as u64),
+            old_root: Word::from(H256::repeat_byte(1)),
+            new_root: Word::from(H256::repeat_byte(2)),
+            ..Default::default()
+        };
+        let stm = SingleTrieModifications {
+            metadata: BlockMetadata {
+                chain_id: Fr::from(1),
+                block_number: Fr::from(100),
+                block_hash: Word::from(block_hash),
+            },
+            proofs: vec![modification.clone(), modification],
+        };
+        let public_inputs = PublicInputs::from(&stm);
+        let layout = InstanceLayout::new(2, false);
+        assert_eq!(public_inputs.len(), layout.len());
+        assert_eq!(public_inputs[InstanceLayout::CHANGE_COUNT], Fr::from(2));
+        let root = Word::<Fr>::from(H256::repeat_byte(2));
+        assert_eq!(public_inputs[InstanceLayout::CURR_STATE_ROOT[1]], root.hi());
+        assert_eq!(public_inputs.chain_id(), Fr::from(1));
+        assert_eq!(public_inputs.block_number(), Fr::from(100));
+        let block_hash = Word::<Fr>::from(block_hash);
+        assert_eq!(
+            public_inputs.block_hash(),
+            [block_hash.lo(), block_hash.hi()]
+        );
+        assert_eq!(
+            public_inputs[layout.change(1).unwrap() + InstanceLayout::CHANGE_PROOF_TYPE],
+            Fr::from(ProofType::StorageChanged as u64)
+        );
+
+        // The changes are replaced by their digest, the empty rows up to the
+        // maximum number of changes are part of it
+        let header = public_inputs[..PublicInputs::<Fr>::HEADER_LEN].to_vec();
+        let digest = PublicInputs::from(&stm).into_mpt_digest(4);
+        assert_eq!(digest.len(), PublicInputs::<Fr>::HEADER_LEN + 2);
+        assert_eq!(digest[..PublicInputs::<Fr>::HEADER_LEN], header[..]);
+        assert_ne!(digest.0, PublicInputs::from(&stm).into_mpt_digest(5).0);
+    }
+
+    #[test]
+    fn public_inputs_from_transforms() {
+        use halo2_proofs::halo2curves::bn256::Fr;
+
+        let address = Address::repeat_byte(0x11);
+        let key = H256::repeat_byte(0x22);
+        let transforms = Transforms {
+            block_no: 100.into(),
+            chain_id: 1,
+            block_hash: H256::repeat_byte(0xab),
+            prev_state_root: H256::repeat_byte(1),
+            curr_state_root: H256::repeat_byte(3),
+            trie_modifications: vec![
+                TrieModification::balance(address, 5.into()),
+                TrieModification::storage(address, key, 7.into()),
+            ],
+            ..Default::default()
+        };
+        // The public inputs of the witness of the same changes
+        let roots = [1, 2, 3].map(|byte| Word::<Fr>::from(H256::repeat_byte(byte)));
+        let stm = SingleTrieModifications {
+            metadata: BlockMetadata {
+                chain_id: Fr::from(1),
+                block_number: Fr::from(100),
+                block_hash: Word::from(transforms.block_hash),
+            },
+            proofs: vec![
+                SingleTrieModification {
+                    typ: Fr::from(ProofType::BalanceChanged as u64),
+                    address: address.to_scalar().unwrap(),
+                    value: Word::from(U256::from(5)),
+                    key: Word::from(H256::zero()),
+                    old_root: roots[0],
+                    new_root: roots[1],
+                },
+                SingleTrieModification {
+                    typ: Fr::from(ProofType::StorageChanged as u64),
+                    address: address.to_scalar().unwrap(),
+                    value: Word::from(U256::from(7)),
+                    key: Word::from(key),
+                    old_root: roots[1],
+                    new_root: roots[2],
+                },
+            ],
+        };
+        assert_eq!(
+            PublicInputs::<Fr>::from_transforms(&transforms).unwrap().0,
+            PublicInputs::from(&stm).0
+        );
+
+        let mut unsupported = transforms;
+        unsupported.trie_modifications[0].typ = ProofType::AccountDoesNotExist;
+        assert!(PublicInputs::<Fr>::from_transforms(&unsupported).is_err());
+    }
+
+    #[test]
+    fn trie_modifications_order() {
+        let [a, b] = [1, 2].map(Address::from_low_u64_be);
+        let [low, high] = [3, 4].map(H256::from_low_u64_be);
+        let mut trie_modifications = vec![
+            TrieModification::storage(b, high, 1.into()),
+            TrieModification::balance(b, 5.into()),
+            TrieModification::storage(a, high, 2.into()),
+            TrieModification::storage(b, low, 3.into()),
+            // Recreated account
+            TrieModification::codehash(a, H256::repeat_byte(1)),
+            TrieModification::nonce(a, 1.into()),
+            TrieModification::destructed(a),
+        ];
+        assert!(check_canonical_order(&trie_modifications).is_err());
+
+        // Any order of the same changes gives the same witness
+        let mut reversed = trie_modifications.clone();
+        reversed.reverse();
+        sort_trie_modifications(&mut trie_modifications);
+        sort_trie_modifications(&mut reversed);
+        let summary = |trie_modifications: &[TrieModification]| {
+            serde_json::to_string(trie_modifications).unwrap()
+        };
+        assert_eq!(summary(&trie_modifications), summary(&reversed));
+        assert!(check_canonical_order(&trie_modifications).is_ok());
+        assert_eq!(
+            trie_modifications
+                .iter()
+                .map(|m| (m.address, m.typ as u8))
+                .collect::<Vec<_>>(),
+            vec![
+                (a, ProofType::AccountDestructed as u8),
+                (a, ProofType::NonceChanged as u8),
+                (a, ProofType::CodeHashChanged as u8),
+                (a, ProofType::StorageChanged as u8),
+                (b, ProofType::BalanceChanged as u8),
+                (b, ProofType::StorageChanged as u8),
+                (b, ProofType::StorageChanged as u8),
+            ]
+        );
+        assert_eq!(trie_modifications[5].key, low);
     }
 }
@@ -0,0 +1,384 @@
+use eth_types::{Address, H256, U256, U64};
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2930::AccessList, BlockId, BlockNumber},
+};
+use eyre::{ensure, eyre, Result};
+use halo2_proofs::halo2curves::bn256::Fr;
+use std::sync::Arc;
+
+use crate::{
+    circuit::{
+        ChainConfig, ProofBundle, PublicInputs, StateUpdateCircuit, StateUpdateCircuitKeys,
+        StateUpdateWitness, Transforms, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT,
+    },
+    rpc::{NotProven, VerifiedState},
+    utils::{new_eth_signer_client, MM},
+};
+
+/// State update proof of a block, with the changes it proves
+#[derive(Debug, Clone)]
+pub struct BlockProof {
+    pub bundle: ProofBundle,
+    pub transforms: Transforms,
+}
+
+/// Keys with the hashes of the circuit they were generated for, unknown for
+/// keys given with `LightClient::with_keys` until they prove a block
+#[derive(Clone)]
+struct CircuitKeys {
+    keys: StateUpdateCircuitKeys,
+    params_hash: Option<[u8; 32]>,
+    circuit_fingerprint: Option<[u8; 32]>,
+}
+
+impl CircuitKeys {
+    fn new(circuit: &StateUpdateCircuit<Fr>) -> Self {
+        Self {
+            keys: StateUpdateCircuitKeys::new(circuit),
+            params_hash: Some(circuit.params_hash()),
+            circuit_fingerprint: Some(circuit.circuit_fingerprint()),
+        }
+    }
+}
+
+/// Light client to embed in other projects: proves the state updates of
+/// blocks with the data of a node, verifies the proofs and keeps the state
+/// values proven by the verified ones.
+///
+/// ```ignore
+/// let mut client = LightClient::new(provider_url, pvk, None).await?;
+/// if let Some(proof) = client.prove_block(block, None).await? {
+///     client.verify(&proof)?;
+/// }
+/// let balance = client.state().balance(&address)?;
+/// ```
+///
+/// With auto fetch enabled the reads of values no verified proof covers
+/// fetch an `eth_getProof` of the value and verify it against the latest
+/// verified state root instead of failing with `NotProven`.
+pub struct LightClient {
+    client: Arc<MM>,
+    provider_url: String,
+    chain: Option<ChainConfig>,
+    degree: usize,
+    max_proof_count: usize,
+    mpt_digest: bool,
+    keys: Option<CircuitKeys>,
+    state: VerifiedState,
+    auto_fetch: bool,
+}
+
+impl LightClient {
+    /// Client of the node at `provider_url`, the witnesses are generated with
+    /// the state rules of `chain`, or of the chain served by the node when no
+    /// chain is selected
+    pub async fn new(provider_url: &str, pvk: &str, chain: Option<ChainConfig>) -> Result<Self> {
+        Ok(Self {
+            client: new_eth_signer_client(provider_url, pvk).await?,
+            provider_url: provider_url.to_string(),
+            chain,
+            degree: DEFAULT_CIRCUIT_DEGREE,
+            max_proof_count: DEFAULT_MAX_PROOF_COUNT,
+            mpt_digest: false,
+            keys: None,
+            state: VerifiedState::default(),
+            auto_fetch: false,
+        })
+    }
+
+    /// Sets the degree of the circuit and the maximum number of trie
+    /// modifications of a proof, the defaults are `DEFAULT_CIRCUIT_DEGREE` and
+    /// `DEFAULT_MAX_PROOF_COUNT`
+    pub fn with_circuit_params(mut self, degree: usize, max_proof_count: usize) -> Self {
+        self.degree = degree;
+        self.max_proof_count = max_proof_count;
+        self.keys = None;
+        self
+    }
+
+    /// Exposes the digest of the changes instead of the changes in the proofs,
+    /// see `StateUpdateCircuitParams::mpt_digest`
+    pub fn with_mpt_digest(mut self, mpt_digest: bool) -> Self {
+        self.mpt_digest = mpt_digest;
+        self.keys = None;
+        self
+    }
+
+    /// Proves and verifies with `keys`, e.g. read with
+    /// `StateUpdateCircuitKeys::unserialize` or generated separately, instead
+    /// of generating them with the first proof. They have to be generated for
+    /// the circuit of the proven blocks. Set the circuit params before, they
+    /// reset the keys.
+    pub fn with_keys(mut self, keys: StateUpdateCircuitKeys) -> Self {
+        self.keys = Some(CircuitKeys {
+            keys,
+            params_hash: None,
+            circuit_fingerprint: None,
+        });
+        self
+    }
+
+    /// Keys used by `prove_block` and `verify`, to save them for later runs
+    pub fn keys(&self) -> Option<&StateUpdateCircuitKeys> {
+        self.keys.as_ref().map(|keys| &keys.keys)
+    }
+
+    /// Fetches and verifies the proofs of the values missing from the state
+    /// when they are read, see `balance`, `nonce` and `storage`
+    pub fn with_auto_fetch(mut self, auto_fetch: bool) -> Self {
+        self.auto_fetch = auto_fetch;
+        self
+    }
+
+    /// Proves the state update of `block`, `None` when the block does not
+    /// change the state. The accounts and slots are taken from `access_list`
+    /// when given instead of from the traces of the node. Without keys from
+    /// `with_keys`, the keys are generated with the first proof and kept for
+    /// the next ones. The proof is not applied to the state, see `verify`.
+    pub async fn prove_block(
+        &mut self,
+        block: U64,
+        access_list: Option<AccessList>,
+    ) -> Result<Option<BlockProof>> {
+        let witness = StateUpdateWitness::<Fr>::build(
+            self.client.clone(),
+            &self.provider_url,
+            block,
+            access_list,
+            self.chain.as_ref(),
+        )
+        .await?;
+        let Some(witness) = witness else {
+            return Ok(None);
+        };
+
+        let mut circuit = StateUpdateCircuit::new(witness, self.degree, self.max_proof_count)?;
+        if self.mpt_digest {
+            circuit = circuit.with_mpt_digest();
+        }
+        let public_inputs = circuit.public_inputs();
+        let transforms = circuit.transforms.clone();
+
+        // Proving is CPU bound, keep it off the async runtime. The keys depend
+        // on the state rules of the block, so they are regenerated when the
+        // rules change.
+        let cached_keys = self.keys.take();
+        let (bundle, keys) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let keys = match cached_keys {
+                // Keys given with `with_keys` are bound to the first proven circuit
+                Some(keys) if keys.params_hash.is_none() => CircuitKeys {
+                    params_hash: Some(circuit.params_hash()),
+                    circuit_fingerprint: Some(circuit.circuit_fingerprint()),
+                    ..keys
+                },
+                cached_keys => cached_keys
+                    .filter(|keys| keys.params_hash == Some(circuit.params_hash()))
+                    .unwrap_or_else(|| CircuitKeys::new(&circuit)),
+            };
+            // Proving consumes the circuit
+            let mut bundle = ProofBundle::new(Vec::new(), &public_inputs, &circuit, &keys.keys);
+            bundle.proof = circuit.prove(&keys.keys)?;
+            Ok((bundle, keys))
+        })
+        .await??;
+        self.keys = Some(keys);
+
+        Ok(Some(BlockProof { bundle, transforms }))
+    }
+
+    /// Verifies `proof` with the keys of `with_keys` or of the last proven
+    /// block and applies its changes to the state. The changes are checked
+    /// against the public inputs of the proof, so only proven values end up in
+    /// the state. The proof has to continue the verified state, see
+    /// `VerifiedState::apply`.
+    pub fn verify(&mut self, proof: &BlockProof) -> Result<()> {
+        let keys = self.keys.as_ref().ok_or_else(|| {
+            eyre!("no circuit keys yet, they are given with with_keys or generated by prove_block")
+        })?;
+        let bundle = &proof.bundle;
+        // The hashes of the circuit are only known for keys that proved a block,
+        // the verifying key is always checked
+        let same_circuit = match (bundle.circuit_fingerprint, keys.circuit_fingerprint) {
+            (Some(fingerprint), Some(keys_fingerprint)) => fingerprint == keys_fingerprint,
+            _ => true,
+        };
+        ensure!(
+            keys.params_hash
+                .map_or(true, |params_hash| bundle.params_hash == params_hash)
+                && same_circuit
+                && bundle.vk_hash == keys.keys.vk_hash(),
+            "proof of block {} was generated for another circuit",
+            proof.transforms.block_no
+        );
+        StateUpdateCircuit::verify(&bundle.proof, &bundle.instances, &keys.keys)?;
+
+        let mut public_inputs = PublicInputs::<Fr>::from_transforms(&proof.transforms)?;
+        if self.mpt_digest {
+            public_inputs = public_inputs.into_mpt_digest(self.max_proof_count);
+        }
+        ensure!(
+            public_inputs.0 == bundle.instances,
+            "the changes of block {} are not the ones of the proof",
+            proof.transforms.block_no
+        );
+        self.state.apply(&proof.transforms)
+    }
+
+    /// State values proven by the verified proofs
+    pub fn state(&self) -> &VerifiedState {
+        &self.state
+    }
+
+    /// Proven balance of `address` at the latest verified block
+    pub async fn balance(&mut self, address: Address) -> Result<U256> {
+        let balance = self.state.balance(&address);
+        self.read(balance, |state| state.balance(&address)).await
+    }
+
+    /// Proven nonce of `address` at the latest verified block
+    pub async fn nonce(&mut self, address: Address) -> Result<U64> {
+        let nonce = self.state.nonce(&address);
+        self.read(nonce, |state| state.nonce(&address)).await
+    }
+
+    /// Proven value of the storage slot `key` of `address` at the latest
+    /// verified block
+    pub async fn storage(&mut self, address: Address, key: H256) -> Result<U256> {
+        let value = self.state.storage(&address, &key);
+        self.read(value, |state| state.storage(&address, &key))
+            .await
+    }
+
+    /// Returns `value`, or with auto fetch the value read again after fetching
+    /// the proof it is missing
+    async fn read<T>(
+        &mut self,
+        value: std::result::Result<T, NotProven>,
+        read: impl Fn(&VerifiedState) -> std::result::Result<T, NotProven>,
+    ) -> Result<T> {
+        match value {
+            Err(missing) if self.auto_fetch => {
+                tracing::debug!(%missing, "fetching missing proof");
+                self.fetch(&missing.access_list()).await?;
+                Ok(read(&self.state)?)
+            }
+            value => Ok(value?),
+        }
+    }
+
+    /// Fetches the proofs of the accounts and slots of `access_list` at the
+    /// latest verified block and adds them to the state once verified against
+    /// its state root. The slots of an account are fetched with one request,
+    /// so reading several slots is cheaper after fetching them together.
+    pub async fn fetch(&mut self, access_list: &AccessList) -> Result<()> {
+        let block = BlockId::Number(BlockNumber::Number(self.state.latest_block));
+        for item in access_list.0.iter() {
+            let proof = self
+                .client
+                .get_proof(item.address, item.storage_keys.clone(), Some(block))
+                .await?;
+            self.state.apply_account_proof(&proof)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::tests::single_account_proof;
+    use axum::{extract::State, routing::post, Json, Router};
+    use ethers::types::EIP1186ProofResponse;
+    use serde_json::{json, Value};
+    use std::{
+        net::SocketAddr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    const PVK: &str = "7ccb34dc5fd31fd0aa7860de89a4adc37ccb34dc5fd31fd0aa7860de89a4adc3";
+
+    /// Node answering `eth_getProof` with `proof` whatever the request
+    struct ProofNode {
+        proof: EIP1186ProofResponse,
+        proof_requests: AtomicUsize,
+    }
+
+    async fn handle(State(node): State<Arc<ProofNode>>, Json(request): Json<Value>) -> Json<Value> {
+        let result = match request["method"].as_str() {
+            Some("eth_chainId") => json!("0x1"),
+            Some("eth_getBalance") => json!("0x0"),
+            Some("eth_getProof") => {
+                node.proof_requests.fetch_add(1, Ordering::Relaxed);
+                json!(node.proof)
+            }
+            method => panic!("unexpected request {:?}", method),
+        };
+        Json(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+    }
+
+    /// Light client of `node` with a verified state root `root`
+    async fn light_client(node: Arc<ProofNode>, root: H256) -> LightClient {
+        let app = Router::new().route("/", post(handle)).with_state(node);
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let mut client = LightClient::new(&url, PVK, None).await.unwrap();
+        client
+            .state
+            .apply(&Transforms {
+                block_no: 10.into(),
+                curr_state_root: root,
+                ..Default::default()
+            })
+            .unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn auto_fetch() {
+        let address = Address::repeat_byte(0x11);
+        let (root, proof) = single_account_proof(address, 2, 1000);
+        let node = Arc::new(ProofNode {
+            proof,
+            proof_requests: AtomicUsize::new(0),
+        });
+        let mut client = light_client(node.clone(), root).await;
+
+        // Without auto fetch the read fails with the proof to fetch
+        let err = client.balance(address).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<NotProven>(),
+            Some(&NotProven {
+                address,
+                slot: None
+            })
+        );
+        assert_eq!(node.proof_requests.load(Ordering::Relaxed), 0);
+
+        // The missing proof is fetched once, then the value is in the state
+        let mut client = client.with_auto_fetch(true);
+        assert_eq!(client.balance(address).await.unwrap(), 1000.into());
+        assert_eq!(client.nonce(address).await.unwrap(), 2.into());
+        assert_eq!(node.proof_requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn auto_fetch_rejects_invalid_proof() {
+        let address = Address::repeat_byte(0x11);
+        let (root, _) = single_account_proof(address, 2, 1000);
+        // The node serves a proof of another state
+        let (_, proof) = single_account_proof(address, 2, 2000);
+        let node = Arc::new(ProofNode {
+            proof,
+            proof_requests: AtomicUsize::new(0),
+        });
+        let mut client = light_client(node.clone(), root).await.with_auto_fetch(true);
+
+        assert!(client.balance(address).await.is_err());
+        assert_eq!(node.proof_requests.load(Ordering::Relaxed), 1);
+        assert!(client.state().balance(&address).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+use eth_types::{Bytes, H256, U256, U64};
+use ethers::{
+    providers::Middleware,
+    utils::{keccak256, rlp::Rlp},
+};
+use eyre::{bail, ensure, Result};
+use halo2_proofs::halo2curves::bn256::Fr;
+use zkevm_circuits::util::word::Word;
+
+use crate::{circuit::PublicInputs, utils::MM};
+
+/// Fields of a block header, in the order they are RLP encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderField {
+    ParentHash,
+    UncleHash,
+    Coinbase,
+    StateRoot,
+    TransactionsRoot,
+    ReceiptsRoot,
+    LogsBloom,
+    Difficulty,
+    Number,
+    GasLimit,
+    GasUsed,
+    Timestamp,
+    ExtraData,
+    MixHash,
+    Nonce,
+    /// EIP-1559 (London)
+    BaseFeePerGas,
+    /// EIP-4895 (Shanghai)
+    WithdrawalsRoot,
+    /// EIP-4844 (Cancun)
+    BlobGasUsed,
+    /// EIP-4844 (Cancun)
+    ExcessBlobGas,
+    /// EIP-4788 (Cancun)
+    ParentBeaconBlockRoot,
+    /// EIP-7685 (Prague)
+    RequestsHash,
+}
+
+/// Header layouts, every fork appends fields to the header of the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HeaderVersion {
+    Frontier,
+    London,
+    Shanghai,
+    Cancun,
+    Prague,
+}
+
+impl HeaderVersion {
+    const ALL: [HeaderVersion; 5] = [
+        HeaderVersion::Frontier,
+        HeaderVersion::London,
+        HeaderVersion::Shanghai,
+        HeaderVersion::Cancun,
+        HeaderVersion::Prague,
+    ];
+
+    /// The last field of the header
+    fn last_field(&self) -> HeaderField {
+        match self {
+            HeaderVersion::Frontier => HeaderField::Nonce,
+            HeaderVersion::London => HeaderField::BaseFeePerGas,
+            HeaderVersion::Shanghai => HeaderField::WithdrawalsRoot,
+            HeaderVersion::Cancun => HeaderField::ParentBeaconBlockRoot,
+            HeaderVersion::Prague => HeaderField::RequestsHash,
+        }
+    }
+
+    pub fn num_fields(&self) -> usize {
+        self.last_field() as usize + 1
+    }
+
+    /// Detects the version from the number of fields, unknown layouts are
+    /// rejected instead of being read at the wrong offsets
+    pub fn from_num_fields(num_fields: usize) -> Result<Self> {
+        match Self::ALL.iter().find(|v| v.num_fields() == num_fields) {
+            Some(version) => Ok(*version),
+            None => bail!("unknown header layout with {} fields", num_fields),
+        }
+    }
+
+    /// Index of `field` in the RLP list, `None` if this version does not have it
+    pub fn offset(&self, field: HeaderField) -> Option<usize> {
+        (field as usize <= self.last_field() as usize).then_some(field as usize)
+    }
+
+    /// Denominator of the blob base fee exponent (EIP-4844, EIP-7691)
+    fn blob_base_fee_update_fraction(&self) -> Option<u64> {
+        match self {
+            HeaderVersion::Frontier | HeaderVersion::London | HeaderVersion::Shanghai => None,
+            HeaderVersion::Cancun => Some(3_338_477),
+            HeaderVersion::Prague => Some(5_007_716),
+        }
+    }
+}
+
+/// A raw header decoded with the layout of its version
+pub struct Header<'a> {
+    rlp: Rlp<'a>,
+    pub version: HeaderVersion,
+}
+
+impl<'a> Header<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let rlp = Rlp::new(bytes);
+        ensure!(rlp.is_list(), "header is not an RLP list");
+        let version = HeaderVersion::from_num_fields(rlp.item_count()?)?;
+        Ok(Self { rlp, version })
+    }
+
+    /// The bytes of `field`, `None` if the header version does not have it
+    pub fn field(&self, field: HeaderField) -> Result<Option<&'a [u8]>> {
+        match self.version.offset(field) {
+            Some(offset) => Ok(Some(self.rlp.at(offset)?.data()?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn h256(&self, field: HeaderField) -> Result<Option<H256>> {
+        self.field(field)?
+            .map(|bytes| {
+                ensure!(bytes.len() == 32, "{:?} is not 32 bytes long", field);
+                Ok(H256::from_slice(bytes))
+            })
+            .transpose()
+    }
+
+    pub fn u64(&self, field: HeaderField) -> Result<Option<U64>> {
+        self.field(field)?
+            .map(|bytes| {
+                ensure!(bytes.len() <= 8, "{:?} does not fit in 64 bits", field);
+                Ok(U64::from_big_endian(bytes))
+            })
+            .transpose()
+    }
+
+    /// Fields present in all the header versions
+    fn required_h256(&self, field: HeaderField) -> Result<H256> {
+        Ok(self.h256(field)?.unwrap())
+    }
+}
+
+/// A verified block header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedHeader {
+    pub number: U64,
+    pub hash: H256,
+    pub state_root: H256,
+    pub version: HeaderVersion,
+    /// Since Cancun
+    pub blob_gas_used: Option<U64>,
+    /// Since Cancun
+    pub excess_blob_gas: Option<U64>,
+    /// Since Cancun
+    pub parent_beacon_block_root: Option<H256>,
+}
+
+impl VerifiedHeader {
+    fn new(header: &Header, hash: H256) -> Result<Self> {
+        Ok(Self {
+            number: header.u64(HeaderField::Number)?.unwrap(),
+            hash,
+            state_root: header.required_h256(HeaderField::StateRoot)?,
+            version: header.version,
+            blob_gas_used: header.u64(HeaderField::BlobGasUsed)?,
+            excess_blob_gas: header.u64(HeaderField::ExcessBlobGas)?,
+            parent_beacon_block_root: header.h256(HeaderField::ParentBeaconBlockRoot)?,
+        })
+    }
+
+    /// Price of a unit of blob gas in the block, `None` before Cancun
+    pub fn blob_base_fee(&self) -> Option<U256> {
+        let fraction = self.version.blob_base_fee_update_fraction()?;
+        Some(fake_exponential(
+            U256::one(),
+            self.excess_blob_gas?.as_u64().into(),
+            fraction.into(),
+        ))
+    }
+}
+
+/// Approximates `factor * e ** (numerator / denominator)` (EIP-4844)
+fn fake_exponential(factor: U256, numerator: U256, denominator: U256) -> U256 {
+    let mut i = U256::one();
+    let mut output = U256::zero();
+    let mut accum = factor * denominator;
+    while !accum.is_zero() {
+        output += accum;
+        accum = accum * numerator / (denominator * i);
+        i += U256::one();
+    }
+    output / denominator
+}
+
+/// Raw headers linking an old block to a trusted more recent block through
+/// their parent hashes, so that the state of the old block can be proven
+/// against the trusted block hash only.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderChain {
+    /// Headers ordered from the target (oldest) block to the trusted block
+    pub headers: Vec<Bytes>,
+}
+
+pub async fn get_raw_header(client: &MM, block_no: U64) -> Result<Bytes> {
+    Ok(client
+        .provider()
+        .request("debug_getRawHeader", vec![format!("0x{:x}", block_no)])
+        .await?)
+}
+
+impl HeaderChain {
+    /// Fetches the headers of the blocks `target..=trusted`
+    pub async fn fetch(client: &MM, target: U64, trusted: U64) -> Result<Self> {
+        ensure!(
+            target <= trusted,
+            "the target block is after the trusted block"
+        );
+        let mut headers = Vec::new();
+        let mut block_no = target;
+        while block_no <= trusted {
+            headers.push(get_raw_header(client, block_no).await?);
+            block_no = block_no + 1;
+        }
+        Ok(Self { headers })
+    }
+
+    /// Checks that every header is the parent of the next one and that the last
+    /// header has hash `trusted_hash`. Returns the target (first) header.
+    pub fn verify(&self, trusted_hash: H256) -> Result<VerifiedHeader> {
+        ensure!(!self.headers.is_empty(), "empty header chain");
+        let mut expected_hash = trusted_hash;
+        let mut target = None;
+        for header in self.headers.iter().rev() {
+            let hash = H256(keccak256(header));
+            ensure!(hash == expected_hash, "broken header chain");
+            let decoded = Header::decode(header)?;
+            expected_hash = decoded.required_h256(HeaderField::ParentHash)?;
+            target = Some(VerifiedHeader::new(&decoded, hash)?);
+        }
+        Ok(target.unwrap())
+    }
+
+    /// Anchors a state update proof of block `target + 1` to a trusted block
+    /// hash, where the chain starts at block `target` (the parent of the proven
+    /// block). The state roots of the public inputs are checked against the
+    /// state roots of the two first headers, and the block number and hash
+    /// against the second header.
+    pub fn check_public_inputs(
+        &self,
+        public_inputs: &PublicInputs<Fr>,
+        trusted_hash: H256,
+    ) -> Result<()> {
+        ensure!(
+            self.headers.len() >= 2,
+            "the header chain needs to contain the parent of the proven block"
+        );
+        let parent = self.verify(trusted_hash)?;
+        let block = HeaderChain {
+            headers: self.headers[1..].to_vec(),
+        }
+        .verify(trusted_hash)?;
+
+        let prev_root = Word::<Fr>::from(parent.state_root);
+        let curr_root = Word::<Fr>::from(block.state_root);
+        ensure!(
+            public_inputs.prev_state_root() == [prev_root.lo(), prev_root.hi()],
+            "old state root does not match block {}",
+            parent.number
+        );
+        ensure!(
+            public_inputs.curr_state_root() == [curr_root.lo(), curr_root.hi()],
+            "new state root does not match block {}",
+            block.number
+        );
+        let block_hash = Word::<Fr>::from(block.hash);
+        ensure!(
+            public_inputs.block_number() == Fr::from(block.number.as_u64())
+                && public_inputs.block_hash() == [block_hash.lo(), block_hash.hi()],
+            "the proof is not for block {} ({:?})",
+            block.number,
+            block.hash
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::utils::rlp::RlpStream;
+
+    /// Header of `version` with each field set to its index
+    fn header(version: HeaderVersion, parent_hash: H256) -> Bytes {
+        let mut stream = RlpStream::new_list(version.num_fields());
+        for idx in 0..version.num_fields() {
+            match idx {
+                0 => stream.append(&parent_hash),
+                3 | 16 | 19 | 20 => stream.append(&H256::from_low_u64_be(idx as u64)),
+                _ => stream.append(&(idx as u64)),
+            };
+        }
+        stream.out().freeze().into()
+    }
+
+    #[test]
+    fn header_versions() {
+        for version in HeaderVersion::ALL {
+            let bytes = header(version, H256::zero());
+            let decoded = Header::decode(&bytes).unwrap();
+            assert_eq!(decoded.version, version);
+            let verified = VerifiedHeader::new(&decoded, H256::zero()).unwrap();
+            assert_eq!(verified.number, 8.into());
+            assert_eq!(verified.state_root, H256::from_low_u64_be(3));
+            let is_cancun = version >= HeaderVersion::Cancun;
+            assert_eq!(verified.blob_gas_used.is_some(), is_cancun);
+            assert_eq!(verified.excess_blob_gas, is_cancun.then(|| 18.into()));
+            assert_eq!(
+                verified.parent_beacon_block_root,
+                is_cancun.then(|| H256::from_low_u64_be(19))
+            );
+        }
+        let mut stream = RlpStream::new_list(18);
+        for idx in 0..18u64 {
+            stream.append(&idx);
+        }
+        assert!(Header::decode(&stream.out()).is_err());
+    }
+
+    #[test]
+    fn cancun_header_chain() {
+        let parent = header(HeaderVersion::Shanghai, H256::zero());
+        let block = header(HeaderVersion::Cancun, H256(keccak256(&parent)));
+        let chain = HeaderChain {
+            headers: vec![parent.clone(), block.clone()],
+        };
+        let target = chain.verify(H256(keccak256(&block))).unwrap();
+        assert_eq!(target.version, HeaderVersion::Shanghai);
+        assert_eq!(target.hash, H256(keccak256(&parent)));
+    }
+
+    #[test]
+    fn blob_base_fee() {
+        let fee = |version, excess_blob_gas: Option<u64>| {
+            VerifiedHeader {
+                number: U64::zero(),
+                hash: H256::zero(),
+                state_root: H256::zero(),
+                version,
+                blob_gas_used: excess_blob_gas.map(|_| U64::zero()),
+                excess_blob_gas: excess_blob_gas.map(U64::from),
+                parent_beacon_block_root: None,
+            }
+            .blob_base_fee()
+        };
+        assert_eq!(fee(HeaderVersion::Shanghai, None), None);
+        assert_eq!(fee(HeaderVersion::Cancun, Some(0)), Some(1.into()));
+        assert_eq!(fee(HeaderVersion::Cancun, Some(3_338_477)), Some(2.into()));
+        assert_eq!(
+            fee(HeaderVersion::Cancun, Some(20 * 3_338_477)),
+            Some(485_165_195.into())
+        );
+        assert_eq!(
+            fee(HeaderVersion::Prague, Some(20 * 3_338_477)),
+            Some(617_436.into())
+        );
+    }
+}
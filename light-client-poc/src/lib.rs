@@ -0,0 +1,25 @@
+//! Light client library: the state update circuit, the witness generation
+//! from a node and the proving and verification of the state updates, see
+//! `LightClient`. The `light-client-poc` binary is a command line wrapper.
+
+pub mod access_list;
+pub mod beacon;
+pub mod bridge;
+pub mod circuit;
+mod client;
+pub mod header_chain;
+pub mod logging;
+pub mod metrics;
+pub mod opcodes;
+pub mod pipeline;
+pub mod rpc;
+pub mod server;
+pub mod service;
+pub mod slot_of;
+pub mod submit;
+#[cfg(test)]
+mod tests;
+pub mod utils;
+pub mod watch;
+
+pub use client::{BlockProof, LightClient};
@@ -0,0 +1,18 @@
+use tracing_subscriber::EnvFilter;
+
+/// Installs the global logger.
+///
+/// `RUST_LOG` selects the levels per module, e.g.
+/// `RUST_LOG=info,light_client::circuit=debug`, the default is `info`. The
+/// transforms and public inputs of a block are logged at the `debug` level
+/// only, they can be megabytes long for big blocks. With `LOG_FORMAT=json`
+/// every event is written as a JSON object on its own line.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    // Fails when a logger is already installed, e.g. by another test
+    let _ = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().try_init(),
+        _ => builder.try_init(),
+    };
+}
@@ -1,11 +1,239 @@
 use eyre::Result;
+use light_client::{
+    access_list, circuit, logging, opcodes, pipeline, server, service, submit, utils, watch,
+};
 
-pub mod circuit;
-pub mod server;
-pub mod tests;
-pub mod utils;
+const PVK: &str = "7ccb34dc5fd31fd0aa7860de89a4adc37ccb34dc5fd31fd0aa7860de89a4adc3";
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    server::serve().await
+    logging::init();
+    let mut args: Vec<String> = std::env::args().collect();
+    // `--chain <mainnet|sepolia|polygon|chain-file>` selects the state rules and
+    // the default node, see `circuit::ChainConfig`
+    let chain = match args.iter().position(|arg| arg == "--chain") {
+        Some(index) => {
+            let name = args
+                .get(index + 1)
+                .ok_or_else(|| eyre::eyre!("--chain needs a chain name or file"))?
+                .clone();
+            args.drain(index..index + 2);
+            Some(circuit::ChainConfig::load(&name)?)
+        }
+        None => None,
+    };
+    // `--mpt-digest` exposes the digest of the changes instead of the changes,
+    // see `circuit::StateUpdateCircuitParams::mpt_digest`
+    let mpt_digest = match args.iter().position(|arg| arg == "--mpt-digest") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+    let provider_url = std::env::var("PROVIDER_URL")
+        .ok()
+        .or_else(|| chain.as_ref().and_then(|chain| chain.rpc_url.clone()))
+        .unwrap_or("http://localhost:8545".to_string());
+    match args.get(1).map(|arg| arg.as_str()) {
+        // Only prove the blocks changing one of the watched slots, see `watch::watch`
+        Some("watch") => {
+            let path = args.get(2).ok_or_else(|| {
+                eyre::eyre!(
+                    "usage: light-client-poc watch <watch-list-file> [checkpoint-file] [--resume]"
+                )
+            })?;
+            let watch_list = watch::WatchList::load(path)?;
+            let checkpoint_path = args.get(3).filter(|arg| *arg != "--resume");
+            let resume = args[3..].iter().any(|arg| arg == "--resume");
+            if resume && checkpoint_path.is_none() {
+                eyre::bail!("--resume needs a checkpoint file");
+            }
+            let metrics_addr = std::env::var("METRICS_ADDR")
+                .ok()
+                .map(|addr| addr.parse())
+                .transpose()?;
+            watch::watch(
+                watch_list,
+                &provider_url,
+                PVK,
+                chain.as_ref(),
+                checkpoint_path.map(|path| path.as_str()),
+                resume,
+                metrics_addr,
+            )
+            .await
+        }
+        // Run the prover as a REST service, see `service::serve`
+        Some("service") => {
+            let addr = std::env::var("LISTEN_ADDR")
+                .unwrap_or("127.0.0.1:3000".to_string())
+                .parse()?;
+            service::serve(addr, &provider_url, PVK, chain.as_ref()).await
+        }
+        // Split proving pipeline, see `pipeline`
+        Some("fetch") => {
+            let (Some(block), Some(out)) = (args.get(2), args.get(3)) else {
+                eyre::bail!(
+                    "usage: light-client-poc fetch <block> <transforms-file> [access-list-file]"
+                );
+            };
+            let access_list = args
+                .get(4)
+                .map(access_list::AccessListFile::load)
+                .transpose()?;
+            pipeline::fetch(
+                &provider_url,
+                PVK,
+                block.parse::<u64>()?.into(),
+                access_list,
+                chain.as_ref(),
+                out,
+            )
+            .await
+        }
+        Some("merge-access-lists") => {
+            let (Some(block), Some(out)) = (args.get(2), args.get(3)) else {
+                eyre::bail!(
+                    "usage: light-client-poc merge-access-lists <block> <out> [access-list-file...]"
+                );
+            };
+            let files = args[4..]
+                .iter()
+                .map(access_list::AccessListFile::load)
+                .collect::<Result<_>>()?;
+            pipeline::merge_access_lists(
+                &provider_url,
+                PVK,
+                block.parse::<u64>()?.into(),
+                files,
+                out,
+            )
+            .await
+        }
+        Some("witness") => {
+            let (Some(transforms), Some(out)) = (args.get(2), args.get(3)) else {
+                eyre::bail!("usage: light-client-poc witness <transforms-file> <witness-file>");
+            };
+            pipeline::witness(&provider_url, transforms, out)
+        }
+        Some("prove") => {
+            let (Some(witness), Some(out)) = (args.get(2), args.get(3)) else {
+                eyre::bail!("usage: light-client-poc prove <witness-file> <bundle-file>");
+            };
+            let calibration = std::env::var("CALIBRATION_FILE").ok();
+            pipeline::prove(witness, out, calibration.as_deref(), mpt_digest)
+        }
+        // Writes the verifier contract of the proofs of `prove-and-submit`,
+        // see `submit::generate_verifier`
+        Some("evm-verifier") => {
+            let (Some(block), Some(out)) = (args.get(2), args.get(3)) else {
+                eyre::bail!(
+                    "usage: light-client-poc evm-verifier <block> <out-file> [access-list-file]"
+                );
+            };
+            let access_list = args
+                .get(4)
+                .map(access_list::AccessListFile::load)
+                .transpose()?
+                .map(|file| file.access_list());
+            submit::generate_verifier(
+                &provider_url,
+                PVK,
+                block.parse::<u64>()?.into(),
+                access_list,
+                chain,
+                mpt_digest,
+                out,
+            )
+            .await
+        }
+        // Proves a block and posts the proof to a verifier contract, see
+        // `submit::prove_and_submit`. The transaction is signed with
+        // `SUBMITTER_KEY`.
+        Some("prove-and-submit") => {
+            let (Some(block), Some(verifier)) = (args.get(2), args.get(3)) else {
+                eyre::bail!(
+                    "usage: light-client-poc prove-and-submit <block> <verifier-address> [access-list-file]"
+                );
+            };
+            let access_list = args
+                .get(4)
+                .map(access_list::AccessListFile::load)
+                .transpose()?
+                .map(|file| file.access_list());
+            let submitter_key = std::env::var("SUBMITTER_KEY").map_err(|_| {
+                eyre::eyre!("SUBMITTER_KEY should be set with the key of the submitting account")
+            })?;
+            submit::prove_and_submit(
+                &provider_url,
+                &submitter_key,
+                block.parse::<u64>()?.into(),
+                access_list,
+                chain,
+                mpt_digest,
+                verifier.parse()?,
+                1,
+            )
+            .await
+        }
+        // Witness size and proving cost estimate, without proving
+        Some("--dry-run") => {
+            let block = args.get(2).ok_or_else(|| {
+                eyre::eyre!("usage: light-client-poc --dry-run <block> [access-list-file]")
+            })?;
+            let access_list = args
+                .get(3)
+                .map(access_list::AccessListFile::load)
+                .transpose()?;
+            let calibration = std::env::var("CALIBRATION_FILE").ok();
+            pipeline::dry_run(
+                &provider_url,
+                PVK,
+                block.parse::<u64>()?.into(),
+                access_list,
+                chain.as_ref(),
+                calibration.as_deref(),
+            )
+            .await
+        }
+        // Whether the execution of a block can be proven, see `opcodes::OpcodeReport`
+        Some("opcodes") => {
+            let block = args
+                .get(2)
+                .ok_or_else(|| eyre::eyre!("usage: light-client-poc opcodes <block>"))?;
+            let client = utils::new_eth_signer_client(&provider_url, PVK).await?;
+            let report =
+                opcodes::OpcodeReport::fetch(&client, block.parse::<u64>()?.into()).await?;
+            println!("{}", report);
+            Ok(())
+        }
+        // Proof size and verification cost, see `circuit::CircuitReport`
+        Some("report") => {
+            let degree = match args.get(2) {
+                Some(degree) => degree.parse()?,
+                None => circuit::DEFAULT_CIRCUIT_DEGREE,
+            };
+            let max_proof_count = match args.get(3) {
+                Some(count) => count.parse()?,
+                None => circuit::DEFAULT_MAX_PROOF_COUNT,
+            };
+            println!("{}", circuit::CircuitReport::new(degree, max_proof_count));
+            Ok(())
+        }
+        // Solidity library with the positions of the public inputs, see
+        // `circuit::InstanceLayout`
+        Some("instances") => {
+            let max_proof_count = match args.get(2) {
+                Some(count) => count.parse()?,
+                None => circuit::DEFAULT_MAX_PROOF_COUNT,
+            };
+            print!(
+                "{}",
+                circuit::InstanceLayout::new(max_proof_count, mpt_digest).solidity()
+            );
+            Ok(())
+        }
+        _ => server::serve().await,
+    }
 }
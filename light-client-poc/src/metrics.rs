@@ -0,0 +1,176 @@
+use axum::{extract::State, http::header, routing::get, Router};
+use eyre::Result;
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Upper bounds of the proving latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0, 7200.0,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket of `LATENCY_BUCKETS`, not cumulative, the last
+    /// one counts the observations above the last bound
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+/// Metrics of a continuously running prover, rendered in the Prometheus text
+/// format by `GET /metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    blocks_proven: AtomicU64,
+    proving_failures: AtomicU64,
+    rpc_errors: AtomicU64,
+    queue_depth: AtomicU64,
+    proving_latency: Mutex<Histogram>,
+}
+
+impl Metrics {
+    /// A block was proven and its proof verified in `latency`
+    pub fn block_proven(&self, latency: Duration) {
+        self.blocks_proven.fetch_add(1, Ordering::Relaxed);
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        let mut histogram = self.proving_latency.lock().unwrap();
+        histogram.counts[bucket] += 1;
+        histogram.sum += seconds;
+    }
+
+    pub fn proving_failed(&self) {
+        self.proving_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A request to the node failed
+    pub fn rpc_error(&self) {
+        self.rpc_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Blocks or jobs waiting to be proven
+    pub fn set_queue_depth(&self, depth: u64) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        };
+        metric(
+            "lc_blocks_proven_total",
+            "counter",
+            "Blocks with a verified state update proof",
+            self.blocks_proven.load(Ordering::Relaxed),
+        );
+        metric(
+            "lc_proving_failures_total",
+            "counter",
+            "Blocks that could not be proven",
+            self.proving_failures.load(Ordering::Relaxed),
+        );
+        metric(
+            "lc_rpc_errors_total",
+            "counter",
+            "Failed requests to the node",
+            self.rpc_errors.load(Ordering::Relaxed),
+        );
+        metric(
+            "lc_queue_depth",
+            "gauge",
+            "Blocks or jobs waiting to be proven",
+            self.queue_depth.load(Ordering::Relaxed),
+        );
+
+        let histogram = self.proving_latency.lock().unwrap();
+        let name = "lc_proving_seconds";
+        writeln!(
+            out,
+            "# HELP {} Time to prove and verify a block, witness generation included",
+            name
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        let mut count = 0;
+        for (bound, bucket_count) in LATENCY_BUCKETS.iter().zip(histogram.counts.iter()) {
+            count += bucket_count;
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count).unwrap();
+        }
+        count += histogram.counts[LATENCY_BUCKETS.len()];
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count).unwrap();
+        writeln!(out, "{}_sum {}", name, histogram.sum).unwrap();
+        writeln!(out, "{}_count {}", name, count).unwrap();
+        out
+    }
+}
+
+pub async fn handle(
+    State(metrics): State<Arc<Metrics>>,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+/// Serves `GET /metrics` on `addr`, for the modes without a REST service
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(handle))
+        .with_state(metrics);
+    println!("Metrics on {}", addr);
+    axum::Server::try_bind(&addr)?
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::default();
+        metrics.block_proven(Duration::from_secs(5));
+        metrics.block_proven(Duration::from_secs(45));
+        metrics.block_proven(Duration::from_secs(10_000));
+        metrics.rpc_error();
+        metrics.set_queue_depth(2);
+
+        let rendered = metrics.render();
+        for line in [
+            "lc_blocks_proven_total 3",
+            "lc_proving_failures_total 0",
+            "lc_rpc_errors_total 1",
+            "lc_queue_depth 2",
+            // Buckets are cumulative
+            "lc_proving_seconds_bucket{le=\"10\"} 1",
+            "lc_proving_seconds_bucket{le=\"30\"} 1",
+            "lc_proving_seconds_bucket{le=\"60\"} 2",
+            "lc_proving_seconds_bucket{le=\"7200\"} 2",
+            "lc_proving_seconds_bucket{le=\"+Inf\"} 3",
+            "lc_proving_seconds_sum 10050",
+            "lc_proving_seconds_count 3",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "missing `{}` in\n{}",
+                line,
+                rendered
+            );
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use eth_types::{evm_types::OpcodeId, H256, U64};
+use ethers::{
+    providers::Middleware,
+    types::{BlockId, BlockNumber, GethDebugTracingOptions, GethTrace, GethTraceFrame},
+};
+use eyre::{eyre, Result};
+use std::{collections::HashSet, str::FromStr};
+use zkevm_circuits::evm_circuit::unsupported_opcodes;
+
+use crate::utils::MM;
+
+/// Opcodes executed by the transactions of a block that the EVM circuit does
+/// not support. When the list is not empty only the state trie transition of
+/// the block can be proven, not its execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeReport {
+    pub block_no: U64,
+    pub unsupported: Vec<(OpcodeId, Vec<H256>)>,
+}
+
+impl OpcodeReport {
+    /// Traces all the transactions of `block_no`, needs the `debug` namespace
+    pub async fn fetch(client: &MM, block_no: U64) -> Result<Self> {
+        let block = client
+            .get_block(BlockId::Number(BlockNumber::Number(block_no)))
+            .await?
+            .ok_or_else(|| eyre!("block {} not found", block_no))?;
+        let mut traced = Vec::new();
+        for tx_hash in block.transactions {
+            let trace = client
+                .debug_trace_transaction(tx_hash, GethDebugTracingOptions::default())
+                .await?;
+            let GethTrace::Known(GethTraceFrame::Default(frame)) = trace else {
+                eyre::bail!("unexpected trace format for transaction {:?}", tx_hash);
+            };
+            let opcodes = frame
+                .struct_logs
+                .iter()
+                // Failing steps are proven by the error states of the circuit
+                .filter(|log| log.error.is_none())
+                .map(|log| {
+                    OpcodeId::from_str(&log.op)
+                        .map_err(|_| eyre!("unknown opcode {} in {:?}", log.op, tx_hash))
+                })
+                .collect::<Result<HashSet<_>>>()?;
+            traced.push((tx_hash, opcodes));
+        }
+        Ok(Self::new(block_no, traced))
+    }
+
+    fn new(block_no: U64, traced: Vec<(H256, HashSet<OpcodeId>)>) -> Self {
+        let unsupported = unsupported_opcodes(traced.iter().flat_map(|(_, ops)| ops.clone()))
+            .into_iter()
+            .map(|op| {
+                let txs = traced
+                    .iter()
+                    .filter(|(_, ops)| ops.contains(&op))
+                    .map(|(tx_hash, _)| *tx_hash)
+                    .collect();
+                (op, txs)
+            })
+            .collect();
+        Self {
+            block_no,
+            unsupported,
+        }
+    }
+
+    /// True if the EVM circuit can prove every transaction of the block
+    pub fn is_supported(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+}
+
+impl std::fmt::Display for OpcodeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_supported() {
+            return write!(f, "block {}: all opcodes are supported", self.block_no);
+        }
+        write!(
+            f,
+            "block {}: only the state trie transition can be proven, unsupported opcodes:",
+            self.block_no
+        )?;
+        for (op, txs) in self.unsupported.iter() {
+            write!(f, "\n  {:?} in {} transaction(s)", op, txs.len())?;
+            for tx_hash in txs {
+                write!(f, "\n    {:?}", tx_hash)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_unsupported_opcodes() {
+        let tx1 = H256::from_low_u64_be(1);
+        let tx2 = H256::from_low_u64_be(2);
+        let report = OpcodeReport::new(
+            1.into(),
+            vec![
+                (tx1, HashSet::from([OpcodeId::ADD, OpcodeId::SELFDESTRUCT])),
+                (tx2, HashSet::from([OpcodeId::SELFDESTRUCT, OpcodeId::STOP])),
+            ],
+        );
+        assert!(!report.is_supported());
+        assert_eq!(
+            report.unsupported,
+            vec![(OpcodeId::SELFDESTRUCT, vec![tx1, tx2])]
+        );
+
+        let report = OpcodeReport::new(1.into(), vec![(tx1, HashSet::from([OpcodeId::ADD]))]);
+        assert!(report.is_supported());
+    }
+}
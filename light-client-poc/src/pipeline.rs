@@ -0,0 +1,252 @@
+use eth_types::U64;
+use ethers::{
+    providers::Middleware,
+    types::{BlockId, BlockNumber},
+};
+use eyre::{bail, eyre, Result};
+use halo2_proofs::halo2curves::bn256::Fr;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use zkevm_circuits::mpt_circuit::witness_row::Node;
+
+use crate::{
+    access_list::{AccessListFile, AccessListSource, MergedAccessList},
+    circuit::{
+        fetch_state_diff, peak_memory_bytes, Calibration, CalibrationPoint, ChainConfig,
+        ProofBundle, StateUpdateCircuit, StateUpdateCircuitKeys, StateUpdateWitness, Transforms,
+        WitnessStats, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT,
+    },
+    utils::new_eth_signer_client,
+};
+
+// The proving pipeline split in stages that can run on different machines,
+// each stage reads the output file of the previous one:
+//
+//   fetch   (node)    block number -> transforms (JSON)
+//   witness (node)    transforms   -> MPT witness (JSON)
+//   prove   (prover)  MPT witness  -> proof bundle
+//
+// The circuit assignment is not serialized, it is cheap to redo from the MPT
+// witness on the prover.
+//
+// `merge_access_lists` assembles the access list given to `fetch` when the
+// node cannot trace the block.
+//
+// `dry_run` runs the first two stages and estimates the cost of the last one
+// from the proving times recorded by `prove` in `CALIBRATION_FILE`.
+
+/// Serialized output of the witness stage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WitnessFile {
+    pub transforms: Transforms,
+    pub mpt_witness: Vec<Node>,
+}
+
+fn write_json<T: Serialize>(path: &str, value: &T) -> Result<()> {
+    std::fs::write(path, serde_json::to_vec(value)?)?;
+    Ok(())
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &str) -> Result<T> {
+    Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+/// Fetches the state changes of `block_no` from the node, the accounts and
+/// slots are taken from `access_list` when given instead of asking the node
+pub async fn fetch(
+    provider_url: &str,
+    pvk: &str,
+    block_no: U64,
+    access_list: Option<AccessListFile>,
+    chain: Option<&ChainConfig>,
+    out: &str,
+) -> Result<()> {
+    let access_list = match access_list {
+        Some(file) if file.block_no() != block_no => {
+            bail!(
+                "the access list is for block {}, not {}",
+                file.block,
+                block_no
+            )
+        }
+        Some(file) => Some(file.access_list()),
+        None => None,
+    };
+    let client = new_eth_signer_client(provider_url, pvk).await?;
+    let transforms =
+        StateUpdateWitness::<Fr>::get_transforms(client, block_no, access_list, chain).await?;
+    write_json(out, &transforms)?;
+    println!(
+        "Block {}: {} trie modifications written to {}",
+        block_no,
+        transforms.trie_modifications.len(),
+        out
+    );
+    Ok(())
+}
+
+/// Merges the access lists of `files`, of the typed transactions of `block_no`
+/// and of the node traces when the node can trace the block, prints the sources
+/// of every entry and writes the merged list to `out` for `fetch`
+pub async fn merge_access_lists(
+    provider_url: &str,
+    pvk: &str,
+    block_no: U64,
+    files: Vec<AccessListFile>,
+    out: &str,
+) -> Result<()> {
+    let client = new_eth_signer_client(provider_url, pvk).await?;
+    let mut merged = MergedAccessList::default();
+    for file in files.iter() {
+        if file.block_no() != block_no {
+            bail!(
+                "an access list is for block {}, not {}",
+                file.block,
+                block_no
+            )
+        }
+        merged.add(AccessListSource::User, &file.access_list());
+    }
+    let block = client
+        .get_block_with_txs(BlockId::Number(BlockNumber::Number(block_no)))
+        .await?
+        .ok_or_else(|| eyre!("block {} not found", block_no))?;
+    merged.add_transactions(&block);
+    match fetch_state_diff(&client, block_no).await {
+        Ok(state_diff) => merged.add(AccessListSource::Trace, &state_diff.access_list()),
+        Err(err) => println!("no trace, the access list may be incomplete: {}", err),
+    }
+
+    print!("{}", merged);
+    let not_in_trace = merged.not_in_trace();
+    if !not_in_trace.is_empty() {
+        println!(
+            "{} entries are not in the trace, they are proven unchanged",
+            not_in_trace.len()
+        );
+    }
+    merged.to_file(block_no.as_u64()).save(out)?;
+    println!("Block {}: merged access list written to {}", block_no, out);
+    Ok(())
+}
+
+/// Generates the MPT witness of the transforms in `transforms_path`, the
+/// witness generator reads the proofs from the node
+pub fn witness(provider_url: &str, transforms_path: &str, out: &str) -> Result<()> {
+    let transforms: Transforms = read_json(transforms_path)?;
+    let block_no = transforms.block_no;
+    let witness = StateUpdateWitness::<Fr>::from_transforms(transforms, provider_url)?
+        .ok_or_else(|| eyre!("block {} does not change the state", block_no))?;
+    println!("{}", WitnessStats::from(witness.mpt_witness.as_slice()));
+    write_json(
+        out,
+        &WitnessFile {
+            transforms: witness.transforms,
+            mpt_witness: witness.mpt_witness,
+        },
+    )?;
+    println!("Block {}: witness written to {}", block_no, out);
+    Ok(())
+}
+
+/// Proves the witness in `witness_path`, no access to a node is needed. The
+/// proving time and memory are recorded in `calibration_path` when given.
+/// With `mpt_digest` the proof exposes the digest of the changes instead of
+/// the changes, see `StateUpdateCircuitParams::mpt_digest`.
+pub fn prove(
+    witness_path: &str,
+    out: &str,
+    calibration_path: Option<&str>,
+    mpt_digest: bool,
+) -> Result<()> {
+    let WitnessFile {
+        transforms,
+        mpt_witness,
+    } = read_json(witness_path)?;
+    let block_no = transforms.block_no;
+    let witness = StateUpdateWitness::<Fr>::from_parts(transforms, mpt_witness)?;
+
+    let mut circuit =
+        StateUpdateCircuit::new(witness, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT)?;
+    if mpt_digest {
+        circuit = circuit.with_mpt_digest();
+    }
+    let public_inputs = circuit.public_inputs();
+    let start = Instant::now();
+    let keys: StateUpdateCircuitKeys = StateUpdateCircuitKeys::new(&circuit);
+
+    // Proving consumes the circuit
+    let mut bundle = ProofBundle::new(Vec::new(), &public_inputs, &circuit, &keys);
+    bundle.proof = circuit.prove(&keys)?;
+    let prove_secs = start.elapsed().as_secs_f64();
+    StateUpdateCircuit::verify(&bundle.proof, &bundle.instances, &keys)?;
+
+    if let (Some(path), Some(peak_memory_bytes)) = (calibration_path, peak_memory_bytes()) {
+        let mut calibration = Calibration::load(path)?;
+        calibration.record(CalibrationPoint {
+            degree: DEFAULT_CIRCUIT_DEGREE,
+            prove_secs,
+            peak_memory_bytes,
+        });
+        calibration.save(path)?;
+    }
+    std::fs::write(out, bundle.to_bytes()?)?;
+
+    println!("Block {}: proof bundle written to {}", block_no, out);
+    Ok(())
+}
+
+/// Generates the witness of `block_no` and reports its size and the estimated
+/// cost of proving it, without proving
+pub async fn dry_run(
+    provider_url: &str,
+    pvk: &str,
+    block_no: U64,
+    access_list: Option<AccessListFile>,
+    chain: Option<&ChainConfig>,
+    calibration_path: Option<&str>,
+) -> Result<()> {
+    let access_list = match access_list {
+        Some(file) if file.block_no() != block_no => {
+            bail!(
+                "the access list is for block {}, not {}",
+                file.block,
+                block_no
+            )
+        }
+        Some(file) => Some(file.access_list()),
+        None => None,
+    };
+    let client = new_eth_signer_client(provider_url, pvk).await?;
+    let witness =
+        StateUpdateWitness::<Fr>::build(client, provider_url, block_no, access_list, chain)
+            .await?
+            .ok_or_else(|| eyre!("block {} does not change the state", block_no))?;
+    let stats = WitnessStats::from(witness.mpt_witness.as_slice());
+    println!("{}", stats);
+
+    let num_proofs = witness.transforms.trie_modifications.len();
+    println!(
+        "trie modifications: {} (at most {})",
+        num_proofs, DEFAULT_MAX_PROOF_COUNT
+    );
+    let degree = stats.min_degree().max(DEFAULT_CIRCUIT_DEGREE);
+    if stats.min_degree() > DEFAULT_CIRCUIT_DEGREE || num_proofs > DEFAULT_MAX_PROOF_COUNT {
+        println!(
+            "the block does not fit the circuit (k={}, {} trie modifications)",
+            DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT
+        );
+    }
+
+    let calibration = match calibration_path {
+        Some(path) => Calibration::load(path)?,
+        None => Calibration::default(),
+    };
+    match calibration.estimate(degree) {
+        Some(estimate) => println!("{}", estimate),
+        None => {
+            println!("no calibration data, set CALIBRATION_FILE and run `prove` to record some")
+        }
+    }
+    Ok(())
+}
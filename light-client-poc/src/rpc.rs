@@ -0,0 +1,441 @@
+use axum::{extract::State, Json};
+use eth_types::{Address, H256, U256, U64};
+use ethers::{
+    types::{
+        transaction::eip2930::{AccessList, AccessListItem},
+        EIP1186ProofResponse,
+    },
+    utils::rlp::Rlp,
+};
+use mpt_witness_generator::ProofType;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use crate::{bridge::verify_mpt_proof, circuit::Transforms};
+
+// Standard JSON-RPC error codes
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+// Server error, the value is not covered by any verified proof
+const NOT_PROVEN: i64 = -32000;
+
+/// Read of a state value that is not covered by any verified proof. Serving it
+/// requires a proof of the account, and of the storage slot when `slot` is set,
+/// see `VerifiedState::apply_account_proof`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotProven {
+    pub address: Address,
+    pub slot: Option<H256>,
+}
+
+impl NotProven {
+    /// Accounts and slots to request with `eth_getProof` to cover the read
+    pub fn access_list(&self) -> AccessList {
+        AccessList(vec![AccessListItem {
+            address: self.address,
+            storage_keys: self.slot.into_iter().collect(),
+        }])
+    }
+}
+
+impl fmt::Display for NotProven {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.slot {
+            Some(slot) => write!(
+                f,
+                "storage slot {:?} of {:?} is not covered by a verified proof, \
+                 a proof of the account and of the slot is required",
+                slot, self.address
+            ),
+            None => write!(
+                f,
+                "account {:?} is not covered by a verified proof, a proof of the account is required",
+                self.address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NotProven {}
+
+/// State values proven by verified state update proofs. The proofs are
+/// applied as a chain, each one starting from the state root the previous one
+/// ends with, so a value proven at an earlier block and not modified since is
+/// still the value at `latest_block`.
+#[derive(Default, Debug, Clone)]
+pub struct VerifiedState {
+    pub latest_block: U64,
+    /// State root of `latest_block`, proven by its state update proof
+    pub latest_root: H256,
+    balances: HashMap<Address, U256>,
+    nonces: HashMap<Address, U64>,
+    storage: HashMap<(Address, H256), U256>,
+}
+
+impl VerifiedState {
+    /// Applies the modifications of a verified state update proof. The proof
+    /// has to start from `latest_root`, blocks without changes keep the state
+    /// root so they can be skipped, but the values of a state with a gap in
+    /// the chain could be stale, so such a proof is rejected.
+    pub fn apply(&mut self, transforms: &Transforms) -> eyre::Result<()> {
+        if !self.latest_root.is_zero() {
+            eyre::ensure!(
+                transforms.block_no > self.latest_block
+                    && transforms.prev_state_root == self.latest_root,
+                "the proof of block {} does not start from the state of the latest verified \
+                 block {}",
+                transforms.block_no,
+                self.latest_block
+            );
+        }
+        for m in transforms.trie_modifications.iter() {
+            match m.typ {
+                ProofType::BalanceChanged => {
+                    self.balances.insert(m.address, m.balance);
+                }
+                ProofType::NonceChanged => {
+                    self.nonces.insert(m.address, m.nonce);
+                }
+                ProofType::StorageChanged => {
+                    self.storage.insert((m.address, m.key), m.value);
+                }
+                _ => {}
+            }
+        }
+        self.latest_block = transforms.block_no;
+        self.latest_root = transforms.curr_state_root;
+        Ok(())
+    }
+
+    /// Verifies `proof`, an `eth_getProof` response for `latest_block`, against
+    /// the latest verified state root and adds the values it proves. This
+    /// covers values that no block modified since the first verified proof.
+    pub fn apply_account_proof(&mut self, proof: &EIP1186ProofResponse) -> eyre::Result<()> {
+        eyre::ensure!(
+            !self.latest_root.is_zero(),
+            "no verified state root to check the proof of {:?} against",
+            proof.address
+        );
+        let account = verify_mpt_proof(
+            self.latest_root,
+            proof.address.as_bytes(),
+            &proof.account_proof,
+        )?;
+        // Non existing accounts are empty
+        let (nonce, balance, storage_root) = match &account {
+            Some(account) => {
+                let rlp = Rlp::new(account);
+                (
+                    U64::from_big_endian(rlp.at(0)?.data()?),
+                    U256::from_big_endian(rlp.at(1)?.data()?),
+                    Some(H256::from_slice(rlp.at(2)?.data()?)),
+                )
+            }
+            None => (U64::zero(), U256::zero(), None),
+        };
+        let mut slots = Vec::new();
+        for storage_proof in proof.storage_proof.iter() {
+            let value = match storage_root {
+                Some(storage_root) => verify_mpt_proof(
+                    storage_root,
+                    storage_proof.key.as_bytes(),
+                    &storage_proof.proof,
+                )?
+                .map(|value| -> eyre::Result<_> {
+                    Ok(U256::from_big_endian(Rlp::new(&value).data()?))
+                })
+                .transpose()?
+                .unwrap_or_default(),
+                None => U256::zero(),
+            };
+            slots.push((storage_proof.key, value));
+        }
+
+        // Only applied once the whole proof is verified
+        self.nonces.insert(proof.address, nonce);
+        self.balances.insert(proof.address, balance);
+        for (slot, value) in slots {
+            self.storage.insert((proof.address, slot), value);
+        }
+        Ok(())
+    }
+
+    pub fn balance(&self, address: &Address) -> Result<U256, NotProven> {
+        self.balances.get(address).copied().ok_or(NotProven {
+            address: *address,
+            slot: None,
+        })
+    }
+
+    pub fn nonce(&self, address: &Address) -> Result<U64, NotProven> {
+        self.nonces.get(address).copied().ok_or(NotProven {
+            address: *address,
+            slot: None,
+        })
+    }
+
+    pub fn storage(&self, address: &Address, key: &H256) -> Result<U256, NotProven> {
+        self.storage
+            .get(&(*address, *key))
+            .copied()
+            .ok_or(NotProven {
+                address: *address,
+                slot: Some(*key),
+            })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RpcRequest {
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcResponse {
+    pub jsonrpc: &'static str,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+type RpcResult = std::result::Result<Value, (i64, String)>;
+
+fn param<T: serde::de::DeserializeOwned>(params: &[Value], idx: usize) -> Result<T, (i64, String)> {
+    let value = params
+        .get(idx)
+        .ok_or((INVALID_PARAMS, format!("missing parameter {}", idx)))?;
+    serde_json::from_value(value.clone())
+        .map_err(|e| (INVALID_PARAMS, format!("invalid parameter {}: {}", idx, e)))
+}
+
+// Only the latest verified state is kept, so only `latest` (or the number of
+// the latest verified block) can be queried.
+fn check_block(state: &VerifiedState, params: &[Value], idx: usize) -> Result<(), (i64, String)> {
+    let Some(block) = params.get(idx) else {
+        return Ok(());
+    };
+    let block = match block.as_str() {
+        Some("latest") => return Ok(()),
+        Some(number) => U64::from_str_radix(number.trim_start_matches("0x"), 16).ok(),
+        None => None,
+    };
+    if block == Some(state.latest_block) {
+        Ok(())
+    } else {
+        Err((
+            NOT_PROVEN,
+            format!(
+                "only the latest verified block ({}) can be queried",
+                state.latest_block
+            ),
+        ))
+    }
+}
+
+fn not_proven(missing: NotProven) -> (i64, String) {
+    (NOT_PROVEN, missing.to_string())
+}
+
+fn dispatch(state: &VerifiedState, request: &RpcRequest) -> RpcResult {
+    let params = &request.params;
+    match request.method.as_str() {
+        "eth_blockNumber" => Ok(json!(state.latest_block)),
+        "eth_getBalance" => {
+            let address: Address = param(params, 0)?;
+            check_block(state, params, 1)?;
+            state
+                .balance(&address)
+                .map(|v| json!(v))
+                .map_err(not_proven)
+        }
+        "eth_getTransactionCount" => {
+            let address: Address = param(params, 0)?;
+            check_block(state, params, 1)?;
+            state.nonce(&address).map(|v| json!(v)).map_err(not_proven)
+        }
+        "eth_getStorageAt" => {
+            let address: Address = param(params, 0)?;
+            let key: H256 = param(params, 1)?;
+            check_block(state, params, 2)?;
+            state
+                .storage(&address, &key)
+                .map(|v| {
+                    let mut bytes = [0u8; 32];
+                    v.to_big_endian(&mut bytes);
+                    json!(H256::from(bytes))
+                })
+                .map_err(not_proven)
+        }
+        method => Err((METHOD_NOT_FOUND, format!("method {} not supported", method))),
+    }
+}
+
+/// JSON-RPC endpoint answering a subset of the Ethereum JSON-RPC API
+/// (`eth_getBalance`, `eth_getStorageAt`, `eth_getTransactionCount`,
+/// `eth_blockNumber`) from the verified state only.
+pub async fn handle(
+    State(state): State<Arc<Mutex<VerifiedState>>>,
+    Json(request): Json<RpcRequest>,
+) -> Json<RpcResponse> {
+    let result = dispatch(&state.lock().unwrap(), &request);
+    let (result, error) = match result {
+        Ok(result) => (Some(result), None),
+        Err((code, message)) => (None, Some(json!({ "code": code, "message": message }))),
+    };
+    Json(RpcResponse {
+        jsonrpc: "2.0",
+        id: request.id,
+        result,
+        error,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::circuit::TrieModificationBuilder;
+    use ethers::utils::{keccak256, rlp::RlpStream};
+    use mpt_witness_generator::TrieModification;
+
+    fn transforms(block: u64, prev_root: u8, curr_root: u8) -> Transforms {
+        Transforms {
+            block_no: block.into(),
+            prev_state_root: H256::repeat_byte(prev_root),
+            curr_state_root: H256::repeat_byte(curr_root),
+            ..Default::default()
+        }
+    }
+
+    /// `eth_getProof` response of `address` in a state trie holding only this
+    /// account, with the root of the trie
+    pub(crate) fn single_account_proof(
+        address: Address,
+        nonce: u64,
+        balance: u64,
+    ) -> (H256, EIP1186ProofResponse) {
+        let empty_root = H256(keccak256([0x80]));
+        let empty_code_hash = H256(keccak256([]));
+        let mut account = RlpStream::new_list(4);
+        account
+            .append(&nonce)
+            .append(&U256::from(balance))
+            .append(&empty_root)
+            .append(&empty_code_hash);
+        // The leaf is the root, its path is the whole hashed address
+        let path = [vec![0x20], keccak256(address.as_bytes()).to_vec()].concat();
+        let mut leaf = RlpStream::new_list(2);
+        leaf.append(&path).append(&account.out().to_vec());
+        let leaf = leaf.out().to_vec();
+
+        let proof = EIP1186ProofResponse {
+            address,
+            balance: balance.into(),
+            code_hash: empty_code_hash,
+            nonce: nonce.into(),
+            storage_hash: empty_root,
+            account_proof: vec![leaf.clone().into()],
+            storage_proof: vec![],
+        };
+        (H256(keccak256(leaf)), proof)
+    }
+
+    #[test]
+    fn reads_not_proven() {
+        let address = Address::repeat_byte(0x11);
+        let slot = H256::repeat_byte(0x22);
+        let state = VerifiedState::default();
+        assert_eq!(
+            state.balance(&address),
+            Err(NotProven {
+                address,
+                slot: None
+            })
+        );
+        assert_eq!(
+            state.storage(&address, &slot),
+            Err(NotProven {
+                address,
+                slot: Some(slot)
+            })
+        );
+        assert_eq!(
+            NotProven {
+                address,
+                slot: Some(slot)
+            }
+            .access_list(),
+            AccessList(vec![AccessListItem {
+                address,
+                storage_keys: vec![slot],
+            }])
+        );
+
+        let request = RpcRequest {
+            id: json!(1),
+            method: "eth_getBalance".to_string(),
+            params: vec![json!(address), json!("latest")],
+        };
+        let (code, _) = dispatch(&state, &request).unwrap_err();
+        assert_eq!(code, NOT_PROVEN);
+    }
+
+    #[test]
+    fn account_proof_is_verified() {
+        let address = Address::repeat_byte(0x11);
+        let (root, proof) = single_account_proof(address, 2, 1000);
+        let verified_state = |root: H256| {
+            let mut state = VerifiedState::default();
+            state.apply(&transforms(10, 1, 2)).unwrap();
+            state.latest_root = root;
+            state
+        };
+
+        let mut state = verified_state(root);
+        state.apply_account_proof(&proof).unwrap();
+        assert_eq!(state.balance(&address), Ok(1000.into()));
+        assert_eq!(state.nonce(&address), Ok(2.into()));
+
+        // The proof is not for the verified state root
+        let mut state = verified_state(H256::repeat_byte(3));
+        assert!(state.apply_account_proof(&proof).is_err());
+        assert!(state.balance(&address).is_err());
+
+        // The node lies about the balance
+        let (_, other) = single_account_proof(address, 2, 2000);
+        let mut state = verified_state(root);
+        assert!(state.apply_account_proof(&other).is_err());
+        assert!(state.balance(&address).is_err());
+    }
+
+    #[test]
+    fn proofs_are_chained() {
+        let address = Address::repeat_byte(0x11);
+        let slot = H256::repeat_byte(0x22);
+        let mut state = VerifiedState::default();
+        let mut first = transforms(10, 1, 2);
+        first.trie_modifications = vec![TrieModification::storage(address, slot, 7.into())];
+        state.apply(&first).unwrap();
+
+        // A block skipped without changes keeps the state root
+        state.apply(&transforms(12, 2, 3)).unwrap();
+        assert_eq!(state.storage(&address, &slot), Ok(7.into()));
+
+        // The slot could have changed in the blocks between the proofs
+        assert!(state.apply(&transforms(14, 4, 5)).is_err());
+        // Or the proof is older than the state
+        assert!(state.apply(&transforms(11, 3, 4)).is_err());
+        assert_eq!(state.latest_block, U64::from(12));
+        assert_eq!(state.latest_root, H256::repeat_byte(3));
+    }
+}
@@ -18,7 +18,7 @@ pub async fn serve() -> Result<()> {
 
     let client = crate::utils::new_eth_signer_client(PROVIDER_URL, PVK).await?;
 
-    let mut keys = None;
+    let mut keys: Option<StateUpdateCircuitKeys> = None;
 
     let mut storage = HashMap::new();
     let mut last_processed_block = U64::from(1);
@@ -45,7 +45,7 @@ pub async fn serve() -> Result<()> {
             continue;
         }
 
-        println!("Processing block {}", last_processed_block);
+        tracing::info!(block = %last_processed_block, "processing block");
 
         last_processed_block = last_processed_block + 1;
 
@@ -54,6 +54,7 @@ pub async fn serve() -> Result<()> {
             PROVIDER_URL,
             last_processed_block,
             None,
+            None,
         )
         .await?;
 
@@ -65,7 +66,7 @@ pub async fn serve() -> Result<()> {
         let circuit =
             StateUpdateCircuit::new(witness, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT)?;
 
-        println!("trns: {:#?}", circuit.transforms);
+        tracing::debug!(transforms = ?circuit.transforms, "block transforms");
 
         circuit.assert_satisfied();
 
@@ -0,0 +1,292 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use eth_types::U64;
+use ethers::types::transaction::eip2930::AccessList;
+use eyre::Result;
+use halo2_proofs::halo2curves::bn256::Fr;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    circuit::{
+        ChainConfig, PublicInputs, StateUpdateCircuit, StateUpdateCircuitKeys, StateUpdateWitness,
+        Transforms, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT,
+    },
+    metrics::{self, Metrics},
+    rpc::{self, VerifiedState},
+    utils::MM,
+};
+
+const JOB_QUEUE_SIZE: usize = 64;
+/// Number of finished jobs whose status is kept, the oldest ones are evicted
+const MAX_FINISHED_JOBS: usize = 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProveRequest {
+    pub block: u64,
+    pub access_list: Option<AccessList>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProveResponse {
+    pub id: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done {
+        block: u64,
+        proof: String,
+        public_inputs: Vec<String>,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Done { .. } | JobStatus::Failed { .. })
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    jobs: Arc<Mutex<HashMap<u64, JobStatus>>>,
+    next_id: Arc<Mutex<u64>>,
+    queue: mpsc::Sender<(u64, ProveRequest)>,
+    verified_state: Arc<Mutex<VerifiedState>>,
+    metrics: Arc<Metrics>,
+}
+
+impl AppState {
+    fn set_status(&self, id: u64, status: JobStatus) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if status.is_finished() {
+            let mut finished: Vec<u64> = jobs
+                .iter()
+                .filter(|(_, status)| status.is_finished())
+                .map(|(id, _)| *id)
+                .collect();
+            // Ids increase with the submission, the oldest jobs go first
+            if finished.len() >= MAX_FINISHED_JOBS {
+                finished.sort_unstable();
+                for id in &finished[..=finished.len() - MAX_FINISHED_JOBS] {
+                    jobs.remove(id);
+                }
+            }
+        }
+        jobs.insert(id, status);
+        let queued = jobs
+            .values()
+            .filter(|status| matches!(status, JobStatus::Queued))
+            .count();
+        self.metrics.set_queue_depth(queued as u64);
+    }
+}
+
+/// Runs the prover as a REST service.
+///   `POST /prove { block, access_list }` queues a proving job and returns its id
+///   `GET /proof/{id}` returns the status of the job, and the proof when done,
+///   for the last `MAX_FINISHED_JOBS` finished jobs
+///   `POST /rpc` answers Ethereum JSON-RPC state queries from the proven state
+///   `GET /metrics` returns the metrics of the prover in the Prometheus format
+/// Jobs are processed one at a time in the order they were submitted, with the
+/// state rules of `chain`, or of the chain served by the node when no chain is
+/// selected.
+pub async fn serve(
+    addr: SocketAddr,
+    provider_url: &str,
+    pvk: &str,
+    chain: Option<&ChainConfig>,
+) -> Result<()> {
+    let client = crate::utils::new_eth_signer_client(provider_url, pvk).await?;
+
+    let (queue, jobs_rx) = mpsc::channel(JOB_QUEUE_SIZE);
+    let state = AppState {
+        jobs: Arc::new(Mutex::new(HashMap::new())),
+        next_id: Arc::new(Mutex::new(0)),
+        queue,
+        verified_state: Arc::new(Mutex::new(VerifiedState::default())),
+        metrics: Arc::new(Metrics::default()),
+    };
+
+    tokio::spawn(worker(
+        state.clone(),
+        jobs_rx,
+        client,
+        provider_url.to_string(),
+        chain.cloned(),
+    ));
+
+    let app = Router::new()
+        .route("/prove", post(submit_job))
+        .route("/proof/:id", get(job_status))
+        .with_state(state.clone())
+        .route("/rpc", post(rpc::handle))
+        .with_state(state.verified_state)
+        .route("/metrics", get(metrics::handle))
+        .with_state(state.metrics);
+
+    println!("Listening on {}", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+
+    Ok(())
+}
+
+async fn submit_job(
+    State(state): State<AppState>,
+    Json(request): Json<ProveRequest>,
+) -> Result<(StatusCode, Json<ProveResponse>), (StatusCode, String)> {
+    if request.block == 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the genesis block cannot be proven".to_string(),
+        ));
+    }
+
+    let id = {
+        let mut next_id = state.next_id.lock().unwrap();
+        *next_id += 1;
+        *next_id
+    };
+    state.set_status(id, JobStatus::Queued);
+
+    if state.queue.try_send((id, request)).is_err() {
+        state.jobs.lock().unwrap().remove(&id);
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "the job queue is full".to_string(),
+        ));
+    }
+
+    Ok((StatusCode::ACCEPTED, Json(ProveResponse { id })))
+}
+
+async fn job_status(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<JobStatus>, StatusCode> {
+    state
+        .jobs
+        .lock()
+        .unwrap()
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn worker(
+    state: AppState,
+    mut jobs: mpsc::Receiver<(u64, ProveRequest)>,
+    client: Arc<MM>,
+    provider_url: String,
+    chain: Option<ChainConfig>,
+) {
+    let mut keys = None;
+    while let Some((id, request)) = jobs.recv().await {
+        state.set_status(id, JobStatus::Running);
+        let start = Instant::now();
+        let result = prove(
+            client.clone(),
+            &provider_url,
+            &request,
+            chain.as_ref(),
+            &mut keys,
+            &state.metrics,
+        )
+        .await;
+        let status = match result {
+            Ok((status, transforms)) => {
+                state.metrics.block_proven(start.elapsed());
+                let mut verified_state = state.verified_state.lock().unwrap();
+                if let Err(err) = verified_state.apply(&transforms) {
+                    // The values of the previous blocks are not carried over
+                    // a gap, the state starts over from this block
+                    tracing::warn!(%err, "restarting the verified state");
+                    *verified_state = VerifiedState::default();
+                    verified_state
+                        .apply(&transforms)
+                        .expect("an empty state accepts any proof");
+                }
+                status
+            }
+            Err(err) => {
+                state.metrics.proving_failed();
+                JobStatus::Failed {
+                    error: err.to_string(),
+                }
+            }
+        };
+        state.set_status(id, status);
+    }
+}
+
+async fn prove(
+    client: Arc<MM>,
+    provider_url: &str,
+    request: &ProveRequest,
+    chain: Option<&ChainConfig>,
+    keys: &mut Option<StateUpdateCircuitKeys>,
+    metrics: &Metrics,
+) -> Result<(JobStatus, Transforms)> {
+    let witness = StateUpdateWitness::<Fr>::build(
+        client,
+        provider_url,
+        U64::from(request.block),
+        request.access_list.clone(),
+        chain,
+    )
+    .await
+    .map_err(|err| {
+        // The witness is built from the data of the node
+        metrics.rpc_error();
+        err
+    })?;
+
+    let Some(witness) = witness else {
+        eyre::bail!("block {} does not change the state root", request.block);
+    };
+
+    let public_inputs: PublicInputs<Fr> = (&witness.lc_witness).into();
+    let circuit =
+        StateUpdateCircuit::new(witness, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT)?;
+    let transforms = circuit.transforms.clone();
+
+    // Proving is CPU bound, keep it off the async runtime
+    let cached_keys = keys.take();
+    let (proof, public_inputs, new_keys) = tokio::task::spawn_blocking(move || -> Result<_> {
+        let keys = cached_keys.unwrap_or_else(|| StateUpdateCircuitKeys::new(&circuit));
+        let proof = circuit.prove(&keys)?;
+        StateUpdateCircuit::verify(&proof, &public_inputs, &keys)?;
+        Ok((proof, public_inputs, keys))
+    })
+    .await??;
+    *keys = Some(new_keys);
+
+    let status = JobStatus::Done {
+        block: request.block,
+        proof: hex::encode(proof),
+        public_inputs: public_inputs
+            .iter()
+            .map(|input| format!("{:?}", input))
+            .collect(),
+    };
+    Ok((status, transforms))
+}
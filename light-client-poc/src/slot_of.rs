@@ -0,0 +1,102 @@
+//! Storage slots of Solidity state variables, following the storage layout
+//! rules of the compiler, to build access lists and slot proofs for arbitrary
+//! contracts.
+//!
+//! The slot of a state variable is its index in the declaration order (see
+//! `slot`), and composite variables are reached from the slot of the variable:
+//!
+//! ```ignore
+//! // mapping(address => mapping(address => uint256)) allowance; at slot 1
+//! let slot = mapping(mapping(slot(1), address_key(owner)), address_key(spender));
+//! // Point[] points; at slot 5, with struct Point { uint256 x; uint256 y; }
+//! let y = field(array_element(slot(5), index.into(), 2.into()), 1.into());
+//! ```
+//!
+//! Values smaller than 32 bytes are packed in a slot with the values declared
+//! after them as long as they fit in the bytes left in the slot, starting from
+//! the lowest-order bytes. Structs and arrays always start a new slot. The
+//! slot holding packed values is the slot of the first value of the group.
+
+use eth_types::{Address, H256, U256};
+use ethers::utils::keccak256;
+
+/// The slot with index `index`
+pub fn slot(index: u64) -> H256 {
+    uint_key(index.into())
+}
+
+/// Mapping key of an address
+pub fn address_key(address: Address) -> H256 {
+    H256::from(address)
+}
+
+/// Mapping key of an unsigned integer
+pub fn uint_key(value: U256) -> H256 {
+    let mut key = H256::zero();
+    value.to_big_endian(key.as_bytes_mut());
+    key
+}
+
+/// Slot of `mapping[key]` of the mapping at `slot`, `key` is the key padded to
+/// a word as returned by `address_key` and `uint_key`. The slot of a nested
+/// mapping is the slot of the value of the outer mapping.
+pub fn mapping(slot: H256, key: H256) -> H256 {
+    mapping_bytes(slot, key.as_bytes())
+}
+
+/// Slot of `mapping[key]` of the mapping at `slot` with `string` or `bytes`
+/// keys, which are not padded
+pub fn mapping_bytes(slot: H256, key: &[u8]) -> H256 {
+    H256(keccak256([key, slot.as_bytes()].concat()))
+}
+
+/// Slot of the element `index` of the dynamic array at `slot`, the length of
+/// the array is at `slot` itself. Every element takes `element_slots` slots,
+/// one for the value types and the number of slots of the struct for structs.
+pub fn array_element(slot: H256, index: U256, element_slots: U256) -> H256 {
+    let data = U256::from_big_endian(&keccak256(slot));
+    let (offset, _) = index.overflowing_mul(element_slots);
+    let (element, _) = data.overflowing_add(offset);
+    uint_key(element)
+}
+
+/// Slot of the field `offset` slots after the start of the struct at `slot`,
+/// also the slot of the element `offset` of a fixed-size array of value types
+pub fn field(slot: H256, offset: U256) -> H256 {
+    let (field, _) = U256::from_big_endian(slot.as_bytes()).overflowing_add(offset);
+    uint_key(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(hex: &str) -> H256 {
+        H256::from_slice(&hex::decode(hex).unwrap())
+    }
+
+    #[test]
+    fn solidity_layout() {
+        let owner = Address::repeat_byte(0x11);
+        let spender = Address::repeat_byte(0x22);
+        assert_eq!(
+            mapping(mapping(slot(1), address_key(owner)), address_key(spender)),
+            word("c1c5f965d29f0d4614dc5d7a10929cd88a089f67386275dfd83b6bd3e280c8cd")
+        );
+        assert_eq!(
+            mapping_bytes(slot(2), b"abc"),
+            word("31f76c90c4bd232b01bb0bd40689518175171c0bb64d053d77a6e90319d96718")
+        );
+        assert_eq!(
+            array_element(slot(0), U256::zero(), U256::one()),
+            word("290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563")
+        );
+        assert_eq!(
+            array_element(slot(5), 3.into(), 2.into()),
+            word("036b6384b5eca791c62761152d0c79bb0604c104a5fb6f4eb0703f3154bb3db6")
+        );
+        assert_eq!(field(slot(7), 2.into()), slot(9));
+        // Slots wrap around
+        assert_eq!(field(uint_key(U256::MAX), U256::one()), slot(0));
+    }
+}
@@ -0,0 +1,205 @@
+use eth_types::{Address, Bytes, U64};
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2930::AccessList, TransactionReceipt, TransactionRequest},
+};
+use eyre::{ensure, eyre, Result};
+use halo2_proofs::halo2curves::bn256::Fr;
+
+use crate::{
+    circuit::{
+        compile_verifier, state_update_verifier_yul, verifier_calldata, ChainConfig,
+        InstanceLayout, KzgShplonkEvm, ProofBundle, StateUpdateCircuit, StateUpdateCircuitKeys,
+        StateUpdateWitness, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT,
+    },
+    utils::{new_eth_signer_client, MM},
+};
+
+/// Calldata of the verifier contract for the proof of `bundle`, the instances
+/// are in the `layout` order, see `circuit::verifier_calldata`. The proof has
+/// to be made with `KzgShplonkEvm`.
+pub fn proof_calldata(bundle: &ProofBundle, layout: InstanceLayout) -> Bytes {
+    verifier_calldata(&bundle.instances, &bundle.proof, layout.len()).into()
+}
+
+/// Submits the proof of `bundle` to the verifier contract at `verifier` and
+/// waits for `confirmations` blocks on top of the one including it. The call
+/// is simulated first, so a proof rejected by the contract is not sent.
+pub async fn submit_proof(
+    client: &MM,
+    verifier: Address,
+    bundle: &ProofBundle,
+    layout: InstanceLayout,
+    confirmations: usize,
+) -> Result<TransactionReceipt> {
+    let tx = TransactionRequest::new()
+        .from(client.address())
+        .to(verifier)
+        .data(proof_calldata(bundle, layout));
+
+    // The verifier reverts on invalid proofs
+    client
+        .call(&tx.clone().into(), None)
+        .await
+        .map_err(|err| eyre!("the verifier at {:?} rejects the proof: {}", verifier, err))?;
+
+    let pending = client.send_transaction(tx, None).await?;
+    let tx_hash = pending.tx_hash();
+    tracing::info!(?tx_hash, "proof submitted");
+    let receipt = pending
+        .confirmations(confirmations)
+        .await?
+        .ok_or_else(|| eyre!("transaction {:?} was dropped", tx_hash))?;
+    ensure!(
+        receipt.status == Some(U64::one()),
+        "transaction {:?} reverted",
+        tx_hash
+    );
+    Ok(receipt)
+}
+
+/// Circuit of the state update of `block_no` with the default degree and
+/// number of changes, exposing the digest of the changes with `mpt_digest`
+async fn block_circuit(
+    provider_url: &str,
+    pvk: &str,
+    block_no: U64,
+    access_list: Option<AccessList>,
+    chain: Option<&ChainConfig>,
+    mpt_digest: bool,
+) -> Result<StateUpdateCircuit<Fr>> {
+    let client = new_eth_signer_client(provider_url, pvk).await?;
+    let witness =
+        StateUpdateWitness::<Fr>::build(client, provider_url, block_no, access_list, chain)
+            .await?
+            .ok_or_else(|| eyre!("block {} does not change the state", block_no))?;
+    let circuit =
+        StateUpdateCircuit::new(witness, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT)?;
+    Ok(if mpt_digest {
+        circuit.with_mpt_digest()
+    } else {
+        circuit
+    })
+}
+
+/// Writes the Yul source of the verifier contract of the state update proofs
+/// to `out` and its deployment bytecode, hex encoded, to `<out>.bin`. The
+/// keys are generated for the circuit of `block_no` and only depend on the
+/// state rules of its chain and on `mpt_digest`, the contract verifies the proofs of
+/// `prove_and_submit` for all the blocks with the same rules. Compiling
+/// needs `solc` in the path.
+pub async fn generate_verifier(
+    provider_url: &str,
+    pvk: &str,
+    block_no: U64,
+    access_list: Option<AccessList>,
+    chain: Option<ChainConfig>,
+    mpt_digest: bool,
+    out: &str,
+) -> Result<()> {
+    let circuit = block_circuit(
+        provider_url,
+        pvk,
+        block_no,
+        access_list,
+        chain.as_ref(),
+        mpt_digest,
+    )
+    .await?;
+    let keys = StateUpdateCircuitKeys::<KzgShplonkEvm>::new(&circuit);
+    let yul = state_update_verifier_yul(&keys, circuit.instance_layout())?;
+    std::fs::write(out, &yul)?;
+    std::fs::write(format!("{}.bin", out), hex::encode(compile_verifier(&yul)))?;
+    println!(
+        "Verifier of the keys with vk hash 0x{} written to {}",
+        hex::encode(keys.vk_hash()),
+        out
+    );
+    Ok(())
+}
+
+/// Proves the state update of `block_no` for the EVM, verifies the proof
+/// locally and submits it to the verifier contract at `verifier`, generated by
+/// `generate_verifier`, with the account of `pvk`, see `submit_proof`
+pub async fn prove_and_submit(
+    provider_url: &str,
+    pvk: &str,
+    block_no: U64,
+    access_list: Option<AccessList>,
+    chain: Option<ChainConfig>,
+    mpt_digest: bool,
+    verifier: Address,
+    confirmations: usize,
+) -> Result<()> {
+    let circuit = block_circuit(
+        provider_url,
+        pvk,
+        block_no,
+        access_list,
+        chain.as_ref(),
+        mpt_digest,
+    )
+    .await?;
+    let layout = circuit.instance_layout();
+    let changes = circuit.transforms.trie_modifications.len();
+    let public_inputs = circuit.public_inputs();
+
+    let (bundle, keys) = tokio::task::spawn_blocking(move || -> Result<_> {
+        let keys = StateUpdateCircuitKeys::<KzgShplonkEvm>::new(&circuit);
+        // Proving consumes the circuit
+        let mut bundle = ProofBundle::new(Vec::new(), &public_inputs, &circuit, &keys);
+        bundle.proof = circuit.prove(&keys)?;
+        Ok((bundle, keys))
+    })
+    .await??;
+    // Never spend gas on a proof that does not verify
+    StateUpdateCircuit::verify(&bundle.proof, &bundle.instances, &keys)?;
+    println!(
+        "Block {}: proof of {} trie modifications generated",
+        block_no, changes
+    );
+
+    let signer = new_eth_signer_client(provider_url, pvk).await?;
+    let receipt = submit_proof(&signer, verifier, &bundle, layout, confirmations).await?;
+    println!(
+        "Block {}: proof included in block {} by transaction {:?}, {} gas used",
+        block_no,
+        receipt
+            .block_number
+            .map_or("?".to_string(), |number| number.to_string()),
+        receipt.transaction_hash,
+        receipt
+            .gas_used
+            .map_or("?".to_string(), |gas| gas.to_string()),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calldata_layout() {
+        let bundle = ProofBundle {
+            version: 2,
+            params_hash: [0; 32],
+            vk_hash: [0; 32],
+            circuit_fingerprint: None,
+            instances: vec![Fr::from(1), Fr::from(0x0102)],
+            proof: vec![0xaa; 40],
+        };
+        let layout = InstanceLayout::new(1, false);
+        let calldata = proof_calldata(&bundle, layout);
+        assert_eq!(calldata.len(), layout.len() * 32 + bundle.proof.len());
+        // The instances are big endian words, padded to the layout
+        let mut instances = [0u8; 32 * 2];
+        instances[31] = 1;
+        instances[62..].copy_from_slice(&[0x01, 0x02]);
+        assert_eq!(calldata[..64], instances);
+        assert!(calldata[64..layout.len() * 32]
+            .iter()
+            .all(|byte| *byte == 0));
+        assert_eq!(calldata[layout.len() * 32..], bundle.proof);
+    }
+}
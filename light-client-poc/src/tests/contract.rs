@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
-use crate::utils::MM;
+use crate::{circuit::Transforms, utils::MM};
 use ethers::{
     core::types::transaction::eip2930::AccessList,
     middleware::SignerMiddleware,
@@ -16,6 +16,7 @@ use ethers::{
     utils::format_units,
 };
 use eyre::Result;
+use mpt_witness_generator::ProofType;
 use num_enum::IntoPrimitive;
 use std::{
     collections::{HashMap, HashSet},
@@ -61,35 +62,43 @@ pub(crate) struct Contract {
     pub receipt: TransactionReceipt,
 }
 
-impl Contract {
-    pub async fn deploy(client: Arc<MM>) -> Result<Self> {
-        let contract = "SimpleStorage";
+/// Compiles `source` and deploys the contract `name` in it
+async fn deploy_contract(
+    client: Arc<MM>,
+    name: &str,
+    source: &str,
+) -> Result<(Address, TransactionReceipt)> {
+    let mut path = std::env::temp_dir();
+    path.push(format!("{}.sol", name));
 
-        let mut path = std::env::temp_dir();
-        path.push("SimpleStorage.sol");
+    std::fs::write(&path, source)?;
 
-        std::fs::write(&path, SIMPLE_STORAGE_SOL)?;
+    // compile it
 
-        // compile it
+    let compiler_input = CompilerInput::new(path)?
+        .remove(0)
+        .evm_version(EvmVersion::Paris);
+    let compiled = Solc::default().compile_exact(&compiler_input)?;
 
-        let compiler_input = CompilerInput::new(path)?
-            .remove(0)
-            .evm_version(EvmVersion::Paris);
-        let compiled = Solc::default().compile_exact(&compiler_input)?;
+    let (abi, bytecode, _runtime_bytecode) = compiled
+        .find(name)
+        .expect("could not find contract")
+        .into_parts_or_default();
 
-        let (abi, bytecode, _runtime_bytecode) = compiled
-            .find(contract)
-            .expect("could not find contract")
-            .into_parts_or_default();
+    println!("deploying contract");
 
-        println!("deploying contract");
+    let factory = ContractFactory::new(abi.clone(), bytecode.clone(), client.clone());
+    let deployer = factory.deploy(())?.legacy();
 
-        let factory = ContractFactory::new(abi.clone(), bytecode.clone(), client.clone());
-        let deployer = factory.deploy(())?.legacy();
+    let (contract, receipt) = deployer.send_with_receipt().await?;
 
-        let (contract, receipt) = deployer.send_with_receipt().await?;
+    Ok((contract.address(), receipt))
+}
 
-        let address = contract.address();
+impl Contract {
+    pub async fn deploy(client: Arc<MM>) -> Result<Self> {
+        let (address, receipt) =
+            deploy_contract(client.clone(), "SimpleStorage", SIMPLE_STORAGE_SOL).await?;
 
         let contract = SimpleStorage::new(address, client.clone());
 
@@ -113,3 +122,214 @@ impl Contract {
         Ok(tx)
     }
 }
+
+/// A watched slot whose proven value does not match the value of the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SlotDivergence {
+    pub key: U256,
+    /// `None` when the block changes the slot but the transforms do not, the
+    /// access list the transforms were built from is stale
+    pub proven: Option<U256>,
+    pub live: U256,
+}
+
+/// Reads the slots of a `SimpleStorage` contract at a block
+pub(crate) struct SlotReader {
+    client: Arc<MM>,
+}
+
+impl SlotReader {
+    pub fn new(client: Arc<MM>) -> Self {
+        Self { client }
+    }
+
+    /// Values of `keys` in `contract` after `block_no`, read with
+    /// `eth_getStorageAt` so that they do not depend on any contract deployed
+    /// after the block
+    pub async fn read(
+        &self,
+        contract: &Contract,
+        keys: &[U256],
+        block_no: U64,
+    ) -> Result<Vec<U256>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            let mut slot = [0u8; 32];
+            key.to_big_endian(&mut slot);
+            let value = self
+                .client
+                .get_storage_at(
+                    contract.contract.address(),
+                    H256(slot),
+                    Some(BlockId::Number(BlockNumber::Number(block_no))),
+                )
+                .await?;
+            values.push(U256::from_big_endian(value.as_bytes()));
+        }
+        Ok(values)
+    }
+
+    /// Reads back the watched `keys` after the proven block and compares them
+    /// with the storage changes of `transforms`. The slots missing from the
+    /// transforms are expected to keep the value of the previous block.
+    pub async fn cross_check(
+        &self,
+        contract: &Contract,
+        keys: &[U256],
+        transforms: &Transforms,
+    ) -> Result<Vec<SlotDivergence>> {
+        let block_no = transforms.block_no;
+        let live = self.read(contract, keys, block_no).await?;
+        let prev = self.read(contract, keys, block_no - 1).await?;
+
+        let mut divergences = Vec::new();
+        for ((key, live), prev) in keys.iter().zip(live).zip(prev) {
+            let mut slot = [0u8; 32];
+            key.to_big_endian(&mut slot);
+            let proven = transforms
+                .trie_modifications
+                .iter()
+                .rev()
+                .find(|m| {
+                    matches!(m.typ, ProofType::StorageChanged)
+                        && m.address == contract.contract.address()
+                        && m.key == H256(slot)
+                })
+                .map(|m| m.value);
+            let diverges = match proven {
+                Some(proven) => proven != live,
+                None => prev != live,
+            };
+            if diverges {
+                divergences.push(SlotDivergence {
+                    key: *key,
+                    proven,
+                    live,
+                });
+            }
+        }
+        Ok(divergences)
+    }
+}
+
+const EPHEMERAL_FACTORY_SOL: &str = r#"
+// SPDX-License-Identifier: Unlicense
+pragma solidity ^0.8.0;
+
+contract Ephemeral {
+    constructor() payable {
+       assembly {
+           sstore(0, 1)
+       }
+       selfdestruct(payable(msg.sender));
+    }
+}
+
+contract EphemeralToSelf {
+    constructor() payable {
+       assembly {
+           sstore(0, 1)
+       }
+       // The balance is burned
+       selfdestruct(payable(address(this)));
+    }
+}
+
+contract EphemeralToBeneficiary {
+    constructor(address beneficiary) payable {
+       assembly {
+           sstore(0, 1)
+       }
+       selfdestruct(payable(beneficiary));
+    }
+}
+
+contract EphemeralFactory {
+    function createAndDestroy(bytes32 salt) payable public {
+       new Ephemeral{salt: salt, value: msg.value}();
+    }
+    function createAndDestroyToSelf(bytes32 salt) payable public {
+       new EphemeralToSelf{salt: salt, value: msg.value}();
+    }
+    function createAndDestroyToNewAccount(bytes32 salt, address beneficiary) payable public {
+       new EphemeralToBeneficiary{salt: salt, value: msg.value}(beneficiary);
+    }
+}
+"#;
+
+abigen!(
+    EphemeralFactory,
+    r"[
+    function createAndDestroy(bytes32 salt) public payable
+    function createAndDestroyToSelf(bytes32 salt) public payable
+    function createAndDestroyToNewAccount(bytes32 salt, address beneficiary) public payable
+]"
+);
+
+/// Creates accounts that are destroyed in the same transaction
+pub(crate) struct Factory {
+    pub contract: EphemeralFactory<MM>,
+    pub receipt: TransactionReceipt,
+}
+
+impl Factory {
+    pub async fn deploy(client: Arc<MM>) -> Result<Self> {
+        let (address, receipt) =
+            deploy_contract(client.clone(), "EphemeralFactory", EPHEMERAL_FACTORY_SOL).await?;
+
+        let contract = EphemeralFactory::new(address, client.clone());
+
+        Ok(Self { contract, receipt })
+    }
+
+    pub async fn create_and_destroy(&self, salt: H256, value: U256) -> Result<TransactionReceipt> {
+        let tx: TransactionReceipt = self
+            .contract
+            .create_and_destroy(salt.0)
+            .value(value)
+            .legacy()
+            .send()
+            .await?
+            .await?
+            .unwrap();
+        Ok(tx)
+    }
+
+    /// Like `create_and_destroy`, but the account is its own beneficiary
+    pub async fn create_and_destroy_to_self(
+        &self,
+        salt: H256,
+        value: U256,
+    ) -> Result<TransactionReceipt> {
+        let tx: TransactionReceipt = self
+            .contract
+            .create_and_destroy_to_self(salt.0)
+            .value(value)
+            .legacy()
+            .send()
+            .await?
+            .await?
+            .unwrap();
+        Ok(tx)
+    }
+
+    /// Like `create_and_destroy`, but the balance goes to `beneficiary`, which
+    /// is created by the transfer when it does not exist yet
+    pub async fn create_and_destroy_to_new_account(
+        &self,
+        salt: H256,
+        beneficiary: Address,
+        value: U256,
+    ) -> Result<TransactionReceipt> {
+        let tx: TransactionReceipt = self
+            .contract
+            .create_and_destroy_to_new_account(salt.0, beneficiary)
+            .value(value)
+            .legacy()
+            .send()
+            .await?
+            .await?
+            .unwrap();
+        Ok(tx)
+    }
+}
@@ -0,0 +1,212 @@
+// Differential test between geth's trie, an independent Rust trie
+// implementation, the witness generator and the MPT circuit. Needs a local
+// geth node, see `start-go-ethereum.sh`.
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        prelude::*,
+        utils::{
+            keccak256,
+            rlp::{self, RlpStream},
+        },
+    };
+    use eyre::{ensure, Result};
+    use halo2_proofs::halo2curves::bn256::Fr;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use std::collections::BTreeMap;
+
+    use crate::{
+        circuit::StateUpdateWitness,
+        utils::{new_eth_signer_client, verify_mpt_witness},
+    };
+
+    const PVK: &str = "7ccb34dc5fd31fd0aa7860de89a4adc37ccb34dc5fd31fd0aa7860de89a4adc3";
+    const PROVIDER_URL: &str = "http://localhost:8545";
+
+    fn nibbles(key: &[u8]) -> Vec<u8> {
+        key.iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xf])
+            .collect()
+    }
+
+    /// Hex prefix encoding of a leaf/extension path
+    fn hex_prefix(path: &[u8], is_leaf: bool) -> Vec<u8> {
+        let flag = (path.len() % 2) as u8 + if is_leaf { 2 } else { 0 };
+        let mut bytes = vec![flag << 4];
+        let mut path = path;
+        if path.len() % 2 == 1 {
+            bytes[0] |= path[0];
+            path = &path[1..];
+        }
+        bytes.extend(path.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+        bytes
+    }
+
+    /// Appends the reference to `node`, nodes shorter than 32 bytes are inlined
+    fn append_ref(stream: &mut RlpStream, node: &[u8]) {
+        if node.len() < 32 {
+            stream.append_raw(node, 1);
+        } else {
+            stream.append(&keccak256(node).to_vec());
+        }
+    }
+
+    /// RLP of the node holding `entries`, which share their first `depth` nibbles
+    fn encode_node(entries: &[(Vec<u8>, Vec<u8>)], depth: usize) -> Vec<u8> {
+        if let [(key, value)] = entries {
+            let mut stream = RlpStream::new_list(2);
+            stream.append(&hex_prefix(&key[depth..], true));
+            stream.append(value);
+            return stream.out().to_vec();
+        }
+
+        let first = &entries[0].0;
+        let prefix_len = entries
+            .iter()
+            .map(|(key, _)| {
+                key[depth..]
+                    .iter()
+                    .zip(first[depth..].iter())
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            })
+            .min()
+            .unwrap();
+        if prefix_len > 0 {
+            let mut stream = RlpStream::new_list(2);
+            stream.append(&hex_prefix(&first[depth..depth + prefix_len], false));
+            append_ref(&mut stream, &encode_node(entries, depth + prefix_len));
+            return stream.out().to_vec();
+        }
+
+        let mut stream = RlpStream::new_list(17);
+        for nibble in 0..16 {
+            let children: Vec<_> = entries
+                .iter()
+                .filter(|(key, _)| key.len() > depth && key[depth] == nibble)
+                .cloned()
+                .collect();
+            if children.is_empty() {
+                stream.append_empty_data();
+            } else {
+                append_ref(&mut stream, &encode_node(&children, depth + 1));
+            }
+        }
+        match entries.iter().find(|(key, _)| key.len() == depth) {
+            Some((_, value)) => stream.append(value),
+            None => stream.append_empty_data(),
+        };
+        stream.out().to_vec()
+    }
+
+    /// Root of the trie with `entries`, keys are used as is (not hashed)
+    fn trie_root(entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> H256 {
+        if entries.is_empty() {
+            return H256(keccak256(rlp::NULL_RLP));
+        }
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|(key, value)| (nibbles(key), value.clone()))
+            .collect();
+        H256(keccak256(encode_node(&entries, 0)))
+    }
+
+    /// Root of the storage trie with the non-zero `slots`
+    fn storage_root(slots: &BTreeMap<U256, U256>) -> H256 {
+        let entries = slots
+            .iter()
+            .filter(|(_, value)| !value.is_zero())
+            .map(|(slot, value)| {
+                let mut key = [0u8; 32];
+                slot.to_big_endian(&mut key);
+                (keccak256(key).to_vec(), rlp::encode(value).to_vec())
+            })
+            .collect();
+        trie_root(&entries)
+    }
+
+    #[test]
+    fn test_trie_root() {
+        assert_eq!(
+            trie_root(&BTreeMap::new()),
+            "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+                .parse()
+                .unwrap()
+        );
+        // Ethereum trie test vector with a value in a branch and an extension
+        let entries = [
+            ("doe", "reindeer"),
+            ("dog", "puppy"),
+            ("dogglesworth", "cat"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+        .collect();
+        assert_eq!(
+            trie_root(&entries),
+            "0x8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"
+                .parse()
+                .unwrap()
+        );
+    }
+
+    async fn run_geth_diff(rounds: usize) -> Result<()> {
+        let client = new_eth_signer_client(PROVIDER_URL, PVK).await?;
+        let contract = crate::tests::contract::Contract::deploy(client.clone()).await?;
+        let address = contract.contract.address();
+
+        // The storage set by the constructor
+        let mut slots = BTreeMap::from([(U256::from(0xcafe), U256::from(0xbabe))]);
+
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        for round in 0..rounds {
+            // Reuse slots to also get updates and deletions
+            let slot = if !slots.is_empty() && rng.gen_bool(0.3) {
+                *slots.keys().nth(rng.gen_range(0..slots.len())).unwrap()
+            } else {
+                U256::from(rng.gen::<u64>())
+            };
+            let value = if rng.gen_bool(0.2) {
+                U256::zero()
+            } else {
+                U256::from(rng.gen::<u128>())
+            };
+            slots.insert(slot, value);
+            slots.retain(|_, value| !value.is_zero());
+
+            let receipt = contract.set(slot, value).await?;
+            let block_no = receipt.block_number.unwrap();
+
+            // geth and the Rust trie agree
+            let proof = client
+                .get_proof(address, vec![], Some(BlockId::Number(block_no.into())))
+                .await?;
+            let expected = storage_root(&slots);
+            ensure!(
+                proof.storage_hash == expected,
+                "round {}: geth storage root {:?}, expected {:?}",
+                round,
+                proof.storage_hash,
+                expected
+            );
+
+            // The witness generator reaches the block state root and the circuit
+            // accepts the witness
+            let witness =
+                StateUpdateWitness::<Fr>::build(client.clone(), PROVIDER_URL, block_no, None, None)
+                    .await?
+                    .unwrap();
+            verify_mpt_witness(witness.mpt_witness)?;
+            println!("round {}: slot {:x} = {:x}", round, slot, value);
+        }
+        Ok(())
+    }
+
+    #[ignore]
+    #[tokio::test]
+    async fn test_geth_diff() {
+        run_geth_diff(32).await.unwrap();
+    }
+}
@@ -6,7 +6,8 @@ mod tests {
     use std::sync::Arc;
 
     use crate::{
-        circuit::StateUpdateWitness,
+        circuit::{StateUpdateWitness, Transforms},
+        tests::contract::SlotReader,
         utils::{new_eth_signer_client, verify_mpt_witness, MM},
     };
 
@@ -18,7 +19,7 @@ mod tests {
         client: &Arc<MM>,
         provider_url: &str,
         recipt: &TransactionReceipt,
-    ) -> Result<()> {
+    ) -> Result<Transforms> {
         println!("Running test {}", test);
 
         let witness = StateUpdateWitness::<Fr>::build(
@@ -26,11 +27,13 @@ mod tests {
             provider_url,
             recipt.block_number.unwrap(),
             None,
+            None,
         )
         .await?
         .unwrap();
-        println!("trns: {:#?}", witness.transforms);
-        verify_mpt_witness(witness.mpt_witness)
+        tracing::debug!(transforms = ?witness.transforms, "block transforms");
+        verify_mpt_witness(witness.mpt_witness)?;
+        Ok(witness.transforms)
     }
 
     async fn run_localnode_test() -> Result<()> {
@@ -48,12 +51,54 @@ mod tests {
 
         // test set value
         let receipt = contract.set(0xad41a.into(), 0xcafe.into()).await?;
-        local_test_proof("test set slot", &client, PROVIDER_URL, &receipt).await?;
+        let transforms = local_test_proof("test set slot", &client, PROVIDER_URL, &receipt).await?;
+
+        // the proven slots match the values of the node, and the slot left
+        // unchanged by the block keeps its value
+        let reader = SlotReader::new(client.clone());
+        let divergences = reader
+            .cross_check(&contract, &[0xad41a.into(), 0xcafe.into()], &transforms)
+            .await?;
+        assert!(divergences.is_empty(), "{:?}", divergences);
 
         // test unset value
         let receipt = contract.set(0xad41a.into(), 0.into()).await?;
         local_test_proof("test remove slot", &client, PROVIDER_URL, &receipt).await?;
 
+        // test account created and destroyed in the same transaction, the
+        // account does not exist at both ends of the block
+        let factory = crate::tests::contract::Factory::deploy(client.clone()).await?;
+        let receipt = factory
+            .create_and_destroy(H256::from_low_u64_be(1), 1000.into())
+            .await?;
+        local_test_proof("test create and destroy", &client, PROVIDER_URL, &receipt).await?;
+
+        // test account self destructing to itself, the balance is burned
+        let receipt = factory
+            .create_and_destroy_to_self(H256::from_low_u64_be(2), 1000.into())
+            .await?;
+        local_test_proof(
+            "test create and destroy to self",
+            &client,
+            PROVIDER_URL,
+            &receipt,
+        )
+        .await?;
+
+        // test account self destructing to an account that does not exist
+        // yet, the beneficiary is created by the transfer
+        let beneficiary = Address::from_low_u64_be(0xde57);
+        let receipt = factory
+            .create_and_destroy_to_new_account(H256::from_low_u64_be(3), beneficiary, 1000.into())
+            .await?;
+        local_test_proof(
+            "test create and destroy to new account",
+            &client,
+            PROVIDER_URL,
+            &receipt,
+        )
+        .await?;
+
         Ok(())
     }
 
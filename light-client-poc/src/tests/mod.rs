@@ -1,3 +1,7 @@
 pub mod contract;
+#[cfg(feature = "geth-diff")]
+pub mod geth_diff;
 pub mod local;
 pub mod mainnet;
+#[cfg(feature = "regression-fixtures")]
+pub mod regression;
@@ -0,0 +1,285 @@
+//! Regression suite replaying the historical mainnet blocks of
+//! `fixtures/blocks.toml` from recorded node responses, so that it runs
+//! without a node. A local JSON-RPC server answers the requests of the light
+//! client and of the MPT witness generator from the recording of the block.
+//!
+//! ```text
+//! cargo test --release --features regression-fixtures regression
+//! ```
+//!
+//! Every block of the list needs a recording, a missing one fails the suite.
+//! They are made with an archive node:
+//!
+//! ```text
+//! PROVIDER_URL=<archive node> cargo test --release --features regression-fixtures \
+//!     record_fixtures -- --ignored
+//! ```
+
+#[cfg(test)]
+mod test {
+    use axum::{extract::State, routing::post, Json, Router};
+    use eyre::{eyre, Result};
+    use halo2_proofs::halo2curves::bn256::Fr;
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+    use std::{
+        collections::BTreeMap,
+        net::SocketAddr,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        access_list::AccessListFile,
+        circuit::{
+            ChainConfig, PublicInputs, StateUpdateCircuit, StateUpdateWitness, WitnessStats,
+            DEFAULT_MAX_PROOF_COUNT,
+        },
+    };
+
+    const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+    const ACCESS_LISTS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/access_lists");
+    const PVK: &str = "7ccb34dc5fd31fd0aa7860de89a4adc37ccb34dc5fd31fd0aa7860de89a4adc3";
+
+    #[derive(Debug, Deserialize)]
+    struct FixtureBlocks {
+        blocks: Vec<FixtureBlock>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct FixtureBlock {
+        number: u64,
+        era: String,
+    }
+
+    fn fixture_blocks() -> Result<Vec<FixtureBlock>> {
+        let path = PathBuf::from(FIXTURES_DIR).join("blocks.toml");
+        Ok(toml::from_str::<FixtureBlocks>(&std::fs::read_to_string(path)?)?.blocks)
+    }
+
+    fn recording_path(block: u64) -> PathBuf {
+        PathBuf::from(FIXTURES_DIR).join(format!("{}.json", block))
+    }
+
+    /// Responses of a node to the requests made while building the witness of
+    /// a block, indexed by method and params
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct Recording {
+        block: u64,
+        responses: BTreeMap<String, Value>,
+    }
+
+    impl Recording {
+        fn load(block: u64) -> Result<Self> {
+            let path = recording_path(block);
+            let content = std::fs::read_to_string(&path)
+                .map_err(|err| eyre!("{}: {}, see `record_fixtures`", path.display(), err))?;
+            Ok(serde_json::from_str(&content)?)
+        }
+
+        fn save(&self) -> Result<()> {
+            std::fs::write(
+                recording_path(self.block),
+                serde_json::to_string_pretty(self)?,
+            )?;
+            Ok(())
+        }
+    }
+
+    /// The ids of the requests change from run to run, so they are left out
+    fn request_key(request: &Value) -> String {
+        format!(
+            "{} {}",
+            request["method"].as_str().unwrap_or_default(),
+            request["params"]
+        )
+    }
+
+    /// JSON-RPC node recording the responses of `upstream`, or replaying them
+    /// when there is no upstream
+    struct FixtureNode {
+        upstream: Option<(String, reqwest::Client)>,
+        recording: Mutex<Recording>,
+    }
+
+    impl FixtureNode {
+        async fn respond(&self, request: &Value) -> Value {
+            let key = request_key(request);
+            let response = match &self.upstream {
+                Some((url, client)) => {
+                    let response = async {
+                        let response = client
+                            .post(url)
+                            .header(reqwest::header::CONTENT_TYPE, "application/json")
+                            .body(request.to_string())
+                            .send()
+                            .await?
+                            .text()
+                            .await?;
+                        Ok::<Value, eyre::Report>(serde_json::from_str(&response)?)
+                    }
+                    .await;
+                    match response {
+                        Ok(mut response) => {
+                            if let Some(response) = response.as_object_mut() {
+                                response.remove("id");
+                            }
+                            self.recording
+                                .lock()
+                                .unwrap()
+                                .responses
+                                .insert(key, response.clone());
+                            Some(response)
+                        }
+                        Err(err) => {
+                            tracing::warn!(%key, %err, "upstream request failed");
+                            None
+                        }
+                    }
+                }
+                None => self.recording.lock().unwrap().responses.get(&key).cloned(),
+            };
+            let mut response = response.unwrap_or_else(|| {
+                tracing::warn!(%key, "request not recorded");
+                json!({
+                    "jsonrpc": "2.0",
+                    "error": { "code": -32000, "message": format!("request not recorded: {}", key) },
+                })
+            });
+            response["id"] = request["id"].clone();
+            response
+        }
+    }
+
+    async fn handle(
+        State(node): State<Arc<FixtureNode>>,
+        Json(request): Json<Value>,
+    ) -> Json<Value> {
+        Json(match request.as_array() {
+            Some(batch) => {
+                let mut responses = Vec::new();
+                for request in batch {
+                    responses.push(node.respond(request).await);
+                }
+                Value::Array(responses)
+            }
+            None => node.respond(&request).await,
+        })
+    }
+
+    /// Serves `node` on a free local port and returns its URL
+    fn serve(node: Arc<FixtureNode>) -> String {
+        let app = Router::new().route("/", post(handle)).with_state(node);
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    /// Builds the witness of `block` with the node at `provider_url` and
+    /// checks it with the MockProver. The keccak table is loaded from the
+    /// witness to keep the circuit small.
+    async fn check_block(provider_url: &str, block: u64) -> Result<()> {
+        let client = crate::utils::new_eth_signer_client(provider_url, PVK).await?;
+        let access_list = AccessListFile::load_dir(ACCESS_LISTS_DIR)?
+            .into_iter()
+            .find(|file| file.block == block)
+            .map(|file| file.access_list());
+        let Some(witness) = StateUpdateWitness::<Fr>::build(
+            client,
+            provider_url,
+            block.into(),
+            access_list,
+            Some(&ChainConfig::mainnet()),
+        )
+        .await?
+        else {
+            return Ok(());
+        };
+
+        // The public inputs only depend on the changes of the block
+        let expected = PublicInputs::<Fr>::from_transforms(&witness.transforms)?;
+        let degree = WitnessStats::from(witness.mpt_witness.as_slice()).min_oracle_degree();
+        let max_proof_count = DEFAULT_MAX_PROOF_COUNT.max(witness.lc_witness.len());
+        let mut circuit = StateUpdateCircuit::new(witness, degree, max_proof_count)?;
+        circuit.keccak_oracle = true;
+        eyre::ensure!(
+            circuit.public_inputs().0 == expected.0,
+            "public inputs of block {} do not match its changes",
+            block
+        );
+        circuit.assert_satisfied();
+        Ok(())
+    }
+
+    // The witness generator blocks its thread while it queries the node, so the
+    // fixture node needs a thread of its own
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn regression() -> Result<()> {
+        let mut failures = Vec::new();
+        for block in fixture_blocks()? {
+            if !recording_path(block.number).exists() {
+                failures.push(format!(
+                    "{} ({}): no recording, see `record_fixtures`",
+                    block.number, block.era
+                ));
+                continue;
+            }
+            let node = Arc::new(FixtureNode {
+                upstream: None,
+                recording: Mutex::new(Recording::load(block.number)?),
+            });
+            let url = serve(node);
+            println!("block {} ({})", block.number, block.era);
+            // The MockProver panics on unsatisfied constraints
+            let number = block.number;
+            let result = tokio::spawn(async move { check_block(&url, number).await }).await;
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => failures.push(format!("{} ({}): {}", block.number, block.era, err)),
+                Err(_) => failures.push(format!(
+                    "{} ({}): constraints not satisfied",
+                    block.number, block.era
+                )),
+            }
+        }
+        assert!(
+            failures.is_empty(),
+            "failing blocks:\n{}",
+            failures.join("\n")
+        );
+        Ok(())
+    }
+
+    /// Records the node responses of the blocks without a recording
+    #[ignore]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn record_fixtures() -> Result<()> {
+        let provider_url = std::env::var("PROVIDER_URL")
+            .expect("PROVIDER_URL environment var should be set with a mainnet archive node");
+        for block in fixture_blocks()? {
+            if recording_path(block.number).exists() {
+                continue;
+            }
+            let node = Arc::new(FixtureNode {
+                upstream: Some((provider_url.clone(), reqwest::Client::new())),
+                recording: Mutex::new(Recording {
+                    block: block.number,
+                    ..Default::default()
+                }),
+            });
+            let url = serve(node.clone());
+            println!("recording block {} ({})", block.number, block.era);
+            // Recorded even when the block fails, the failure is a regression
+            // to fix
+            let number = block.number;
+            let result = tokio::spawn(async move { check_block(&url, number).await }).await;
+            if !matches!(result, Ok(Ok(()))) {
+                println!("block {} fails", block.number);
+            }
+            node.recording.lock().unwrap().save()?;
+        }
+        Ok(())
+    }
+}
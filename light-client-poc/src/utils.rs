@@ -5,11 +5,24 @@ use ethers::{
     signers::{LocalWallet, Signer},
     utils::format_units,
 };
-use eyre::Result;
-use halo2_proofs::{dev::MockProver, halo2curves::bn256::Fr};
+use eyre::{ensure, eyre, Result};
+use halo2_proofs::{
+    dev::{MockProver, VerifyFailure},
+    halo2curves::bn256::Fr,
+};
 
-use std::{convert::TryFrom, sync::Arc, time::Duration};
-use zkevm_circuits::mpt_circuit::witness_row::*;
+use std::{
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use zkevm_circuits::{
+    mpt_circuit::{witness_check::check_witness, witness_row::*, KeyDerivation},
+    table::mpt_table::MPTProofType,
+};
 
 pub fn print_nodes(node: &[Node]) {
     for n in node {
@@ -55,7 +68,12 @@ pub fn print_nodes(node: &[Node]) {
         if let Some(account) = &n.account {
             println!("   account:");
             println!("       address: {}", hex::encode(&*account.address));
-            println!("       key: {}", hex::encode(&account.key.to_vec()));
+            if let Some(hashed_key) = &account.hashed_key {
+                println!("       hashed_key: {}", hex::encode(&hashed_key.to_vec()));
+            }
+            if let Some(preimage) = &account.preimage {
+                println!("       preimage: {}", hex::encode(&preimage.to_vec()));
+            }
             println!(
                 "       list_rlp_bytes[0]: {}",
                 hex::encode(&account.list_rlp_bytes[0].to_vec())
@@ -93,7 +111,12 @@ pub fn print_nodes(node: &[Node]) {
         if let Some(storage) = &n.storage {
             println!("   storage:");
             println!("       address: {}", hex::encode(&*storage.address));
-            println!("       key: {}", hex::encode(&storage.key.to_vec()));
+            if let Some(hashed_key) = &storage.hashed_key {
+                println!("       hashed_key: {}", hex::encode(&hashed_key.to_vec()));
+            }
+            if let Some(preimage) = &storage.preimage {
+                println!("       preimage: {}", hex::encode(&preimage.to_vec()));
+            }
             println!(
                 "       list_rlp_bytes[0]: {}",
                 hex::encode(&storage.list_rlp_bytes[0].to_vec())
@@ -132,18 +155,155 @@ pub fn print_nodes(node: &[Node]) {
 }
 
 pub fn verify_mpt_witness(nodes: Vec<Node>) -> Result<()> {
-    // get the number of rows in the witness
-    let num_rows: usize = nodes.iter().map(|node| node.values.len()).sum();
+    // fail fast on an inconsistent witness before running the circuit
+    check_witness(&nodes).map_err(|e| eyre!("invalid MPT witness: {}", e))?;
 
     // populate the keccak data
-    let mut keccak_data = vec![];
-    for node in nodes.iter() {
-        for k in node.keccak_data.iter() {
-            keccak_data.push(k.to_vec());
+    let keccak_data = keccak_data(&nodes);
+
+    // verify the circuit
+    assert_eq!(mock_verify_mpt(nodes, keccak_data), Ok(()));
+
+    println!("success!");
+
+    Ok(())
+}
+
+/// Independent proofs of the witness that touch the same account
+#[derive(Debug, Clone)]
+pub struct MptSegment {
+    pub index: usize,
+    /// `None` for the proofs before the first account proof
+    pub address: Option<Address>,
+    pub num_proofs: usize,
+    pub nodes: Vec<Node>,
+}
+
+/// Splits the witness in segments of consecutive proofs of the same account.
+/// Every proof starts with a start node and ends with the end node, so the
+/// segments can be verified on their own.
+pub fn split_mpt_witness(nodes: Vec<Node>) -> Vec<MptSegment> {
+    let mut segments = Vec::new();
+    let mut proof = Vec::new();
+    for node in nodes {
+        let starts_proof = node
+            .start
+            .as_ref()
+            .map_or(false, |start| start.proof_type != MPTProofType::Disabled);
+        if starts_proof {
+            push_proof(&mut segments, std::mem::take(&mut proof));
         }
+        proof.push(node);
     }
+    push_proof(&mut segments, proof);
+    segments
+}
+
+fn push_proof(segments: &mut Vec<MptSegment>, proof: Vec<Node>) {
+    if proof.is_empty() {
+        return;
+    }
+    let address = proof
+        .iter()
+        .find_map(|node| node.account.as_ref())
+        .map(|account| Address::from_slice(&account.address));
+    match segments.last_mut() {
+        Some(segment) if segment.address == address => {
+            segment.num_proofs += 1;
+            segment.nodes.extend(proof);
+        }
+        _ => segments.push(MptSegment {
+            index: segments.len(),
+            address,
+            num_proofs: 1,
+            nodes: proof,
+        }),
+    }
+}
+
+/// Verifies the segments of the witness on `threads` threads with the
+/// MockProver, all the failing segments are reported.
+pub fn verify_mpt_witness_par(nodes: Vec<Node>, threads: usize) -> Result<()> {
+    check_witness(&nodes).map_err(|e| eyre!("invalid MPT witness: {}", e))?;
+    // Keccak data is deduplicated over the whole witness, so every segment
+    // gets all of it
+    let keccak_data = keccak_data(&nodes);
+    verify_mpt_segments(split_mpt_witness(nodes), threads, |segment| {
+        mock_verify_mpt(segment.nodes.clone(), keccak_data.clone())
+            .map_err(|failures| failures.iter().map(|f| f.to_string()).collect())
+    })
+}
+
+/// Runs `verify` on all segments on `threads` threads, `verify` returns the
+/// failures of a segment. Can be used with the MockProver or with real proofs.
+pub fn verify_mpt_segments<V>(segments: Vec<MptSegment>, threads: usize, verify: V) -> Result<()>
+where
+    V: Fn(&MptSegment) -> std::result::Result<(), Vec<String>> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let mut failed: Vec<(usize, Vec<String>)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut failed = Vec::new();
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(segment) = segments.get(idx) else {
+                            break;
+                        };
+                        if let Err(failures) = verify(segment) {
+                            failed.push((idx, failures));
+                        }
+                    }
+                    failed
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    failed.sort_by_key(|(idx, _)| *idx);
+
+    for (idx, failures) in failed.iter() {
+        let segment = &segments[*idx];
+        println!(
+            "segment {} (account {:?}, {} proofs, {} nodes) failed:",
+            segment.index,
+            segment.address,
+            segment.num_proofs,
+            segment.nodes.len()
+        );
+        for failure in failures.iter() {
+            println!("   {}", failure);
+        }
+    }
+    ensure!(
+        failed.is_empty(),
+        "{} of {} segments failed: {:?}",
+        failed.len(),
+        segments.len(),
+        failed.iter().map(|(idx, _)| idx).collect::<Vec<_>>()
+    );
+    println!("success! {} segments verified", segments.len());
+    Ok(())
+}
+
+fn keccak_data(nodes: &[Node]) -> Vec<Vec<u8>> {
+    nodes
+        .iter()
+        .flat_map(|node| node.keccak_data.iter().map(|k| k.to_vec()))
+        .collect()
+}
+
+fn mock_verify_mpt(
+    nodes: Vec<Node>,
+    keccak_data: Vec<Vec<u8>>,
+) -> std::result::Result<(), Vec<VerifyFailure>> {
+    // get the number of rows in the witness
+    let num_rows: usize = nodes.iter().map(|node| node.values.len()).sum();
 
-    // verify the circuit
     let disable_preimage_check = nodes[0].start.clone().unwrap().disable_preimage_check;
     let degree = 15;
     let circuit = zkevm_circuits::mpt_circuit::MPTCircuit::<Fr> {
@@ -151,15 +311,18 @@ pub fn verify_mpt_witness(nodes: Vec<Node>) -> Result<()> {
         keccak_data,
         degree,
         disable_preimage_check,
+        strict_empty_accounts: false,
+        strict_codehash: false,
+        nonce_increment: false,
+        balance_delta: false,
+        key_derivation: KeyDerivation::Keccak,
+        max_nodes: 0,
+        single_pass: true,
         _marker: std::marker::PhantomData,
     };
 
     let prover = MockProver::<Fr>::run(degree as u32, &circuit, vec![]).unwrap();
-    assert_eq!(prover.verify_at_rows(0..num_rows, 0..num_rows,), Ok(()));
-
-    println!("success!");
-
-    Ok(())
+    prover.verify_at_rows(0..num_rows, 0..num_rows)
 }
 
 pub type MM = SignerMiddleware<Provider<Http>, Wallet<SigningKey>>;
@@ -184,3 +347,49 @@ pub async fn new_eth_signer_client(provider_url: &str, pvk: &str) -> Result<Arc<
 
     Ok(client)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zkevm_circuits::mpt_circuit::load_proof;
+
+    fn load_proofs(names: &[&str]) -> Vec<Node> {
+        names
+            .iter()
+            .flat_map(|name| {
+                load_proof(&format!(
+                    "../zkevm-circuits/src/mpt_circuit/tests/{}.json",
+                    name
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_and_verify_par() {
+        let nodes = load_proofs(&["AccountAfterFirstLevel", "UpdateOneLevel", "AddBranch"]);
+        let num_nodes = nodes.len();
+        let segments = split_mpt_witness(nodes.clone());
+        assert!(segments.len() > 1);
+        assert_eq!(
+            segments.iter().map(|s| s.nodes.len()).sum::<usize>(),
+            num_nodes
+        );
+        for segment in segments.iter() {
+            assert!(segment.nodes[0].start.is_some());
+        }
+        verify_mpt_witness_par(nodes.clone(), 2).unwrap();
+
+        // Break the root of the last proof, only its segment fails
+        let mut nodes = nodes;
+        let last_start = nodes.iter().rposition(|node| {
+            node.start
+                .as_ref()
+                .map_or(false, |start| start.proof_type != MPTProofType::Disabled)
+        });
+        let mut root = nodes[last_start.unwrap()].values[0].to_vec();
+        root[1] ^= 1;
+        nodes[last_start.unwrap()].values[0] = root.into();
+        assert!(verify_mpt_witness_par(nodes, 2).is_err());
+    }
+}
@@ -0,0 +1,546 @@
+use eth_types::{Address, H256, U64};
+use ethers::{
+    providers::{JsonRpcError, Middleware, MiddlewareError, ProviderError, RpcError},
+    types::{BlockId, BlockNumber},
+};
+use eyre::{bail, eyre, Result};
+use halo2_proofs::halo2curves::bn256::Fr;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    circuit::{
+        ChainConfig, PublicInputs, StateUpdateCircuit, StateUpdateCircuitKeys, StateUpdateWitness,
+        DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT,
+    },
+    metrics::{self, Metrics},
+    utils::MM,
+};
+
+/// A list of storage slots to monitor
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchList(pub Vec<(Address, H256)>);
+
+impl FromStr for WatchList {
+    type Err = eyre::Report;
+
+    /// Parses one `address:slot` pair per line, empty lines and lines starting
+    /// with `#` are ignored.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut slots = Vec::new();
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (address, slot) = line
+                .split_once(':')
+                .ok_or_else(|| eyre!("line {}: expected `address:slot`", idx + 1))?;
+            let address = Address::from_str(address.trim())
+                .map_err(|e| eyre!("line {}: invalid address: {}", idx + 1, e))?;
+            let slot = H256::from_str(slot.trim())
+                .map_err(|e| eyre!("line {}: invalid slot: {}", idx + 1, e))?;
+            slots.push((address, slot));
+        }
+        Ok(WatchList(slots))
+    }
+}
+
+impl WatchList {
+    pub fn load(path: &str) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Returns the watched slots whose value changed in `block_no`
+    pub async fn changed_slots(&self, client: &MM, block_no: U64) -> Result<Vec<(Address, H256)>> {
+        let mut by_address: HashMap<Address, Vec<H256>> = HashMap::new();
+        for (address, slot) in self.0.iter() {
+            by_address.entry(*address).or_default().push(*slot);
+        }
+
+        let mut changed = Vec::new();
+        for (address, slots) in by_address {
+            let old = client
+                .get_proof(
+                    address,
+                    slots.clone(),
+                    Some(BlockId::Number(BlockNumber::Number(block_no - 1))),
+                )
+                .await?;
+            let new = client
+                .get_proof(
+                    address,
+                    slots.clone(),
+                    Some(BlockId::Number(BlockNumber::Number(block_no))),
+                )
+                .await?;
+            // The whole storage trie is unchanged, no need to look at the slots
+            if old.storage_hash == new.storage_hash {
+                continue;
+            }
+            for slot in slots {
+                let old_value = old.storage_proof.iter().find(|p| p.key == slot);
+                let new_value = new.storage_proof.iter().find(|p| p.key == slot);
+                if old_value.map(|p| p.value) != new_value.map(|p| p.value) {
+                    changed.push((address, slot));
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Value of a watched slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotValue {
+    pub address: Address,
+    pub slot: H256,
+    pub value: H256,
+}
+
+/// State of a `watch` run, written after every processed block so that a long
+/// run can be restarted where it stopped instead of at the chain tip
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Last processed block, blocks without a change of a watched slot included
+    pub block_no: U64,
+    pub block_hash: H256,
+    pub state_root: H256,
+    /// Last block with a verified state update proof
+    pub last_proven_block: Option<U64>,
+    /// Values of the watched slots at `block_no`
+    pub slots: Vec<SlotValue>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &str) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// Writes to a temporary file first, so that an interrupted run never
+    /// leaves a truncated checkpoint behind
+    pub fn save(&self, path: &str) -> Result<()> {
+        let tmp_path = format!("{}.tmp", path);
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads the block and the values of the watched slots at `block_no`
+    pub async fn fetch(client: &MM, watch_list: &WatchList, block_no: U64) -> Result<Self> {
+        let mut checkpoint = Checkpoint {
+            slots: watch_list
+                .0
+                .iter()
+                .map(|(address, slot)| SlotValue {
+                    address: *address,
+                    slot: *slot,
+                    value: H256::zero(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        checkpoint
+            .advance(client, block_no, &watch_list.0, false)
+            .await?;
+        Ok(checkpoint)
+    }
+
+    /// Moves the checkpoint to `block_no`, only the values of the `changed`
+    /// slots are read again
+    pub async fn advance(
+        &mut self,
+        client: &MM,
+        block_no: U64,
+        changed: &[(Address, H256)],
+        is_proven: bool,
+    ) -> Result<()> {
+        let block_id = BlockId::Number(BlockNumber::Number(block_no));
+        let block = client
+            .get_block(block_id)
+            .await?
+            .ok_or_else(|| eyre!("block {} not found", block_no))?;
+        for (address, slot) in changed.iter() {
+            let value = client
+                .get_storage_at(*address, *slot, Some(block_id))
+                .await?;
+            if let Some(slot_value) = self.slot_mut(*address, *slot) {
+                slot_value.value = value;
+            }
+        }
+        self.block_no = block_no;
+        self.block_hash = block
+            .hash
+            .ok_or_else(|| eyre!("block {} is pending", block_no))?;
+        self.state_root = block.state_root;
+        if is_proven {
+            self.last_proven_block = Some(block_no);
+        }
+        Ok(())
+    }
+
+    /// Checks that the checkpoint watches the slots of `watch_list` and is
+    /// still on the chain of the node
+    pub async fn check(&self, client: &MM, watch_list: &WatchList) -> Result<()> {
+        let sorted = |mut slots: Vec<(Address, H256)>| {
+            slots.sort();
+            slots.dedup();
+            slots
+        };
+        let checkpoint_slots = sorted(self.slots.iter().map(|s| (s.address, s.slot)).collect());
+        if checkpoint_slots != sorted(watch_list.0.clone()) {
+            bail!("the checkpoint was written for another watch list");
+        }
+
+        let block = client
+            .get_block(BlockId::Number(BlockNumber::Number(self.block_no)))
+            .await?
+            .ok_or_else(|| eyre!("checkpoint block {} not found", self.block_no))?;
+        if block.hash != Some(self.block_hash) {
+            bail!(
+                "checkpoint block {} {:?} is not on the chain anymore",
+                self.block_no,
+                self.block_hash
+            );
+        }
+        Ok(())
+    }
+
+    pub fn slot(&self, address: Address, slot: H256) -> Option<&SlotValue> {
+        self.slots
+            .iter()
+            .find(|s| s.address == address && s.slot == slot)
+    }
+
+    fn slot_mut(&mut self, address: Address, slot: H256) -> Option<&mut SlotValue> {
+        self.slots
+            .iter_mut()
+            .find(|s| s.address == address && s.slot == slot)
+    }
+}
+
+/// Pause before retrying a block after a failed request to the node
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Follows the chain and only generates a state update proof for the blocks
+/// where at least one of the watched slots changed.
+///
+/// With `checkpoint_path` the state of the run is saved after every block, and
+/// with `resume` the run continues after the block of the saved checkpoint.
+/// With `metrics_addr` the metrics of the run are served on `GET /metrics`.
+/// The witnesses follow the state rules of `chain`, or of the chain served by
+/// the node when no chain is selected.
+pub async fn watch(
+    watch_list: WatchList,
+    provider_url: &str,
+    pvk: &str,
+    chain: Option<&ChainConfig>,
+    checkpoint_path: Option<&str>,
+    resume: bool,
+    metrics_addr: Option<SocketAddr>,
+) -> Result<()> {
+    let client: Arc<MM> = crate::utils::new_eth_signer_client(provider_url, pvk).await?;
+
+    let metrics = Arc::new(Metrics::default());
+    let mut metrics_server =
+        metrics_addr.map(|addr| tokio::spawn(metrics::serve(addr, metrics.clone())));
+
+    let mut keys: Option<StateUpdateCircuitKeys> = None;
+    let mut checkpoint = match checkpoint_path {
+        Some(path) if resume => {
+            let checkpoint = Checkpoint::load(path)?;
+            checkpoint.check(&client, &watch_list).await?;
+            checkpoint
+        }
+        _ => {
+            let block_no = client.get_block_number().await?;
+            Checkpoint::fetch(&client, &watch_list, block_no).await?
+        }
+    };
+    let mut last_processed_block = checkpoint.block_no;
+
+    println!(
+        "Watching {} slots from block {}",
+        watch_list.0.len(),
+        last_processed_block
+    );
+
+    loop {
+        if let Some(server) = metrics_server
+            .as_mut()
+            .filter(|server| server.is_finished())
+        {
+            server.await??;
+            bail!("the metrics server stopped");
+        }
+
+        let current_block = match client.get_block_number().await {
+            Ok(block_no) => block_no,
+            Err(err) => {
+                retry_later(&metrics, last_processed_block + 1, err.into()).await?;
+                continue;
+            }
+        };
+        if current_block <= last_processed_block {
+            metrics.set_queue_depth(0);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        metrics.set_queue_depth((current_block - last_processed_block).as_u64());
+
+        let block_no = last_processed_block + 1;
+
+        let changed = match watch_list.changed_slots(&client, block_no).await {
+            Ok(changed) => changed,
+            Err(err) => {
+                retry_later(&metrics, block_no, err).await?;
+                continue;
+            }
+        };
+        if changed.is_empty() {
+            last_processed_block = block_no;
+            advance_checkpoint(
+                &mut checkpoint,
+                &client,
+                &metrics,
+                checkpoint_path,
+                block_no,
+                &[],
+                false,
+            )
+            .await?;
+            continue;
+        }
+
+        for (address, slot) in changed.iter() {
+            println!(
+                "Block {}: slot {:?} of {:?} changed from {:?}",
+                block_no,
+                slot,
+                address,
+                checkpoint.slot(*address, *slot).map(|s| s.value)
+            );
+        }
+
+        // The state root transition is proven for the whole block
+        let start = Instant::now();
+        let witness = match StateUpdateWitness::<Fr>::build(
+            client.clone(),
+            provider_url,
+            block_no,
+            None,
+            chain,
+        )
+        .await
+        {
+            Ok(witness) => witness,
+            Err(err) => {
+                retry_later(&metrics, block_no, err).await?;
+                continue;
+            }
+        };
+        last_processed_block = block_no;
+
+        let Some(witness) = witness else {
+            advance_checkpoint(
+                &mut checkpoint,
+                &client,
+                &metrics,
+                checkpoint_path,
+                block_no,
+                &changed,
+                false,
+            )
+            .await?;
+            continue;
+        };
+
+        let public_inputs: PublicInputs<Fr> = (&witness.lc_witness).into();
+        let proof = (|| {
+            let circuit =
+                StateUpdateCircuit::new(witness, DEFAULT_CIRCUIT_DEGREE, DEFAULT_MAX_PROOF_COUNT)?;
+            if keys.is_none() {
+                keys = Some(StateUpdateCircuitKeys::new(&circuit));
+            }
+            let proof = circuit.prove(keys.as_ref().unwrap())?;
+            StateUpdateCircuit::verify(&proof, &public_inputs, keys.as_ref().unwrap())?;
+            Ok::<_, eyre::Report>(proof)
+        })()
+        .map_err(|err| {
+            metrics.proving_failed();
+            err
+        })?;
+        metrics.block_proven(start.elapsed());
+
+        println!(
+            "Block {}: proof generated ({} bytes)",
+            block_no,
+            proof.len()
+        );
+
+        advance_checkpoint(
+            &mut checkpoint,
+            &client,
+            &metrics,
+            checkpoint_path,
+            block_no,
+            &changed,
+            true,
+        )
+        .await?;
+    }
+}
+
+/// Moves `checkpoint` to `block_no` like `Checkpoint::advance`, retrying
+/// after the transient errors, and saves it to `checkpoint_path`
+async fn advance_checkpoint(
+    checkpoint: &mut Checkpoint,
+    client: &MM,
+    metrics: &Metrics,
+    checkpoint_path: Option<&str>,
+    block_no: U64,
+    changed: &[(Address, H256)],
+    is_proven: bool,
+) -> Result<()> {
+    while let Err(err) = checkpoint
+        .advance(client, block_no, changed, is_proven)
+        .await
+    {
+        retry_later(metrics, block_no, err).await?;
+    }
+    if let Some(path) = checkpoint_path {
+        checkpoint.save(path)?;
+    }
+    Ok(())
+}
+
+/// Reports a failed request to the node, the block is retried after a pause.
+/// Fails with `err` when retrying can not help, see `is_transient`.
+async fn retry_later(metrics: &Metrics, block_no: U64, err: eyre::Report) -> Result<()> {
+    if !is_transient(&err) {
+        return Err(err.wrap_err(format!("block {}", block_no)));
+    }
+    println!(
+        "Block {}: request to the node failed, retrying: {}",
+        block_no, err
+    );
+    metrics.rpc_error();
+    tokio::time::sleep(RETRY_DELAY).await;
+    Ok(())
+}
+
+/// Whether `err` is a failed request to the node that can succeed later: the
+/// node could not be reached, it rate limits the requests or it does not have
+/// the block yet. The other errors of the node reject the request itself, and
+/// the errors outside of the requests, building the witness for instance, do
+/// not depend on the node either.
+fn is_transient(err: &eyre::Report) -> bool {
+    err.chain().any(|cause| {
+        let provider_err = cause
+            .downcast_ref::<<MM as Middleware>::Error>()
+            .and_then(|err| err.as_provider_error())
+            .or_else(|| cause.downcast_ref::<ProviderError>());
+        match provider_err {
+            Some(ProviderError::HTTPError(_)) => true,
+            Some(ProviderError::JsonRpcClientError(err)) => match err.as_error_response() {
+                Some(response) => is_transient_response(response),
+                // The request did not get a response, unless it can't be parsed
+                None => err.as_serde_error().is_none(),
+            },
+            _ => false,
+        }
+    })
+}
+
+/// Whether the node returned `response` for a request it may serve later
+fn is_transient_response(response: &JsonRpcError) -> bool {
+    // Rate limits, and load balanced nodes behind the chain tip
+    response.code == 429
+        || response.code == -32005
+        || response.message.contains("header not found")
+        || response.message.contains("unknown block")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_watch_list() {
+        let watch_list: WatchList = "
+            # comment
+            0x4E5B2e1dc63F6b91cb6Cd759936495434C7e972F:0x0000000000000000000000000000000000000000000000000000000000000012
+
+            0x4E5B2e1dc63F6b91cb6Cd759936495434C7e972F : 0x0000000000000000000000000000000000000000000000000000000000000000
+        "
+        .parse()
+        .unwrap();
+        assert_eq!(watch_list.0.len(), 2);
+        assert_eq!(watch_list.0[0].1, H256::from_low_u64_be(0x12));
+
+        assert!("0x4E5B2e1dc63F6b91cb6Cd759936495434C7e972F"
+            .parse::<WatchList>()
+            .is_err());
+    }
+
+    #[test]
+    fn transient_errors() {
+        let response = |code: i64, message: &str| {
+            eyre::Report::new(ProviderError::JsonRpcClientError(Box::new(
+                ethers::providers::HttpClientError::JsonRpcError(JsonRpcError {
+                    code,
+                    message: message.to_string(),
+                    data: None,
+                }),
+            )))
+        };
+        // Rate limits and blocks the node does not have yet are retried
+        assert!(is_transient(&response(429, "Too Many Requests")));
+        assert!(is_transient(&response(-32005, "limit exceeded")));
+        assert!(is_transient(
+            &response(-32000, "header not found").wrap_err("changed slots")
+        ));
+        // The node rejects the request
+        assert!(!is_transient(&response(
+            -32601,
+            "the method does not exist"
+        )));
+        assert!(!is_transient(&response(-32000, "missing trie node")));
+        // Not a request to the node
+        assert!(!is_transient(&eyre!("the MPT witness is invalid")));
+    }
+
+    #[test]
+    fn checkpoint_roundtrip() {
+        let checkpoint = Checkpoint {
+            block_no: 123.into(),
+            block_hash: H256::from_low_u64_be(1),
+            state_root: H256::from_low_u64_be(2),
+            last_proven_block: Some(120.into()),
+            slots: vec![SlotValue {
+                address: Address::from_low_u64_be(3),
+                slot: H256::from_low_u64_be(0x12),
+                value: H256::from_low_u64_be(4),
+            }],
+        };
+        let mut path = std::env::temp_dir();
+        path.push(format!("watch-checkpoint-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        checkpoint.save(path).unwrap();
+        assert_eq!(Checkpoint::load(path).unwrap(), checkpoint);
+        assert_eq!(
+            checkpoint
+                .slot(Address::from_low_u64_be(3), H256::from_low_u64_be(0x12))
+                .map(|s| s.value),
+            Some(H256::from_low_u64_be(4))
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
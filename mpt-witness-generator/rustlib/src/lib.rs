@@ -1,12 +1,15 @@
 use ethers::types::{Address, H256, U64};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     ffi::{CStr, CString},
     os::raw::c_char,
 };
 
 use num_enum::IntoPrimitive;
-use zkevm_circuits::{mpt_circuit::witness_row::Node, util::U256};
+use zkevm_circuits::{
+    mpt_circuit::{parse_proof, witness_row::Node},
+    util::U256,
+};
 
 mod golang {
     use super::*;
@@ -16,7 +19,7 @@ mod golang {
     }
 }
 
-#[derive(Default, Debug, IntoPrimitive, Clone, Copy)]
+#[derive(Default, Debug, IntoPrimitive, Clone, Copy, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum ProofType {
     #[default]
@@ -31,7 +34,7 @@ pub enum ProofType {
     AccountCreate = 8,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct TrieModification {
     pub typ: ProofType,
     pub key: H256,
@@ -100,26 +103,8 @@ pub fn get_witness(block_no: u64, mods: &[TrieModification], node_url: &str) ->
     let c_str = unsafe { CStr::from_ptr(result) };
     let json = c_str.to_str().expect("Error translating from library");
 
-    let mut nodes: Vec<Node> = serde_json::from_str(json).unwrap();
+    let nodes = parse_proof(json).unwrap();
     unsafe { golang::FreeString(c_str.as_ptr()) };
-
-    // Add the address and the key to the list of values in the Account and Storage nodes
-    for node in nodes.iter_mut() {
-        if node.account.is_some() {
-            let account = node.account.clone().unwrap();
-            node.values
-                .push([vec![148], account.address.to_vec()].concat().into());
-            node.values
-                .push([vec![160], account.key.to_vec()].concat().into());
-        }
-        if node.storage.is_some() {
-            let storage = node.storage.clone().unwrap();
-            node.values
-                .push([vec![160], storage.address.to_vec()].concat().into());
-            node.values
-                .push([vec![160], storage.key.to_vec()].concat().into());
-        }
-    }
     nodes
 }
 
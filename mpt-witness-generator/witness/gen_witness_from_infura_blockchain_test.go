@@ -2133,6 +2133,109 @@ func TestBranchAfterExtNode(t *testing.T) {
 	oracle.PreventHashingInSecureTrie = false
 }
 
+// prepareChainedExtensions stores the (unhashed) keys in a fresh storage trie and
+// modifies the first one. The keys are chosen so that the path of the first key
+// goes through several extension nodes, each followed by a branch.
+func prepareChainedExtensions(testName string, keysHex []string) {
+	blockNum := 0
+	blockNumberParent := big.NewInt(int64(blockNum))
+	blockHeaderParent := oracle.PrefetchBlock(blockNumberParent, true, nil)
+	database := state.NewDatabase(blockHeaderParent)
+	statedb, _ := state.New(blockHeaderParent.Root, database, nil)
+	addr := common.HexToAddress("0x40efbf12580138bc623c95757286df4e24eb81c9")
+
+	statedb.DisableLoadingRemoteAccounts()
+
+	statedb.CreateAccount(addr)
+
+	oracle.PreventHashingInSecureTrie = true // to store the unchanged key
+
+	for i := 0; i < len(keysHex); i++ {
+		key := common.HexToHash(keysHex[i])
+		val := common.BigToHash(big.NewInt(int64(111 * (i + 1))))
+		statedb.SetState(addr, key, val)
+	}
+
+	statedb.IntermediateRoot(false)
+
+	val := common.BigToHash(big.NewInt(int64(17)))
+	trieMod := TrieModification{
+		Type:    StorageChanged,
+		Key:     common.HexToHash(keysHex[0]),
+		Value:   val,
+		Address: addr,
+	}
+	trieModifications := []TrieModification{trieMod}
+
+	prepareWitness(testName, trieModifications, statedb)
+
+	oracle.PreventHashingInSecureTrie = false
+}
+
+func TestChainedExtensionsEvenOdd(t *testing.T) {
+	// extension [0,0,0,0] -> branch (1) -> extension [0,0,0] -> branch (1) -> leaf
+	keys := []string{
+		"0x0000100010000000000000000000000000000000000000000000000000000000",
+		"0x0000100020000000000000000000000000000000000000000000000000000000",
+		"0x0000200000000000000000000000000000000000000000000000000000000000",
+	}
+	prepareChainedExtensions("ChainedExtensionsEvenOdd", keys)
+}
+
+func TestChainedExtensionsOddEven(t *testing.T) {
+	// extension [0,0,0] -> branch (1) -> extension [0,0] -> branch (1) -> leaf
+	keys := []string{
+		"0x0001001000000000000000000000000000000000000000000000000000000000",
+		"0x0001002000000000000000000000000000000000000000000000000000000000",
+		"0x0002000000000000000000000000000000000000000000000000000000000000",
+	}
+	prepareChainedExtensions("ChainedExtensionsOddEven", keys)
+}
+
+func TestChainedExtensionsThreeLevels(t *testing.T) {
+	// extension [0] -> branch (1) -> extension [0,0] -> branch (1) ->
+	// extension [0,0,0] -> branch (1) -> leaf
+	keys := []string{
+		"0x0100100010000000000000000000000000000000000000000000000000000000",
+		"0x0100100020000000000000000000000000000000000000000000000000000000",
+		"0x0100200000000000000000000000000000000000000000000000000000000000",
+		"0x0200000000000000000000000000000000000000000000000000000000000000",
+	}
+	prepareChainedExtensions("ChainedExtensionsThreeLevels", keys)
+}
+
+// prepareMinedKeys stores the mined keys (see key_collisions.go) in the storage trie of a new
+// account and modifies the first of them.
+func prepareMinedKeys(testName string, keys []common.Hash) {
+	addr := common.HexToAddress("0x50efbf12580138bc623c95757286df4e24eb81c9")
+	var values []common.Hash
+	var addresses []common.Address
+	for i := 0; i < len(keys); i++ {
+		values = append(values, common.BigToHash(big.NewInt(int64(i+1))))
+		addresses = append(addresses, addr)
+	}
+
+	trieMod := TrieModification{
+		Type:    StorageChanged,
+		Key:     keys[0],
+		Value:   common.BigToHash(big.NewInt(int64(17))),
+		Address: addr,
+	}
+	trieModifications := []TrieModification{trieMod}
+
+	updateStateAndPrepareWitness(testName, keys, values, addresses, trieModifications)
+}
+
+func TestMinedKeysBranchChain(t *testing.T) {
+	// The modified leaf is below five branches
+	prepareMinedKeys("MinedKeysBranchChain", MineStorageKeysForBranchChain(5))
+}
+
+func TestMinedKeysLongExtension(t *testing.T) {
+	// The three leaves are below an extension node of at least five nibbles
+	prepareMinedKeys("MinedKeysLongExtension", MineStorageKeysWithCommonPrefix(5, 3))
+}
+
 func TestNonExistingStorage(t *testing.T) {
 	ks := [...]common.Hash{common.HexToHash("0x11"), common.HexToHash("0x12")}
 	// hexed keys:
@@ -0,0 +1,81 @@
+package witness
+
+import (
+	"math/big"
+
+	"github.com/ethereum/go-ethereum/common"
+	"github.com/ethereum/go-ethereum/crypto"
+)
+
+// The storage trie is keyed by the keccak hashes of the storage keys, so the deep branches
+// and the long extension nodes only appear for keys whose hashes collide in their top nibbles.
+// The functions below mine such keys by trying the keys 0, 1, 2, ... in order, so the same
+// trie is built on every run.
+
+// nibbleAt returns the nibble at position pos of the hash.
+func nibbleAt(hash []byte, pos int) byte {
+	if pos%2 == 0 {
+		return hash[pos/2] / 16
+	}
+	return hash[pos/2] % 16
+}
+
+// commonNibblePrefixLen returns the number of top nibbles that the two hashes share.
+func commonNibblePrefixLen(a, b []byte) int {
+	n := 0
+	for n < 2*len(a) && n < 2*len(b) && nibbleAt(a, n) == nibbleAt(b, n) {
+		n++
+	}
+	return n
+}
+
+// minedKey returns the i-th candidate storage key and its hash.
+func minedKey(i int64) (common.Hash, []byte) {
+	key := common.BigToHash(big.NewInt(i))
+	return key, crypto.Keccak256(key.Bytes())
+}
+
+// MineStorageKeysWithCommonPrefix returns count storage keys whose hashes share their top
+// prefixNibbles nibbles. Stored together in an otherwise empty storage trie, the keys are
+// below an extension node of at least prefixNibbles nibbles.
+func MineStorageKeysWithCommonPrefix(prefixNibbles, count int) []common.Hash {
+	if prefixNibbles > 2*common.HashLength || count < 1 {
+		panic("invalid prefix length or key count")
+	}
+	buckets := make(map[string][]common.Hash)
+	for i := int64(0); ; i++ {
+		key, hash := minedKey(i)
+		prefix := make([]byte, prefixNibbles)
+		for j := 0; j < prefixNibbles; j++ {
+			prefix[j] = nibbleAt(hash, j)
+		}
+		bucket := append(buckets[string(prefix)], key)
+		if len(bucket) == count {
+			return bucket
+		}
+		buckets[string(prefix)] = bucket
+	}
+}
+
+// MineStorageKeysForBranchChain returns depth + 1 storage keys such that the hash of the
+// key i (i > 0) shares exactly i - 1 top nibbles with the hash of the first key. Stored
+// together in an otherwise empty storage trie, the first key is below a chain of depth
+// branches, each of them having two children. The cost grows as 16^depth, depth 5 takes
+// about a million hashes.
+func MineStorageKeysForBranchChain(depth int) []common.Hash {
+	if depth < 1 || depth >= 2*common.HashLength {
+		panic("invalid depth")
+	}
+	first, firstHash := minedKey(0)
+	keys := []common.Hash{first}
+	for d := 0; d < depth; d++ {
+		for i := int64(1); ; i++ {
+			key, hash := minedKey(i)
+			if commonNibblePrefixLen(firstHash, hash) == d {
+				keys = append(keys, key)
+				break
+			}
+		}
+	}
+	return keys
+}
@@ -0,0 +1,41 @@
+package witness
+
+import (
+	"testing"
+
+	"github.com/ethereum/go-ethereum/crypto"
+)
+
+func TestMineStorageKeysWithCommonPrefix(t *testing.T) {
+	keys := MineStorageKeysWithCommonPrefix(4, 3)
+	if len(keys) != 3 {
+		t.Fatalf("expected 3 keys, got %d", len(keys))
+	}
+	first := crypto.Keccak256(keys[0].Bytes())
+	for _, key := range keys[1:] {
+		if n := commonNibblePrefixLen(first, crypto.Keccak256(key.Bytes())); n < 4 {
+			t.Fatalf("key %x shares only %d nibbles", key, n)
+		}
+	}
+
+	// The keys are mined deterministically
+	again := MineStorageKeysWithCommonPrefix(4, 3)
+	for i := range keys {
+		if keys[i] != again[i] {
+			t.Fatalf("key %d differs between runs", i)
+		}
+	}
+}
+
+func TestMineStorageKeysForBranchChain(t *testing.T) {
+	keys := MineStorageKeysForBranchChain(4)
+	if len(keys) != 5 {
+		t.Fatalf("expected 5 keys, got %d", len(keys))
+	}
+	first := crypto.Keccak256(keys[0].Bytes())
+	for i, key := range keys[1:] {
+		if n := commonNibblePrefixLen(first, crypto.Keccak256(key.Bytes())); n != i {
+			t.Fatalf("key %d shares %d nibbles, expected %d", i+1, n, i)
+		}
+	}
+}
@@ -95,12 +95,16 @@ type AccountNode struct {
 	DriftedRlpBytes   []byte
 	WrongRlpBytes     []byte
 	IsModExtension    [2]bool
+
+	// Key is not hashed because the preimage check is disabled
+	IsPreimage bool
 }
 
 func (n *AccountNode) MarshalJSON() ([]byte, error) {
 	jsonData := struct {
 		Address           string   `json:"address"`
-		Key               string   `json:"key"`
+		HashedKey         string   `json:"hashed_key,omitempty"`
+		Preimage          string   `json:"preimage,omitempty"`
 		ListRlpBytes      []string `json:"list_rlp_bytes"`
 		ValueRlpBytes     []string `json:"value_rlp_bytes"`
 		ValueListRlpBytes []string `json:"value_list_rlp_bytes"`
@@ -109,7 +113,8 @@ func (n *AccountNode) MarshalJSON() ([]byte, error) {
 		IsModExtension    [2]bool  `json:"is_mod_extension"`
 	}{
 		Address:           base64ToString(n.Address.Bytes()),
-		Key:               base64ToString(n.Key),
+		HashedKey:         hashedKey(n.Key, n.IsPreimage),
+		Preimage:          preimage(n.Key, n.IsPreimage),
 		ListRlpBytes:      encodeArray(n.ListRlpBytes[:]),
 		ValueRlpBytes:     encodeArray(n.ValueRlpBytes[:]),
 		ValueListRlpBytes: encodeArray(n.ValueListRlpBytes[:]),
@@ -128,12 +133,16 @@ type StorageNode struct {
 	DriftedRlpBytes []byte
 	WrongRlpBytes   []byte
 	IsModExtension  [2]bool
+
+	// Key is not hashed because the preimage check is disabled
+	IsPreimage bool
 }
 
 func (n *StorageNode) MarshalJSON() ([]byte, error) {
 	jsonData := struct {
 		Address         string   `json:"address"`
-		Key             string   `json:"key"`
+		HashedKey       string   `json:"hashed_key,omitempty"`
+		Preimage        string   `json:"preimage,omitempty"`
 		ListRlpBytes    []string `json:"list_rlp_bytes"`
 		ValueRlpBytes   []string `json:"value_rlp_bytes"`
 		DriftedRlpBytes string   `json:"drifted_rlp_bytes"`
@@ -141,7 +150,8 @@ func (n *StorageNode) MarshalJSON() ([]byte, error) {
 		IsModExtension  [2]bool  `json:"is_mod_extension"`
 	}{
 		Address:         base64ToString(n.Address.Bytes()),
-		Key:             base64ToString(n.Key),
+		HashedKey:       hashedKey(n.Key, n.IsPreimage),
+		Preimage:        preimage(n.Key, n.IsPreimage),
 		ListRlpBytes:    encodeArray(n.ListRlpBytes[:]),
 		ValueRlpBytes:   encodeArray(n.ValueRlpBytes[:]),
 		DriftedRlpBytes: base64ToString(n.DriftedRlpBytes),
@@ -151,6 +161,37 @@ func (n *StorageNode) MarshalJSON() ([]byte, error) {
 	return json.Marshal(jsonData)
 }
 
+func hashedKey(key []byte, isPreimage bool) string {
+	if isPreimage {
+		return ""
+	}
+	return base64ToString(key)
+}
+
+func preimage(key []byte, isPreimage bool) string {
+	if !isPreimage {
+		return ""
+	}
+	return base64ToString(key)
+}
+
+// setKeyTypes marks the keys of the account and storage nodes of the proofs
+// that have the preimage check disabled as not hashed.
+func setKeyTypes(nodes []Node) {
+	disablePreimageCheck := false
+	for _, node := range nodes {
+		if node.Start != nil {
+			disablePreimageCheck = node.Start.DisablePreimageCheck
+		}
+		if node.Account != nil {
+			node.Account.IsPreimage = disablePreimageCheck
+		}
+		if node.Storage != nil {
+			node.Storage.IsPreimage = disablePreimageCheck
+		}
+	}
+}
+
 type JSONableValues [][]byte
 
 func (u JSONableValues) MarshalJSON() ([]byte, error) {
@@ -300,6 +300,7 @@ func obtainTwoProofsAndConvertToWitness(trieModifications []TrieModification, st
 			nodes = append(nodes, accountNodes...)
 		}
 	}
+	setKeyTypes(nodes)
 
 	return nodes
 }
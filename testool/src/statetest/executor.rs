@@ -17,6 +17,7 @@ use thiserror::Error;
 use zkevm_circuits::{
     super_circuit::SuperCircuit,
     test_util::{CircuitTestBuilder, CircuitTestError},
+    util::Randomness,
     witness::Block,
 };
 
@@ -376,8 +377,12 @@ pub fn run_test(
             max_evm_rows: 0,
             max_keccak_rows: 0,
         };
-        let (k, circuit, instance, _builder) =
-            SuperCircuit::<Fr>::build(geth_data, circuits_params, Fr::from(0x100)).unwrap();
+        let (k, circuit, instance, _builder) = SuperCircuit::<Fr>::build(
+            geth_data,
+            circuits_params,
+            Randomness::mock(Fr::from(0x100)),
+        )
+        .unwrap();
         builder = _builder;
 
         let prover = MockProver::run(k, &circuit, instance).unwrap();
@@ -14,7 +14,7 @@ use crate::{
         constraint_builder::{BaseConstraintBuilder, ConstrainBuilderCommon},
         not, or, select,
     },
-    table::{BytecodeFieldTag, BytecodeTable, KeccakTable, LookupTable},
+    table::{BytecodeFieldTag, BytecodeTable, KeccakTable, LookupTable, MPTProofType, MptTable},
     util::{
         self, get_push_size,
         word::{empty_code_hash_word_value, Word, Word32, WordExpr},
@@ -182,6 +182,10 @@ pub struct BytecodeCircuitConfigArgs<F: Field> {
     pub bytecode_table: BytecodeTable,
     /// KeccakTable
     pub keccak_table: KeccakTable,
+    /// MptTable, when given every non-empty bytecode must be the code hash of an
+    /// account in the table, so that only code proven to belong to an account
+    /// can be executed. Not usable when init code is in the bytecode table.
+    pub mpt_table: Option<MptTable>,
     /// Challenges
     pub challenges: Challenges<Expression<F>>,
 }
@@ -195,6 +199,7 @@ impl<F: Field> SubCircuitConfig<F> for BytecodeCircuitConfig<F> {
         Self::ConfigArgs {
             bytecode_table,
             keccak_table,
+            mpt_table,
             challenges,
         }: Self::ConfigArgs,
     ) -> Self {
@@ -549,6 +554,28 @@ impl<F: Field> SubCircuitConfig<F> for BytecodeCircuitConfig<F> {
             },
         );
 
+        // When is_byte_to_header and the MPT table is given ->
+        // assert mpt_table_lookup(CodeHashChanged, cur.hash_word)
+        if let Some(mpt_table) = mpt_table {
+            meta.lookup_any("mpt_table_lookup(CodeHashChanged, cur.hash_word)", |meta| {
+                let enable = and::expr(vec![
+                    meta.query_fixed(q_enable, Rotation::cur()),
+                    not::expr(meta.query_fixed(q_last, Rotation::cur())),
+                    is_byte_to_header(meta),
+                ]);
+                let code_hash = bytecode_table.code_hash.query_advice(meta, Rotation::cur());
+                let new_value = mpt_table.new_value.query_advice(meta, Rotation::cur());
+                vec![
+                    (
+                        enable.clone() * MPTProofType::CodeHashChanged.expr(),
+                        meta.query_advice(mpt_table.proof_type, Rotation::cur()),
+                    ),
+                    (enable.clone() * code_hash.lo(), new_value.lo()),
+                    (enable * code_hash.hi(), new_value.hi()),
+                ]
+            });
+        }
+
         let push_data_left_is_zero = IsZeroChip::construct(push_data_left_is_zero);
         let index_length_diff_is_zero = IsZeroChip::construct(index_length_diff_is_zero);
 
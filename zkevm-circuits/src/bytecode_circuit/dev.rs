@@ -33,6 +33,7 @@ impl<F: Field> Circuit<F> for BytecodeCircuit<F> {
                 BytecodeCircuitConfigArgs {
                     bytecode_table,
                     keccak_table,
+                    mpt_table: None,
                     challenges,
                 },
             )
@@ -1,8 +1,21 @@
-use super::{BytecodeCircuit, BytecodeCircuitRow};
-use crate::util::{log2_ceil, unusable_rows, SubCircuit};
+use super::{
+    BytecodeCircuit, BytecodeCircuitConfig, BytecodeCircuitConfigArgs, BytecodeCircuitRow,
+};
+use crate::{
+    table::{AccountFieldTag, BytecodeTable, KeccakTable, MptTable},
+    util::{log2_ceil, unusable_rows, Challenges, SubCircuit, SubCircuitConfig},
+    witness::{MptUpdates, Rw},
+};
 use bus_mapping::{evm::OpcodeId, state_db::CodeDB};
-use eth_types::Field;
-use halo2_proofs::{arithmetic::Field as Halo2Field, dev::MockProver, halo2curves::bn256::Fr};
+use eth_types::{Address, Field, ToWord};
+use halo2_proofs::{
+    arithmetic::Field as Halo2Field,
+    circuit::{Layouter, SimpleFloorPlanner},
+    dev::MockProver,
+    halo2curves::bn256::Fr,
+    plonk::{Circuit, ConstraintSystem, Error},
+};
+use itertools::Itertools;
 use log::error;
 
 #[test]
@@ -238,3 +251,89 @@ fn bytecode_soundness_bug_1() {
         })
         .verify(false);
 }
+
+/// Bytecode circuit with the MPT table lookup, the code hashes of `proven`
+/// are in the MPT table
+#[derive(Default)]
+struct ProvenBytecodeCircuit {
+    bytecode_circuit: BytecodeCircuit<Fr>,
+    proven: Vec<Vec<u8>>,
+}
+
+impl Circuit<Fr> for ProvenBytecodeCircuit {
+    type Config = (BytecodeCircuitConfig<Fr>, MptTable, Challenges);
+    type FloorPlanner = SimpleFloorPlanner;
+    type Params = ();
+
+    fn without_witnesses(&self) -> Self {
+        Self::default()
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let bytecode_table = BytecodeTable::construct(meta);
+        let keccak_table = KeccakTable::construct(meta);
+        let mpt_table = MptTable::construct(meta);
+        let challenges = Challenges::construct(meta);
+        let config = BytecodeCircuitConfig::new(
+            meta,
+            BytecodeCircuitConfigArgs {
+                bytecode_table,
+                keccak_table,
+                mpt_table: Some(mpt_table),
+                challenges: challenges.exprs(meta),
+            },
+        );
+        (config, mpt_table, challenges)
+    }
+
+    fn synthesize(
+        &self,
+        (config, mpt_table, challenges): Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let challenges = challenges.values(&mut layouter);
+        let bytecodes = self.bytecode_circuit.bytecodes.clone();
+        config.keccak_table.dev_load(
+            &mut layouter,
+            &bytecodes.into_iter().map(|b| b.code()).collect_vec(),
+            &challenges,
+        )?;
+        let rows = self
+            .proven
+            .iter()
+            .enumerate()
+            .map(|(idx, code)| {
+                let code_hash = CodeDB::hash(code).to_word();
+                Rw::Account {
+                    rw_counter: idx + 1,
+                    is_write: false,
+                    account_address: Address::from_low_u64_be(idx as u64 + 1),
+                    field_tag: AccountFieldTag::CodeHash,
+                    value: code_hash,
+                    value_prev: code_hash,
+                }
+            })
+            .collect_vec();
+        mpt_table.load(&mut layouter, &MptUpdates::mock_from(&rows))?;
+        self.bytecode_circuit
+            .synthesize_sub(&config, &challenges, &mut layouter)
+    }
+}
+
+#[test]
+fn bytecode_mpt_lookup() {
+    let k = 9;
+    let bytecodes = vec![vec![], vec![7u8], vec![OpcodeId::PUSH32.as_u8(), 1, 2]];
+    let verify = |proven: Vec<Vec<u8>>| {
+        let circuit = ProvenBytecodeCircuit {
+            bytecode_circuit: BytecodeCircuit::<Fr>::from_bytes(bytecodes.clone(), k),
+            proven,
+        };
+        MockProver::<Fr>::run(k, &circuit, Vec::new())
+            .unwrap()
+            .verify_par()
+    };
+    // The empty code is not looked up
+    assert_eq!(verify(bytecodes[1..].to_vec()), Ok(()));
+    assert!(verify(bytecodes[..2].to_vec()).is_err());
+}
@@ -6,3 +6,4 @@ pub mod cached_region;
 pub mod cell_manager;
 pub mod gadgets;
 pub mod memory;
+pub mod printer;
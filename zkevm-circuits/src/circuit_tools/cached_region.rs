@@ -11,8 +11,7 @@ use std::{
 };
 
 use super::{
-    cell_manager::{CellColumn, CellType},
-    constraint_builder::ConstraintBuilder,
+    cell_manager::CellType, constraint_builder::ConstraintBuilder, printer::constant_to_string,
 };
 
 pub trait ChallengeSet<F: Field> {
@@ -25,25 +24,40 @@ impl<F: Field, V: AsRef<[Value<F>]>> ChallengeSet<F> for V {
     }
 }
 
+/// Advice assignment recorded when tracing is enabled
+#[derive(Clone, Debug)]
+pub struct TracedAssignment<F> {
+    pub offset: usize,
+    pub column: Column<Advice>,
+    /// What was being assigned, see `CachedRegion::set_trace_context`
+    pub context: String,
+    pub annotation: String,
+    pub value: F,
+}
+
 pub struct CachedRegion<'r, 'b, F: Field> {
     region: &'r mut Region<'b, F>,
     pub advice: HashMap<(usize, usize), F>,
     pub fixed: HashMap<(usize, usize), F>,
     disable_description: bool,
     regions: Vec<(usize, usize)>,
+    trace: Option<Vec<TracedAssignment<F>>>,
+    trace_context: String,
     pub key_r: F,
     pub keccak_r: F,
 }
 
 impl<'r, 'b, F: Field> CachedRegion<'r, 'b, F> {
-    pub(crate) fn new(region: &'r mut Region<'b, F>, keccak_r: F) -> Self {
+    pub(crate) fn new(region: &'r mut Region<'b, F>, keccak_r: F, key_r: F) -> Self {
         Self {
             region,
             advice: HashMap::new(),
             fixed: HashMap::new(),
             disable_description: false,
             regions: Vec::new(),
-            key_r: keccak_r,
+            trace: None,
+            trace_context: String::new(),
+            key_r,
             keccak_r,
         }
     }
@@ -60,32 +74,43 @@ impl<'r, 'b, F: Field> CachedRegion<'r, 'b, F> {
         // Nothing to do
     }
 
+    /// Records all advice assignments from now on
+    pub(crate) fn enable_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    /// Sets the context stored with the traced assignments, only evaluated when
+    /// tracing is enabled
+    pub(crate) fn set_trace_context<C: Fn() -> String>(&mut self, context: C) {
+        if self.trace.is_some() {
+            self.trace_context = context();
+        }
+    }
+
+    /// Returns the assignments recorded up to now
+    pub(crate) fn take_trace(&mut self) -> Vec<TracedAssignment<F>> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
     pub(crate) fn assign_stored_expressions<C: CellType, S: ChallengeSet<F>>(
         &mut self,
         cb: &ConstraintBuilder<F, C>,
         challenges: &S,
     ) -> Result<(), Error> {
         for (offset, region_id) in self.regions.clone() {
-            for stored_expression in cb.get_stored_expressions(region_id).iter() {
+            for stored_expression in cb.get_stored_expressions(region_id) {
+                self.set_trace_context(|| format!("stored expression {}", stored_expression.name));
                 stored_expression.assign(self, challenges, offset)?;
             }
         }
         Ok(())
     }
 
-    pub(crate) fn annotate_columns<C: CellType>(&mut self, cell_columns: &[CellColumn<F, C>]) {
-        for c in cell_columns {
-            self.region.name_column(
-                || {
-                    format!(
-                        "{:?} {:?}: {:?} queried",
-                        c.cell_type.clone(),
-                        c.index,
-                        c.height
-                    )
-                },
-                c.column,
-            );
+    /// Annotates the columns of the region, the names show up in the
+    /// MockProver errors and in dev-graph
+    pub(crate) fn name_columns(&mut self, column_names: &[(Column<Any>, String)]) {
+        for (column, name) in column_names {
+            self.region.name_column(|| name.clone(), *column);
         }
     }
 
@@ -104,17 +129,26 @@ impl<'r, 'b, F: Field> CachedRegion<'r, 'b, F> {
         AR: Into<String>,
     {
         // Actually set the value
-        let res = self.region.assign_advice(annotation, column, offset, &to);
+        let res = self.region.assign_advice(&annotation, column, offset, &to);
         // Cache the value
         // Note that the `value_field` in `AssignedCell` might be `Value::unknown` if
         // the column has different phase than current one, so we call to `to`
         // again here to cache the value.
         if res.is_ok() {
             to().map(|f: VR| {
-                let existing = self
-                    .advice
-                    .insert((column.index(), offset), Assigned::from(&f).evaluate());
+                let value = Assigned::from(&f).evaluate();
+                let existing = self.advice.insert((column.index(), offset), value);
+                #[cfg(not(feature = "fast_assign"))]
                 assert!(existing.is_none());
+                if let Some(trace) = self.trace.as_mut() {
+                    trace.push(TracedAssignment {
+                        offset,
+                        column,
+                        context: self.trace_context.clone(),
+                        annotation: annotation().into(),
+                        value,
+                    });
+                }
                 existing
             });
         }
@@ -155,6 +189,7 @@ impl<'r, 'b, F: Field> CachedRegion<'r, 'b, F> {
                 let existing = self
                     .fixed
                     .insert((column.index(), offset), Assigned::from(&f).evaluate());
+                #[cfg(not(feature = "fast_assign"))]
                 assert!(existing.is_none());
                 existing
             });
@@ -178,6 +213,28 @@ impl<'r, 'b, F: Field> CachedRegion<'r, 'b, F> {
             .unwrap_or(&zero)
     }
 
+    /// Evaluates an expression on the witness assigned up to now at `offset`,
+    /// used to evaluate the `ifx` conditions of `configure` when assigning.
+    /// The expression cannot contain challenges.
+    pub fn evaluate(&self, offset: usize, expr: &Expression<F>) -> F {
+        expr.evaluate(
+            &|scalar| scalar,
+            &|_| unimplemented!("selector column"),
+            &|fixed_query| {
+                self.get_fixed(offset, fixed_query.column_index(), fixed_query.rotation())
+            },
+            &|advice_query| {
+                self.get_advice(offset, advice_query.column_index(), advice_query.rotation())
+            },
+            &|_| unimplemented!("instance column"),
+            &|_| unimplemented!("challenge"),
+            &|a| -a,
+            &|a, b| a + b,
+            &|a, b| a * b,
+            &|a, scalar| a * scalar,
+        )
+    }
+
     /// Constrains a cell to have a constant value.
     ///
     /// Returns an error if the cell is in a column where equality has not been
@@ -245,3 +302,27 @@ impl<F: Field, C: CellType> StoredExpression<F, C> {
         Ok(value)
     }
 }
+
+/// Renders traced assignments as CSV, one assignment per line ordered by row
+/// and column
+pub(crate) fn trace_to_csv<F: Field, N: Fn(Column<Advice>) -> String>(
+    trace: &[TracedAssignment<F>],
+    column_name: N,
+) -> String {
+    let escape = |field: &str| format!("\"{}\"", field.replace('"', "\"\""));
+    let mut trace = trace.to_vec();
+    trace.sort_by_key(|a| (a.offset, a.column.index()));
+    let mut csv = String::from("row,column,column_name,context,annotation,value\n");
+    for a in trace.iter() {
+        csv += &format!(
+            "{},{},{},{},{},{}\n",
+            a.offset,
+            a.column.index(),
+            escape(&column_name(a.column)),
+            escape(&a.context),
+            escape(&a.annotation),
+            constant_to_string(a.value)
+        );
+    }
+    csv
+}
@@ -1,6 +1,6 @@
 //! Circuit utilities
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     marker::PhantomData,
     ops::{Add, Mul},
     vec,
@@ -21,6 +21,30 @@ use super::{
     cell_manager::{Cell, CellManager, CellType, WordCell},
 };
 
+/// Collects all advice cells (column index, rotation) queried in `expr`
+fn collect_advice_queries<F: Field>(expr: &Expression<F>, queries: &mut HashSet<(usize, i32)>) {
+    match expr {
+        Expression::Advice(query) => {
+            queries.insert((query.column_index(), query.rotation().0));
+        }
+        Expression::Negated(a) | Expression::Scaled(a, _) => collect_advice_queries(a, queries),
+        Expression::Sum(a, b) | Expression::Product(a, b) => {
+            collect_advice_queries(a, queries);
+            collect_advice_queries(b, queries);
+        }
+        _ => {}
+    }
+}
+
+/// Number of nodes in the expression tree of `expr`
+fn expr_size<F: Field>(expr: &Expression<F>) -> usize {
+    match expr {
+        Expression::Negated(a) | Expression::Scaled(a, _) => 1 + expr_size(a),
+        Expression::Sum(a, b) | Expression::Product(a, b) => 1 + expr_size(a) + expr_size(b),
+        _ => 1,
+    }
+}
+
 fn get_condition_expr<F: Field>(conditions: &Vec<Expression<F>>) -> Expression<F> {
     if conditions.is_empty() {
         1.expr()
@@ -412,6 +436,9 @@ impl<F: Field, C: CellType> ConstraintBuilder<F, C> {
         cell_type: C,
         values: Vec<Expression<F>>,
     ) -> Expression<F> {
+        if let Some(table_type) = cell_type.lookup_table_type() {
+            self.check_lookup_schema(description, table_type, values.len());
+        }
         let challenge = self.lookup_challenge.clone().unwrap();
         let rlc = rlc::expr(&values, challenge.expr()) * self.get_condition_expr();
         let reduced_rlc = self.split_expression("compression", rlc);
@@ -425,6 +452,11 @@ impl<F: Field, C: CellType> ConstraintBuilder<F, C> {
         table_type: C::TableType,
         table: &dyn LookupTable<F>,
     ) {
+        assert!(
+            !self.tables.contains_key(&table_type),
+            "Lookup table {:?} registered more than once",
+            table_type
+        );
         query_expression(meta, |meta| {
             let description = format!("{:?}", table_type);
             self.store_table_with_condition(
@@ -476,11 +508,45 @@ impl<F: Field, C: CellType> ConstraintBuilder<F, C> {
     }
 
     pub(crate) fn table(&self, table_type: C::TableType) -> Vec<Expression<F>> {
-        let tables = self.tables.get(&table_type).unwrap();
-        assert!(tables.len() == 1);
+        let tables = self.tables.get(&table_type).unwrap_or_else(|| {
+            panic!(
+                "Lookup table {:?} not registered (registered: {:?})",
+                table_type,
+                self.registered_tables()
+            )
+        });
+        assert!(
+            tables.len() == 1,
+            "Lookup table {:?} is dynamic, use dynamic_table_merged",
+            table_type
+        );
         tables[0].values.clone()
     }
 
+    /// Returns the types of all the tables registered so far, sorted
+    pub(crate) fn registered_tables(&self) -> Vec<C::TableType> {
+        self.tables.keys().cloned().sorted().collect()
+    }
+
+    /// Checks that the lookup targets a registered table and that it does not
+    /// use more values than the table has columns.
+    pub(crate) fn check_lookup_schema(
+        &self,
+        description: &str,
+        table_type: C::TableType,
+        num_values: usize,
+    ) {
+        let width = self.table(table_type).len();
+        assert!(
+            num_values <= width,
+            "Lookup \"{}\" into {:?} uses {} values but the table only has {} columns",
+            description,
+            table_type,
+            num_values,
+            width
+        );
+    }
+
     pub(crate) fn add_lookup(
         &mut self,
         description: String,
@@ -502,7 +568,13 @@ impl<F: Field, C: CellType> ConstraintBuilder<F, C> {
         let data = self
             .tables
             .get(&tag)
-            .unwrap_or_else(|| panic!("Dynamic table {:?} not found", tag))
+            .unwrap_or_else(|| {
+                panic!(
+                    "Dynamic table {:?} not found (registered: {:?})",
+                    tag,
+                    self.registered_tables()
+                )
+            })
             .clone();
         let table_merger = TableMerger {
             data,
@@ -549,10 +621,91 @@ impl<F: Field, C: CellType> ConstraintBuilder<F, C> {
         }
     }
 
-    pub(crate) fn get_stored_expressions(&self, region_id: usize) -> Vec<StoredExpression<F, C>> {
+    /// Stores the expression in a cell and returns the cell expression, so that
+    /// a sub-expression used in multiple constraints only has to be expanded
+    /// once. Caching an identical expression again in the same region returns
+    /// the same cell. Constants and single queries are returned as is.
+    pub(crate) fn cached_expr(&mut self, name: &'static str, expr: Expression<F>) -> Expression<F> {
+        match expr {
+            Expression::Constant(_)
+            | Expression::Fixed(_)
+            | Expression::Advice(_)
+            | Expression::Challenge(_) => expr,
+            _ => {
+                let expr = self.split_expression(name, expr);
+                let cell_type = C::storage_for_expr(&expr);
+                self.store_expression(name, expr, cell_type, None)
+            }
+        }
+    }
+
+    /// Returns the cells allocated in the current region that are not queried by
+    /// any constraint or lookup of the region. Cells in columns that are looked
+    /// up on every row (e.g. byte columns) are always constrained.
+    pub(crate) fn unconstrained_region_cells(&self) -> Vec<Cell<F>> {
+        let mut queries = HashSet::new();
+        for (_, constraint) in self.constraints[self.region_constraints_start..].iter() {
+            collect_advice_queries(constraint, &mut queries);
+        }
+        let table_data = self.tables.values().flatten();
+        for data in table_data.filter(|data| data.region_id == self.region_id) {
+            for value in data.values.iter() {
+                collect_advice_queries(value, &mut queries);
+            }
+        }
+        let mut global_columns = HashSet::new();
+        for lookup in self.lookups.iter() {
+            if lookup.region_id == self.region_id {
+                for value in lookup.values.iter() {
+                    collect_advice_queries(value, &mut queries);
+                }
+            } else if lookup.region_id == 0 {
+                let mut lookup_queries = HashSet::new();
+                for value in lookup.values.iter() {
+                    collect_advice_queries(value, &mut lookup_queries);
+                }
+                global_columns.extend(lookup_queries.iter().map(|(column, _)| *column));
+            }
+        }
+
+        let mut cells = Vec::new();
+        for column in self.cell_manager.as_ref().unwrap().columns().iter() {
+            if global_columns.contains(&column.column.index()) {
+                continue;
+            }
+            for cell in column.cells[..column.height].iter() {
+                if !queries.contains(&(column.column.index(), cell.rotation as i32)) {
+                    cells.push(cell.clone());
+                }
+            }
+        }
+        cells
+    }
+
+    /// Returns the indices of all advice columns queried by a constraint or
+    /// lookup, at any rotation
+    pub(crate) fn queried_advice_columns(&self) -> HashSet<usize> {
+        let mut queries = HashSet::new();
+        for (_, constraint) in self.constraints.iter() {
+            collect_advice_queries(constraint, &mut queries);
+        }
+        for data in self.tables.values().flatten() {
+            for value in data.values.iter() {
+                collect_advice_queries(value, &mut queries);
+            }
+        }
+        for lookup in self.lookups.iter() {
+            for value in lookup.values.iter() {
+                collect_advice_queries(value, &mut queries);
+            }
+        }
+        queries.iter().map(|(column, _)| *column).collect()
+    }
+
+    pub(crate) fn get_stored_expressions(&self, region_id: usize) -> &[StoredExpression<F, C>] {
         self.stored_expressions
             .get(&region_id)
-            .cloned()
+            .map(Vec::as_slice)
             .unwrap_or_default()
     }
 
@@ -619,8 +772,16 @@ impl<F: Field, C: CellType> ConstraintBuilder<F, C> {
         let mut expressions = self.constraints.clone();
         expressions.sort_by(|a, b| a.1.degree().cmp(&b.1.degree()));
         for (name, expr) in expressions.iter() {
-            println!("'{}': {}", name, expr.degree());
+            println!("'{}': {} (size {})", name, expr.degree(), expr_size(expr));
         }
+        println!(
+            "{} constraints, total size {}",
+            expressions.len(),
+            expressions
+                .iter()
+                .map(|(_, expr)| expr_size(expr))
+                .sum::<usize>()
+        );
     }
 }
 
@@ -1140,7 +1301,7 @@ macro_rules! _require {
         let description = concat_with_preamble!(
             stringify!($values),
             " => @",
-            stringify!($tag),
+            stringify!($table),
         );
         $cb.add_lookup(
             description.to_string(),
@@ -1253,9 +1414,14 @@ macro_rules! _ifx {
 
 /// matchw - Resembles matchx so that the witness generation can look like the
 /// circuit code.
+///
+/// In debug builds the witness conditions are checked the same way `matchx`
+/// constrains them: exactly one arm has to match (at most one when a catch-all
+/// arm is present). An optional name can be given (`matchw!("name"; ...)`)
+/// which is used in the panic message to identify the gadget.
 #[macro_export]
 macro_rules! matchw {
-    ($($condition:expr => $when:expr),* $(, _ => $catch_all:expr)? $(,)?)  => {{
+    (@select $($condition:expr => $when:expr),* $(, _ => $catch_all:expr)? $(,)?)  => {{
         if false {
             unreachable!()
         }
@@ -1264,11 +1430,57 @@ macro_rules! matchw {
         }
         )*
         else {
-            $(
-                $catch_all
-            )*
-            unreachable!()
+            $crate::matchw!(@catch_all $($catch_all)?)
+        }
+    }};
+    (@catch_all $catch_all:expr) => {
+        $catch_all
+    };
+    (@catch_all) => {
+        unreachable!()
+    };
+    ($name:expr; $($condition:expr => $when:expr),* $(, _ => $catch_all:expr)? $(,)?)  => {{
+        #[cfg(all(debug_assertions, not(feature = "fast_assign")))]
+        {
+            let num_matches = 0usize $(+ usize::from($condition))*;
+            let has_catch_all = false $(|| { let _ = stringify!($catch_all); true })?;
+            assert!(
+                num_matches == 1 || (has_catch_all && num_matches == 0),
+                "{}: witness matches {} arms of ({}), expected exactly one",
+                $name,
+                num_matches,
+                stringify!($($condition),*),
+            );
         }
+        $crate::matchw!(@select $($condition => $when),* $(, _ => $catch_all)?)
+    }};
+    ($($condition:expr => $when:expr),* $(, _ => $catch_all:expr)? $(,)?)  => {{
+        $crate::matchw!(
+            concat!(module_path!(), ":", line!());
+            $($condition => $when),* $(, _ => $catch_all)?
+        )
+    }};
+}
+
+/// ifw - The witness counterpart of ifx, evaluates the same condition
+/// expression used in `configure` on the cells already assigned at `offset`,
+/// so `assign` takes the branch the constraints are enabled for. Without an
+/// `elsex` branch the default value is returned when the condition is false,
+/// just like `ifx` returns zero.
+#[macro_export]
+macro_rules! ifw {
+    ($region:expr, $offset:expr; $condition:expr => $when_true:block elsex $when_false:block) => {{
+        let condition = $region.evaluate($offset, &$condition);
+        debug_assert!(
+            condition == F::ZERO || condition == F::ONE,
+            "{}: condition {} is not boolean",
+            concat!(module_path!(), ":", line!()),
+            stringify!($condition),
+        );
+        if condition == F::ONE $when_true else $when_false
+    }};
+    ($region:expr, $offset:expr; $condition:expr => $when_true:block) => {{
+        $crate::ifw!($region, $offset; $condition => $when_true elsex { Default::default() })
     }};
 }
 
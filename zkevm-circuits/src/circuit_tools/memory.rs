@@ -74,6 +74,22 @@ impl<F: Field, C: CellType, MB: MemoryBank<F, C>> Memory<F, C, MB> {
         })
     }
 
+    /// The columns of every bank with the tag of the bank and the annotation
+    /// of the column in the bank, ordered by tag
+    pub(crate) fn annotated_columns(&self) -> Vec<(C, Column<Advice>, String)> {
+        let mut banks: Vec<_> = self.banks.iter().collect();
+        banks.sort_by_key(|(tag, _)| **tag);
+        banks
+            .into_iter()
+            .flat_map(|(tag, bank)| {
+                bank.columns()
+                    .into_iter()
+                    .zip(bank.annotations())
+                    .map(|(column, annotation)| (*tag, column, annotation))
+            })
+            .collect()
+    }
+
     pub(crate) fn build_constraints(
         &self,
         cb: &mut ConstraintBuilder<F, C>,
@@ -122,6 +138,8 @@ pub(crate) trait MemoryBank<F: Field, C: CellType>: Clone {
         values: &[Expression<F>],
     );
     fn columns(&self) -> Vec<Column<Advice>>;
+    /// Annotations of `columns`, in the same order
+    fn annotations(&self) -> Vec<String>;
     fn tag(&self) -> C;
     fn witness_store(&mut self, offset: usize, values: &[F]);
     fn witness_load(&self, offset: usize) -> Vec<F>;
@@ -245,6 +263,14 @@ impl<F: Field, C: CellType> MemoryBank<F, C> for RwBank<F, C> {
         vec![self.key, self.reads, self.writes]
     }
 
+    fn annotations(&self) -> Vec<String> {
+        vec![
+            String::from("index"),
+            String::from("reads"),
+            String::from("writes"),
+        ]
+    }
+
     fn build_constraints(&self, cb: &mut ConstraintBuilder<F, C>, q_start: Expression<F>) {
         let condition = self
             .local_conditions
@@ -0,0 +1,243 @@
+//! Human readable rendering of expressions and constraint systems
+use std::{collections::HashMap, fmt::Write};
+
+use eth_types::Field;
+use halo2_proofs::{
+    plonk::{Any, Column, ConstraintSystem, Expression},
+    poly::Rotation,
+};
+
+use crate::table::LookupTable;
+
+/// Renders expressions using column names instead of column indices
+#[derive(Clone, Debug, Default)]
+pub struct ExpressionPrinter {
+    advice: HashMap<usize, String>,
+    fixed: HashMap<usize, String>,
+    instance: HashMap<usize, String>,
+}
+
+impl ExpressionPrinter {
+    /// Creates a printer without any column names
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names a column, unnamed columns are printed as `a{index}`, `f{index}`
+    /// and `i{index}` for advice, fixed and instance columns.
+    pub fn name_column<C: Into<Column<Any>>>(&mut self, column: C, name: &str) {
+        let column: Column<Any> = column.into();
+        let names = match column.column_type() {
+            Any::Advice(_) => &mut self.advice,
+            Any::Fixed => &mut self.fixed,
+            Any::Instance => &mut self.instance,
+        };
+        names.insert(column.index(), name.to_string());
+    }
+
+    /// Names all columns of a lookup table using its annotations
+    pub fn name_table<F: Field>(&mut self, table: &dyn LookupTable<F>) {
+        for (column, name) in table.columns().iter().zip(table.annotations().iter()) {
+            self.name_column(*column, name);
+        }
+    }
+
+    /// Renders `expr` to a string
+    pub fn expr_to_string<F: Field>(&self, expr: &Expression<F>) -> String {
+        let name = |names: &HashMap<usize, String>, prefix: &str, index: usize, rot: Rotation| {
+            let name = names
+                .get(&index)
+                .cloned()
+                .unwrap_or_else(|| format!("{}{}", prefix, index));
+            match rot.0 {
+                0 => name,
+                rot => format!("{}[{:+}]", name, rot),
+            }
+        };
+        expr.evaluate(
+            &constant_to_string,
+            &|selector| format!("{:?}", selector),
+            &|query| name(&self.fixed, "f", query.column_index(), query.rotation()),
+            &|query| name(&self.advice, "a", query.column_index(), query.rotation()),
+            &|query| name(&self.instance, "i", query.column_index(), query.rotation()),
+            &|challenge| format!("challenge{}", challenge.index()),
+            &|a| format!("-{}", parens(a)),
+            &|a, b| match b.strip_prefix('-') {
+                Some(b) => format!("{} - {}", a, b),
+                None => format!("{} + {}", a, b),
+            },
+            &|a, b| format!("{} * {}", parens(a), parens(b)),
+            &|a, value| format!("{} * {}", constant_to_string(value), parens(a)),
+        )
+    }
+
+    /// Renders all gates and lookups of the constraint system, one constraint
+    /// per line prefixed by its name
+    pub fn constraint_system_to_string<F: Field>(&self, meta: &ConstraintSystem<F>) -> String {
+        let mut out = String::new();
+        for gate in meta.gates().iter() {
+            writeln!(out, "gate '{}':", gate.name()).unwrap();
+            for (idx, poly) in gate.polynomials().iter().enumerate() {
+                writeln!(
+                    out,
+                    "  [{}] '{}' (degree {}): {}",
+                    idx,
+                    gate.constraint_name(idx),
+                    poly.degree(),
+                    self.expr_to_string(poly)
+                )
+                .unwrap();
+            }
+        }
+        for (idx, lookup) in meta.lookups().iter().enumerate() {
+            writeln!(out, "lookup {} '{}':", idx, lookup.name()).unwrap();
+            for (input, table) in lookup
+                .input_expressions()
+                .iter()
+                .zip(lookup.table_expressions().iter())
+            {
+                writeln!(
+                    out,
+                    "  {} => {}",
+                    self.expr_to_string(input),
+                    self.expr_to_string(table)
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+
+    /// Writes all gates and lookups of the constraint system to `path`
+    pub fn dump<F: Field>(&self, meta: &ConstraintSystem<F>, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.constraint_system_to_string(meta))
+    }
+}
+
+/// Renders the structure of the constraint system: the column counts, the
+/// names and degrees of the constraints of every gate and the lookups. Unlike
+/// `ExpressionPrinter::constraint_system_to_string` the expressions are left
+/// out, so the summary only changes when a constraint or column is added,
+/// removed or renamed, or a degree changes.
+pub fn constraint_system_summary<F: Field>(meta: &ConstraintSystem<F>) -> String {
+    let mut out = String::new();
+    writeln!(out, "advice columns: {}", meta.num_advice_columns()).unwrap();
+    writeln!(out, "fixed columns: {}", meta.num_fixed_columns()).unwrap();
+    writeln!(out, "instance columns: {}", meta.num_instance_columns()).unwrap();
+    writeln!(out, "selectors: {}", meta.num_selectors()).unwrap();
+    writeln!(out, "challenges: {}", meta.num_challenges()).unwrap();
+    writeln!(
+        out,
+        "permutation columns: {}",
+        meta.permutation().get_columns().len()
+    )
+    .unwrap();
+    writeln!(out, "max degree: {}", meta.degree()).unwrap();
+    for gate in meta.gates().iter() {
+        writeln!(out, "gate '{}':", gate.name()).unwrap();
+        for (idx, poly) in gate.polynomials().iter().enumerate() {
+            writeln!(
+                out,
+                "  '{}' (degree {})",
+                gate.constraint_name(idx),
+                poly.degree()
+            )
+            .unwrap();
+        }
+    }
+    for lookup in meta.lookups().iter() {
+        let degree = |exprs: &[Expression<F>]| exprs.iter().map(|e| e.degree()).max().unwrap_or(0);
+        writeln!(
+            out,
+            "lookup '{}': {} expressions (input degree {}, table degree {})",
+            lookup.name(),
+            lookup.input_expressions().len(),
+            degree(lookup.input_expressions()),
+            degree(lookup.table_expressions())
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Renders small (negative) values as integers
+pub(crate) fn constant_to_string<F: Field>(value: F) -> String {
+    let is_small = |value: F| value.to_repr()[16..].iter().all(|byte| *byte == 0);
+    if is_small(value) {
+        value.get_lower_128().to_string()
+    } else if is_small(-value) {
+        format!("-{}", (-value).get_lower_128())
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+/// Wraps compound expressions in parentheses
+fn parens(expr: String) -> String {
+    if expr.contains(' ') {
+        format!("({})", expr)
+    } else {
+        expr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::{halo2curves::bn256::Fr, plonk::Advice};
+
+    #[test]
+    fn print_expression() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let a: Column<Advice> = meta.advice_column();
+        let b: Column<Advice> = meta.advice_column();
+        let q = meta.fixed_column();
+        let mut printer = ExpressionPrinter::new();
+        printer.name_column(a, "a");
+        printer.name_column(q, "q");
+
+        let mut expr = None;
+        meta.create_gate("test", |meta| {
+            let q = meta.query_fixed(q, Rotation::cur());
+            let a_next = meta.query_advice(a, Rotation::next());
+            let b = meta.query_advice(b, Rotation::prev());
+            let e = q * (a_next - b * Expression::Constant(Fr::from(3)));
+            expr = Some(e.clone());
+            vec![e]
+        });
+
+        assert_eq!(
+            printer.expr_to_string(&expr.unwrap()),
+            "q * (a[+1] - (a1[-1] * 3))"
+        );
+    }
+
+    #[test]
+    fn print_summary() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let a: Column<Advice> = meta.advice_column();
+        let q = meta.fixed_column();
+        meta.create_gate("test", |meta| {
+            let q = meta.query_fixed(q, Rotation::cur());
+            let a = meta.query_advice(a, Rotation::cur());
+            vec![
+                ("square", q.clone() * a.clone() * a.clone()),
+                ("zero", q * a),
+            ]
+        });
+
+        assert_eq!(
+            constraint_system_summary(&meta),
+            "advice columns: 1\n\
+             fixed columns: 1\n\
+             instance columns: 0\n\
+             selectors: 0\n\
+             challenges: 0\n\
+             permutation columns: 0\n\
+             max degree: 3\n\
+             gate 'test':\n\
+             \x20 'square' (degree 3)\n\
+             \x20 'zero' (degree 2)\n"
+        );
+    }
+}
@@ -13,7 +13,7 @@ pub(crate) mod util;
 
 #[cfg(test)]
 pub(crate) mod test;
-use self::step::HasExecutionState;
+use self::step::{ExecutionState, HasExecutionState};
 #[cfg(feature = "test-circuits")]
 pub use self::EvmCircuit as TestEvmCircuit;
 
@@ -294,6 +294,37 @@ pub(crate) fn detect_fixed_table_tags<F: Field>(block: &Block<F>) -> Vec<FixedTa
         .collect()
 }
 
+/// Opcodes whose successful execution is constrained by the EVM circuit,
+/// sorted by value
+pub fn supported_opcodes() -> Vec<OpcodeId> {
+    ExecutionState::iter()
+        .filter(|state| {
+            !state.is_dummy()
+                && !matches!(
+                    state,
+                    ExecutionState::ErrorStack | ExecutionState::ErrorInvalidOpcode
+                )
+        })
+        .flat_map(|state| state.responsible_opcodes())
+        .map(|op| op.opcode())
+        .sorted_by_key(|op| op.as_u8())
+        .dedup()
+        .collect()
+}
+
+/// The opcodes in `opcodes` that are not in `supported_opcodes`, sorted by
+/// value. Blocks executing any of them can not be fully proven, only their
+/// state trie transition.
+pub fn unsupported_opcodes(opcodes: impl IntoIterator<Item = OpcodeId>) -> Vec<OpcodeId> {
+    let supported = supported_opcodes();
+    opcodes
+        .into_iter()
+        .filter(|op| !supported.contains(op))
+        .sorted_by_key(|op| op.as_u8())
+        .dedup()
+        .collect()
+}
+
 #[cfg(any(feature = "test-util", test))]
 pub(crate) mod cached {
     use super::*;
@@ -438,12 +469,12 @@ impl<F: Field> Circuit<F> for EvmCircuit<F> {
 #[cfg(test)]
 mod evm_circuit_stats {
     use crate::{
-        evm_circuit::EvmCircuit,
+        evm_circuit::{unsupported_opcodes, EvmCircuit},
         test_util::CircuitTestBuilder,
         util::{unusable_rows, SubCircuit},
         witness::block_convert,
     };
-    use bus_mapping::{circuit_input_builder::FixedCParams, mock::BlockData};
+    use bus_mapping::{circuit_input_builder::FixedCParams, evm::OpcodeId, mock::BlockData};
 
     use eth_types::{bytecode, geth_types::GethData};
     use halo2_proofs::{self, dev::MockProver, halo2curves::bn256::Fr};
@@ -453,6 +484,24 @@ mod evm_circuit_stats {
         TestContext,
     };
 
+    #[test]
+    fn supported_opcodes() {
+        let supported = super::supported_opcodes();
+        assert!(supported.contains(&OpcodeId::ADD));
+        assert!(supported.contains(&OpcodeId::PUSH0));
+        assert!(!supported.contains(&OpcodeId::SELFDESTRUCT));
+        assert!(!supported.contains(&OpcodeId::INVALID(0xfe)));
+        assert_eq!(
+            unsupported_opcodes([
+                OpcodeId::SELFDESTRUCT,
+                OpcodeId::ADD,
+                OpcodeId::INVALID(0xfe),
+                OpcodeId::SELFDESTRUCT,
+            ]),
+            vec![OpcodeId::INVALID(0xfe), OpcodeId::SELFDESTRUCT]
+        );
+    }
+
     #[test]
     fn evm_circuit_unusable_rows() {
         assert_eq!(
@@ -355,6 +355,19 @@ impl ExecutionState {
             || self.halts_in_exception()
     }
 
+    /// Execution states implemented with `DummyGadget`, their steps are not
+    /// constrained
+    pub(crate) fn is_dummy(&self) -> bool {
+        matches!(
+            self,
+            Self::SELFDESTRUCT
+                | Self::ErrorOutOfGasEXTCODECOPY
+                | Self::ErrorOutOfGasSELFDESTRUCT
+                | Self::ErrorDepth
+                | Self::ErrorContractAddressCollision
+        )
+    }
+
     /// Get the opocdes that are related to the execution state
     pub fn responsible_opcodes(&self) -> Vec<ResponsibleOp> {
         if matches!(self, Self::ErrorStack) {
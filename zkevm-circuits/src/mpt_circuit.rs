@@ -2,35 +2,42 @@
 use eth_types::Field;
 use gadgets::{impl_expr, util::Scalar};
 use halo2_proofs::{
-    circuit::{Layouter, SimpleFloorPlanner, Value},
+    circuit::{floor_planner::V1, FloorPlanner, Layouter, Region, SimpleFloorPlanner, Value},
     plonk::{
-        Advice, Circuit, Column, ConstraintSystem, Error, Expression, Fixed, SecondPhase,
+        Advice, Any, Circuit, Column, ConstraintSystem, Error, Expression, Fixed, SecondPhase,
         VirtualCells,
     },
     poly::Rotation,
 };
 
-use std::{convert::TryInto, env::var, marker::PhantomData};
+use std::{collections::HashMap, convert::TryInto, env::var, marker::PhantomData};
 
 mod account_leaf;
 mod branch;
+#[cfg(test)]
+mod coverage;
 mod extension;
 mod extension_branch;
+#[cfg(test)]
+mod gadget_test;
 mod helpers;
+mod padding;
 mod param;
 mod rlp_gadgets;
 mod start;
 mod storage_leaf;
+/// Checks of the MPT witness outside of the circuit
+pub mod witness_check;
 /// MPT witness row
 pub mod witness_row;
 
 use self::{
     account_leaf::AccountLeafConfig,
     helpers::RLPItemView,
-    param::RLP_UNIT_NUM_BYTES,
+    param::{KEY_LEN, RLP_UNIT_NUM_BYTES},
     rlp_gadgets::decode_rlp,
     witness_row::{
-        AccountRowType, ExtensionBranchRowType, Node, StartRowType, StorageRowType,
+        AccountRowType, ExtensionBranchRowType, Node, PaddingRowType, StartRowType, StorageRowType,
         NODE_RLP_TYPES_ACCOUNT, NODE_RLP_TYPES_BRANCH, NODE_RLP_TYPES_START,
         NODE_RLP_TYPES_STORAGE,
     },
@@ -38,23 +45,25 @@ use self::{
 use crate::{
     assign, assignf, circuit,
     circuit_tools::{
-        cached_region::CachedRegion,
-        cell_manager::{CellColumn, CellManager},
+        cached_region::{trace_to_csv, CachedRegion},
+        cell_manager::{Cell, CellColumn, CellManager},
         memory::{Memory, RwBank},
+        printer::ExpressionPrinter,
     },
     mpt_circuit::{
         helpers::{MPTConstraintBuilder, MainRLPGadget, MptCellType, MptTableType},
+        padding::PaddingConfig,
         start::StartConfig,
         storage_leaf::StorageLeafConfig,
     },
-    table::{KeccakTable, MPTProofType, MptTable},
+    table::{BytecodeTable, KeccakTable, LookupTable, MPTProofType, MptTable},
     util::Challenges,
 };
 
 use extension_branch::ExtensionBranchConfig;
 use param::HASH_WIDTH;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub(crate) enum MPTRegion {
     Default,
     RLP,
@@ -62,6 +71,7 @@ pub(crate) enum MPTRegion {
     Branch,
     Account,
     Storage,
+    Padding,
     Count,
 }
 
@@ -72,11 +82,13 @@ pub struct StateMachineConfig<F> {
     is_branch: Column<Advice>,
     is_account: Column<Advice>,
     is_storage: Column<Advice>,
+    is_padding: Column<Advice>,
 
     start_config: StartConfig<F>,
     branch_config: ExtensionBranchConfig<F>,
     storage_config: StorageLeafConfig<F>,
     account_config: AccountLeafConfig<F>,
+    padding_config: PaddingConfig<F>,
 }
 
 impl<F: Field> StateMachineConfig<F> {
@@ -87,10 +99,12 @@ impl<F: Field> StateMachineConfig<F> {
             is_branch: meta.advice_column(),
             is_account: meta.advice_column(),
             is_storage: meta.advice_column(),
+            is_padding: meta.advice_column(),
             start_config: StartConfig::default(),
             branch_config: ExtensionBranchConfig::default(),
             storage_config: StorageLeafConfig::default(),
             account_config: AccountLeafConfig::default(),
+            padding_config: PaddingConfig::default(),
         }
     }
 
@@ -101,6 +115,7 @@ impl<F: Field> StateMachineConfig<F> {
             self.is_branch,
             self.is_account,
             self.is_storage,
+            self.is_padding,
         ]
     }
 
@@ -136,6 +151,8 @@ pub struct MPTContext<F: Field> {
     pub(crate) rlp_item: MainRLPGadget<F>,
     pub(crate) memory: MptMemory<F>,
     pub(crate) params: MPTCircuitParams,
+    /// Looks up the code hashes of the accounts in the bytecode table
+    pub(crate) bytecode_lookup: bool,
 }
 
 /// RLP item type
@@ -173,17 +190,22 @@ pub struct MPTConfig<F: Field> {
     pub(crate) q_enable: Column<Fixed>,
     pub(crate) q_first: Column<Fixed>,
     pub(crate) q_last: Column<Fixed>,
+    /// Enables the recurrence of the mult table, on all its rows but the last
+    q_mult: Column<Fixed>,
     pub(crate) memory: MptMemory<F>,
     /// MPT table
     pub mpt_table: MptTable,
     /// Keccak table
     pub keccak_table: KeccakTable,
+    /// Bytecode table the code hashes of the accounts are looked up in, if any
+    pub bytecode_table: Option<BytecodeTable>,
     fixed_table: [Column<Fixed>; 6],
-    mult_table: [Column<Advice>; 2],
+    mult_table: [Column<Advice>; 3],
     rlp_item: MainRLPGadget<F>,
     state_machine: StateMachineConfig<F>,
     params: MPTCircuitParams,
     cell_columns: Vec<CellColumn<F, MptCellType>>,
+    column_names: Vec<(Column<Any>, String)>,
     cb: MPTConstraintBuilder<F>,
 }
 
@@ -214,12 +236,42 @@ impl<F: Field> MPTConfig<F> {
         challenges: Challenges<Expression<F>>,
         keccak_table: KeccakTable,
         params: MPTCircuitParams,
+    ) -> Self {
+        Self::configure(meta, challenges, keccak_table, None, params)
+    }
+
+    /// Configure MPT Circuit, also checking that the code of every account
+    /// with code is in `bytecode_table`. The code hash of the account leaves
+    /// is looked up in the header rows of the table, so that composed with the
+    /// bytecode circuit the code run by the EVM is the one in the trie.
+    #[cfg(feature = "mpt-codehash-lookup")]
+    pub fn new_with_bytecode_table(
+        meta: &mut ConstraintSystem<F>,
+        challenges: Challenges<Expression<F>>,
+        keccak_table: KeccakTable,
+        bytecode_table: BytecodeTable,
+        params: MPTCircuitParams,
+    ) -> Self {
+        Self::configure(meta, challenges, keccak_table, Some(bytecode_table), params)
+    }
+
+    fn configure(
+        meta: &mut ConstraintSystem<F>,
+        challenges: Challenges<Expression<F>>,
+        keccak_table: KeccakTable,
+        bytecode_table: Option<BytecodeTable>,
+        params: MPTCircuitParams,
     ) -> Self {
         let q_enable = meta.fixed_column();
         let q_first = meta.fixed_column();
         let q_last = meta.fixed_column();
+        let q_mult = meta.fixed_column();
 
-        let mpt_table = MptTable::construct(meta);
+        let mpt_table = if params.balance_delta {
+            MptTable::construct_with_balance_delta(meta)
+        } else {
+            MptTable::construct(meta)
+        };
 
         let fixed_table: [Column<Fixed>; 6] = (0..6)
             .map(|_| meta.fixed_column())
@@ -227,8 +279,12 @@ impl<F: Field> MPTConfig<F> {
             .try_into()
             .unwrap();
 
-        let mult_table: [Column<Advice>; 2] =
-            [meta.advice_column(), meta.advice_column_in(SecondPhase)];
+        // Powers of the keccak and of the key randomness, indexed by the exponent
+        let mult_table: [Column<Advice>; 3] = [
+            meta.advice_column(),
+            meta.advice_column_in(SecondPhase),
+            meta.advice_column_in(SecondPhase),
+        ];
 
         let mut cb = MPTConstraintBuilder::new(5, Some(challenges), None);
 
@@ -236,7 +292,11 @@ impl<F: Field> MPTConfig<F> {
         cb.load_table(meta, MptTableType::Keccak, &keccak_table);
         cb.load_table(meta, MptTableType::Byte, &[fixed_table[2]]);
         cb.load_table(meta, MptTableType::Fixed, &fixed_table);
-        cb.load_table(meta, MptTableType::Mult, &mult_table);
+        cb.load_table(meta, MptTableType::Mult, &[mult_table[0], mult_table[1]]);
+        cb.load_table(meta, MptTableType::KeyMult, &[mult_table[0], mult_table[2]]);
+        if let Some(bytecode_table) = &bytecode_table {
+            cb.load_table(meta, MptTableType::Bytecode, bytecode_table);
+        }
 
         let mut state_machine = StateMachineConfig::construct(meta);
         let mut rlp_item = MainRLPGadget::default();
@@ -255,10 +315,20 @@ impl<F: Field> MPTConfig<F> {
         state_cm.add_columns(meta, &mut cb.base, MptCellType::StoragePhase1, 0, false, 20);
         state_cm.add_columns(meta, &mut cb.base, MptCellType::StoragePhase2, 1, false, 6);
         state_cm.add_columns(meta, &mut cb.base, MptCellType::StoragePhase3, 2, false, 5);
-        state_cm.add_columns(meta, &mut cb.base, lu(MptTableType::Byte), 0, false, 4);
+        // The balance delta takes 32 more bytes in the account leaf
+        let num_byte_columns = if params.balance_delta { 5 } else { 4 };
+        state_cm.add_columns(
+            meta,
+            &mut cb.base,
+            lu(MptTableType::Byte),
+            0,
+            false,
+            num_byte_columns,
+        );
         state_cm.add_columns(meta, &mut cb.base, lu(MptTableType::Fixed), 2, false, 3);
         state_cm.add_columns(meta, &mut cb.base, lu(MptTableType::Keccak), 2, false, 1);
         state_cm.add_columns(meta, &mut cb.base, lu(MptTableType::Mult), 2, false, 2);
+        state_cm.add_columns(meta, &mut cb.base, lu(MptTableType::KeyMult), 2, false, 1);
 
         let mut memory = Memory::new();
         memory.add_memory_bank(meta, &mut cb.base, &mut state_cm, MptCellType::MemKeyC, 2);
@@ -279,36 +349,47 @@ impl<F: Field> MPTConfig<F> {
         );
         memory.add_memory_bank(meta, &mut cb.base, &mut state_cm, MptCellType::MemMain, 2);
 
+        // Cells of each region not used in any constraint or lookup
+        let mut unconstrained_cells = Vec::new();
+
         let mut ctx = MPTContext {
             mpt_table,
             rlp_item: rlp_item.clone(),
             memory: memory.clone(),
             params,
+            bytecode_lookup: bytecode_table.is_some(),
         };
         meta.create_gate("MPT", |meta| {
             circuit!([meta, cb], {
-                ifx!{f!(q_enable) => {
-                    // Mult table verification
+                // Mult table verification, the table is shorter than the MPT region
+                ifx!{f!(q_mult) => {
                     ifx! {f!(q_first) => {
                         require!(a!(mult_table[0]) => 0);
                         require!(a!(mult_table[1]) => 1);
+                        require!(a!(mult_table[2]) => 1);
                     }}
                     require!(a!(mult_table[0], 1) => a!(mult_table[0]) + 1.expr());
                     require!(a!(mult_table[1], 1) => a!(mult_table[1]) * cb.keccak_r.expr());
-
+                    require!(a!(mult_table[2], 1) => a!(mult_table[2]) * cb.key_r.expr());
+                }}
+                ifx!{f!(q_enable) => {
                     // RLP item decoding unit
                     cb.base.set_cell_manager(rlp_cm.clone());
                     cb.base.push_region(MPTRegion::RLP as usize, 1);
                     rlp_item = MainRLPGadget::construct(&mut cb, params);
+                    unconstrained_cells.push((MPTRegion::RLP, cb.base.unconstrained_region_cells()));
                     cb.base.pop_region();
                     ctx.rlp_item = rlp_item.clone();
 
                     // Main MPT circuit
                     // State machine
                     cb.base.set_cell_manager(state_cm.clone());
-                    ifx! {f!(q_first) + f!(q_last) => {
+                    ifx! {f!(q_first) => {
                         require!(a!(state_machine.is_start) => true);
                     }};
+                    ifx! {f!(q_last) => {
+                        require!(a!(state_machine.is_start) + a!(state_machine.is_padding) => true);
+                    }};
                     // Main state machine
                     matchx! {(
                         a!(state_machine.is_start) => {
@@ -316,6 +397,7 @@ impl<F: Field> MPTConfig<F> {
                             cb.base.push_region(MPTRegion::Start as usize, StartRowType::Count as usize);
                             state_machine.start_config = StartConfig::configure(meta, &mut cb, &mut ctx);
                             ctx.memory.build_constraints(&mut cb.base, f!(q_first));
+                            unconstrained_cells.push((MPTRegion::Start, cb.base.unconstrained_region_cells()));
                             cb.base.pop_region();
                         },
                         a!(state_machine.is_branch) => {
@@ -323,6 +405,7 @@ impl<F: Field> MPTConfig<F> {
                             cb.base.push_region(MPTRegion::Branch as usize, ExtensionBranchRowType::Count as usize);
                             state_machine.branch_config = ExtensionBranchConfig::configure(meta, &mut cb, &mut ctx);
                             ctx.memory.build_constraints(&mut cb.base, f!(q_first));
+                            unconstrained_cells.push((MPTRegion::Branch, cb.base.unconstrained_region_cells()));
                             cb.base.pop_region();
                         },
                         a!(state_machine.is_account) => {
@@ -330,6 +413,7 @@ impl<F: Field> MPTConfig<F> {
                             cb.base.push_region(MPTRegion::Account as usize, AccountRowType::Count as usize);
                             state_machine.account_config = AccountLeafConfig::configure(meta, &mut cb, &mut ctx);
                             ctx.memory.build_constraints(&mut cb.base, f!(q_first));
+                            unconstrained_cells.push((MPTRegion::Account, cb.base.unconstrained_region_cells()));
                             cb.base.pop_region();
                         },
                         a!(state_machine.is_storage) => {
@@ -337,6 +421,17 @@ impl<F: Field> MPTConfig<F> {
                             cb.base.push_region(MPTRegion::Storage as usize, StorageRowType::Count as usize);
                             state_machine.storage_config = StorageLeafConfig::configure(meta, &mut cb, &mut ctx);
                             ctx.memory.build_constraints(&mut cb.base, f!(q_first));
+                            unconstrained_cells.push((MPTRegion::Storage, cb.base.unconstrained_region_cells()));
+                            cb.base.pop_region();
+                        },
+                        a!(state_machine.is_padding) => {
+                            state_machine.step_constraints(meta, &mut cb, PaddingRowType::Count as usize);
+                            cb.base.push_region(MPTRegion::Padding as usize, PaddingRowType::Count as usize);
+                            // Padding only follows the end node or padding, so no proof
+                            // is left unfinished
+                            state_machine.padding_config = PaddingConfig::configure(&mut cb, &mut ctx);
+                            ctx.memory.build_constraints(&mut cb.base, f!(q_first));
+                            unconstrained_cells.push((MPTRegion::Padding, cb.base.unconstrained_region_cells()));
                             cb.base.pop_region();
                         },
                         _ => ctx.memory.build_constraints(&mut cb.base, f!(q_first)),
@@ -345,6 +440,8 @@ impl<F: Field> MPTConfig<F> {
                     ifx! {not!(a!(state_machine.is_account) + a!(state_machine.is_storage)) => {
                         require!(a!(ctx.mpt_table.proof_type) => MPTProofType::Disabled.expr());
                     }}
+                    // Only the first row of a leaf holds an update that can be looked up
+                    require!(a!(ctx.mpt_table.q_lookup) => a!(state_machine.is_account) + a!(state_machine.is_storage));
                 }}
             });
             cb.base.build_constraints()
@@ -359,18 +456,111 @@ impl<F: Field> MPTConfig<F> {
         }
         let cell_columns = [rlp_cm.columns(), state_cm.columns()].concat();
 
+        // Readable names of all columns, for the MockProver errors, dev-graph,
+        // the gates dump and the assignment trace
+        let mut column_names: Vec<(Column<Any>, String)> = vec![
+            (q_enable.into(), "q_enable".to_string()),
+            (q_first.into(), "q_first".to_string()),
+            (q_last.into(), "q_last".to_string()),
+            (q_mult.into(), "q_mult".to_string()),
+        ];
+        for (idx, column) in fixed_table.iter().enumerate() {
+            column_names.push(((*column).into(), format!("fixed_table_{}", idx)));
+        }
+        for (column, name) in mult_table.iter().zip(MULT_TABLE_NAMES) {
+            column_names.push(((*column).into(), name.to_string()));
+        }
+        let state_selector_names = [
+            "is_start",
+            "is_branch",
+            "is_account",
+            "is_storage",
+            "is_padding",
+        ];
+        for (column, name) in state_machine
+            .state_selectors()
+            .iter()
+            .zip(state_selector_names)
+        {
+            column_names.push(((*column).into(), name.to_string()));
+        }
+        let mut tables = vec![
+            ("mpt_table", &mpt_table as &dyn LookupTable<F>),
+            ("keccak_table", &keccak_table as &dyn LookupTable<F>),
+        ];
+        if let Some(bytecode_table) = &bytecode_table {
+            tables.push(("bytecode_table", bytecode_table as &dyn LookupTable<F>));
+        }
+        for (prefix, table) in tables {
+            table.annotate_columns(meta);
+            for (column, annotation) in table.columns().into_iter().zip(table.annotations()) {
+                column_names.push((column, format!("{}.{}", prefix, annotation)));
+            }
+        }
+        // Not part of the table, prefixed to its lookups
+        column_names.push((mpt_table.q_lookup.into(), "mpt_table.q_lookup".to_string()));
+        // The reads and writes of the memory are also cell columns
+        for (tag, column, annotation) in memory.annotated_columns() {
+            let name = format!("{}_{}", tag.column_name(), annotation);
+            column_names.push((column.into(), name));
+        }
+        // Numbered by type, e.g. `rlp_byte_03` for the fourth byte column
+        for (prefix, cm) in [("rlp", &rlp_cm), ("state", &state_cm)] {
+            let mut counts = HashMap::new();
+            for c in cm.columns() {
+                let column: Column<Any> = c.column.into();
+                if column_names.iter().any(|(named, _)| *named == column) {
+                    continue;
+                }
+                let count = counts.entry(c.cell_type).or_insert(0);
+                let name = format!("{}_{}_{:02}", prefix, c.cell_type.column_name(), count);
+                column_names.push((column, name));
+                *count += 1;
+            }
+        }
+
+        // Writes all gates and lookups in a readable form to the given file
+        if let Ok(path) = var("MPT_DUMP_GATES") {
+            let mut printer = ExpressionPrinter::new();
+            for (column, name) in column_names.iter() {
+                printer.name_column(*column, name);
+            }
+            printer
+                .dump(meta, &path)
+                .expect("Cannot write the MPT gates");
+            println!("MPT gates written to {}", path);
+        }
+
         println!("max expression degree: {}", meta.degree());
         println!("num lookups: {}", meta.lookups().len());
         println!("num advices: {}", meta.num_advice_columns());
         println!("num fixed: {}", meta.num_fixed_columns());
-        // cb.base.print_stats();
+        // Degree and size of every constraint, to check the effect of caching
+        // sub-expressions with `cached_expr`
+        if var("MPT_PRINT_STATS").is_ok() {
+            cb.base.print_stats();
+        }
+
+        // The RLP cells are mostly constrained in the node regions (through
+        // `RLPItemView`s), so only report the ones not used anywhere.
+        let queried_columns = cb.base.queried_advice_columns();
+        for (region, cells) in unconstrained_cells.iter_mut() {
+            if *region == MPTRegion::RLP {
+                cells.retain(|cell| !queried_columns.contains(&cell.column.unwrap().index()));
+            }
+        }
+        if var("MPT_AUDIT_CELLS").is_ok() {
+            print_unconstrained_cells(&unconstrained_cells, &cell_columns);
+        }
 
         MPTConfig {
             q_enable,
             q_first,
             q_last,
+            q_mult,
             memory,
             keccak_table,
+            bytecode_table,
             fixed_table,
             mult_table,
             state_machine,
@@ -378,36 +568,96 @@ impl<F: Field> MPTConfig<F> {
             params,
             mpt_table,
             cell_columns,
+            column_names,
             cb,
         }
     }
 
-    /// Make the assignments to the MPTCircuit
+    /// Name of an advice column of the MPT circuit
+    fn column_name(&self, column: Column<Advice>) -> String {
+        let column: Column<Any> = column.into();
+        self.column_names
+            .iter()
+            .find(|(named, _)| *named == column)
+            .map_or_else(
+                || format!("advice {}", column.index()),
+                |(_, name)| name.clone(),
+            )
+    }
+
+    /// Make the assignments to the MPTCircuit. With `single_pass` the first
+    /// call of the region closure, the shape pass of `SimpleFloorPlanner`,
+    /// only declares the shape of the region, so the witness is computed once.
     pub fn assign(
         &self,
         layouter: &mut impl Layouter<F>,
         nodes: &[Node],
         challenges: &Challenges<Value<F>>,
     ) -> Result<usize, Error> {
-        let mut height = 0;
+        // Writes all advice assignments, annotated with the node that made them,
+        // to the given CSV file
+        let trace_path = var("MPT_ASSIGNMENT_TRACE").ok();
+
+        // The circuit is filled with padding nodes after the end node of the
+        // last proof. The row checked by `q_last` must be the start of the end
+        // node or padding, so there is either no padding or at least two
+        // padding rows.
+        let used_rows: usize = nodes.iter().map(Node::num_rows).sum();
+        // Small witnesses are padded to a minimum height
+        let min_height = 2 * HASH_WIDTH + 1;
+        let height = match self.params.padded_height() {
+            Some(padded_height) => {
+                let max_rows = padded_height - StartRowType::Count as usize;
+                if used_rows > max_rows {
+                    log::error!(
+                        "MPT witness needs {} rows, more than the {} rows of {} nodes",
+                        used_rows,
+                        max_rows,
+                        self.params.max_nodes
+                    );
+                    return Err(Error::Synthesis);
+                }
+                padded_height
+            }
+            None if used_rows >= min_height => used_rows,
+            None => min_height.max(used_rows + StartRowType::Count as usize),
+        };
+        let padding = vec![Node::padding(); height - used_rows];
+
+        let mut is_shape_pass = true;
         layouter.assign_region(
             || "MPT",
             |mut region| {
                 let mut keccak_r = F::ZERO;
                 challenges.keccak_input().map(|v| keccak_r = v);
+                let mut key_r = F::ZERO;
+                challenges.key_input().map(|v| key_r = v);
+
+                // The first call only measures the region, its shape is
+                // declared without computing the witness
+                if self.params.single_pass && is_shape_pass {
+                    is_shape_pass = false;
+                    return self.assign_shape(&mut region, height);
+                }
 
                 let mut memory = self.memory.clone();
 
+                let mut trace = Vec::new();
                 let mut offset = 0;
-                for node in nodes.iter() {
-                    //println!("offset: {}", offset);
-                    let mut cached_region = CachedRegion::new(
-                        &mut region,
-                        keccak_r,
-                    );
-                    cached_region.annotate_columns(&self.cell_columns);
+                for (node_idx, node) in nodes.iter().chain(padding.iter()).enumerate() {
+                    // println!("offset: {}", offset);
+                    let mut cached_region = CachedRegion::new(&mut region, keccak_r, key_r);
+                    // The column names are the same for the whole region
+                    if node_idx == 0 {
+                        cached_region.name_columns(&self.column_names);
+                    }
+                    if trace_path.is_some() {
+                        cached_region.enable_trace();
+                    }
 
-                    let item_types = if node.start.is_some() {
+                    let item_types = if node.padding {
+                        vec![]
+                    } else if node.start.is_some() {
                         NODE_RLP_TYPES_START.to_vec()
                     } else if node.extension_branch.is_some() {
                         NODE_RLP_TYPES_BRANCH.to_vec()
@@ -422,8 +672,13 @@ impl<F: Field> MPTConfig<F> {
                     // Assign bytes
                     let mut rlp_values = Vec::new();
                     // Decompose RLP
-                    for (idx, (bytes, item_type)) in node.values.iter().zip(item_types.iter()).enumerate() {
+                    for (idx, (bytes, item_type)) in
+                        node.values.iter().zip(item_types.iter()).enumerate()
+                    {
                         cached_region.push_region(offset + idx, MPTRegion::RLP as usize);
+                        cached_region.set_trace_context(|| {
+                            format!("node {} rlp item {} ({:?})", node_idx, idx, item_type)
+                        });
                         let rlp_value = self.rlp_item.assign(
                             &mut cached_region,
                             offset + idx,
@@ -435,9 +690,20 @@ impl<F: Field> MPTConfig<F> {
                     }
 
                     // Assign nodes
-                    if node.start.is_some() {
-                        //println!("{}: start", offset);
+                    if node.padding {
+                        cached_region.push_region(offset, MPTRegion::Padding as usize);
+                        cached_region.set_trace_context(|| format!("node {} padding", node_idx));
+                        assign!(cached_region, (self.state_machine.is_padding, offset) => "is_padding", true.scalar())?;
+                        self.state_machine.padding_config.assign(
+                            &mut cached_region,
+                            &mut memory,
+                            offset,
+                        )?;
+                        cached_region.pop_region();
+                    } else if node.start.is_some() {
+                        // println!("{}: start", offset);
                         cached_region.push_region(offset, MPTRegion::Start as usize);
+                        cached_region.set_trace_context(|| format!("node {} start", node_idx));
                         assign!(cached_region, (self.state_machine.is_start, offset) => "is_start", true.scalar())?;
                         self.state_machine.start_config.assign(
                             &mut cached_region,
@@ -449,8 +715,9 @@ impl<F: Field> MPTConfig<F> {
                         )?;
                         cached_region.pop_region();
                     } else if node.extension_branch.is_some() {
-                        //println!("{}: branch", offset);
+                        // println!("{}: branch", offset);
                         cached_region.push_region(offset, MPTRegion::Branch as usize);
+                        cached_region.set_trace_context(|| format!("node {} branch", node_idx));
                         assign!(cached_region, (self.state_machine.is_branch, offset) => "is_branch", true.scalar())?;
                         self.state_machine.branch_config.assign(
                             &mut cached_region,
@@ -462,8 +729,9 @@ impl<F: Field> MPTConfig<F> {
                         )?;
                         cached_region.pop_region();
                     } else if node.account.is_some() {
-                        //println!("{}: account", offset);
+                        // println!("{}: account", offset);
                         cached_region.push_region(offset, MPTRegion::Account as usize);
+                        cached_region.set_trace_context(|| format!("node {} account", node_idx));
                         assign!(cached_region, (self.state_machine.is_account, offset) => "is_account", true.scalar())?;
                         self.state_machine.account_config.assign(
                             &mut cached_region,
@@ -475,8 +743,9 @@ impl<F: Field> MPTConfig<F> {
                         )?;
                         cached_region.pop_region();
                     } else if node.storage.is_some() {
-                        //println!("{}: storage", offset);
+                        // println!("{}: storage", offset);
                         cached_region.push_region(offset, MPTRegion::Storage as usize);
+                        cached_region.set_trace_context(|| format!("node {} storage", node_idx));
                         assign!(cached_region, (self.state_machine.is_storage, offset) => "is_storage", true.scalar())?;
                         self.state_machine.storage_config.assign(
                             &mut cached_region,
@@ -489,19 +758,18 @@ impl<F: Field> MPTConfig<F> {
                         cached_region.pop_region();
                     }
 
-                    offset += node.values.len();
+                    offset += node.num_rows();
 
+                    cached_region.set_trace_context(|| format!("node {} memory", node_idx));
                     memory.assign(&mut cached_region, offset)?;
 
                     cached_region.assign_stored_expressions(&self.cb.base, challenges)?;
+                    trace.extend(cached_region.take_trace());
                 }
-                height = offset;
-
-                // Make sure the circuit is high enough for the mult table
-                while height < (2 * HASH_WIDTH + 1) {
-                    height += 1;
+                if let Some(path) = &trace_path {
+                    let csv = trace_to_csv(&trace, |column| self.column_name(column));
+                    std::fs::write(path, csv).expect("Cannot write the assignment trace");
                 }
-
                 for offset in 0..height {
                     assignf!(region, (self.q_enable, offset) => true.scalar())?;
                     assignf!(region, (self.q_first, offset) => (offset == 0).scalar())?;
@@ -515,136 +783,242 @@ impl<F: Field> MPTConfig<F> {
         Ok(height)
     }
 
+    /// Declares the shape of the MPT region of `height` rows: the selectors
+    /// and the last row of every advice column the witness is assigned to.
+    /// The memory banks assign their key up to row `height`.
+    fn assign_shape(&self, region: &mut Region<'_, F>, height: usize) -> Result<(), Error> {
+        for offset in 0..height {
+            assignf!(region, (self.q_enable, offset) => true.scalar())?;
+            assignf!(region, (self.q_first, offset) => (offset == 0).scalar())?;
+            assignf!(region, (self.q_last, offset) => (offset == height - 2).scalar())?;
+        }
+        let columns = self
+            .cell_columns
+            .iter()
+            .map(|c| c.column)
+            .chain(self.state_machine.state_selectors())
+            .chain(self.memory.get_columns())
+            .chain(LookupTable::<F>::advice_columns(&self.mpt_table));
+        for column in columns {
+            assign!(region, (column, height) => 0.scalar())?;
+        }
+        Ok(())
+    }
+
     /// Loads MPT fixed table
     pub fn load_fixed_table(&self, layouter: &mut impl Layouter<F>) -> Result<(), Error> {
-        layouter.assign_region(
-            || "fixed table",
-            |mut region| {
-                let mut offset = 0;
+        load_fixed_table(layouter, &self.fixed_table, self.params)
+    }
 
-                // Zero lookup
-                for fixed_table in self.fixed_table.iter() {
-                    assignf!(region, (*fixed_table, offset) => 0.scalar())?;
-                }
-                offset += 1;
+    /// Loads the mult table, see `MPTCircuitParams::mult_table_len`
+    pub fn load_mult_table(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        challenges: &Challenges<Value<F>>,
+    ) -> Result<(), Error> {
+        load_mult_table(
+            layouter,
+            &self.mult_table,
+            Some(self.q_mult),
+            challenges,
+            self.params.mult_table_len(),
+        )
+    }
+}
 
-                // Byte range table
-                for ind in 0..256 {
-                    assignf!(region, (self.fixed_table[0], offset) => FixedTableTag::Range256.scalar())?;
-                    assignf!(region, (self.fixed_table[1], offset) => ind.scalar())?;
-                    offset += 1;
-                }
+/// Loads MPT fixed table
+pub(crate) fn load_fixed_table<F: Field>(
+    layouter: &mut impl Layouter<F>,
+    fixed_table: &[Column<Fixed>; 6],
+    params: MPTCircuitParams,
+) -> Result<(), Error> {
+    layouter.assign_region(
+        || "fixed table",
+        |mut region| {
+            for (idx, column) in fixed_table.iter().enumerate() {
+                region.name_column(|| format!("fixed_table_{}", idx), *column);
+            }
+            let mut offset = 0;
 
-                // Nibble range table
-                for ind in 0..16 {
-                    assignf!(region, (self.fixed_table[0], offset) => FixedTableTag::Range16.scalar())?;
-                    assignf!(region, (self.fixed_table[1], offset) => ind.scalar())?;
-                    offset += 1;
-                }
+            // Zero lookup
+            for fixed_table in fixed_table.iter() {
+                assignf!(region, (*fixed_table, offset) => 0.scalar())?;
+            }
+            offset += 1;
 
-                // Byte range with length table
-                // This allows us to easily check whether there are zeros in the unused columns (the number of unused columns vary).
-                // The lookups ensure that when the unused columns start, the values in these columns are zeros -
-                // when the unused columns start, the value that is used for the lookup in the last column is zero or negative
-                // and thus a zero is enforced.
-                for (tag, range, out_of_range) in [
-                    (FixedTableTag::RangeKeyLen256, 256, 1),
-                    (FixedTableTag::RangeKeyLen16, 16, 16),
-                ] {
-                    let get_range = |n: i32| {
-                        if n <= 0 { out_of_range } else { range }
-                    };
-                    let max_length = RLP_UNIT_NUM_BYTES as i32;
-                    for idx in -max_length..=max_length {
-                        if self.params.is_two_byte_lookup_enabled() {
-                            let range1 = get_range(idx);
-                            for byte1 in 0..range1 {
-                                let range2 = get_range(idx - 1);
-                                for byte2 in 0..range2 {
-                                    assignf!(region, (self.fixed_table[0], offset) => tag.scalar())?;
-                                    assignf!(region, (self.fixed_table[1], offset) => idx.scalar())?;
-                                    assignf!(region, (self.fixed_table[2], offset) => byte1.scalar())?;
-                                    assignf!(region, (self.fixed_table[3], offset) => byte2.scalar())?;
-                                    offset += 1;
-                                }
+            // Byte range table
+            for ind in 0..256 {
+                assignf!(region, (fixed_table[0], offset) => FixedTableTag::Range256.scalar())?;
+                assignf!(region, (fixed_table[1], offset) => ind.scalar())?;
+                offset += 1;
+            }
+
+            // Nibble range table
+            for ind in 0..16 {
+                assignf!(region, (fixed_table[0], offset) => FixedTableTag::Range16.scalar())?;
+                assignf!(region, (fixed_table[1], offset) => ind.scalar())?;
+                offset += 1;
+            }
+
+            // Byte range with length table
+            // This allows us to easily check whether there are zeros in the unused columns (the number of unused columns vary).
+            // The lookups ensure that when the unused columns start, the values in these columns are zeros -
+            // when the unused columns start, the value that is used for the lookup in the last column is zero or negative
+            // and thus a zero is enforced.
+            for (tag, range, out_of_range) in [
+                (FixedTableTag::RangeKeyLen256, 256, 1),
+                (FixedTableTag::RangeKeyLen16, 16, 16),
+            ] {
+                let get_range = |n: i32| {
+                    if n <= 0 { out_of_range } else { range }
+                };
+                let max_length = RLP_UNIT_NUM_BYTES as i32;
+                for idx in -max_length..=max_length {
+                    if params.is_two_byte_lookup_enabled() {
+                        let range1 = get_range(idx);
+                        for byte1 in 0..range1 {
+                            let range2 = get_range(idx - 1);
+                            for byte2 in 0..range2 {
+                                assignf!(region, (fixed_table[0], offset) => tag.scalar())?;
+                                assignf!(region, (fixed_table[1], offset) => idx.scalar())?;
+                                assignf!(region, (fixed_table[2], offset) => byte1.scalar())?;
+                                assignf!(region, (fixed_table[3], offset) => byte2.scalar())?;
+                                offset += 1;
                             }
-                        } else {
-                            let range = get_range(idx);
-                            for byte in 0..range {
-                                for msb_nonzero_check in [false, true] {
-                                    // Don't put 0 in the table at index 1 when having to do the msb non-zero check
-                                    if !(idx == 1 && byte == 0 && msb_nonzero_check) {
-                                        assignf!(region, (self.fixed_table[0], offset) => tag.scalar())?;
-                                        assignf!(region, (self.fixed_table[1], offset) => idx.scalar())?;
-                                        assignf!(region, (self.fixed_table[2], offset) => byte.scalar())?;
-                                        assignf!(region, (self.fixed_table[3], offset) => msb_nonzero_check.scalar())?;
-                                        offset += 1;
-                                    }
+                        }
+                    } else {
+                        let range = get_range(idx);
+                        for byte in 0..range {
+                            for msb_nonzero_check in [false, true] {
+                                // Don't put 0 in the table at index 1 when having to do the msb non-zero check
+                                if !(idx == 1 && byte == 0 && msb_nonzero_check) {
+                                    assignf!(region, (fixed_table[0], offset) => tag.scalar())?;
+                                    assignf!(region, (fixed_table[1], offset) => idx.scalar())?;
+                                    assignf!(region, (fixed_table[2], offset) => byte.scalar())?;
+                                    assignf!(region, (fixed_table[3], offset) => msb_nonzero_check.scalar())?;
+                                    offset += 1;
                                 }
                             }
                         }
                     }
                 }
+            }
 
-                // Compact encoding of the extension key, find out if the key is odd or not.
-                // Even - The full byte is simply 0.
-                assignf!(region, (self.fixed_table[0], offset) => FixedTableTag::ExtOddKey.scalar())?;
-                assignf!(region, (self.fixed_table[1], offset) => 0.scalar())?;
-                assignf!(region, (self.fixed_table[2], offset) => false.scalar())?;
+            // Compact encoding of the extension key, find out if the key is odd or not.
+            // Even - The full byte is simply 0.
+            assignf!(region, (fixed_table[0], offset) => FixedTableTag::ExtOddKey.scalar())?;
+            assignf!(region, (fixed_table[1], offset) => 0.scalar())?;
+            assignf!(region, (fixed_table[2], offset) => false.scalar())?;
+            offset += 1;
+            // Odd - First nibble is 1, the second nibble can be any value.
+            for idx in 0..16 {
+                assignf!(region, (fixed_table[0], offset) => FixedTableTag::ExtOddKey.scalar())?;
+                assignf!(region, (fixed_table[1], offset) => ((0b1_0000) + idx).scalar())?;
+                assignf!(region, (fixed_table[2], offset) => true.scalar())?;
                 offset += 1;
-                // Odd - First nibble is 1, the second nibble can be any value.
-                for idx in 0..16 {
-                    assignf!(region, (self.fixed_table[0], offset) => FixedTableTag::ExtOddKey.scalar())?;
-                    assignf!(region, (self.fixed_table[1], offset) => ((0b1_0000) + idx).scalar())?;
-                    assignf!(region, (self.fixed_table[2], offset) => true.scalar())?;
-                    offset += 1;
-                }
+            }
 
-                // RLP
-                for byte in 0..255 {
-                    let (is_list, is_short, is_long, is_very_long) = decode_rlp(byte);
-                    assignf!(region, (self.fixed_table[0], offset) => FixedTableTag::RLP.scalar())?;
-                    assignf!(region, (self.fixed_table[1], offset) => byte.scalar())?;
-                    assignf!(region, (self.fixed_table[2], offset) => is_list.scalar())?;
-                    assignf!(region, (self.fixed_table[3], offset) => is_short.scalar())?;
-                    assignf!(region, (self.fixed_table[4], offset) => is_long.scalar())?;
-                    assignf!(region, (self.fixed_table[5], offset) => is_very_long.scalar())?;
-                    offset += 1;
-                }
+            // RLP
+            for byte in 0..255 {
+                let (is_list, is_short, is_long, is_very_long) = decode_rlp(byte);
+                assignf!(region, (fixed_table[0], offset) => FixedTableTag::RLP.scalar())?;
+                assignf!(region, (fixed_table[1], offset) => byte.scalar())?;
+                assignf!(region, (fixed_table[2], offset) => is_list.scalar())?;
+                assignf!(region, (fixed_table[3], offset) => is_short.scalar())?;
+                assignf!(region, (fixed_table[4], offset) => is_long.scalar())?;
+                assignf!(region, (fixed_table[5], offset) => is_very_long.scalar())?;
+                offset += 1;
+            }
+            debug_assert_eq!(offset, params.fixed_table_len());
 
-                Ok(())
-            },
-        )
-    }
+            Ok(())
+        },
+    )
+}
 
-    ///
-    pub fn load_mult_table(
-        &self,
-        layouter: &mut impl Layouter<F>,
-        challenges: &Challenges<Value<F>>,
-        height: usize,
-    ) -> Result<(), Error> {
-        layouter.assign_region(
-            || "mult table",
-            |mut region| {
-                let mut r = F::ZERO;
-                challenges.keccak_input().map(|k| r = k);
-
-                let mut mult = F::ONE;
-                for idx in 0..=height {
-                    assign!(region, (self.mult_table[0], idx) => idx.scalar())?;
-                    assign!(region, (self.mult_table[1], idx) => mult)?;
-                    mult *= r;
+/// Names of the columns of the mult table: the exponent, the power of the
+/// keccak randomness and the power of the key randomness
+const MULT_TABLE_NAMES: [&str; 3] = ["mult_table_idx", "mult_table_mult", "mult_table_key_mult"];
+
+/// Loads the table of the powers of the keccak and of the key randomness, with
+/// the exponents `0..len`. `q_mult` is enabled on the rows checked against the
+/// next one, if the table is constrained.
+pub(crate) fn load_mult_table<F: Field>(
+    layouter: &mut impl Layouter<F>,
+    mult_table: &[Column<Advice>; 3],
+    q_mult: Option<Column<Fixed>>,
+    challenges: &Challenges<Value<F>>,
+    len: usize,
+) -> Result<(), Error> {
+    layouter.assign_region(
+        || "mult table",
+        |mut region| {
+            for (column, name) in mult_table.iter().zip(MULT_TABLE_NAMES) {
+                region.name_column(|| name, *column);
+            }
+            let mut r = F::ZERO;
+            challenges.keccak_input().map(|k| r = k);
+            let mut key_r = F::ZERO;
+            challenges.key_input().map(|k| key_r = k);
+
+            let mut mult = F::ONE;
+            let mut key_mult = F::ONE;
+            for idx in 0..len {
+                assign!(region, (mult_table[0], idx) => idx.scalar())?;
+                assign!(region, (mult_table[1], idx) => mult)?;
+                assign!(region, (mult_table[2], idx) => key_mult)?;
+                if let Some(q_mult) = q_mult {
+                    assignf!(region, (q_mult, idx) => (idx + 1 < len).scalar())?;
                 }
-                Ok(())
-            },
-        )
+                mult *= r;
+                key_mult *= key_r;
+            }
+            Ok(())
+        },
+    )
+}
+
+/// Prints the cells of each region that are not used in any constraint or lookup
+pub(crate) fn print_unconstrained_cells<F: Field>(
+    regions: &[(MPTRegion, Vec<Cell<F>>)],
+    cell_columns: &[CellColumn<F, MptCellType>],
+) {
+    for (region, cells) in regions.iter() {
+        println!("{:?}: {} unconstrained cells", region, cells.len());
+        for cell in cells.iter() {
+            let column = cell.column.unwrap();
+            let cell_type = cell_columns
+                .iter()
+                .find(|c| c.column == column)
+                .map(|c| c.cell_type);
+            println!(
+                "  {:?} column {} rotation {}",
+                cell_type,
+                column.index(),
+                cell.rotation
+            );
+        }
     }
 }
 
+/// Floor planner the MPT circuit is laid out with
+pub trait MptFloorPlanner: FloorPlanner {
+    /// Whether the closure of a region is called twice per synthesis, first to
+    /// measure the region and then to assign it. `V1` measures all the regions
+    /// in a synthesis of its own instead.
+    const REGION_SHAPE_PASS: bool;
+}
+
+impl MptFloorPlanner for SimpleFloorPlanner {
+    const REGION_SHAPE_PASS: bool = true;
+}
+
+impl MptFloorPlanner for V1 {
+    const REGION_SHAPE_PASS: bool = false;
+}
+
 /// MPT Circuit for proving the storage modification is valid.
-#[derive(Default)]
-pub struct MPTCircuit<F: Field> {
+pub struct MPTCircuit<F: Field, P: MptFloorPlanner = SimpleFloorPlanner> {
     /// MPT nodes
     pub nodes: Vec<Node>,
     /// MPT keccak_data
@@ -654,8 +1028,80 @@ pub struct MPTCircuit<F: Field> {
     /// Can be used to test artificially created tests with keys without known their known
     /// preimage. ONLY ENABLE FOR TESTS!
     pub disable_preimage_check: bool,
+    /// Disallows leaving behind empty accounts (no code, zero nonce and zero balance) like
+    /// required after EIP-161. Tests and pre-EIP-161 blocks can contain empty accounts.
+    pub strict_empty_accounts: bool,
+    /// Only allows the code hash of an account to change in a code hash update, accounts
+    /// created by other updates start without code.
+    pub strict_codehash: bool,
+    /// Requires nonce updates of existing accounts to increment the nonce by one, as done by a
+    /// single transaction. Not suited to witnesses merging several transactions.
+    pub nonce_increment: bool,
+    /// Adds columns to the MPT table holding the balance change of BalanceChanged updates, so
+    /// that consuming circuits can sum the changes without decomposing the values again.
+    pub balance_delta: bool,
+    /// How the trie keys are derived from the addresses and storage slots
+    pub key_derivation: KeyDerivation,
+    /// Number of nodes the witness is padded to, zero to size the circuit to
+    /// the witness
+    pub max_nodes: usize,
+    /// Computes the witness only once per synthesis instead of once per
+    /// layouter pass, the shape of the MPT region is declared without it.
+    /// Requires a floor planner with a region shape pass.
+    pub single_pass: bool,
     /// Marker
-    pub _marker: PhantomData<F>,
+    pub _marker: PhantomData<(F, P)>,
+}
+
+impl<F: Field, P: MptFloorPlanner> Default for MPTCircuit<F, P> {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            keccak_data: Vec::new(),
+            degree: 0,
+            disable_preimage_check: false,
+            strict_empty_accounts: false,
+            strict_codehash: false,
+            nonce_increment: false,
+            balance_delta: false,
+            key_derivation: KeyDerivation::default(),
+            max_nodes: 0,
+            single_pass: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Derivation of the trie key from an address or a storage slot, checked for every leaf
+/// unless the preimage check is disabled
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum KeyDerivation {
+    /// `keccak256(preimage)`, the secure trie of Ethereum mainnet
+    #[default]
+    Keccak,
+    /// The preimage itself, for chains that do not hash the keys of their state trie
+    Identity,
+}
+
+impl From<KeyDerivation> for u8 {
+    fn from(key_derivation: KeyDerivation) -> Self {
+        match key_derivation {
+            KeyDerivation::Keccak => 0,
+            KeyDerivation::Identity => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for KeyDerivation {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(KeyDerivation::Keccak),
+            1 => Ok(KeyDerivation::Identity),
+            _ => Err(value),
+        }
+    }
 }
 
 /// MPT Circuit configuration parameters
@@ -665,9 +1111,68 @@ pub struct MPTCircuitParams {
     pub degree: usize,
     ///
     pub disable_preimage_check: bool,
+    ///
+    pub strict_empty_accounts: bool,
+    /// Checks that the code hash is immutable outside of code hash updates
+    pub strict_codehash: bool,
+    /// Checks that nonce updates increment the nonce by one
+    pub nonce_increment: bool,
+    /// Exposes the balance changes in the MPT table
+    pub balance_delta: bool,
+    ///
+    pub key_derivation: KeyDerivation,
+    /// Number of nodes the witness is padded to, so that the fixed columns and
+    /// thus the verifying key do not depend on the witness. Zero sizes the
+    /// circuit to the witness.
+    pub max_nodes: usize,
+    /// Skips the witness in the region shape pass, see `MPTConfig::assign`
+    pub single_pass: bool,
 }
 
 impl MPTCircuitParams {
+    /// Rows assigned when padding to `max_nodes` nodes of any type, with room
+    /// left for at least two padding rows
+    pub fn padded_height(&self) -> Option<usize> {
+        (self.max_nodes > 0).then(|| {
+            self.max_nodes * ExtensionBranchRowType::Count as usize + StartRowType::Count as usize
+        })
+    }
+
+    /// Number of powers of the randomness in the mult table, derived from the
+    /// trie the circuit is built for. The exponents looked up are the bytes of
+    /// an RLP item after its RLP byte (`MainRLPGadget`) and the bytes of an
+    /// extension key in compact encoding (`ExtensionGadget`). The gadgets
+    /// check their lookups against it.
+    pub fn mult_table_len(&self) -> usize {
+        let max_item_len = RLP_UNIT_NUM_BYTES - 1;
+        let max_key_len = KEY_LEN + 1;
+        max_item_len.max(max_key_len) + 1
+    }
+
+    /// Number of rows of the fixed table, see `load_fixed_table`
+    pub fn fixed_table_len(&self) -> usize {
+        let max_length = RLP_UNIT_NUM_BYTES as i32;
+        let key_len_rows: usize = [(256, 1), (16, 16)]
+            .into_iter()
+            .map(|(range, out_of_range)| {
+                let get_range = |n: i32| if n <= 0 { out_of_range } else { range };
+                (-max_length..=max_length)
+                    .map(|idx| {
+                        if self.is_two_byte_lookup_enabled() {
+                            get_range(idx) * get_range(idx - 1)
+                        } else {
+                            // Without 0 at index 1 with the msb non-zero check
+                            2 * get_range(idx) - usize::from(idx == 1)
+                        }
+                    })
+                    .sum::<usize>()
+            })
+            .sum();
+        // Zero lookup, byte and nibble ranges, key lengths, odd extension keys
+        // and RLP bytes
+        1 + 256 + 16 + key_len_rows + 1 + 16 + 255
+    }
+
     fn is_two_byte_lookup_enabled(&self) -> bool {
         // Currently not enabled because the two byte lookup table does not support msb non-zero
         // check.
@@ -679,9 +1184,9 @@ impl MPTCircuitParams {
     }
 }
 
-impl<F: Field> Circuit<F> for MPTCircuit<F> {
+impl<F: Field, P: MptFloorPlanner> Circuit<F> for MPTCircuit<F, P> {
     type Config = (MPTConfig<F>, Challenges);
-    type FloorPlanner = SimpleFloorPlanner;
+    type FloorPlanner = P;
     type Params = MPTCircuitParams;
 
     fn without_witnesses(&self) -> Self {
@@ -692,11 +1197,22 @@ impl<F: Field> Circuit<F> for MPTCircuit<F> {
         MPTCircuitParams {
             degree: self.degree,
             disable_preimage_check: self.disable_preimage_check,
+            strict_empty_accounts: self.strict_empty_accounts,
+            strict_codehash: self.strict_codehash,
+            nonce_increment: self.nonce_increment,
+            balance_delta: self.balance_delta,
+            key_derivation: self.key_derivation,
+            max_nodes: self.max_nodes,
+            single_pass: self.single_pass,
         }
     }
 
     fn configure_with_params(meta: &mut ConstraintSystem<F>, params: Self::Params) -> Self::Config {
-        let challenges = Challenges::construct(meta);
+        assert!(
+            P::REGION_SHAPE_PASS || !params.single_pass,
+            "single pass synthesis needs a floor planner with a region shape pass"
+        );
+        let challenges = Challenges::construct_with_key_input(meta);
         let challenges_expr = challenges.exprs(meta);
         let keccak_table = KeccakTable::construct(meta);
         (
@@ -715,9 +1231,9 @@ impl<F: Field> Circuit<F> for MPTCircuit<F> {
         mut layouter: impl Layouter<F>,
     ) -> Result<(), Error> {
         let challenges = _challenges.values(&mut layouter);
-        let height = config.assign(&mut layouter, &self.nodes, &challenges)?;
+        config.assign(&mut layouter, &self.nodes, &challenges)?;
         config.load_fixed_table(&mut layouter)?;
-        config.load_mult_table(&mut layouter, &challenges, height)?;
+        config.load_mult_table(&mut layouter, &challenges)?;
         config
             .keccak_table
             .dev_load(&mut layouter, &self.keccak_data, &challenges)?;
@@ -728,34 +1244,113 @@ impl<F: Field> Circuit<F> for MPTCircuit<F> {
 
 /// Loads an MPT proof from disk
 pub fn load_proof(path: &str) -> Vec<Node> {
-    let file = std::fs::File::open(path);
-    let reader = std::io::BufReader::new(file.unwrap());
-    let mut nodes: Vec<Node> = serde_json::from_reader(reader).unwrap();
+    let json = std::fs::read_to_string(path).unwrap();
+    parse_proof(&json).unwrap()
+}
 
+/// Parses an MPT proof. The key of the account and storage nodes is taken from
+/// `preimage` when the preimage check of the proof is disabled and from
+/// `hashed_key` otherwise, parsing fails when that field is missing.
+pub fn parse_proof(json: &str) -> Result<Vec<Node>, serde_json::Error> {
+    let mut nodes: Vec<Node> = serde_json::from_str(json)?;
+
+    let mut disable_preimage_check = false;
+    let missing_key = |disable_preimage_check: bool| {
+        <serde_json::Error as serde::de::Error>::missing_field(if disable_preimage_check {
+            "preimage"
+        } else {
+            "hashed_key"
+        })
+    };
     // Add the address and the key to the list of values in the Account and Storage nodes
     for node in nodes.iter_mut() {
+        if let Some(start) = &node.start {
+            disable_preimage_check = start.disable_preimage_check;
+        }
         if let Some(account) = node.account.clone() {
+            let key = account
+                .key(disable_preimage_check)
+                .ok_or_else(|| missing_key(disable_preimage_check))?;
             node.values
                 .push([vec![148], account.address.to_vec()].concat().into());
-            node.values
-                .push([vec![160], account.key.to_vec()].concat().into());
+            node.values.push([vec![160], key.to_vec()].concat().into());
         }
         if let Some(storage) = node.storage.clone() {
+            let key = storage
+                .key(disable_preimage_check)
+                .ok_or_else(|| missing_key(disable_preimage_check))?;
             node.values
                 .push([vec![160], storage.address.to_vec()].concat().into());
-            node.values
-                .push([vec![160], storage.key.to_vec()].concat().into());
+            node.values.push([vec![160], key.to_vec()].concat().into());
         }
     }
-    nodes
+    Ok(nodes)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::DualVerification;
     use halo2_proofs::{dev::MockProver, halo2curves::bn256::Fr};
     use std::{fs, ops::Deref};
 
+    fn run_mpt_test(idx: usize, path: &std::path::Path) {
+        assert_eq!(verify_mpt_test(idx, path, false), Ok(()));
+    }
+
+    fn mpt_test_circuit(
+        path: &std::path::Path,
+        strict_empty_accounts: bool,
+        max_nodes: usize,
+    ) -> MPTCircuit<Fr> {
+        let nodes = load_proof(path.to_str().unwrap());
+
+        let mut keccak_data = vec![];
+        for node in nodes.iter() {
+            for k in node.keccak_data.iter() {
+                keccak_data.push(k.deref().clone());
+            }
+        }
+
+        let disable_preimage_check = nodes[0].start.clone().unwrap().disable_preimage_check;
+        MPTCircuit::<Fr> {
+            nodes,
+            keccak_data,
+            degree: 15,
+            disable_preimage_check,
+            strict_empty_accounts,
+            strict_codehash: false,
+            nonce_increment: false,
+            balance_delta: false,
+            key_derivation: KeyDerivation::Keccak,
+            max_nodes,
+            single_pass: false,
+            _marker: PhantomData,
+        }
+    }
+
+    fn verify_mpt_test(
+        idx: usize,
+        path: &std::path::Path,
+        strict_empty_accounts: bool,
+    ) -> Result<(), Vec<halo2_proofs::dev::VerifyFailure>> {
+        let circuit = mpt_test_circuit(path, strict_empty_accounts, 0);
+        let num_rows: usize = circuit.nodes.iter().map(|node| node.values.len()).sum();
+
+        println!("{} {:?}", idx, path);
+        let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+        prover.verify_at_rows(0..num_rows, 0..num_rows)
+        // assert_eq!(prover.verify_par(), Ok(()));
+        // prover.assert_satisfied();
+    }
+
+    fn run_mpt_tests(names: &[&str]) {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        for (idx, name) in names.iter().enumerate() {
+            run_mpt_test(idx, &path.join(format!("{}.json", name)));
+        }
+    }
+
     #[test]
     fn test_mpt() {
         let path = "src/mpt_circuit/tests";
@@ -770,36 +1365,786 @@ mod tests {
                 }
             })
             .enumerate()
-            .for_each(|(idx, f)| {
-                let path = f.path();
-                let mut parts = path.to_str().unwrap().split('-');
-                parts.next();
-
-                let nodes = load_proof(path.to_str().unwrap());
-                let num_rows: usize = nodes.iter().map(|node| node.values.len()).sum();
-
-                let mut keccak_data = vec![];
-                for node in nodes.iter() {
-                    for k in node.keccak_data.iter() {
-                        keccak_data.push(k.deref().clone());
+            .for_each(|(idx, f)| run_mpt_test(idx, &f.path()));
+    }
+
+    /// Golden witnesses grouped by the node transition they cover, a circuit
+    /// change breaking one of the transitions fails with its name
+    const TEST_VECTORS: &[(&str, &[&str])] = &[
+        ("insert into empty trie", &["LeafAddedToEmptyTrie"]),
+        (
+            "leaf split into branch",
+            &[
+                "AddBranch",
+                "AddBranchLong",
+                "AddBranchTwoLevels",
+                "AddBranchTwoLevelsLong",
+                "AccountAddPlaceholderBranch",
+            ],
+        ),
+        (
+            "extension shortening",
+            &[
+                "ExtensionAddedOneKeyByteSel1",
+                "ExtensionAddedOneKeyByteSel2",
+                "ExtensionAddedTwoKeyBytesSel1",
+                "ExtensionAddedTwoKeyBytesSel2",
+                "ExtensionAddedThreeKeyBytesSel2",
+                "ExtensionAddedInFirstStorageLevelOneKeyByte",
+                "ExtensionAddedInFirstStorageLevelTwoKeyBytes",
+                "AccountAddPlaceholderExtension",
+            ],
+        ),
+        (
+            "extension lengthening",
+            &[
+                "ExtensionDeletedOneKeyByteSel1",
+                "ExtensionDeletedOneKeyByteSel2",
+                "ExtensionDeletedTwoKeyBytesSel1",
+                "ExtensionDeletedTwoKeyBytesSel2",
+                "ExtensionDeletedThreeKeyBytesSel2",
+                "AccountDeletePlaceholderExtension",
+            ],
+        ),
+        (
+            "deletion collapsing branch",
+            &[
+                "DeleteBranch",
+                "DeleteBranchLong",
+                "DeleteBranchTwoLevels",
+                "DeleteBranchTwoLevelsLong",
+                "AccountDeletePlaceholderBranch",
+            ],
+        ),
+        ("deletion to empty trie", &["DeleteToEmptyTrie"]),
+        (
+            "non-existence proofs",
+            &[
+                "NonExistingAccount",
+                "NonExistingAccountAfterFirstLevel",
+                "NonExistingAccountInFirstLevel",
+                "NonExistingAccountNilObject",
+                "NonExistingAccountNilObjectInFirstLevel",
+                "NonExistingStorage",
+                "NonExistingStorageLong",
+                "NonExistingStorageNil",
+                "StorageInFirstLevelNonExisting",
+                "StorageInFirstLevelNonExistingLong",
+            ],
+        ),
+        (
+            "chained extensions",
+            &[
+                "ChainedExtensionsEvenOdd",
+                "ChainedExtensionsOddEven",
+                "ChainedExtensionsThreeLevels",
+            ],
+        ),
+        (
+            "deep tries from mined keys",
+            &["MinedKeysBranchChain", "MinedKeysLongExtension"],
+        ),
+    ];
+
+    #[test]
+    fn test_mpt_vectors() {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        for (transition, names) in TEST_VECTORS.iter() {
+            for (idx, name) in names.iter().enumerate() {
+                let file = path.join(format!("{}.json", name));
+                assert!(
+                    file.exists(),
+                    "{}: missing test vector {}, generated by the Go tests of \
+                     mpt-witness-generator/witness",
+                    transition,
+                    name
+                );
+                assert_eq!(
+                    verify_mpt_test(idx, &file, false),
+                    Ok(()),
+                    "{}: {}",
+                    transition,
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_mpt_max_nodes() {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        let mut fixed = Vec::new();
+        for name in ["LeafAddedToEmptyTrie", "AddBranch"] {
+            let circuit = mpt_test_circuit(&path.join(format!("{}.json", name)), false, 64);
+            let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+            assert_eq!(prover.verify(), Ok(()), "{}", name);
+            fixed.push(prover.fixed().clone());
+        }
+        // Same fixed columns, so the same verifying key, for both witnesses
+        assert!(fixed[0] == fixed[1]);
+
+        // Padding has to follow the end node of the last proof
+        let mut circuit = mpt_test_circuit(&path.join("AddBranch.json"), false, 64);
+        circuit.nodes.pop();
+        let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err());
+
+        let circuit = mpt_test_circuit(&path.join("AddBranch.json"), false, 1);
+        assert!(MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).is_err());
+    }
+
+    #[test]
+    fn test_mpt_taller_than_mult_table() {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        let mult_table_len = MPTCircuitParams::default().mult_table_len();
+        for name in [
+            "LeafAddedToEmptyTrie",
+            "AddBranchTwoLevelsLong",
+            "DeleteBranchLong",
+        ] {
+            let circuit = mpt_test_circuit(&path.join(format!("{}.json", name)), false, 0);
+            let used_rows: usize = circuit.nodes.iter().map(Node::num_rows).sum();
+            assert!(used_rows.max(2 * HASH_WIDTH + 1) > mult_table_len);
+            // All rows, including the ones past the end of the mult table
+            let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+            assert_eq!(prover.verify_par(), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_mpt_single_pass() {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        for name in ["LeafAddedToEmptyTrie", "AddBranch", "DeleteBranch"] {
+            let file = path.join(format!("{}.json", name));
+            let circuit = mpt_test_circuit(&file, false, 0);
+            let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+            let fixed = prover.fixed().clone();
+
+            let circuit = MPTCircuit {
+                single_pass: true,
+                ..mpt_test_circuit(&file, false, 0)
+            };
+            let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+            assert_eq!(prover.verify_par(), Ok(()), "{}", name);
+            // Same layout as with the two passes
+            assert!(prover.fixed() == &fixed, "{}", name);
+
+            let circuit = mpt_test_circuit(&file, false, 0);
+            let circuit = MPTCircuit::<Fr, V1> {
+                nodes: circuit.nodes,
+                keccak_data: circuit.keccak_data,
+                degree: circuit.degree,
+                disable_preimage_check: circuit.disable_preimage_check,
+                ..Default::default()
+            };
+            let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+            assert_eq!(prover.verify_par(), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "region shape pass")]
+    fn test_mpt_single_pass_v1() {
+        let path = std::path::Path::new("src/mpt_circuit/tests/LeafAddedToEmptyTrie.json");
+        let circuit = mpt_test_circuit(path, false, 0);
+        let circuit = MPTCircuit::<Fr, V1> {
+            nodes: circuit.nodes,
+            keccak_data: circuit.keccak_data,
+            degree: circuit.degree,
+            disable_preimage_check: circuit.disable_preimage_check,
+            single_pass: true,
+            ..Default::default()
+        };
+        let _ = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]);
+    }
+
+    #[ignore = "Runs the real prover"]
+    #[test]
+    fn test_mpt_dual_verification() {
+        use halo2_proofs::dev::VerifyFailure;
+
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        for name in ["LeafAddedToEmptyTrie", "DeleteToEmptyTrie"] {
+            let circuit = mpt_test_circuit(&path.join(format!("{}.json", name)), false, 0);
+            let result = DualVerification::run(circuit.degree as u32, &circuit, vec![]);
+            result.assert_agree();
+            assert!(result.real.is_ok(), "{}: {:?}", name, result);
+        }
+        // Both reject a witness not fitting the padding
+        let circuit = mpt_test_circuit(&path.join("AddBranch.json"), false, 1);
+        let result = DualVerification::run(circuit.degree as u32, &circuit, vec![]);
+        result.assert_agree();
+        assert!(result.real.is_err());
+        // Both reject a witness that is fully assigned but violates a gate: the
+        // nonce modification leaves behind an empty account
+        let circuit = mpt_test_circuit(&path.join("AddAccount.json"), true, 0);
+        let result = DualVerification::run(circuit.degree as u32, &circuit, vec![]);
+        result.assert_agree();
+        assert!(result.real.is_err());
+        assert!(
+            result.mock_failures.iter().any(|failure| matches!(
+                failure,
+                VerifyFailure::ConstraintNotSatisfied { constraint, .. }
+                    if constraint.to_string().contains("is_zero_nonce")
+            )),
+            "{:?}",
+            result.mock_failures
+        );
+    }
+
+    #[test]
+    fn test_mpt_fingerprint() {
+        let params = MPTCircuitParams {
+            degree: 15,
+            disable_preimage_check: false,
+            strict_empty_accounts: false,
+            strict_codehash: false,
+            nonce_increment: false,
+            balance_delta: false,
+            key_derivation: KeyDerivation::Keccak,
+            max_nodes: 0,
+            single_pass: false,
+        };
+        let fingerprint = crate::util::circuit_fingerprint::<Fr, MPTCircuit<Fr>>(params);
+        assert_eq!(
+            fingerprint,
+            crate::util::circuit_fingerprint::<Fr, MPTCircuit<Fr>>(params)
+        );
+        assert_ne!(
+            fingerprint,
+            crate::util::circuit_fingerprint::<Fr, MPTCircuit<Fr>>(MPTCircuitParams {
+                disable_preimage_check: true,
+                ..params
+            })
+        );
+        assert_ne!(
+            fingerprint,
+            crate::util::circuit_fingerprint::<Fr, MPTCircuit<Fr>>(MPTCircuitParams {
+                key_derivation: KeyDerivation::Identity,
+                ..params
+            })
+        );
+        assert_ne!(
+            fingerprint,
+            crate::util::circuit_fingerprint::<Fr, MPTCircuit<Fr>>(MPTCircuitParams {
+                strict_codehash: true,
+                ..params
+            })
+        );
+        assert_ne!(
+            fingerprint,
+            crate::util::circuit_fingerprint::<Fr, MPTCircuit<Fr>>(MPTCircuitParams {
+                nonce_increment: true,
+                ..params
+            })
+        );
+        assert_ne!(
+            fingerprint,
+            crate::util::circuit_fingerprint::<Fr, MPTCircuit<Fr>>(MPTCircuitParams {
+                balance_delta: true,
+                ..params
+            })
+        );
+        // Only the synthesis changes
+        assert_eq!(
+            fingerprint,
+            crate::util::circuit_fingerprint::<Fr, MPTCircuit<Fr>>(MPTCircuitParams {
+                single_pass: true,
+                ..params
+            })
+        );
+    }
+
+    #[test]
+    fn test_mpt_no_backward_rotations() {
+        // Nodes only read their own rows and the memory, so that they can be
+        // assigned in any order
+        let mut meta = ConstraintSystem::<Fr>::default();
+        MPTCircuit::<Fr>::configure_with_params(
+            &mut meta,
+            MPTCircuitParams {
+                degree: 15,
+                ..Default::default()
+            },
+        );
+        for (column, rotation) in meta.advice_queries() {
+            assert!(rotation.0 >= 0, "{:?} queried at {:?}", column, rotation);
+        }
+    }
+
+    #[test]
+    fn test_mpt_column_names() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let (config, _) = MPTCircuit::<Fr>::configure_with_params(
+            &mut meta,
+            MPTCircuitParams {
+                degree: 15,
+                balance_delta: true,
+                ..Default::default()
+            },
+        );
+        // Every column of the circuit has a name of its own
+        let names: std::collections::HashSet<_> =
+            config.column_names.iter().map(|(_, name)| name).collect();
+        assert_eq!(names.len(), config.column_names.len());
+        assert_eq!(
+            config.column_names.len(),
+            meta.num_advice_columns() + meta.num_fixed_columns()
+        );
+        assert!(names.contains(&"rlp_byte_03".to_string()));
+        assert!(names.contains(&"memory_key_s_index".to_string()));
+        assert!(names.contains(&"mpt_table.proof_type".to_string()));
+    }
+
+    #[test]
+    fn test_mpt_constraint_system_snapshot() {
+        // Changes to the constraints show up in the diff of the snapshot, run
+        // with UPDATE_SNAPSHOT=1 to accept them
+        let path = "src/mpt_circuit/constraint_system.snap";
+        let mut meta = ConstraintSystem::<Fr>::default();
+        MPTCircuit::<Fr>::configure_with_params(
+            &mut meta,
+            MPTCircuitParams {
+                degree: 15,
+                ..Default::default()
+            },
+        );
+        let summary = crate::circuit_tools::printer::constraint_system_summary(&meta);
+        if var("UPDATE_SNAPSHOT").as_deref() == Ok("1") {
+            fs::write(path, &summary).unwrap();
+            println!("MPT constraint system snapshot written to {}", path);
+        }
+        let snapshot = fs::read_to_string(path).unwrap_or_else(|err| {
+            panic!(
+                "cannot read the snapshot {}: {}, run with UPDATE_SNAPSHOT=1 to write it",
+                path, err
+            )
+        });
+        assert!(
+            snapshot == summary,
+            "the MPT constraint system differs from {}, run with UPDATE_SNAPSHOT=1 and review \
+             the diff of the snapshot",
+            path
+        );
+    }
+
+    #[test]
+    fn test_parse_proof_key() {
+        let path = "src/mpt_circuit/tests";
+        // The preimage check is enabled, the hashed key is used
+        let json = fs::read_to_string(format!("{}/AccountAfterFirstLevel.json", path)).unwrap();
+        assert!(parse_proof(&json).is_ok());
+        assert!(parse_proof(&json.replace("\"hashed_key\"", "\"preimage\"")).is_err());
+        // The preimage check is disabled, the preimage is used
+        let json = fs::read_to_string(format!("{}/LeafInLastLevel.json", path)).unwrap();
+        assert!(parse_proof(&json).is_ok());
+        assert!(parse_proof(&json.replace("\"preimage\"", "\"hashed_key\"")).is_err());
+    }
+
+    #[test]
+    fn test_compact_values() {
+        let files = fs::read_dir("src/mpt_circuit/tests").unwrap();
+        for file in files.filter_map(Result::ok) {
+            let path = file.path();
+            if path.extension().map_or(true, |e| e != "json") {
+                continue;
+            }
+            let json = fs::read_to_string(&path).unwrap();
+            let nodes: Vec<Node> = serde_json::from_str(&json).unwrap();
+            let compact = serde_json::to_string(&nodes).unwrap();
+            let parsed: Vec<Node> = serde_json::from_str(&compact).unwrap();
+            for (node, parsed) in nodes.iter().zip(parsed.iter()) {
+                let values = node.values.iter().map(|v| v.deref()).collect::<Vec<_>>();
+                let parsed = parsed.values.iter().map(|v| v.deref()).collect::<Vec<_>>();
+                assert_eq!(values, parsed, "{:?}", path);
+            }
+        }
+
+        // Empty children are a single byte
+        let json = fs::read_to_string("src/mpt_circuit/tests/AddBranch.json").unwrap();
+        let nodes: Vec<Node> = serde_json::from_str(&json).unwrap();
+        let compact = serde_json::to_string(&nodes).unwrap();
+        assert!(compact.contains("\"80\""));
+        assert!(compact.len() < json.len());
+        // and the compact witness is parsed like the original one
+        assert!(parse_proof(&compact).is_ok());
+    }
+
+    #[test]
+    fn test_mpt_wrong_leaf() {
+        // Non-existing proofs where a different leaf is found at the end of the path
+        run_mpt_tests(&[
+            "NonExistingAccount",
+            "NonExistingAccountAfterFirstLevel",
+            "NonExistingAccountInFirstLevel",
+            "NonExistingStorage",
+            "NonExistingStorageLong",
+            "StorageInFirstLevelNonExisting",
+            "StorageInFirstLevelNonExistingLong",
+        ]);
+    }
+
+    #[test]
+    fn test_mpt_drifted_leaf_insert() {
+        // A new branch is inserted (S placeholder), the existing leaf drifts into it
+        run_mpt_tests(&[
+            "AccountAddPlaceholderBranch",
+            "AccountAddPlaceholderExtension",
+            "AddBranch",
+            "AddBranchLong",
+            "AddBranchTwoLevels",
+            "AddBranchTwoLevelsLong",
+            "ExtensionAddedOneKeyByteSel1",
+            "StorageLeafInFirstLevelAfterPlaceholder",
+        ]);
+    }
+
+    #[test]
+    fn test_mpt_drifted_leaf_delete() {
+        // A branch is removed (C placeholder), the remaining leaf drifts up into its parent
+        run_mpt_tests(&[
+            "AccountDeletePlaceholderBranch",
+            "AccountDeletePlaceholderExtension",
+            "DeleteBranch",
+            "DeleteBranchLong",
+            "DeleteBranchTwoLevels",
+            "DeleteBranchTwoLevelsLong",
+            "ExtensionDeletedOneKeyByteSel1",
+            "NeighbourNodeInHashedBranch",
+        ]);
+    }
+
+    #[test]
+    fn test_mpt_drifted_leaf_multiple_nibbles() {
+        // An extension is inserted (or removed) together with its branch, the leaf drifts by
+        // the extension nibbles plus the branch nibble
+        run_mpt_tests(&[
+            "ExtensionAddedInFirstStorageLevelTwoKeyBytes",
+            "ExtensionAddedThreeKeyBytesSel2",
+            "ExtensionAddedTwoKeyBytesSel1",
+            "ExtensionAddedTwoKeyBytesSel2",
+            "ExtensionDeletedThreeKeyBytesSel2",
+            "ExtensionDeletedTwoKeyBytesSel1",
+            "ExtensionDeletedTwoKeyBytesSel2",
+        ]);
+    }
+
+    #[test]
+    fn test_mpt_chained_extensions() {
+        // Extension -> branch -> extension chains, the key accumulation has to carry the odd/even
+        // nibble parity from one extension to the next
+        run_mpt_tests(&[
+            "ChainedExtensionsEvenOdd",
+            "ChainedExtensionsOddEven",
+            "ChainedExtensionsThreeLevels",
+        ]);
+    }
+
+    #[test]
+    fn test_mpt_strict_empty_accounts() {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        // Modifications leaving non-empty accounts
+        for (idx, name) in [
+            "BalanceModCLong",
+            "NonceModCShort",
+            "ImplicitlyCreateAccountWithBalance",
+            "ImplicitlyCreateAccountWithNonce",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let path = path.join(format!("{}.json", name));
+            assert_eq!(verify_mpt_test(idx, &path, true), Ok(()));
+        }
+        // Deleting an empty account is always allowed
+        assert_eq!(
+            verify_mpt_test(0, &path.join("DeleteAccount.json"), true),
+            Ok(())
+        );
+        // The nonce modification leaves behind an empty account
+        assert!(verify_mpt_test(0, &path.join("AddAccount.json"), true).is_err());
+    }
+
+    #[test]
+    fn test_mpt_strict_codehash() {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        let verify = |name: &str| {
+            let mut circuit = mpt_test_circuit(&path.join(format!("{}.json", name)), false, 0);
+            circuit.strict_codehash = true;
+            let num_rows: usize = circuit.nodes.iter().map(|node| node.values.len()).sum();
+            let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+            prover.verify_at_rows(0..num_rows, 0..num_rows)
+        };
+        // Updates keeping the code hash, code hash updates, accounts created without code and
+        // deleted accounts
+        for name in [
+            "BalanceModCLong",
+            "NonceModCShort",
+            "WrongAccount",
+            "AccountAddPlaceholderBranch",
+            "AccountAddPlaceholderExtension",
+            "ImplicitlyCreateAccountWithBalance",
+            "AddAccount",
+            "AccountDeletePlaceholderBranch",
+            "DeleteAccount",
+        ] {
+            assert_eq!(verify(name), Ok(()), "{}", name);
+        }
+        // The balance update creates the account with code, only caught by the option
+        let name = "AccountAddPlaceholderBranchWithCode";
+        assert_eq!(
+            verify_mpt_test(0, &path.join(format!("{}.json", name)), false),
+            Ok(())
+        );
+        assert!(verify(name).is_err());
+    }
+
+    #[test]
+    fn test_mpt_nonce_increment() {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        let verify = |name: &str| {
+            let mut circuit = mpt_test_circuit(&path.join(format!("{}.json", name)), false, 0);
+            circuit.nonce_increment = true;
+            let num_rows: usize = circuit.nodes.iter().map(|node| node.values.len()).sum();
+            let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+            prover.verify_at_rows(0..num_rows, 0..num_rows)
+        };
+        // Nonce bumped from 0 to 1, accounts created with any nonce and other updates
+        for name in [
+            "AccountInFirstLevel",
+            "AddAccount",
+            "ImplicitlyCreateAccountWithNonce",
+            "AccountBranchPlaceholder",
+            "BalanceModCLong",
+        ] {
+            assert_eq!(verify(name), Ok(()), "{}", name);
+        }
+        // Nonce rewritten from 7 to 33
+        assert!(verify("NonceModCShort").is_err());
+    }
+
+    #[test]
+    fn test_mpt_balance_delta() {
+        let path = std::path::Path::new("src/mpt_circuit/tests");
+        // Balance increases and decreases (wrapping around 2^256), and the other updates with a
+        // zero delta
+        for name in [
+            "BalanceModCLong",
+            "BalanceModCShort",
+            "AccountAddPlaceholderBranch",
+            "WrongAccount",
+            "NonceModCShort",
+            "DeleteAccount",
+            "UpdateOneLevel",
+        ] {
+            let mut circuit = mpt_test_circuit(&path.join(format!("{}.json", name)), false, 0);
+            circuit.balance_delta = true;
+            let num_rows: usize = circuit.nodes.iter().map(|node| node.values.len()).sum();
+            let prover = MockProver::<Fr>::run(circuit.degree as u32, &circuit, vec![]).unwrap();
+            assert_eq!(
+                prover.verify_at_rows(0..num_rows, 0..num_rows),
+                Ok(()),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[cfg(feature = "mpt-codehash-lookup")]
+    mod bytecode_lookup {
+        use super::*;
+        use crate::{table::BytecodeFieldTag, util::word::Word};
+        use eth_types::H256;
+        use halo2_proofs::{dev::VerifyFailure, halo2curves::ff::Field};
+
+        /// MPT circuit configured with a bytecode table holding the header rows of
+        /// `code`, the code hashes with their code sizes
+        struct BytecodeLookupCircuit {
+            mpt: MPTCircuit<Fr>,
+            code: Vec<(H256, usize)>,
+        }
+
+        impl Circuit<Fr> for BytecodeLookupCircuit {
+            type Config = (MPTConfig<Fr>, Challenges, BytecodeTable);
+            type FloorPlanner = SimpleFloorPlanner;
+            type Params = MPTCircuitParams;
+
+            fn without_witnesses(&self) -> Self {
+                Self {
+                    mpt: MPTCircuit::default(),
+                    code: vec![],
+                }
+            }
+
+            fn params(&self) -> Self::Params {
+                self.mpt.params()
+            }
+
+            fn configure_with_params(
+                meta: &mut ConstraintSystem<Fr>,
+                params: Self::Params,
+            ) -> Self::Config {
+                let challenges = Challenges::construct_with_key_input(meta);
+                let challenges_expr = challenges.exprs(meta);
+                let keccak_table = KeccakTable::construct(meta);
+                let bytecode_table = BytecodeTable::construct(meta);
+                (
+                    MPTConfig::new_with_bytecode_table(
+                        meta,
+                        challenges_expr,
+                        keccak_table,
+                        bytecode_table.clone(),
+                        params,
+                    ),
+                    challenges,
+                    bytecode_table,
+                )
+            }
+
+            fn configure(_meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+                unreachable!();
+            }
+
+            fn synthesize(
+                &self,
+                (config, challenges, bytecode_table): Self::Config,
+                mut layouter: impl Layouter<Fr>,
+            ) -> Result<(), Error> {
+                self.mpt
+                    .synthesize((config, challenges), layouter.namespace(|| "mpt"))?;
+                // The header rows as the bytecode circuit lays them out. The code hashes of the
+                // test vectors are set without code, so there are no byte rows.
+                layouter.assign_region(
+                    || "bytecode table",
+                    |mut region| {
+                        let columns =
+                            <BytecodeTable as LookupTable<Fr>>::advice_columns(&bytecode_table);
+                        let rows = std::iter::once([Fr::ZERO; 6]).chain(self.code.iter().map(
+                            |(hash, size)| {
+                                let hash = Word::<Fr>::from(*hash);
+                                [
+                                    hash.lo(),
+                                    hash.hi(),
+                                    Fr::from(BytecodeFieldTag::Header as u64),
+                                    Fr::ZERO,
+                                    Fr::ZERO,
+                                    Fr::from(*size as u64),
+                                ]
+                            },
+                        ));
+                        for (offset, row) in rows.enumerate() {
+                            for (column, value) in columns.iter().zip(row) {
+                                region.assign_advice(
+                                    || "bytecode table",
+                                    *column,
+                                    offset,
+                                    || Value::known(value),
+                                )?;
+                            }
+                        }
+                        Ok(())
+                    },
+                )
+            }
+        }
+
+        #[test]
+        fn test_mpt_bytecode_lookup() {
+            let path = std::path::Path::new(
+                "src/mpt_circuit/tests/ImplicitlyCreateAccountWithCodeHash.json",
+            );
+            // The account is created with this code hash, the S side is a placeholder
+            let code_hash = H256([1; 32]);
+            let code_size = 12;
+            let verify = |code: Vec<(H256, usize)>| {
+                let mut mpt = mpt_test_circuit(path, false, 0);
+                for node in mpt.nodes.iter_mut() {
+                    if let Some(account) = node.account.as_mut() {
+                        account.code_size = [0, code_size];
                     }
                 }
+                let num_rows: usize = mpt.nodes.iter().map(|node| node.values.len()).sum();
+                let circuit = BytecodeLookupCircuit { mpt, code };
+                let prover =
+                    MockProver::<Fr>::run(circuit.mpt.degree as u32, &circuit, vec![]).unwrap();
+                prover.verify_at_rows(0..num_rows, 0..num_rows)
+            };
+
+            // The code is in the table
+            assert_eq!(verify(vec![(code_hash, code_size)]), Ok(()));
+            // The code is not in the table
+            let is_bytecode_lookup = |failures: Vec<VerifyFailure>| {
+                failures.iter().any(|failure| {
+                    matches!(failure, VerifyFailure::Lookup { name, .. } if name.contains("Bytecode"))
+                })
+            };
+            assert!(is_bytecode_lookup(verify(vec![]).unwrap_err()));
+            assert!(is_bytecode_lookup(
+                verify(vec![(H256([2; 32]), code_size)]).unwrap_err()
+            ));
+            // The code in the table has another size
+            assert!(is_bytecode_lookup(
+                verify(vec![(code_hash, code_size + 1)]).unwrap_err()
+            ));
+        }
+    }
 
-                let disable_preimage_check = nodes[0].start.clone().unwrap().disable_preimage_check;
-                let degree = 15;
-                let circuit = MPTCircuit::<Fr> {
-                    nodes,
-                    keccak_data,
-                    degree,
-                    disable_preimage_check,
-                    _marker: PhantomData,
-                };
+    #[test]
+    fn test_key_and_data_rlc_domains() {
+        // Only the circuits RLCing keys get the key challenge, the verifying
+        // keys of the other circuits do not change
+        let mut meta = ConstraintSystem::<Fr>::default();
+        Challenges::construct(&mut meta);
+        assert_eq!(meta.num_challenges(), 2);
+
+        // Key RLCs and data RLCs use independent challenges, see
+        // `MPTConstraintBuilder`. The circuit rejecting a key RLC computed in
+        // the data domain is tested in `gadget_test`.
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let challenges = Challenges::construct_with_key_input(&mut meta).exprs(&mut meta);
+        assert_eq!(meta.num_challenges(), 3);
+        let cb = MPTConstraintBuilder::new(5, Some(challenges), None);
+        let challenge_index = |expr: &Expression<Fr>| match expr {
+            Expression::Challenge(challenge) => challenge.index(),
+            _ => unreachable!("expected a challenge"),
+        };
+        assert_ne!(challenge_index(&cb.key_r), challenge_index(&cb.keccak_r));
+    }
+
+    #[test]
+    fn test_unconstrained_region_cells() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let challenges = Challenges::construct(&mut meta).exprs(&mut meta);
+        let mut cb = MPTConstraintBuilder::new(5, Some(challenges), None);
+        let mut cm = CellManager::new(1, 0);
+        cm.add_columns(
+            &mut meta,
+            &mut cb.base,
+            MptCellType::StoragePhase1,
+            0,
+            false,
+            3,
+        );
 
-                println!("{} {:?}", idx, path);
-                let prover = MockProver::<Fr>::run(degree as u32, &circuit, vec![]).unwrap();
-                assert_eq!(prover.verify_at_rows(0..num_rows, 0..num_rows,), Ok(()));
-                // assert_eq!(prover.verify_par(), Ok(()));
-                // prover.assert_satisfied();
+        let mut unconstrained = Vec::new();
+        let mut free = None;
+        meta.create_gate("test", |meta| {
+            circuit!([meta, cb], {
+                cb.base.set_cell_manager(cm.clone());
+                cb.base.push_region(MPTRegion::Start as usize, 1);
+                let a = cb.query_cell();
+                let _b = cb.query_bool();
+                free = Some(cb.query_cell());
+                require!(a => 1);
+                unconstrained = cb.base.unconstrained_region_cells();
+                cb.base.pop_region();
             });
+            cb.base.build_constraints()
+        });
+
+        let free = free.unwrap();
+        assert_eq!(unconstrained.len(), 1);
+        assert_eq!(unconstrained[0].column, free.column);
+        assert_eq!(unconstrained[0].rotation, free.rotation);
     }
 }
@@ -1,4 +1,5 @@
-use eth_types::Field;
+use bus_mapping::state_db::CodeDB;
+use eth_types::{Address, Field, ToScalar};
 use gadgets::util::{pow, Scalar};
 use halo2_proofs::{
     circuit::Value,
@@ -16,19 +17,18 @@ use crate::{
         cached_region::CachedRegion,
         cell_manager::Cell,
         constraint_builder::{RLCChainableRev, RLCable},
-        gadgets::IsEqualGadget,
+        gadgets::IsEqualWordGadget,
     },
-    evm_circuit::util::from_bytes,
     mpt_circuit::{
         helpers::{
-            key_memory, main_memory, num_nibbles, parent_memory, DriftedGadget, Indexable,
-            IsPlaceholderLeafGadget, KeyData, MPTConstraintBuilder, ParentData, WrongGadget,
-            KECCAK,
+            key_memory, main_memory, num_nibbles, parent_memory, AccountValueGadget, DriftedGadget,
+            Indexable, IsPlaceholderLeafGadget, KeyData, MPTConstraintBuilder, MptTableType,
+            ParentData, ProofTypeGadget, WordDeltaGadget, WrongGadget, KECCAK,
         },
-        param::{KEY_LEN_IN_NIBBLES, RLP_LIST_LONG, RLP_LONG},
-        MPTConfig, MPTContext, MptMemory, RlpItemType,
+        param::{BALANCE_MAX_LEN, KEY_LEN_IN_NIBBLES, NONCE_MAX_LEN, RLP_LIST_LONG, RLP_LONG},
+        KeyDerivation, MPTConfig, MPTContext, MptMemory, RlpItemType,
     },
-    table::MPTProofType,
+    table::{BytecodeFieldTag, MPTProofType},
     util::word::{self, Word},
     witness::MptUpdateRow,
 };
@@ -41,15 +41,17 @@ pub(crate) struct AccountLeafConfig<F> {
     rlp_key: [ListKeyGadget<F>; 2],
     value_rlp_bytes: [[Cell<F>; 2]; 2],
     value_list_rlp_bytes: [[Cell<F>; 2]; 2],
+    nonce_value: [AccountValueGadget<F>; 2],
+    balance_value: [AccountValueGadget<F>; 2],
     is_placeholder_leaf: [IsPlaceholderLeafGadget<F>; 2],
     drifted: DriftedGadget<F>,
     wrong: WrongGadget<F>,
-    is_non_existing_account_proof: IsEqualGadget<F>,
-    is_account_delete_mod: IsEqualGadget<F>,
-    is_nonce_mod: IsEqualGadget<F>,
-    is_balance_mod: IsEqualGadget<F>,
-    is_storage_mod: IsEqualGadget<F>,
-    is_codehash_mod: IsEqualGadget<F>,
+    proof_type: ProofTypeGadget<F>,
+    is_zero_nonce: IsEqualWordGadget<F>,
+    is_zero_balance: IsEqualWordGadget<F>,
+    is_empty_codehash: [IsEqualWordGadget<F>; 2],
+    code_size: [Cell<F>; 2],
+    balance_delta: WordDeltaGadget<F>,
 }
 
 impl<F: Field> AccountLeafConfig<F> {
@@ -165,6 +167,12 @@ impl<F: Field> AccountLeafConfig<F> {
                 let rlp_key = &mut config.rlp_key[is_s.idx()];
                 *rlp_key = ListKeyGadget::construct(cb, &key_items[is_s.idx()]);
 
+                // Range checks on the nonce and the balance
+                config.nonce_value[is_s.idx()] =
+                    AccountValueGadget::construct(cb, &nonce_items[is_s.idx()], NONCE_MAX_LEN);
+                config.balance_value[is_s.idx()] =
+                    AccountValueGadget::construct(cb, &balance_items[is_s.idx()], BALANCE_MAX_LEN);
+
                 let nonce_rlp_rlc;
                 let balance_rlp_rlc;
                 let storage_rlp_rlc;
@@ -190,7 +198,7 @@ impl<F: Field> AccountLeafConfig<F> {
                 let keccak_r = &cb.keccak_r;
                 let value_rlp_bytes = config.value_rlp_bytes[is_s.idx()].to_expr_vec();
                 let value_list_rlp_bytes = config.value_list_rlp_bytes[is_s.idx()].to_expr_vec();
-                leaf_no_key_rlc[is_s.idx()] = value_rlp_bytes
+                let no_key_rlc = value_rlp_bytes
                     .rlc_rev(keccak_r)
                     .rlc_chain_rev((
                         value_list_rlp_bytes.rlc_rev(keccak_r),
@@ -200,18 +208,22 @@ impl<F: Field> AccountLeafConfig<F> {
                     .rlc_chain_rev(balance_rlp_rlc.clone())
                     .rlc_chain_rev(storage_rlp_rlc.clone())
                     .rlc_chain_rev(codehash_rlp_rlc.clone());
-                leaf_no_key_rlc_mult[is_s.idx()] = pow::expr(keccak_r.expr(), 4)
+                let no_key_rlc_mult = pow::expr(keccak_r.expr(), 4)
                     * nonce_rlp_rlc.1
                     * balance_rlp_rlc.1
                     * storage_rlp_rlc.1
                     * codehash_rlp_rlc.1;
+                // Also used for the drifted leaf, so only expand them once
+                leaf_no_key_rlc[is_s.idx()] = cb.cached_expr("leaf_no_key_rlc", no_key_rlc);
+                leaf_no_key_rlc_mult[is_s.idx()] =
+                    cb.cached_expr("leaf_no_key_rlc_mult", no_key_rlc_mult);
                 let leaf_rlc = rlp_key.rlc2(&cb.keccak_r).rlc_chain_rev((
                     leaf_no_key_rlc[is_s.idx()].expr(),
                     leaf_no_key_rlc_mult[is_s.idx()].expr(),
                 ));
 
                 // Key
-                key_rlc[is_s.idx()] = key_data.rlc.expr()
+                let leaf_key_rlc = key_data.rlc.expr()
                     + rlp_key.key.expr(
                         cb,
                         rlp_key.key_value.clone(),
@@ -219,6 +231,7 @@ impl<F: Field> AccountLeafConfig<F> {
                         key_data.is_odd.expr(),
                         &cb.key_r.expr(),
                     );
+                key_rlc[is_s.idx()] = cb.cached_expr("key_rlc", leaf_key_rlc);
                 // Total number of nibbles needs to be KEY_LEN_IN_NIBBLES.
                 let num_nibbles =
                     num_nibbles::expr(rlp_key.key_value.len(), key_data.is_odd.expr());
@@ -262,36 +275,116 @@ impl<F: Field> AccountLeafConfig<F> {
                 );
             }
             // Proof types
-            config.is_non_existing_account_proof = IsEqualGadget::construct(
-                &mut cb.base,
-                config.main_data.proof_type.expr(),
-                MPTProofType::AccountDoesNotExist.expr(),
-            );
-            config.is_account_delete_mod = IsEqualGadget::construct(
-                &mut cb.base,
-                config.main_data.proof_type.expr(),
-                MPTProofType::AccountDestructed.expr(),
-            );
-            config.is_nonce_mod = IsEqualGadget::construct(
-                &mut cb.base,
-                config.main_data.proof_type.expr(),
-                MPTProofType::NonceChanged.expr(),
-            );
-            config.is_balance_mod = IsEqualGadget::construct(
-                &mut cb.base,
-                config.main_data.proof_type.expr(),
-                MPTProofType::BalanceChanged.expr(),
-            );
-            config.is_storage_mod = IsEqualGadget::construct(
-                &mut cb.base,
-                config.main_data.proof_type.expr(),
-                MPTProofType::StorageChanged.expr(),
-            );
-            config.is_codehash_mod = IsEqualGadget::construct(
-                &mut cb.base,
-                config.main_data.proof_type.expr(),
-                MPTProofType::CodeHashChanged.expr(),
-            );
+            config.proof_type = ProofTypeGadget::construct(cb, config.main_data.proof_type.expr());
+            let is_non_existing_account_proof =
+                config.proof_type.is(MPTProofType::AccountDoesNotExist);
+            let is_account_delete_mod = config.proof_type.is(MPTProofType::AccountDestructed);
+            let is_nonce_mod = config.proof_type.is(MPTProofType::NonceChanged);
+            let is_balance_mod = config.proof_type.is(MPTProofType::BalanceChanged);
+            let is_storage_mod = config.proof_type.is(MPTProofType::StorageChanged);
+            let is_codehash_mod = config.proof_type.is(MPTProofType::CodeHashChanged);
+
+            // The C side is used by the strict checks, both sides by the bytecode lookup
+            let empty_codehash = Word::<F>::from(CodeDB::empty_code_hash());
+            for is_s in [true, false] {
+                if ctx.bytecode_lookup
+                    || (!is_s && (ctx.params.strict_empty_accounts || ctx.params.strict_codehash))
+                {
+                    config.is_empty_codehash[is_s.idx()] = IsEqualWordGadget::construct(
+                        &mut cb.base,
+                        &codehash[is_s.idx()],
+                        &Word::new([
+                            Expression::Constant(empty_codehash.lo()),
+                            Expression::Constant(empty_codehash.hi()),
+                        ]),
+                    );
+                }
+            }
+
+            // The code of the accounts is in the bytecode table, except for the empty code
+            // which the bytecode circuit does not need to hold. Placeholder leaves have no
+            // code hash.
+            if ctx.bytecode_lookup {
+                for is_s in [true, false] {
+                    config.code_size[is_s.idx()] = cb.query_cell();
+                    ifx! {not!(or::expr([
+                        config.is_placeholder_leaf[is_s.idx()].expr(),
+                        config.is_empty_codehash[is_s.idx()].expr(),
+                    ])) => {
+                        let codehash = codehash[is_s.idx()].clone();
+                        // Header row of the code: code hash, tag, zero index and is_code, and
+                        // the code size as value
+                        require!((
+                            codehash.lo(),
+                            codehash.hi(),
+                            BytecodeFieldTag::Header.expr(),
+                            0.expr(),
+                            0.expr(),
+                            config.code_size[is_s.idx()].expr()
+                        ) => @cb.table(MptTableType::Bytecode));
+                    }}
+                }
+            }
+
+            // EIP-161: empty accounts are deleted when touched, so a modification can't
+            // leave behind an account without code, with a zero nonce and a zero balance.
+            if ctx.params.strict_empty_accounts {
+                config.is_zero_nonce =
+                    IsEqualWordGadget::construct(&mut cb.base, &nonce[false.idx()], &Word::zero());
+                config.is_zero_balance = IsEqualWordGadget::construct(
+                    &mut cb.base,
+                    &balance[false.idx()],
+                    &Word::zero(),
+                );
+                let is_modification = is_nonce_mod.expr()
+                    + is_balance_mod.expr()
+                    + is_storage_mod.expr()
+                    + is_codehash_mod.expr();
+                ifx! {is_modification => {
+                    require!(and::expr([
+                        config.is_zero_nonce.expr(),
+                        config.is_zero_balance.expr(),
+                        config.is_empty_codehash[false.idx()].expr(),
+                    ]) => false);
+                }}
+            }
+
+            // The code hash only changes in a code hash update. The checks below are
+            // skipped when a placeholder separates the S and C leaves, so also make sure
+            // that an account created by another update starts without code.
+            if ctx.params.strict_codehash {
+                let keeps_codehash = not!(or::expr([
+                    is_codehash_mod.expr(),
+                    is_account_delete_mod.expr(),
+                    is_non_existing_account_proof.expr(),
+                ]));
+                ifx! {keeps_codehash => {
+                    ifx! {or::expr([
+                        config.parent_data[true.idx()].is_placeholder.expr(),
+                        config.is_placeholder_leaf[true.idx()].expr(),
+                    ]) => {
+                        require!(config.is_empty_codehash[false.idx()].expr() => true);
+                    } elsex {
+                        require!(codehash[false.idx()] => codehash[true.idx()]);
+                    }}
+                }}
+            }
+
+            // A nonce update increments the nonce of an existing account, like a transaction
+            // does for its sender. An account created by the update can start with any nonce:
+            // contracts start at one (EIP-161) and an account can be created and used in the
+            // same block.
+            if ctx.params.nonce_increment {
+                ifx! {is_nonce_mod => {
+                    ifx! {not!(or::expr([
+                        config.parent_data[true.idx()].is_placeholder.expr(),
+                        config.is_placeholder_leaf[true.idx()].expr(),
+                    ])) => {
+                        require!(nonce[false.idx()].lo() => nonce[true.idx()].lo() + 1.expr());
+                        require!(nonce[false.idx()].hi() => nonce[true.idx()].hi());
+                    }}
+                }}
+            }
 
             // Drifted leaf handling
             config.drifted = DriftedGadget::construct(
@@ -310,7 +403,7 @@ impl<F: Field> AccountLeafConfig<F> {
             config.wrong = WrongGadget::construct(
                 cb,
                 key_item.hash_rlc(),
-                config.is_non_existing_account_proof.expr(),
+                is_non_existing_account_proof.expr(),
                 &config.rlp_key[true.idx()].key_value,
                 &key_rlc[true.idx()],
                 &wrong_bytes,
@@ -334,10 +427,11 @@ impl<F: Field> AccountLeafConfig<F> {
                     config.main_data.new_root.hi().expr(),
                     config.main_data.old_root.lo().expr(),
                     config.main_data.old_root.hi().expr(),
+                    false.expr(),
                 ],
             );
 
-            ifx! {config.is_account_delete_mod => {
+            ifx! {is_account_delete_mod => {
                 // Account delete
                 // We need to make sure there is no leaf when account is deleted. Two possible
                 // cases:
@@ -354,25 +448,30 @@ impl<F: Field> AccountLeafConfig<F> {
                 ifx! {and::expr(&[not!(config.parent_data[true.idx()].is_placeholder), not!(config.parent_data[false.idx()].is_placeholder)]) => {
                     // Check that there is only one modification, except when the account is being deleted or
                     // the parent branch is a placeholder (meaning the account leafs in S are C are different).
+                    // The unmodified values are shared through these equalities and not through copy
+                    // constraints between the S and C cells: the permutation is part of the verifying key,
+                    // so it can't depend on which field a proof modifies, and the S and C values are
+                    // decoded on their own rows by the RLP unit shared by all nodes, so the C cells
+                    // can't be left out of the layout either.
                     // Nonce needs to remain the same when not modifying the nonce
-                    ifx!{not!(config.is_nonce_mod) => {
+                    ifx!{not!(is_nonce_mod) => {
                         require!(nonce[false.idx()] => nonce[true.idx()]);
                     }}
                     // Balance needs to remain the same when not modifying the balance
-                    ifx!{not!(config.is_balance_mod) => {
+                    ifx!{not!(is_balance_mod) => {
                         require!(balance[false.idx()] => balance[true.idx()]);
                     }}
                     // Storage root needs to remain the same when not modifying the storage root
-                    ifx!{not!(config.is_storage_mod) => {
+                    ifx!{not!(is_storage_mod) => {
                         require!(storage[false.idx()] => storage[true.idx()]);
                     }}
                     // Codehash root needs to remain the same when not modifying the codehash
-                    ifx!{not!(config.is_codehash_mod) => {
+                    ifx!{not!(is_codehash_mod) => {
                         require!(codehash[false.idx()] => codehash[true.idx()]);
                     }}
                 }}
             }}
-            ifx! {config.is_non_existing_account_proof => {
+            ifx! {is_non_existing_account_proof => {
                 // For non-existing proofs the tree needs to remain the same
                 require!(config.main_data.new_root => config.main_data.old_root);
                 require!(key_rlc[true.idx()] => key_rlc[false.idx()]);
@@ -380,15 +479,15 @@ impl<F: Field> AccountLeafConfig<F> {
 
             // Put the data in the lookup table
             let (proof_type, old_value_lo, old_value_hi, new_value_lo, new_value_hi) = _matchx! {cb, (
-                config.is_nonce_mod => (MPTProofType::NonceChanged.expr(), nonce[true.idx()].lo(), nonce[true.idx()].hi(), nonce[false.idx()].lo(), nonce[false.idx()].hi()),
-                config.is_balance_mod => (MPTProofType::BalanceChanged.expr(), balance[true.idx()].lo(), balance[true.idx()].hi(), balance[false.idx()].lo(), balance[false.idx()].hi()),
-                config.is_storage_mod => (MPTProofType::StorageChanged.expr(), storage[true.idx()].lo(), storage[true.idx()].hi(), storage[false.idx()].lo(), storage[false.idx()].hi()),
-                config.is_codehash_mod => (MPTProofType::CodeHashChanged.expr(), codehash[true.idx()].lo(), codehash[true.idx()].hi(), codehash[false.idx()].lo(), codehash[false.idx()].hi()),
-                config.is_account_delete_mod => (MPTProofType::AccountDestructed.expr(), 0.expr(), 0.expr(), 0.expr(), 0.expr()),
-                config.is_non_existing_account_proof => (MPTProofType::AccountDoesNotExist.expr(), 0.expr(), 0.expr(), 0.expr(), 0.expr()),
+                is_nonce_mod => (MPTProofType::NonceChanged.expr(), nonce[true.idx()].lo(), nonce[true.idx()].hi(), nonce[false.idx()].lo(), nonce[false.idx()].hi()),
+                is_balance_mod => (MPTProofType::BalanceChanged.expr(), balance[true.idx()].lo(), balance[true.idx()].hi(), balance[false.idx()].lo(), balance[false.idx()].hi()),
+                is_storage_mod => (MPTProofType::StorageChanged.expr(), storage[true.idx()].lo(), storage[true.idx()].hi(), storage[false.idx()].lo(), storage[false.idx()].hi()),
+                is_codehash_mod => (MPTProofType::CodeHashChanged.expr(), codehash[true.idx()].lo(), codehash[true.idx()].hi(), codehash[false.idx()].lo(), codehash[false.idx()].hi()),
+                is_account_delete_mod => (MPTProofType::AccountDestructed.expr(), 0.expr(), 0.expr(), 0.expr(), 0.expr()),
+                is_non_existing_account_proof => (MPTProofType::AccountDoesNotExist.expr(), 0.expr(), 0.expr(), 0.expr(), 0.expr()),
                 _ => (MPTProofType::Disabled.expr(), 0.expr(), 0.expr(), 0.expr(), 0.expr()),
             )};
-            ifx! {not!(config.is_non_existing_account_proof) => {
+            ifx! {not!(is_non_existing_account_proof) => {
                 let key_rlc = ifx!{not!(config.parent_data[true.idx()].is_placeholder) => {
                     key_rlc[true.idx()].expr()
                 } elsex {
@@ -399,7 +498,15 @@ impl<F: Field> AccountLeafConfig<F> {
                 // Check if the key is correct for the given address
                 if ctx.params.is_preimage_check_enabled() {
                     let key = key_item.word();
-                    require!((1.expr(), address_item.bytes_le()[1..21].rlc(&cb.keccak_r), 20.expr(), key.lo(), key.hi()) =>> @KECCAK);
+                    match ctx.params.key_derivation {
+                        KeyDerivation::Keccak => {
+                            require!((1.expr(), address_item.bytes_le()[1..21].rlc(&cb.keccak_r), 20.expr(), key.lo(), key.hi()) =>> @KECCAK);
+                        }
+                        KeyDerivation::Identity => {
+                            require!(key.lo() => address_item.word().lo());
+                            require!(key.hi() => address_item.word().hi());
+                        }
+                    }
                 }
             }};
             let to_hi = Expression::<F>::Constant(pow::value::<F>(256.scalar(), 16));
@@ -418,6 +525,7 @@ impl<F: Field> AccountLeafConfig<F> {
                     config.main_data.old_root.expr(),
                     Word::<Expression<F>>::new([new_value_lo, new_value_hi]),
                     Word::<Expression<F>>::new([old_value_lo.clone(), old_value_hi.clone()]),
+                    0.expr(),
                 );
             } elsex {
                 ctx.mpt_table.constrain(
@@ -430,8 +538,24 @@ impl<F: Field> AccountLeafConfig<F> {
                     config.main_data.old_root.expr(),
                     Word::<Expression<F>>::new([0.expr(), 0.expr()]),
                     Word::<Expression<F>>::new([old_value_lo, old_value_hi]),
+                    0.expr(),
                 );
             }};
+
+            // The balance change, computed from the values in the lookup table
+            if let Some(balance_delta) = ctx.mpt_table.balance_delta {
+                let delta = Word::new([a!(balance_delta.lo()), a!(balance_delta.hi())]);
+                ifx! {is_balance_mod => {
+                    config.balance_delta = WordDeltaGadget::construct(
+                        cb,
+                        delta.clone(),
+                        Word::new([a!(ctx.mpt_table.new_value.lo()), a!(ctx.mpt_table.new_value.hi())]),
+                        Word::new([a!(ctx.mpt_table.old_value.lo()), a!(ctx.mpt_table.old_value.hi())]),
+                    );
+                } elsex {
+                    require!(delta => [0.expr(), 0.expr()]);
+                }}
+            }
         });
 
         config
@@ -528,6 +652,9 @@ impl<F: Field> AccountLeafConfig<F> {
                 &key_items[is_s.idx()],
             )?;
 
+            self.nonce_value[is_s.idx()].assign(region, offset, &nonce_items[is_s.idx()])?;
+            self.balance_value[is_s.idx()].assign(region, offset, &balance_items[is_s.idx()])?;
+
             nonce[is_s.idx()] = nonce_items[is_s.idx()].word();
             balance[is_s.idx()] = balance_items[is_s.idx()].word();
             storage[is_s.idx()] = storage_items[is_s.idx()].word();
@@ -566,42 +693,54 @@ impl<F: Field> AccountLeafConfig<F> {
         }
 
         // Proof types
-        let is_non_existing_proof = self.is_non_existing_account_proof.assign(
-            region,
-            offset,
-            main_data.proof_type.scalar(),
-            MPTProofType::AccountDoesNotExist.scalar(),
-        )? == true.scalar();
-        let is_account_delete_mod = self.is_account_delete_mod.assign(
-            region,
-            offset,
-            main_data.proof_type.scalar(),
-            MPTProofType::AccountDestructed.scalar(),
-        )? == true.scalar();
-        let is_nonce_mod = self.is_nonce_mod.assign(
-            region,
-            offset,
-            main_data.proof_type.scalar(),
-            MPTProofType::NonceChanged.scalar(),
-        )? == true.scalar();
-        let is_balance_mod = self.is_balance_mod.assign(
-            region,
-            offset,
-            main_data.proof_type.scalar(),
-            MPTProofType::BalanceChanged.scalar(),
-        )? == true.scalar();
-        let is_storage_mod = self.is_storage_mod.assign(
-            region,
-            offset,
-            main_data.proof_type.scalar(),
-            MPTProofType::StorageChanged.scalar(),
-        )? == true.scalar();
-        let is_codehash_mod = self.is_codehash_mod.assign(
-            region,
-            offset,
-            main_data.proof_type.scalar(),
-            MPTProofType::CodeHashChanged.scalar(),
-        )? == true.scalar();
+        let proof_type = self
+            .proof_type
+            .assign(region, offset, main_data.proof_type)?;
+        let is_non_existing_proof = proof_type == MPTProofType::AccountDoesNotExist;
+        let is_account_delete_mod = proof_type == MPTProofType::AccountDestructed;
+        let is_nonce_mod = proof_type == MPTProofType::NonceChanged;
+        let is_balance_mod = proof_type == MPTProofType::BalanceChanged;
+        let is_storage_mod = proof_type == MPTProofType::StorageChanged;
+        let is_codehash_mod = proof_type == MPTProofType::CodeHashChanged;
+        let bytecode_lookup = mpt_config.bytecode_table.is_some();
+        for is_s in [true, false] {
+            if bytecode_lookup
+                || (!is_s
+                    && (mpt_config.params.strict_empty_accounts
+                        || mpt_config.params.strict_codehash))
+            {
+                self.is_empty_codehash[is_s.idx()].assign(
+                    region,
+                    offset,
+                    codehash[is_s.idx()],
+                    Word::from(CodeDB::empty_code_hash()),
+                )?;
+            }
+        }
+        if bytecode_lookup {
+            for is_s in [true, false] {
+                self.code_size[is_s.idx()].assign(
+                    region,
+                    offset,
+                    F::from(account.code_size[is_s.idx()] as u64),
+                )?;
+            }
+        }
+        if mpt_config.params.strict_empty_accounts {
+            self.is_zero_nonce.assign(
+                region,
+                offset,
+                nonce[false.idx()],
+                Word::new([0.scalar(), 0.scalar()]),
+            )?;
+            self.is_zero_balance.assign(
+                region,
+                offset,
+                balance[false.idx()],
+                Word::new([0.scalar(), 0.scalar()]),
+            )?;
+        }
+
         // Drifted leaf handling
         self.drifted.assign(
             region,
@@ -638,6 +777,7 @@ impl<F: Field> AccountLeafConfig<F> {
             address,
             main_data.new_root,
             main_data.old_root,
+            false,
         )?;
 
         // Put the data in the lookup table
@@ -674,17 +814,27 @@ impl<F: Field> AccountLeafConfig<F> {
             region,
             offset,
             &MptUpdateRow {
-                address: Value::known(from_bytes::value(
-                    &account.address.iter().cloned().rev().collect::<Vec<_>>(),
-                )),
+                address: Value::known(Address::from_slice(&account.address).to_scalar().unwrap()),
                 storage_key: word::Word::<F>::new([0.scalar(), 0.scalar()]).into_value(),
                 proof_type: Value::known(proof_type.scalar()),
                 new_root: main_data.new_root.into_value(),
                 old_root: main_data.old_root.into_value(),
                 new_value: new_value.into_value(),
                 old_value: value[true.idx()].into_value(),
+                trie_id: Value::known(F::ZERO),
             },
         )?;
+        if mpt_config.mpt_table.balance_delta.is_some() {
+            let delta = if is_balance_mod {
+                self.balance_delta
+                    .assign(region, offset, new_value, value[true.idx()])?
+            } else {
+                Word::new([0.scalar(), 0.scalar()])
+            };
+            mpt_config
+                .mpt_table
+                .assign_balance_delta(region, offset, delta.into_value())?;
+        }
 
         Ok(())
     }
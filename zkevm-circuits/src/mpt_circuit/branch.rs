@@ -1,4 +1,4 @@
-use eth_types::Field;
+use eth_types::{Field, KeyPath};
 use gadgets::util::Scalar;
 use halo2_proofs::plonk::{Error, Expression, VirtualCells};
 
@@ -329,21 +329,13 @@ impl<F: Field> BranchGadget<F> {
             )?;
         }
 
+        // Update the key RLC and multiplier for the branch nibble.
+        let (nibble_mult, mult) = KeyPath::nibble_mults(*num_nibbles, region.key_r);
+
         // one nibble is used for position in branch
         *num_nibbles += 1;
         // Update key parity
         *is_key_odd = !*is_key_odd;
-
-        // Update the key RLC and multiplier for the branch nibble.
-        let (nibble_mult, mult): (F, F) = if *is_key_odd {
-            // The nibble will be added as the most significant nibble using the same
-            // multiplier
-            (16.scalar(), 1.scalar())
-        } else {
-            // The nibble will be added as the least significant nibble, the multiplier
-            // needs to advance
-            (1.scalar(), region.key_r)
-        };
         let key_rlc_post_branch =
             *key_rlc + F::from(branch.modified_index as u64) * nibble_mult * *key_mult;
         let key_rlc_post_drifted =
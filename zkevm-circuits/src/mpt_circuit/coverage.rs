@@ -0,0 +1,206 @@
+//! Coverage of the node variants of the MPT circuit by the test witnesses.
+//!
+//! Every node of a witness is classified by the circuit branches it takes:
+//! placeholder branches and leaves, odd and even extension keys, the encodings
+//! of a leaf key, wrong leaves and nil objects of non-existing proofs... The
+//! corpus test prints how often every variant is hit and fails when a variant
+//! is not exercised by any witness anymore, so that the effect of adding or
+//! changing a witness on the tested circuit branches is visible.
+
+use super::{
+    load_proof,
+    param::{EMPTY_TRIE_HASH, KEY_TERMINAL_PREFIX_EVEN, RLP_LIST_LONG, RLP_NIL},
+    witness_check::rlp_item,
+    witness_row::{AccountRowType, ExtensionBranchRowType, Node, StartRowType, StorageRowType},
+};
+use crate::table::MPTProofType;
+use std::{collections::BTreeMap, fs};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// Node variants handled by different branches of the circuit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, EnumIter)]
+enum NodeVariant {
+    NonceChanged,
+    BalanceChanged,
+    CodeHashChanged,
+    AccountDestructed,
+    AccountDoesNotExist,
+    StorageChanged,
+    StorageDoesNotExist,
+    Branch,
+    BranchPlaceholderS,
+    BranchPlaceholderC,
+    ExtensionOddKey,
+    ExtensionEvenKey,
+    AccountLeaf,
+    StorageLeaf,
+    LeafPlaceholderS,
+    LeafPlaceholderC,
+    /// Key in a single byte without nibbles
+    LeafKeyNoNibbles,
+    /// Key in a single byte with one nibble
+    LeafKeyOneNibble,
+    LeafKeyOdd,
+    LeafKeyEven,
+    /// Storage leaf with an RLP list shorter than 56 bytes
+    LeafShortList,
+    LeafLongList,
+    /// Non-existing proof with another leaf at the path of the key
+    WrongLeaf,
+    /// Non-existing proof with a nil object at the path of the key
+    NilObject,
+}
+
+/// Counts the node variants in the witness of the MPT circuit, as returned by
+/// `parse_proof`. The witness is expected to pass `check_witness`.
+fn witness_coverage(nodes: &[Node], coverage: &mut BTreeMap<NodeVariant, usize>) {
+    let mut hit = |variant: NodeVariant| *coverage.entry(variant).or_default() += 1;
+    let is_nil = |value: &[u8]| {
+        let reference = rlp_item(value).unwrap();
+        reference == [RLP_NIL] || reference[1..] == EMPTY_TRIE_HASH
+    };
+
+    let mut proof_type = MPTProofType::Disabled;
+    // The parent of the next node is a nil object, for S and C
+    let mut is_nil_parent = [false; 2];
+    for node in nodes {
+        if let Some(start) = &node.start {
+            proof_type = start.proof_type;
+            match proof_type {
+                MPTProofType::Disabled => {}
+                MPTProofType::NonceChanged => hit(NodeVariant::NonceChanged),
+                MPTProofType::BalanceChanged => hit(NodeVariant::BalanceChanged),
+                MPTProofType::CodeHashChanged => hit(NodeVariant::CodeHashChanged),
+                MPTProofType::AccountDestructed => hit(NodeVariant::AccountDestructed),
+                MPTProofType::AccountDoesNotExist => hit(NodeVariant::AccountDoesNotExist),
+                MPTProofType::StorageChanged => hit(NodeVariant::StorageChanged),
+                MPTProofType::StorageDoesNotExist => hit(NodeVariant::StorageDoesNotExist),
+            }
+            is_nil_parent = [
+                is_nil(&node.values[StartRowType::RootS as usize]),
+                is_nil(&node.values[StartRowType::RootC as usize]),
+            ];
+        } else if let Some(extension_branch) = &node.extension_branch {
+            hit(NodeVariant::Branch);
+            if extension_branch.is_extension {
+                let key = rlp_item(&node.values[ExtensionBranchRowType::KeyS as usize]).unwrap();
+                let flags = if key[0] < RLP_NIL { key[0] } else { key[1] };
+                hit(if flags >> 4 == 1 {
+                    NodeVariant::ExtensionOddKey
+                } else {
+                    NodeVariant::ExtensionEvenKey
+                });
+            }
+            let modified_index = extension_branch.branch.modified_index;
+            for (side, variant) in [
+                NodeVariant::BranchPlaceholderS,
+                NodeVariant::BranchPlaceholderC,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                // A placeholder branch leaves the parent of the leaf unchanged
+                if extension_branch.is_placeholder[side] {
+                    hit(variant);
+                    continue;
+                }
+                // Only the modified child of C is stored, in the first row
+                let child = if side == 0 { 1 + modified_index } else { 0 };
+                is_nil_parent[side] = is_nil(&node.values[child]);
+            }
+        } else if node.account.is_some() || node.storage.is_some() {
+            let (key_rows, list_rlp_bytes) = if let Some(account) = &node.account {
+                hit(NodeVariant::AccountLeaf);
+                (
+                    [AccountRowType::KeyS as usize, AccountRowType::KeyC as usize],
+                    &account.list_rlp_bytes,
+                )
+            } else {
+                let storage = node.storage.as_ref().unwrap();
+                hit(NodeVariant::StorageLeaf);
+                (
+                    [StorageRowType::KeyS as usize, StorageRowType::KeyC as usize],
+                    &storage.list_rlp_bytes,
+                )
+            };
+            for (side, variant) in [NodeVariant::LeafPlaceholderS, NodeVariant::LeafPlaceholderC]
+                .into_iter()
+                .enumerate()
+            {
+                if is_nil_parent[side] {
+                    hit(variant);
+                    continue;
+                }
+                let key = rlp_item(&node.values[key_rows[side]]).unwrap();
+                hit(if key[0] == KEY_TERMINAL_PREFIX_EVEN {
+                    NodeVariant::LeafKeyNoNibbles
+                } else if key[0] < RLP_NIL {
+                    NodeVariant::LeafKeyOneNibble
+                } else if key[1] >> 4 == 3 {
+                    NodeVariant::LeafKeyOdd
+                } else {
+                    NodeVariant::LeafKeyEven
+                });
+                // The list of an account leaf is always long
+                if node.storage.is_some() {
+                    hit(if list_rlp_bytes[side][0] > RLP_LIST_LONG {
+                        NodeVariant::LeafLongList
+                    } else {
+                        NodeVariant::LeafShortList
+                    });
+                }
+            }
+            if matches!(
+                proof_type,
+                MPTProofType::AccountDoesNotExist | MPTProofType::StorageDoesNotExist
+            ) {
+                hit(if is_nil_parent[0] {
+                    NodeVariant::NilObject
+                } else {
+                    NodeVariant::WrongLeaf
+                });
+            }
+            // The storage trie starts below the account
+            if node.account.is_some() {
+                is_nil_parent = [
+                    is_nil(&node.values[AccountRowType::StorageS as usize]),
+                    is_nil(&node.values[AccountRowType::StorageC as usize]),
+                ];
+            }
+        }
+    }
+}
+
+#[test]
+fn test_witness_coverage() {
+    let mut files: Vec<_> = fs::read_dir("src/mpt_circuit/tests")
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|file| file.path())
+        .filter(|path| path.extension().map_or(false, |e| e == "json"))
+        .collect();
+    files.sort();
+
+    let mut coverage = BTreeMap::new();
+    for path in files {
+        witness_coverage(&load_proof(path.to_str().unwrap()), &mut coverage);
+    }
+
+    println!("MPT witness coverage:");
+    for variant in NodeVariant::iter() {
+        println!(
+            "  {:?}: {}",
+            variant,
+            coverage.get(&variant).copied().unwrap_or_default()
+        );
+    }
+    let missing: Vec<_> = NodeVariant::iter()
+        .filter(|variant| !coverage.contains_key(variant))
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "node variants without a test witness: {:?}",
+        missing
+    );
+}
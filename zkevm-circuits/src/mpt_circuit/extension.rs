@@ -1,5 +1,5 @@
-use eth_types::Field;
-use gadgets::util::{pow, Scalar};
+use eth_types::{Field, KeyPath};
+use gadgets::util::Scalar;
 use halo2_proofs::plonk::{Error, Expression, VirtualCells};
 
 use super::{
@@ -16,10 +16,10 @@ use crate::{
     },
     mpt_circuit::{
         helpers::{
-            ext_key_rlc_calc_value, ext_key_rlc_expr, num_nibbles, Indexable, KeyData, MptCellType,
-            ParentData, FIXED, KECCAK, MULT,
+            ext_key_rlc_expr, num_nibbles, Indexable, KeyData, MptCellType, ParentData, FIXED,
+            KECCAK, KEY_MULT,
         },
-        param::HASH_WIDTH,
+        param::{HASH_WIDTH, KEY_LEN},
         FixedTableTag, MPTConfig, MptMemory, RlpItemType,
     },
     util::word::Word,
@@ -149,7 +149,10 @@ impl<F: Field> ExtensionGadget<F> {
             let key_len = config.rlp_key.key_value.len();
             // Calculate the number of nibbles
             let num_nibbles = num_nibbles::expr(key_len.expr(), config.is_key_part_odd.expr());
-            // Make sure the nibble counter is updated correctly
+            // Make sure the nibble counter is updated correctly. The counter, the parity and
+            // the multiplier all continue from the key data of the previous branch, so
+            // consecutive extension nodes on the same path (each followed by its branch)
+            // accumulate their nibbles like a single longer extension would.
             let num_nibbles = key_data.num_nibbles.expr() + num_nibbles.expr();
 
             // The parity alternates when there's an even number of nibbles, remains the
@@ -186,9 +189,10 @@ impl<F: Field> ExtensionGadget<F> {
             let key_len = config.rlp_key.key_value.len();
             let key_num_bytes_for_mult = key_len
                 - ifx! {not!(key_data.is_odd.expr() * config.is_key_part_odd.expr()) => { 1.expr() }};
-            // Get the multiplier for this key length
+            // Get the multiplier for this key length, the key is at most KEY_LEN + 1 bytes
+            assert!(KEY_LEN + 1 < ctx.params.mult_table_len());
             config.mult_key = cb.query_cell_with_type(MptCellType::StoragePhase2);
-            require!((key_num_bytes_for_mult, config.mult_key.expr()) =>> @MULT);
+            require!((key_num_bytes_for_mult, config.mult_key.expr()) =>> @KEY_MULT);
 
             // Store the post ext state
             config.post_state = Some(ExtState {
@@ -245,6 +249,7 @@ impl<F: Field> ExtensionGadget<F> {
         let first_key_byte = key_items[true.idx()].bytes[rlp_key.key_item.num_rlp_bytes()];
         // Compact encoding
         let is_key_part_odd = first_key_byte >> 4 == 1;
+        #[cfg(not(feature = "fast_assign"))]
         if is_key_part_odd {
             assert!(first_key_byte < 0b10_0000);
         } else {
@@ -260,13 +265,19 @@ impl<F: Field> ExtensionGadget<F> {
             HASH_WIDTH.scalar(),
         )?;
 
-        let mut key_len_mult = rlp_key.key_item.len();
-        if !(*is_key_odd && is_key_part_odd) {
-            key_len_mult -= 1;
-        }
+        // Key RLC and mult, the extension nibbles follow the nibbles of the parents
+        let key_item = &rlp_key.key_item;
+        let nibbles = KeyPath::compact_nibbles(
+            &key_items[true.idx()].bytes[key_item.num_rlp_bytes()..key_item.num_bytes()],
+        );
+        let (key_rlc_ext, mult_key) =
+            KeyPath::rlc_chain(*num_nibbles, &nibbles, (F::ZERO, F::ONE), region.key_r);
+        *key_rlc = key_data.rlc + key_rlc_ext * key_data.mult;
+        self.mult_key.assign(region, offset, mult_key)?;
+        *key_mult = key_data.mult * mult_key;
 
         // Update number of nibbles
-        *num_nibbles += num_nibbles::value(rlp_key.key_item.len(), is_key_part_odd);
+        *num_nibbles += nibbles.len();
 
         // Update parity
         *is_key_odd = if is_key_part_odd {
@@ -275,26 +286,18 @@ impl<F: Field> ExtensionGadget<F> {
             *is_key_odd
         };
 
-        // Key RLC
-        let (key_rlc_ext, _) = ext_key_rlc_calc_value(
-            rlp_key.key_item,
-            key_data.mult,
-            is_key_part_odd,
-            !*is_key_odd,
-            key_items
+        // The second nibble of every key byte is also in the C key row, the
+        // circuit uses it when the extension ends at an odd position: its bytes
+        // then straddle the bytes of the key
+        #[cfg(not(feature = "fast_assign"))]
+        if key_item.is_long() && *is_key_odd {
+            for (byte, nibble) in key_items[true.idx()].bytes[2..]
                 .iter()
-                .map(|item| item.bytes.clone())
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap(),
-            region.key_r,
-        );
-        *key_rlc = key_data.rlc + key_rlc_ext;
-
-        // Key mult
-        let mult_key = pow::value(region.key_r, key_len_mult);
-        self.mult_key.assign(region, offset, mult_key)?;
-        *key_mult = key_data.mult * mult_key;
+                .zip(key_items[false.idx()].bytes[2..].iter())
+            {
+                assert!(byte & 0xf == *nibble);
+            }
+        }
 
         Ok(())
     }
@@ -161,6 +161,8 @@ impl<F: Field> ExtensionBranchConfig<F> {
                         Word::<Expression<F>>::new([0.expr(), 0.expr()])
                     );
                  } elsex {
+                    // The drifted key also contains the nibbles of a placeholder extension,
+                    // so the leaf can drift by any number of nibbles
                     KeyData::store(
                         cb,
                         &mut ctx.memory[key_memory(is_s)],
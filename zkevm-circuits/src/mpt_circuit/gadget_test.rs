@@ -0,0 +1,1036 @@
+//! Minimal circuits to test a single MPT gadget in isolation.
+//!
+//! A gadget implementing `GadgetTest` is wrapped in `GadgetTestCircuit`, which
+//! only contains the lookup tables used by the MPT gadgets and a single cell
+//! manager. Every test case is assigned on its own row so a lot of (random)
+//! inputs can be checked with a single `MockProver` run.
+use std::marker::PhantomData;
+
+use eth_types::{Field, Word as U256};
+use gadgets::util::Scalar;
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner, Value},
+    dev::{MockProver, VerifyFailure},
+    halo2curves::bn256::Fr,
+    plonk::{Advice, Circuit, Column, ConstraintSystem, Error, Fixed, SecondPhase, VirtualCells},
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use strum::IntoEnumIterator;
+
+use super::{
+    helpers::{
+        AccountValueGadget, LeafKeyGadget, MPTConstraintBuilder, MainRLPGadget, MptCellType,
+        MptTableType, ProofTypeGadget, RLPItemView, WordDeltaGadget,
+    },
+    load_fixed_table, load_mult_table,
+    param::{BALANCE_MAX_LEN, KEY_TERMINAL_PREFIX_EVEN, NONCE_MAX_LEN},
+    rlp_gadgets::{decode_rlp, RLPItemWitness, RLPValueGadget, RLPValueWitness},
+    MPTCircuitParams, MPTRegion, RlpItemType,
+};
+use crate::{
+    assignf, circuit,
+    circuit_tools::{
+        cached_region::CachedRegion,
+        cell_manager::{Cell, CellManager},
+    },
+    table::MPTProofType,
+    util::{word::Word, Challenges, Expr},
+};
+
+const DEGREE: u32 = 15;
+
+/// A gadget that can be tested in a standalone circuit
+pub(crate) trait GadgetTest<F: Field>: Clone {
+    /// A single test case, assigned on its own row
+    type Input: Clone;
+
+    /// Key RLCs use the keccak randomness, as in circuits without a separate
+    /// key challenge
+    const SHARED_KEY_R: bool = false;
+
+    /// Constructs the gadget, all cells are queried on a single row
+    fn configure(meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self;
+
+    /// Assigns the test case `input` at `offset`
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error>;
+}
+
+#[derive(Clone)]
+pub(crate) struct GadgetTestConfig<F: Field, G> {
+    q_enable: Column<Fixed>,
+    fixed_table: [Column<Fixed>; 6],
+    mult_table: [Column<Advice>; 3],
+    gadget: G,
+    cb: MPTConstraintBuilder<F>,
+}
+
+/// Circuit containing only the gadget `G` and the lookup tables it can use
+pub(crate) struct GadgetTestCircuit<F: Field, G: GadgetTest<F>> {
+    inputs: Vec<G::Input>,
+    _marker: PhantomData<F>,
+}
+
+impl<F: Field, G: GadgetTest<F>> GadgetTestCircuit<F, G> {
+    pub(crate) fn new(inputs: Vec<G::Input>) -> Self {
+        Self {
+            inputs,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<F: Field, G: GadgetTest<F>> Circuit<F> for GadgetTestCircuit<F, G> {
+    type Config = (GadgetTestConfig<F, G>, Challenges);
+    type FloorPlanner = SimpleFloorPlanner;
+    type Params = ();
+
+    fn without_witnesses(&self) -> Self {
+        Self::new(Vec::new())
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let challenges = if G::SHARED_KEY_R {
+            Challenges::construct(meta)
+        } else {
+            Challenges::construct_with_key_input(meta)
+        };
+        let challenges_expr = challenges.exprs(meta);
+
+        let q_enable = meta.fixed_column();
+        let fixed_table: [Column<Fixed>; 6] = (0..6)
+            .map(|_| meta.fixed_column())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+        let mult_table: [Column<Advice>; 3] = [
+            meta.advice_column(),
+            meta.advice_column_in(SecondPhase),
+            meta.advice_column_in(SecondPhase),
+        ];
+
+        let mut cb = MPTConstraintBuilder::new(5, Some(challenges_expr), None);
+        cb.load_table(meta, MptTableType::Byte, &[fixed_table[2]]);
+        cb.load_table(meta, MptTableType::Fixed, &fixed_table);
+        cb.load_table(meta, MptTableType::Mult, &[mult_table[0], mult_table[1]]);
+        cb.load_table(meta, MptTableType::KeyMult, &[mult_table[0], mult_table[2]]);
+
+        // Same layout as the RLP decoding unit of the MPT circuit, with some room
+        // for the cells of the gadget under test.
+        let lu = MptCellType::Lookup;
+        let mut cm = CellManager::new(1, 0);
+        cm.add_columns(meta, &mut cb.base, MptCellType::StoragePhase1, 0, false, 80);
+        cm.add_columns(meta, &mut cb.base, MptCellType::StoragePhase2, 1, false, 8);
+        cm.add_columns(meta, &mut cb.base, MptCellType::StoragePhase3, 2, false, 5);
+        cm.add_columns(meta, &mut cb.base, lu(MptTableType::Byte), 0, false, 32);
+        cm.add_columns(meta, &mut cb.base, lu(MptTableType::Fixed), 2, false, 4);
+        cm.add_columns(meta, &mut cb.base, lu(MptTableType::Mult), 2, false, 2);
+
+        let mut gadget = None;
+        meta.create_gate("Gadget test", |meta| {
+            circuit!([meta, cb], {
+                ifx! {f!(q_enable) => {
+                    cb.base.set_cell_manager(cm.clone());
+                    cb.base.push_region(MPTRegion::RLP as usize, 1);
+                    gadget = Some(G::configure(meta, &mut cb));
+                    cb.base.pop_region();
+                }}
+            });
+            cb.base.build_constraints()
+        });
+        cb.base.build_lookups(meta);
+
+        (
+            GadgetTestConfig {
+                q_enable,
+                fixed_table,
+                mult_table,
+                gadget: gadget.unwrap(),
+                cb,
+            },
+            challenges,
+        )
+    }
+
+    fn synthesize(
+        &self,
+        (config, challenges): Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let challenges = challenges.values(&mut layouter);
+        layouter.assign_region(
+            || "Gadget test",
+            |mut region| {
+                let mut keccak_r = F::ZERO;
+                challenges.keccak_input().map(|v| keccak_r = v);
+                let mut key_r = F::ZERO;
+                challenges.key_input().map(|v| key_r = v);
+
+                for (offset, input) in self.inputs.iter().enumerate() {
+                    let mut cached_region = CachedRegion::new(&mut region, keccak_r, key_r);
+                    cached_region.push_region(offset, MPTRegion::RLP as usize);
+                    config.gadget.assign(&mut cached_region, offset, input)?;
+                    cached_region.pop_region();
+                    cached_region.assign_stored_expressions(&config.cb.base, &challenges)?;
+                }
+                for offset in 0..self.inputs.len() {
+                    assignf!(region, (config.q_enable, offset) => true.scalar())?;
+                }
+                Ok(())
+            },
+        )?;
+        load_fixed_table(
+            &mut layouter,
+            &config.fixed_table,
+            MPTCircuitParams::default(),
+        )?;
+        load_mult_table(
+            &mut layouter,
+            &config.mult_table,
+            None,
+            &challenges,
+            MPTCircuitParams::default().mult_table_len(),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Runs the gadget `G` on all `inputs`
+pub(crate) fn run_gadget_test<G: GadgetTest<Fr>>(
+    inputs: Vec<G::Input>,
+) -> Result<(), Vec<VerifyFailure>> {
+    let circuit = GadgetTestCircuit::<Fr, G>::new(inputs);
+    let prover = MockProver::<Fr>::run(DEGREE, &circuit, vec![]).unwrap();
+    prover.verify()
+}
+
+/// The gadget `G` with the keccak randomness used for the key RLCs
+#[derive(Clone)]
+struct SharedKeyR<G>(G);
+
+impl<F: Field, G: GadgetTest<F>> GadgetTest<F> for SharedKeyR<G> {
+    type Input = G::Input;
+
+    const SHARED_KEY_R: bool = true;
+
+    fn configure(meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        SharedKeyR(G::configure(meta, cb))
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        self.0.assign(region, offset, input)
+    }
+}
+
+/// `RLPValueGadget` decoding the RLP byte of a value or list
+#[derive(Clone)]
+struct RLPValueTest<F> {
+    rlp_byte: Cell<F>,
+    rlp_value: RLPValueGadget<F>,
+}
+
+#[derive(Clone)]
+struct RLPValueInput {
+    rlp_byte: u8,
+    /// Flips the decoded `is_short` flag
+    wrong_is_short: bool,
+}
+
+impl<F: Field> GadgetTest<F> for RLPValueTest<F> {
+    type Input = RLPValueInput;
+
+    fn configure(_meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        let rlp_byte = cb.query_byte();
+        let rlp_value = RLPValueGadget::construct(cb, &[rlp_byte.expr()]);
+        RLPValueTest {
+            rlp_byte,
+            rlp_value,
+        }
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        self.rlp_byte
+            .assign(region, offset, input.rlp_byte.scalar())?;
+        if input.wrong_is_short {
+            // Cells can only be assigned once, so assign the flags directly
+            let (is_list, is_short, is_long, is_very_long) = decode_rlp(input.rlp_byte);
+            let gadget = &self.rlp_value;
+            gadget.is_list.assign(region, offset, is_list.scalar())?;
+            gadget
+                .is_short
+                .assign(region, offset, (!is_short).scalar())?;
+            gadget.is_long.assign(region, offset, is_long.scalar())?;
+            gadget
+                .is_very_long
+                .assign(region, offset, is_very_long.scalar())?;
+        } else {
+            self.rlp_value.assign(region, offset, &[input.rlp_byte])?;
+        }
+        Ok(())
+    }
+}
+
+/// Longest value decoded by `RLPLongValueTest`
+const LONG_VALUE_MAX_LEN: usize = 64;
+
+/// `RLPValueGadget` decoding values longer than 55 bytes, like the values of
+/// the receipts and transactions tries.
+#[derive(Clone)]
+struct RLPLongValueTest<F> {
+    bytes: Vec<Cell<F>>,
+    rlp_value: RLPValueGadget<F>,
+    len: Cell<F>,
+    value_rlc: Cell<F>,
+}
+
+#[derive(Clone)]
+struct RLPLongValueInput {
+    /// RLP encoded value, padded with zeros
+    bytes: Vec<u8>,
+    /// Added to the expected length
+    len_offset: u64,
+}
+
+impl RLPLongValueInput {
+    fn new(value: &[u8]) -> Self {
+        let mut bytes = match value.len() {
+            1 if value[0] < 0x80 => vec![],
+            len if len <= 55 => vec![0x80 + len as u8],
+            len => vec![0xb8, len as u8],
+        };
+        bytes.extend_from_slice(value);
+        Self::from_rlp(bytes)
+    }
+
+    fn from_rlp(mut bytes: Vec<u8>) -> Self {
+        bytes.resize(2 + LONG_VALUE_MAX_LEN, 0);
+        Self {
+            bytes,
+            len_offset: 0,
+        }
+    }
+}
+
+impl<F: Field> GadgetTest<F> for RLPLongValueTest<F> {
+    type Input = RLPLongValueInput;
+
+    fn configure(_meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        let bytes: Vec<_> = (0..2 + LONG_VALUE_MAX_LEN)
+            .map(|_| cb.query_cell())
+            .collect();
+        let bytes_expr: Vec<_> = bytes.iter().map(|byte| byte.expr()).collect();
+        let rlp_value = RLPValueGadget::construct_with_max_len(cb, &bytes_expr, LONG_VALUE_MAX_LEN);
+        let len = cb.query_cell();
+        let value_rlc = cb.query_cell_with_type(MptCellType::StoragePhase2);
+        let r = cb.keccak_r.expr();
+        cb.base
+            .require_equal("value length", len.expr(), rlp_value.len());
+        cb.base
+            .require_equal("value rlc", value_rlc.expr(), rlp_value.rlc_value(&r));
+        RLPLongValueTest {
+            bytes,
+            rlp_value,
+            len,
+            value_rlc,
+        }
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        for (cell, byte) in self.bytes.iter().zip(input.bytes.iter()) {
+            cell.assign(region, offset, byte.scalar())?;
+        }
+        let rlp_value = self.rlp_value.assign(region, offset, &input.bytes)?;
+        self.len.assign(
+            region,
+            offset,
+            F::from(rlp_value.len() as u64 + input.len_offset),
+        )?;
+        self.value_rlc
+            .assign(region, offset, rlp_value.rlc_value(region.keccak_r))?;
+        Ok(())
+    }
+}
+
+/// `LeafKeyGadget` computing the RLC of the key of a leaf, the key is decoded
+/// by the RLP decoding unit of the MPT circuit.
+#[derive(Clone)]
+struct LeafKeyTest<F> {
+    rlp_key: MainRLPGadget<F>,
+    leaf_key: LeafKeyGadget<F>,
+    is_key_odd: Cell<F>,
+    key_rlc: Cell<F>,
+}
+
+#[derive(Clone)]
+struct LeafKeyInput {
+    /// RLP encoded compact key
+    key: Vec<u8>,
+    is_key_odd: bool,
+    /// Added to the expected key RLC
+    rlc_offset: u64,
+    /// Computes the expected key RLC with the randomness of the node data
+    data_domain: bool,
+}
+
+impl<F: Field> GadgetTest<F> for LeafKeyTest<F> {
+    type Input = LeafKeyInput;
+
+    fn configure(meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        let rlp_key = MainRLPGadget::construct(cb, MPTCircuitParams::default());
+        let key_item = rlp_key.create_view(meta, cb, 0, RlpItemType::Key);
+        let leaf_key = LeafKeyGadget::construct(cb, key_item.clone());
+        let is_key_odd = cb.query_bool();
+        let key_rlc = cb.query_cell_with_type(MptCellType::StoragePhase2);
+        let r = cb.key_r.expr();
+        circuit!([meta, cb], {
+            let rlc = leaf_key.expr(cb, key_item, 1.expr(), is_key_odd.expr(), &r);
+            require!(key_rlc => rlc);
+        });
+        LeafKeyTest {
+            rlp_key,
+            leaf_key,
+            is_key_odd,
+            key_rlc,
+        }
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        let key_item = self
+            .rlp_key
+            .assign(region, offset, &input.key, RlpItemType::Key)?;
+        let leaf_key = self.leaf_key.assign(region, offset, &key_item)?;
+        let r = if input.data_domain {
+            region.keccak_r
+        } else {
+            region.key_r
+        };
+        let (key_rlc, _) = leaf_key.key(key_item, F::ZERO, F::ONE, r);
+        self.is_key_odd
+            .assign(region, offset, input.is_key_odd.scalar())?;
+        self.key_rlc
+            .assign(region, offset, key_rlc + F::from(input.rlc_offset))?;
+        Ok(())
+    }
+}
+
+/// `LeafKeyGadget` on a key item that is not decoded, so that its own length
+/// check is the only one applying to the key
+#[derive(Clone)]
+struct LeafKeyLenTest<F> {
+    first_byte: Cell<F>,
+    len: Cell<F>,
+    leaf_key: LeafKeyGadget<F>,
+}
+
+impl<F: Field> GadgetTest<F> for LeafKeyLenTest<F> {
+    /// RLP encoded compact key
+    type Input = Vec<u8>;
+
+    fn configure(_meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        let first_byte = cb.query_byte();
+        let len = cb.query_cell();
+        let key_item = RLPItemView::undecoded_key(vec![first_byte.expr()], len.expr());
+        let leaf_key = LeafKeyGadget::construct(cb, key_item);
+        LeafKeyLenTest {
+            first_byte,
+            len,
+            leaf_key,
+        }
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        let key_item = RLPItemWitness {
+            value: RLPValueWitness {
+                is_long: true,
+                bytes: input.clone(),
+                ..Default::default()
+            },
+            bytes: input.clone(),
+            ..Default::default()
+        };
+        self.first_byte.assign(region, offset, input[0].scalar())?;
+        self.len.assign(region, offset, key_item.len().scalar())?;
+        self.leaf_key.assign(region, offset, &key_item)?;
+        Ok(())
+    }
+}
+
+/// `AccountValueGadget` checking a nonce or a balance of at most `MAX_LEN`
+/// bytes, decoded by the RLP decoding unit of the MPT circuit.
+#[derive(Clone)]
+struct AccountValueTest<F, const MAX_LEN: usize> {
+    rlp_value: MainRLPGadget<F>,
+    account_value: AccountValueGadget<F>,
+}
+
+impl<F: Field, const MAX_LEN: usize> GadgetTest<F> for AccountValueTest<F, MAX_LEN> {
+    /// RLP encoded value
+    type Input = Vec<u8>;
+
+    fn configure(meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        let rlp_value = MainRLPGadget::construct(cb, MPTCircuitParams::default());
+        let value_item = rlp_value.create_view(meta, cb, 0, RlpItemType::Value);
+        let account_value = AccountValueGadget::construct(cb, &value_item, MAX_LEN);
+        AccountValueTest {
+            rlp_value,
+            account_value,
+        }
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        let value_item = self
+            .rlp_value
+            .assign(region, offset, input, RlpItemType::Value)?;
+        self.account_value.assign(region, offset, &value_item)
+    }
+}
+
+/// `WordDeltaGadget` computing the difference of two words
+#[derive(Clone)]
+struct WordDeltaTest<F> {
+    new_value: [Cell<F>; 2],
+    old_value: [Cell<F>; 2],
+    delta: [Cell<F>; 2],
+    word_delta: WordDeltaGadget<F>,
+}
+
+#[derive(Clone)]
+struct WordDeltaInput {
+    new_value: U256,
+    old_value: U256,
+    /// Delta assigned to the cells checked by the gadget
+    delta: U256,
+}
+
+impl<F: Field> GadgetTest<F> for WordDeltaTest<F> {
+    type Input = WordDeltaInput;
+
+    fn configure(_meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        let new_value = cb.query_cells();
+        let old_value = cb.query_cells();
+        let delta = cb.query_cells();
+        let word = |cells: &[Cell<F>; 2]| Word::new([cells[0].expr(), cells[1].expr()]);
+        let word_delta =
+            WordDeltaGadget::construct(cb, word(&delta), word(&new_value), word(&old_value));
+        WordDeltaTest {
+            new_value,
+            old_value,
+            delta,
+            word_delta,
+        }
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        for (cells, value) in [
+            (&self.new_value, input.new_value),
+            (&self.old_value, input.old_value),
+            (&self.delta, input.delta),
+        ] {
+            let value = Word::<F>::from(value);
+            cells[0].assign(region, offset, value.lo())?;
+            cells[1].assign(region, offset, value.hi())?;
+        }
+        let delta = self.word_delta.assign(
+            region,
+            offset,
+            Word::from(input.new_value),
+            Word::from(input.old_value),
+        )?;
+        assert_eq!(
+            delta,
+            Word::from(input.new_value.overflowing_sub(input.old_value).0)
+        );
+        Ok(())
+    }
+}
+
+/// `ProofTypeGadget` decoding the proof type of a row
+#[derive(Clone)]
+struct ProofTypeTest<F> {
+    proof_type: Cell<F>,
+    gadget: ProofTypeGadget<F>,
+}
+
+#[derive(Clone)]
+struct ProofTypeInput {
+    proof_type: usize,
+    /// Flags assigned instead of the decoded ones
+    flags: Option<Vec<bool>>,
+}
+
+impl<F: Field> GadgetTest<F> for ProofTypeTest<F> {
+    type Input = ProofTypeInput;
+
+    fn configure(_meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        let proof_type = cb.query_cell();
+        let gadget = ProofTypeGadget::construct(cb, proof_type.expr());
+        ProofTypeTest { proof_type, gadget }
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        self.proof_type
+            .assign(region, offset, input.proof_type.scalar())?;
+        if let Some(flags) = &input.flags {
+            for (cell, flag) in self.gadget.flags.iter().zip(flags.iter()) {
+                cell.assign(region, offset, flag.scalar())?;
+            }
+        } else {
+            let proof_type = self.gadget.assign(region, offset, input.proof_type)?;
+            assert_eq!(proof_type as usize, input.proof_type);
+        }
+        Ok(())
+    }
+}
+
+/// Sub-expression cached in a cell with `cached_expr`
+#[derive(Clone)]
+struct CachedExprTest<F> {
+    a: Cell<F>,
+    b: Cell<F>,
+    expected: Cell<F>,
+}
+
+#[derive(Clone)]
+struct CachedExprInput {
+    a: u64,
+    b: u64,
+    /// Value the cached expression `a * b + a` is required to equal
+    expected: u64,
+}
+
+impl<F: Field> GadgetTest<F> for CachedExprTest<F> {
+    type Input = CachedExprInput;
+
+    fn configure(_meta: &mut VirtualCells<'_, F>, cb: &mut MPTConstraintBuilder<F>) -> Self {
+        let [a, b, expected] = cb.query_cells();
+        let cached = cb.cached_expr("cached", a.expr() * b.expr() + a.expr());
+        // Caching the same expression again reuses the cell
+        let cached_again = cb.cached_expr("cached", a.expr() * b.expr() + a.expr());
+        assert_eq!(cached.identifier(), cached_again.identifier());
+        cb.require_equal("cached expression", cached, expected.expr());
+        CachedExprTest { a, b, expected }
+    }
+
+    fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        input: &Self::Input,
+    ) -> Result<(), Error> {
+        self.a.assign(region, offset, input.a.scalar())?;
+        self.b.assign(region, offset, input.b.scalar())?;
+        self.expected
+            .assign(region, offset, input.expected.scalar())?;
+        Ok(())
+    }
+}
+
+/// RLP encoding of an account nonce or balance
+fn rlp_account_value(value: U256) -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    let bytes = &bytes[32 - (value.bits() + 7) / 8..];
+    match bytes {
+        [byte] if *byte < 0x80 => vec![*byte],
+        _ => [vec![0x80 + bytes.len() as u8], bytes.to_vec()].concat(),
+    }
+}
+
+/// Hex prefix encodes the last `num_nibbles` nibbles of a random key as a leaf
+/// key, returns the RLP encoded key and if the key has an odd number of nibbles
+fn random_leaf_key(rng: &mut impl Rng, num_nibbles: usize) -> (Vec<u8>, bool) {
+    let nibbles: Vec<u8> = (0..num_nibbles).map(|_| rng.gen_range(0..16)).collect();
+    let is_odd = num_nibbles % 2 == 1;
+    let mut key = if is_odd {
+        vec![KEY_TERMINAL_PREFIX_EVEN + 0x10 + nibbles[0]]
+    } else {
+        vec![KEY_TERMINAL_PREFIX_EVEN]
+    };
+    for pair in nibbles[usize::from(is_odd)..].chunks(2) {
+        key.push(pair[0] * 16 + pair[1]);
+    }
+    ([vec![0x80 + key.len() as u8], key].concat(), is_odd)
+}
+
+#[test]
+fn test_rlp_value_gadget() {
+    let mut rng = ChaCha20Rng::seed_from_u64(1);
+    // The fixed table does not contain 0xff, the longest list prefix, as
+    // no MPT node can be that long.
+    let mut inputs: Vec<_> = [0u8, 0x7f, 0x80, 0xb7, 0xb8, 0xbf, 0xc0, 0xf7, 0xf8, 0xfe]
+        .iter()
+        .map(|rlp_byte| RLPValueInput {
+            rlp_byte: *rlp_byte,
+            wrong_is_short: false,
+        })
+        .collect();
+    inputs.extend((0..100).map(|_| RLPValueInput {
+        rlp_byte: rng.gen_range(0..0xff),
+        wrong_is_short: false,
+    }));
+    assert_eq!(run_gadget_test::<RLPValueTest<Fr>>(inputs), Ok(()));
+}
+
+#[test]
+fn test_rlp_value_gadget_wrong_flags() {
+    let mut rng = ChaCha20Rng::seed_from_u64(2);
+    for _ in 0..4 {
+        let rlp_byte = rng.gen_range(0..0xff);
+        let (_, is_short, _, _) = decode_rlp(rlp_byte);
+        let inputs = vec![RLPValueInput {
+            rlp_byte,
+            wrong_is_short: true,
+        }];
+        assert!(
+            run_gadget_test::<RLPValueTest<Fr>>(inputs).is_err(),
+            "rlp byte {} accepted with is_short = {}",
+            rlp_byte,
+            !is_short
+        );
+    }
+}
+
+#[test]
+fn test_rlp_long_value_gadget() {
+    let mut rng = ChaCha20Rng::seed_from_u64(5);
+    let inputs = [1, 1, 2, 32, 55, 56, 57, LONG_VALUE_MAX_LEN]
+        .iter()
+        .chain([rng.gen_range(56..=LONG_VALUE_MAX_LEN)].iter())
+        .map(|len| {
+            let value: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+            RLPLongValueInput::new(&value)
+        })
+        .collect();
+    assert_eq!(run_gadget_test::<RLPLongValueTest<Fr>>(inputs), Ok(()));
+}
+
+#[test]
+fn test_rlp_long_value_gadget_invalid() {
+    let value = vec![0xaa; LONG_VALUE_MAX_LEN];
+
+    // Wrong length
+    let mut input = RLPLongValueInput::new(&value);
+    input.len_offset = 1;
+    assert!(run_gadget_test::<RLPLongValueTest<Fr>>(vec![input]).is_err());
+
+    // Longer than the maximum length
+    let rlp = [vec![0xb8, LONG_VALUE_MAX_LEN as u8 + 1], value.clone()].concat();
+    let input = RLPLongValueInput::from_rlp(rlp);
+    assert!(run_gadget_test::<RLPLongValueTest<Fr>>(vec![input]).is_err());
+
+    // Non-canonical length
+    let rlp = [vec![0xb8, 32], value[..32].to_vec()].concat();
+    let input = RLPLongValueInput::from_rlp(rlp);
+    assert!(run_gadget_test::<RLPLongValueTest<Fr>>(vec![input]).is_err());
+
+    // Two length bytes
+    let rlp = [vec![0xb9, 0, LONG_VALUE_MAX_LEN as u8], value].concat();
+    let input = RLPLongValueInput::from_rlp(rlp[..2 + LONG_VALUE_MAX_LEN].to_vec());
+    assert!(run_gadget_test::<RLPLongValueTest<Fr>>(vec![input]).is_err());
+}
+
+#[test]
+fn test_leaf_key_gadget() {
+    let mut rng = ChaCha20Rng::seed_from_u64(3);
+    // Keys with at least two nibbles left are encoded as RLP strings
+    let inputs = (0..100)
+        .map(|_| {
+            let num_nibbles = rng.gen_range(2..=64);
+            let (key, is_key_odd) = random_leaf_key(&mut rng, num_nibbles);
+            LeafKeyInput {
+                key,
+                is_key_odd,
+                rlc_offset: 0,
+                data_domain: false,
+            }
+        })
+        .collect();
+    assert_eq!(run_gadget_test::<LeafKeyTest<Fr>>(inputs), Ok(()));
+}
+
+#[test]
+fn test_leaf_key_gadget_invalid() {
+    let mut rng = ChaCha20Rng::seed_from_u64(4);
+    let (key, is_key_odd) = random_leaf_key(&mut rng, 63);
+
+    // Wrong key RLC
+    let inputs = vec![LeafKeyInput {
+        key: key.clone(),
+        is_key_odd,
+        rlc_offset: 1,
+        data_domain: false,
+    }];
+    assert!(run_gadget_test::<LeafKeyTest<Fr>>(inputs).is_err());
+
+    // Wrong key parity
+    let inputs = vec![LeafKeyInput {
+        key,
+        is_key_odd: !is_key_odd,
+        rlc_offset: 0,
+        data_domain: false,
+    }];
+    assert!(run_gadget_test::<LeafKeyTest<Fr>>(inputs).is_err());
+
+    // More nibbles than a key has, the key item is 34 bytes long. The RLP
+    // decoding unit rejects it already.
+    let (key, is_key_odd) = random_leaf_key(&mut rng, 66);
+    let inputs = vec![LeafKeyInput {
+        key,
+        is_key_odd,
+        rlc_offset: 0,
+        data_domain: false,
+    }];
+    assert!(run_gadget_test::<LeafKeyTest<Fr>>(inputs).is_err());
+}
+
+#[test]
+fn test_leaf_key_gadget_data_domain() {
+    let mut rng = ChaCha20Rng::seed_from_u64(8);
+    let (key, is_key_odd) = random_leaf_key(&mut rng, 64);
+    // The key RLC computed with the randomness of the node data
+    let input = LeafKeyInput {
+        key,
+        is_key_odd,
+        rlc_offset: 0,
+        data_domain: true,
+    };
+    // Is the same RLC when keys and data share the randomness
+    assert_eq!(
+        run_gadget_test::<SharedKeyR<LeafKeyTest<Fr>>>(vec![input.clone()]),
+        Ok(())
+    );
+    // And is rejected with the key challenge
+    assert!(run_gadget_test::<LeafKeyTest<Fr>>(vec![input]).is_err());
+}
+
+#[test]
+fn test_leaf_key_gadget_max_len() {
+    let mut rng = ChaCha20Rng::seed_from_u64(7);
+    // The longest key, 64 nibbles
+    let (key, _) = random_leaf_key(&mut rng, 64);
+    assert_eq!(key.len(), 34);
+    assert_eq!(run_gadget_test::<LeafKeyLenTest<Fr>>(vec![key]), Ok(()));
+
+    // Without the RLP decoding unit, only the gadget stops a longer key
+    let (key, _) = random_leaf_key(&mut rng, 66);
+    let failures = run_gadget_test::<LeafKeyLenTest<Fr>>(vec![key]).unwrap_err();
+    assert!(!failures.is_empty());
+    for failure in failures {
+        match failure {
+            VerifyFailure::ConstraintNotSatisfied { constraint, .. } => assert!(
+                constraint.to_string().contains("is_below_max_len"),
+                "{}",
+                constraint
+            ),
+            failure => panic!("unexpected failure {:?}", failure),
+        }
+    }
+}
+
+#[test]
+fn test_account_value_gadget() {
+    let mut rng = ChaCha20Rng::seed_from_u64(6);
+    let nonces = [0, 1, 0x7f, 0x80, 0xff, 1 << 32, u64::MAX - 1, u64::MAX]
+        .iter()
+        .map(|nonce| rlp_account_value(U256::from(*nonce)))
+        .collect();
+    assert_eq!(
+        run_gadget_test::<AccountValueTest<Fr, NONCE_MAX_LEN>>(nonces),
+        Ok(())
+    );
+
+    // Balances crossing the limbs of the word, up to the full 256 bits
+    let mut balances: Vec<_> = [1, 8, 12, 15, 16, 17, 31, 32]
+        .iter()
+        .map(|num_bytes| rlp_account_value(U256::MAX >> (256 - 8 * num_bytes)))
+        .collect();
+    balances.push(rlp_account_value(U256::zero()));
+    balances.extend((0..20).map(|_| {
+        let mut bytes = [0u8; 32];
+        rng.fill(&mut bytes[rng.gen_range(0..32)..]);
+        rlp_account_value(U256::from_big_endian(&bytes))
+    }));
+    assert_eq!(
+        run_gadget_test::<AccountValueTest<Fr, BALANCE_MAX_LEN>>(balances),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_account_value_gadget_invalid() {
+    // Nonce longer than 64 bits
+    let nonce = rlp_account_value(U256::from(u64::MAX) + 1);
+    assert!(run_gadget_test::<AccountValueTest<Fr, NONCE_MAX_LEN>>(vec![nonce]).is_err());
+
+    // Single byte below 0x80 encoded as a string
+    assert!(
+        run_gadget_test::<AccountValueTest<Fr, NONCE_MAX_LEN>>(vec![vec![0x81, 0x7f]]).is_err()
+    );
+    assert!(
+        run_gadget_test::<AccountValueTest<Fr, BALANCE_MAX_LEN>>(vec![vec![0x81, 0x01]]).is_err()
+    );
+}
+
+#[test]
+fn test_word_delta_gadget() {
+    let mut rng = ChaCha20Rng::seed_from_u64(7);
+    let mut values = vec![
+        (U256::zero(), U256::zero()),
+        (U256::one(), U256::zero()),
+        (U256::zero(), U256::one()),
+        (U256::MAX, U256::zero()),
+        (U256::zero(), U256::MAX),
+        // Borrow from the high limb
+        (U256::one() << 128, U256::one()),
+        (U256::one(), U256::one() << 128),
+    ];
+    values.extend((0..20).map(|_| {
+        let mut bytes = [[0u8; 32]; 2];
+        for bytes in bytes.iter_mut() {
+            rng.fill(&mut bytes[rng.gen_range(0..32)..]);
+        }
+        (
+            U256::from_big_endian(&bytes[0]),
+            U256::from_big_endian(&bytes[1]),
+        )
+    }));
+    let inputs = values
+        .into_iter()
+        .map(|(new_value, old_value)| WordDeltaInput {
+            new_value,
+            old_value,
+            delta: new_value.overflowing_sub(old_value).0,
+        })
+        .collect();
+    assert_eq!(run_gadget_test::<WordDeltaTest<Fr>>(inputs), Ok(()));
+}
+
+#[test]
+fn test_word_delta_gadget_invalid() {
+    // The delta does not match the values
+    let inputs = vec![WordDeltaInput {
+        new_value: U256::from(5),
+        old_value: U256::from(3),
+        delta: U256::from(3),
+    }];
+    assert!(run_gadget_test::<WordDeltaTest<Fr>>(inputs).is_err());
+
+    // The negative difference without wrapping around 2^256
+    let inputs = vec![WordDeltaInput {
+        new_value: U256::from(3),
+        old_value: U256::from(5),
+        delta: U256::from(2),
+    }];
+    assert!(run_gadget_test::<WordDeltaTest<Fr>>(inputs).is_err());
+}
+
+#[test]
+fn test_cached_expr() {
+    let inputs = vec![
+        CachedExprInput {
+            a: 3,
+            b: 5,
+            expected: 18,
+        },
+        CachedExprInput {
+            a: 0,
+            b: 7,
+            expected: 0,
+        },
+    ];
+    assert_eq!(run_gadget_test::<CachedExprTest<Fr>>(inputs), Ok(()));
+
+    let inputs = vec![CachedExprInput {
+        a: 3,
+        b: 5,
+        expected: 15,
+    }];
+    assert!(run_gadget_test::<CachedExprTest<Fr>>(inputs).is_err());
+}
+
+#[test]
+fn test_proof_type_gadget() {
+    let inputs = MPTProofType::iter()
+        .map(|proof_type| ProofTypeInput {
+            proof_type: proof_type as usize,
+            flags: None,
+        })
+        .collect();
+    assert_eq!(run_gadget_test::<ProofTypeTest<Fr>>(inputs), Ok(()));
+}
+
+#[test]
+fn test_proof_type_gadget_invalid() {
+    // Sets the flags of the given proof types
+    let flags = |proof_types: &[MPTProofType]| {
+        MPTProofType::iter()
+            .map(|proof_type| proof_types.contains(&proof_type))
+            .collect::<Vec<_>>()
+    };
+    let nonce_changed = MPTProofType::NonceChanged;
+    let balance_changed = MPTProofType::BalanceChanged;
+    let invalid_flags = [
+        // An unknown proof type without any flag set
+        (MPTProofType::iter().count() + 1, flags(&[])),
+        // The flag of another proof type
+        (nonce_changed as usize, flags(&[balance_changed])),
+        // Two flags adding up to the proof type
+        (
+            nonce_changed as usize + balance_changed as usize,
+            flags(&[nonce_changed, balance_changed]),
+        ),
+    ];
+    for (proof_type, flags) in invalid_flags {
+        let inputs = vec![ProofTypeInput {
+            proof_type,
+            flags: Some(flags),
+        }];
+        assert!(run_gadget_test::<ProofTypeTest<Fr>>(inputs).is_err());
+    }
+}
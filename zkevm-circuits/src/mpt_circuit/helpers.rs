@@ -13,32 +13,31 @@ use crate::{
         param::{N_BYTES_HALF_WORD, N_BYTES_WORD},
         util::from_bytes,
     },
-    matchw,
     mpt_circuit::{
         param::{
-            ADDRESS_WIDTH, EMPTY_TRIE_HASH, HASH_WIDTH, KEY_LEN_IN_NIBBLES, KEY_PREFIX_EVEN,
-            KEY_TERMINAL_PREFIX_EVEN, RLP_UNIT_NUM_BYTES, RLP_UNIT_NUM_VALUE_BYTES,
+            ADDRESS_WIDTH, EMPTY_TRIE_HASH, HASH_WIDTH, KEY_LEN, KEY_LEN_IN_NIBBLES,
+            KEY_PREFIX_EVEN, KEY_TERMINAL_PREFIX_EVEN, RLP_SHORT, RLP_UNIT_NUM_BYTES,
+            RLP_UNIT_NUM_VALUE_BYTES,
         },
         rlp_gadgets::{get_ext_odd_nibble, get_terminal_odd_nibble},
     },
-    table::LookupTable,
+    table::{LookupTable, MPTProofType},
     util::{
         word::{self, Word},
         Challenges, Expr,
     },
 };
-use eth_types::{Field, Word as U256};
-use gadgets::util::{not, or, pow, Scalar};
+use eth_types::{Field, KeyPath, Word as U256};
+use gadgets::util::{not, or, pow, sum, Scalar};
 use halo2_proofs::{
     circuit::Value,
     plonk::{ConstraintSystem, Error, Expression, VirtualCells},
 };
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 use super::{
-    rlp_gadgets::{
-        get_ext_odd_nibble_value, RLPItemGadget, RLPItemWitness, RLPListGadget, RLPListWitness,
-    },
+    rlp_gadgets::{RLPItemGadget, RLPItemWitness, RLPListGadget, RLPListWitness},
     FixedTableTag, MPTCircuitParams, RlpItemType,
 };
 
@@ -54,6 +53,8 @@ pub enum MptTableType {
     Byte,
     Keccak,
     Mult,
+    KeyMult,
+    Bytecode,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -106,9 +107,36 @@ impl CellType for MptCellType {
     }
 }
 
+impl MptCellType {
+    /// Name of the columns of this type, see `MPTConfig::column_names`
+    pub(crate) fn column_name(&self) -> String {
+        match self {
+            MptCellType::StoragePhase1 => "phase1".to_string(),
+            MptCellType::StoragePhase2 => "phase2".to_string(),
+            MptCellType::StoragePhase3 => "phase3".to_string(),
+            MptCellType::StoragePermutation => "permutation".to_string(),
+            MptCellType::Lookup(table) => match table {
+                MptTableType::Fixed => "fixed".to_string(),
+                MptTableType::Byte => "byte".to_string(),
+                MptTableType::Keccak => "keccak".to_string(),
+                MptTableType::Mult => "mult".to_string(),
+                MptTableType::KeyMult => "key_mult".to_string(),
+                MptTableType::Bytecode => "bytecode".to_string(),
+            },
+            MptCellType::Dynamic(id) => format!("dynamic_{}", id),
+            MptCellType::MemParentS => "memory_parent_s".to_string(),
+            MptCellType::MemParentC => "memory_parent_c".to_string(),
+            MptCellType::MemKeyS => "memory_key_s".to_string(),
+            MptCellType::MemKeyC => "memory_key_c".to_string(),
+            MptCellType::MemMain => "memory_main".to_string(),
+        }
+    }
+}
+
 pub const FIXED: MptCellType = MptCellType::Lookup(MptTableType::Fixed);
 pub const KECCAK: MptCellType = MptCellType::Lookup(MptTableType::Keccak);
 pub const MULT: MptCellType = MptCellType::Lookup(MptTableType::Mult);
+pub const KEY_MULT: MptCellType = MptCellType::Lookup(MptTableType::KeyMult);
 
 /// Indexable object
 pub trait Indexable {
@@ -125,6 +153,8 @@ impl Indexable for bool {
 #[derive(Clone, Debug, Default)]
 pub(crate) struct LeafKeyGadget<F> {
     has_no_nibbles: IsEqualGadget<F>,
+    // The key item is at most KEY_LEN + 1 bytes long (compact prefix + 64 nibbles)
+    is_below_max_len: LtGadget<F, 1>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -140,7 +170,15 @@ impl<F: Field> LeafKeyGadget<F> {
                 rlp_key.bytes_be()[0].expr(),
                 KEY_TERMINAL_PREFIX_EVEN.expr(),
             );
-            LeafKeyGadget { has_no_nibbles }
+            // Don't rely on the RLP decoding for the max length, a longer key would
+            // overflow the nibble counter of the key
+            let is_below_max_len =
+                LtGadget::construct(&mut cb.base, rlp_key.len(), (KEY_LEN + 2).expr());
+            require!(is_below_max_len.expr() => true);
+            LeafKeyGadget {
+                has_no_nibbles,
+                is_below_max_len,
+            }
         })
     }
 
@@ -177,14 +215,20 @@ impl<F: Field> LeafKeyGadget<F> {
         &self,
         region: &mut CachedRegion<'_, '_, F>,
         offset: usize,
-        bytes: &[u8],
+        key_item: &RLPItemWitness,
     ) -> Result<LeafKeyWitness, Error> {
         let has_no_nibble = self.has_no_nibbles.assign(
             region,
             offset,
-            F::from(bytes[0] as u64),
+            F::from(key_item.bytes[0] as u64),
             F::from(KEY_TERMINAL_PREFIX_EVEN as u64),
         )?;
+        self.is_below_max_len.assign(
+            region,
+            offset,
+            key_item.len().scalar(),
+            (KEY_LEN + 2).scalar(),
+        )?;
         Ok(LeafKeyWitness {
             has_no_nibble: has_no_nibble != 0.scalar(),
         })
@@ -199,23 +243,10 @@ impl LeafKeyWitness {
         key_mult: F,
         r: F,
     ) -> (F, F) {
-        if rlp_key.len() <= 1 {
-            return (key_rlc, key_mult);
-        }
-
-        let start = 0;
-        let len = rlp_key.len();
-        let even_num_of_nibbles = rlp_key.bytes[start + 1] == 32;
-
-        let mut key_rlc = key_rlc;
-        let mut key_mult = key_mult;
-        if !even_num_of_nibbles {
-            // If odd number of nibbles, we have nibble+48 in s_advices[0].
-            key_rlc += F::from((rlp_key.bytes[start + 1] - 48) as u64) * key_mult;
-            key_mult *= r;
-        }
-        (key_rlc, key_mult)
-            .rlc_chain_value(rlp_key.bytes[start + 2..start + 2 + len - 1].to_vec(), r)
+        let nibbles =
+            KeyPath::compact_nibbles(&rlp_key.bytes[rlp_key.num_rlp_bytes()..rlp_key.num_bytes()]);
+        // The leaf ends the path, an odd number of nibbles starts at an odd position
+        KeyPath::rlc_chain(nibbles.len() % 2, &nibbles, (key_rlc, key_mult), r)
     }
 }
 
@@ -272,51 +303,6 @@ pub(crate) fn ext_key_rlc_expr<F: Field>(
     })
 }
 
-pub(crate) fn ext_key_rlc_calc_value<F: Field>(
-    key_value: RLPItemWitness,
-    key_mult_prev: F,
-    is_key_part_odd: bool,
-    is_key_odd: bool,
-    data: [Vec<u8>; 2],
-    r: F,
-) -> (F, F) {
-    let (is_short, is_long) = (key_value.is_short(), key_value.is_long());
-    let mult_first_odd = if is_key_odd { 1.scalar() } else { 16.scalar() };
-    let calc_rlc = |bytes: &[F], key_mult_first_even: F| {
-        ext_key_rlc_value(
-            bytes,
-            key_mult_prev,
-            is_key_part_odd,
-            mult_first_odd,
-            key_mult_first_even,
-            r,
-        )
-    };
-    matchw! {
-        is_long && !is_key_odd => {
-            // Here we need to multiply nibbles over bytes with different r's so we need to rlc over separate nibbles.
-            // Note that there can be at max 31 key bytes because 32 same bytes would mean
-            // the two keys being the same - update operation, not splitting into extension node.
-            let mut key_bytes = vec![data[0][1].scalar()];
-            key_bytes.append(&mut data[0][1..].iter().skip(1).zip(data[1][2..].iter()).map(|(byte, nibble_hi)| {
-                let nibble_lo = (byte - nibble_hi) >> 4;
-                // Check that `nibble_hi` is correct.
-                assert!(*byte == nibble_lo * 16 + nibble_hi);
-                // Collect bytes
-                (F::from(*nibble_hi as u64) * F::from(16_u64) * r) + F::from(nibble_lo as u64)
-            }).collect::<Vec<_>>());
-            calc_rlc(&key_bytes, 1.scalar())
-        },
-        is_long && is_key_odd => {
-            let additional_mult = if is_key_part_odd { r } else { 1.scalar() };
-            calc_rlc(&data[0][1..].iter().map(|byte| byte.scalar()).collect::<Vec<_>>(), additional_mult)
-        },
-        is_short => {
-            calc_rlc(&data[0][..1].iter().map(|byte| byte.scalar()).collect::<Vec<_>>(), 1.scalar())
-        },
-    }
-}
-
 #[derive(Clone, Debug, Default)]
 pub(crate) struct ListKeyGadget<F> {
     pub(crate) rlp_list_bytes: [Cell<F>; 3],
@@ -356,7 +342,7 @@ impl<F: Field> ListKeyGadget<F> {
             cell.assign(region, offset, byte.scalar())?;
         }
         let rlp_list = self.rlp_list.assign(region, offset, list_bytes)?;
-        let key = self.key.assign(region, offset, &key_item.bytes)?;
+        let key = self.key.assign(region, offset, key_item)?;
 
         Ok(ListKeyWitness {
             rlp_list,
@@ -689,6 +675,8 @@ pub(crate) struct MainData<F> {
     pub(crate) address: Cell<F>,
     pub(crate) new_root: WordCell<F>,
     pub(crate) old_root: WordCell<F>,
+    /// Stored by the end node, padding can only follow it
+    pub(crate) is_end: Cell<F>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -698,6 +686,7 @@ pub(crate) struct MainDataWitness<F> {
     pub(crate) address: F,
     pub(crate) new_root: word::Word<F>,
     pub(crate) old_root: word::Word<F>,
+    pub(crate) is_end: bool,
 }
 
 impl<F: Field> MainData<F> {
@@ -712,6 +701,7 @@ impl<F: Field> MainData<F> {
             address: cb.query_cell(),
             new_root: cb.query_word_unchecked(),
             old_root: cb.query_word_unchecked(),
+            is_end: cb.query_cell(),
         };
         circuit!([meta, cb.base], {
             memory.load(
@@ -725,6 +715,7 @@ impl<F: Field> MainData<F> {
                     main_data.new_root.hi().expr(),
                     main_data.old_root.lo().expr(),
                     main_data.old_root.hi().expr(),
+                    main_data.is_end.expr(),
                 ],
             );
         });
@@ -734,7 +725,7 @@ impl<F: Field> MainData<F> {
     pub(crate) fn store<MB: MemoryBank<F, MptCellType>>(
         cb: &mut MPTConstraintBuilder<F>,
         memory: &mut MB,
-        values: [Expression<F>; 7],
+        values: [Expression<F>; 8],
     ) {
         memory.store(&mut cb.base, &values);
     }
@@ -749,6 +740,7 @@ impl<F: Field> MainData<F> {
         address: F,
         new_root: word::Word<F>,
         old_root: word::Word<F>,
+        is_end: bool,
     ) -> Result<(), Error> {
         let values = [
             proof_type.scalar(),
@@ -758,6 +750,7 @@ impl<F: Field> MainData<F> {
             new_root.hi(),
             old_root.lo(),
             old_root.hi(),
+            is_end.scalar(),
         ];
         memory.witness_store(offset, &values);
 
@@ -780,6 +773,7 @@ impl<F: Field> MainData<F> {
         self.new_root.hi().assign(region, offset, values[4])?;
         self.old_root.lo().assign(region, offset, values[5])?;
         self.old_root.hi().assign(region, offset, values[6])?;
+        self.is_end.assign(region, offset, values[7])?;
 
         Ok(MainDataWitness {
             proof_type: values[0].get_lower_32() as usize,
@@ -787,6 +781,7 @@ impl<F: Field> MainData<F> {
             address: values[2],
             new_root: word::Word::new([values[3], values[4]]),
             old_root: word::Word::new([values[5], values[6]]),
+            is_end: values[7] == 1.scalar(),
         })
     }
 }
@@ -855,27 +850,6 @@ pub(crate) fn ext_key_rlc<F: Field>(
     })
 }
 
-pub(crate) fn ext_key_rlc_value<F: Field>(
-    bytes: &[F],
-    key_mult_prev: F,
-    is_odd: bool,
-    rlc_mult_first_odd: F,
-    key_mult_first_odd: F,
-    r: F,
-) -> (F, F) {
-    // Add the odd nibble first if we have one.
-    let (rlc, mult) = if is_odd {
-        (
-            get_ext_odd_nibble_value(bytes[0]) * key_mult_prev * rlc_mult_first_odd,
-            key_mult_first_odd,
-        )
-    } else {
-        assert!(bytes[0] == KEY_PREFIX_EVEN.scalar());
-        (0.scalar(), 1.scalar())
-    };
-    (rlc, key_mult_prev * mult).rlc_chain_value(bytes[1..].iter().collect::<Vec<&F>>(), r)
-}
-
 // Returns the number of nibbles stored in a key value
 pub(crate) mod num_nibbles {
     use crate::{_cb, circuit, circuit_tools::constraint_builder::ConstraintBuilder};
@@ -894,13 +868,6 @@ pub(crate) mod num_nibbles {
             }}
         })
     }
-    pub(crate) fn value(key_len: usize, is_key_odd: bool) -> usize {
-        if is_key_odd {
-            key_len * 2 - 1
-        } else {
-            (key_len - 1) * 2
-        }
-    }
 }
 
 pub(crate) fn parent_memory(is_s: bool) -> MptCellType {
@@ -924,6 +891,13 @@ pub(crate) fn main_memory() -> MptCellType {
 }
 
 /// MPTConstraintBuilder
+///
+/// Keys and node data are accumulated with different challenges: `key_r` for
+/// the key RLCs (nibbles of the path, hashed keys) and `keccak_r` for the RLCs
+/// of the RLP encoded data (node hashes, values, keccak inputs). A key RLC is
+/// only ever compared to another key RLC and a data RLC to another data RLC,
+/// so a key and a data item can only collide with negligible probability,
+/// even when their bytes are related.
 #[derive(Clone)]
 pub struct MPTConstraintBuilder<F> {
     pub base: ConstraintBuilder<F, MptCellType>,
@@ -944,7 +918,7 @@ impl<F: Field> MPTConstraintBuilder<F> {
                 cell_manager,
                 Some(challenges.clone().unwrap().lookup_input().expr()),
             ),
-            key_r: challenges.clone().unwrap().keccak_input().expr(),
+            key_r: challenges.clone().unwrap().key_input().expr(),
             keccak_r: challenges.clone().unwrap().keccak_input().expr(),
             challenges,
         }
@@ -1052,6 +1026,10 @@ impl<F: Field> MPTConstraintBuilder<F> {
         self.base.store_tuple(description, table_type, values)
     }
 
+    pub(crate) fn cached_expr(&mut self, name: &'static str, expr: Expression<F>) -> Expression<F> {
+        self.base.cached_expr(name, expr)
+    }
+
     pub(crate) fn table(&self, table_type: MptTableType) -> Vec<Expression<F>> {
         self.base.table(table_type)
     }
@@ -1118,6 +1096,186 @@ impl<F: Field> IsPlaceholderLeafGadget<F> {
     }
 }
 
+/// Decodes the proof type into one flag per `MPTProofType`. Exactly one flag
+/// is set and it is the flag of the proof type, so unknown proof types are
+/// rejected and the gadgets can branch on the flags.
+#[derive(Clone, Debug, Default)]
+pub struct ProofTypeGadget<F> {
+    pub(crate) flags: Vec<Cell<F>>,
+}
+
+impl<F: Field> ProofTypeGadget<F> {
+    pub(crate) fn construct(cb: &mut MPTConstraintBuilder<F>, proof_type: Expression<F>) -> Self {
+        let flags = MPTProofType::iter()
+            .map(|_| cb.query_bool())
+            .collect::<Vec<_>>();
+        circuit!([meta, cb.base], {
+            require!(sum::expr(&flags) => 1);
+            require!(proof_type => sum::expr(
+                MPTProofType::iter().zip(flags.iter()).map(|(variant, flag)| variant.expr() * flag.expr())
+            ));
+        });
+        Self { flags }
+    }
+
+    /// 1 when the proof type is `proof_type`, else 0
+    pub(crate) fn is(&self, proof_type: MPTProofType) -> Expression<F> {
+        let idx = MPTProofType::iter().position(|p| p == proof_type).unwrap();
+        self.flags[idx].expr()
+    }
+
+    pub(crate) fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        proof_type: usize,
+    ) -> Result<MPTProofType, Error> {
+        let mut decoded = None;
+        for (variant, flag) in MPTProofType::iter().zip(self.flags.iter()) {
+            let is_variant = variant as usize == proof_type;
+            flag.assign(region, offset, is_variant.scalar())?;
+            if is_variant {
+                decoded = Some(variant);
+            }
+        }
+        decoded.ok_or_else(|| {
+            log::error!("unknown MPT proof type {}", proof_type);
+            Error::Synthesis
+        })
+    }
+}
+
+/// Checks a nonce or a balance of an account decoded by `MainRLPGadget`, which
+/// already limits values to 32 bytes without leading zeros. The value has at
+/// most `max_len` bytes and a value below 0x80 is stored in the RLP byte
+/// itself, so an account has a single encoding.
+#[derive(Clone, Debug, Default)]
+pub struct AccountValueGadget<F> {
+    below_limit: LtGadget<F, 1>,
+    is_single_byte_string: IsEqualGadget<F>,
+    is_below_rlp_short: LtGadget<F, 1>,
+    max_len: usize,
+}
+
+impl<F: Field> AccountValueGadget<F> {
+    pub(crate) fn construct(
+        cb: &mut MPTConstraintBuilder<F>,
+        item: &RLPItemView<F>,
+        max_len: usize,
+    ) -> Self {
+        circuit!([meta, cb.base], {
+            let below_limit = LtGadget::construct(&mut cb.base, item.len(), (max_len + 1).expr());
+            require!(below_limit.expr() => true);
+
+            // The bytes are little endian, so for a single byte string the
+            // byte after the RLP byte is the value
+            let bytes = item.bytes_le();
+            let is_single_byte_string =
+                IsEqualGadget::construct(&mut cb.base, bytes[0].expr(), (RLP_SHORT + 1).expr());
+            let is_below_rlp_short =
+                LtGadget::construct(&mut cb.base, bytes[1].expr(), RLP_SHORT.expr());
+            ifx! {is_single_byte_string => {
+                require!(is_below_rlp_short.expr() => false);
+            }}
+
+            Self {
+                below_limit,
+                is_single_byte_string,
+                is_below_rlp_short,
+                max_len,
+            }
+        })
+    }
+
+    pub(crate) fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        item: &RLPItemWitness,
+    ) -> Result<(), Error> {
+        self.below_limit.assign(
+            region,
+            offset,
+            item.len().scalar(),
+            (self.max_len + 1).scalar(),
+        )?;
+        self.is_single_byte_string.assign(
+            region,
+            offset,
+            item.bytes[0].scalar(),
+            (RLP_SHORT + 1).scalar(),
+        )?;
+        // Least significant byte, stored first by `MainRLPGadget`
+        let lsb = if item.len() == 0 {
+            0
+        } else {
+            item.bytes[item.len()]
+        };
+        self.is_below_rlp_short
+            .assign(region, offset, lsb.scalar(), RLP_SHORT.scalar())?;
+        Ok(())
+    }
+}
+
+/// Constrains `delta` to `new_value - old_value` modulo 2^256, like the
+/// subtraction of two EVM words. The limbs of both values are at most 128
+/// bits, the limbs of the delta are range checked here.
+#[derive(Clone, Debug, Default)]
+pub struct WordDeltaGadget<F> {
+    bytes: [[Cell<F>; N_BYTES_HALF_WORD]; 2],
+    carry: [Cell<F>; 2],
+}
+
+impl<F: Field> WordDeltaGadget<F> {
+    pub(crate) fn construct(
+        cb: &mut MPTConstraintBuilder<F>,
+        delta: Word<Expression<F>>,
+        new_value: Word<Expression<F>>,
+        old_value: Word<Expression<F>>,
+    ) -> Self {
+        circuit!([meta, cb.base], {
+            let bytes = [cb.query_bytes(), cb.query_bytes()];
+            let carry = [cb.query_bool(), cb.query_bool()];
+            require!(delta.lo() => from_bytes::expr(&bytes[0]));
+            require!(delta.hi() => from_bytes::expr(&bytes[1]));
+
+            // old_value + delta = new_value + carry * 2^256
+            let to_hi = Expression::<F>::Constant(pow::value::<F>(256.scalar(), 16));
+            require!(old_value.lo() + delta.lo() => new_value.lo() + carry[0].expr() * to_hi.expr());
+            require!(old_value.hi() + delta.hi() + carry[0].expr() => new_value.hi() + carry[1].expr() * to_hi.expr());
+
+            Self { bytes, carry }
+        })
+    }
+
+    /// Assigns the delta of the two values and returns it
+    pub(crate) fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        new_value: Word<F>,
+        old_value: Word<F>,
+    ) -> Result<Word<F>, Error> {
+        let (lo, borrow_lo) = new_value
+            .lo()
+            .get_lower_128()
+            .overflowing_sub(old_value.lo().get_lower_128());
+        let (hi, borrow_hi) = new_value
+            .hi()
+            .get_lower_128()
+            .overflowing_sub(old_value.hi().get_lower_128());
+        let (hi, borrow_carry) = hi.overflowing_sub(borrow_lo as u128);
+        for (cells, limb) in self.bytes.iter().zip([lo, hi]) {
+            for (cell, byte) in cells.iter().zip(limb.to_le_bytes()) {
+                cell.assign(region, offset, byte.scalar())?;
+            }
+        }
+        self.carry[0].assign(region, offset, borrow_lo.scalar())?;
+        self.carry[1].assign(region, offset, (borrow_hi || borrow_carry).scalar())?;
+        Ok(Word::new([F::from_u128(lo), F::from_u128(hi)]))
+    }
+}
+
 /// Handles drifted leaves
 #[derive(Clone, Debug, Default)]
 pub struct DriftedGadget<F> {
@@ -1387,6 +1545,7 @@ impl<F: Field> MainRLPGadget<F> {
 
             // Check the multiplier values
             // `num_bytes - 1` because the RLP byte is handled separately
+            assert!(RLP_UNIT_NUM_BYTES <= params.mult_table_len());
             require!((config.rlp.num_bytes() - 1.expr(), config.mult_diff.expr()) =>> @MULT);
             require!(config.mult_inv.expr() * pow::expr(cb.keccak_r.expr(), RLP_UNIT_NUM_BYTES - 1) => config.mult_diff.expr());
 
@@ -1637,6 +1796,18 @@ pub struct RLPItemView<F> {
 }
 
 impl<F: Field> RLPItemView<F> {
+    /// View of a key item that is not decoded by the RLP decoding unit, so
+    /// that the checks of the key gadgets can be tested on their own
+    #[cfg(test)]
+    pub(crate) fn undecoded_key(bytes: Vec<Expression<F>>, len: Expression<F>) -> Self {
+        RLPItemView {
+            is_big_endian: true,
+            bytes,
+            len: Some(len),
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn num_bytes(&self) -> Expression<F> {
         self.num_bytes.clone().unwrap()
     }
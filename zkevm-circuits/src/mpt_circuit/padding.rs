@@ -0,0 +1,56 @@
+use crate::{
+    circuit_tools::cached_region::CachedRegion,
+    mpt_circuit::{
+        helpers::{main_memory, parent_memory, MPTConstraintBuilder, MainData, ParentData},
+        MPTContext, MptMemory,
+    },
+};
+use eth_types::Field;
+use gadgets::util::Expr;
+use halo2_proofs::plonk::Error;
+
+/// Padding after the end node of the last proof. Whether the previous node is
+/// the end node or padding is read from the memory, the state left behind by
+/// the end node is only found after the end node and padding.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PaddingConfig<F> {
+    main_data: MainData<F>,
+    parent_data: ParentData<F>,
+}
+
+impl<F: Field> PaddingConfig<F> {
+    pub fn configure(cb: &mut MPTConstraintBuilder<F>, ctx: &mut MPTContext<F>) -> Self {
+        // Leaves store their own main data and branches a parent below the
+        // root, so only the end node and padding leave this state
+        let main_data = MainData::load(cb, &mut ctx.memory[main_memory()], 0.expr());
+        let parent_data = ParentData::load(cb, &mut ctx.memory[parent_memory(true)], 0.expr());
+        cb.base.require_equal(
+            "Padding follows the end node",
+            main_data.is_end.expr(),
+            1.expr(),
+        );
+        cb.base.require_equal(
+            "Padding follows the end node",
+            parent_data.is_root.expr(),
+            1.expr(),
+        );
+        // No stores, the memory is unchanged
+        PaddingConfig {
+            main_data,
+            parent_data,
+        }
+    }
+
+    pub fn assign(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        memory: &mut MptMemory<F>,
+        offset: usize,
+    ) -> Result<(), Error> {
+        self.main_data
+            .witness_load(region, offset, &mut memory[main_memory()], 0)?;
+        self.parent_data
+            .witness_load(region, offset, &mut memory[parent_memory(true)], 0)?;
+        Ok(())
+    }
+}
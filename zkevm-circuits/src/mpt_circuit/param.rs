@@ -1,3 +1,4 @@
+// Shape of the trie, the other sizes are derived from these
 pub const ARITY: usize = 16;
 pub const HASH_WIDTH: usize = 32;
 pub const ADDRESS_WIDTH: usize = 20;
@@ -14,9 +15,17 @@ pub const RLP_LONG: u8 = 183; //  0xb7
 pub const RLP_LIST_SHORT: u8 = 192; //  0xc0
 pub const RLP_LIST_LONG: u8 = 247; //  0xf7
 pub const RLP_NIL: u8 = 128; //  0x80
-pub const RLP_HASH_VALUE: u8 = 128 + 32; //  0x80
+pub const RLP_HASH_VALUE: u8 = RLP_SHORT + HASH_WIDTH as u8; //  0xa0
 
-// Key parameters
+// Longest string with the length encoded in the RLP byte
+pub const RLP_SHORT_MAX_LEN: usize = (RLP_LONG - RLP_SHORT) as usize;
+
+// Longest nonce and balance of an account. EIP-2681 limits the nonce to 64
+// bits, the balance can use all 256 bits of the value.
+pub const NONCE_MAX_LEN: usize = 8;
+pub const BALANCE_MAX_LEN: usize = 32;
+
+// Key parameters, the length of a key once hashed
 pub const KEY_LEN: usize = 32;
 pub const KEY_LEN_IN_NIBBLES: usize = KEY_LEN * 2;
 
@@ -26,6 +35,11 @@ pub const EMPTY_TRIE_HASH: [u8; 32] = [
     108, 173, 192, 1, 98, 47, 181, 227, 99, 180, 33,
 ];
 
-// Number of bytes required to decode an RLP item
-pub const RLP_UNIT_NUM_BYTES: usize = 34;
+// Number of bytes required to decode an RLP item: the longest string is a hash
+// or a key in compact encoding (one more byte than the key), plus the RLP byte
+pub const RLP_UNIT_NUM_BYTES: usize = (if HASH_WIDTH > KEY_LEN {
+    HASH_WIDTH
+} else {
+    KEY_LEN
+}) + 2;
 pub const RLP_UNIT_NUM_VALUE_BYTES: usize = RLP_UNIT_NUM_BYTES - 1;
@@ -4,17 +4,18 @@ use crate::{
         cached_region::CachedRegion,
         cell_manager::Cell,
         constraint_builder::{ConstraintBuilder, RLCable, RLCableValue},
+        gadgets::LtGadget,
     },
-    evm_circuit::util::from_bytes,
+    evm_circuit::param::N_BYTES_WORD,
     matchw,
     mpt_circuit::{
         helpers::FIXED,
-        param::{RLP_LIST_LONG, RLP_LIST_SHORT, RLP_SHORT},
+        param::{RLP_LIST_LONG, RLP_LIST_SHORT, RLP_SHORT, RLP_SHORT_MAX_LEN},
         FixedTableTag,
     },
     util::{word, Expr},
 };
-use eth_types::Field;
+use eth_types::{Field, ToWord, Word as U256};
 use gadgets::util::{not, pow, Scalar};
 use halo2_proofs::plonk::{Error, Expression, VirtualCells};
 
@@ -236,7 +237,7 @@ impl RLPListWitness {
 
     /// Number of RLP bytes
     pub(crate) fn num_rlp_bytes(&self) -> usize {
-        matchw! {
+        matchw! {"RLPListWitness::num_rlp_bytes";
             self.is_short() => 1,
             self.is_long() => 2,
             self.is_very_long() => 3,
@@ -245,7 +246,7 @@ impl RLPListWitness {
 
     /// Returns the total length of the list (including RLP bytes)
     pub(crate) fn num_bytes(&self) -> usize {
-        matchw! {
+        matchw! {"RLPListWitness::num_bytes";
             self.is_short => get_num_bytes_list_short::value(self.bytes[0]),
             self.is_long => 2 + (self.bytes[1] as usize),
             self.is_very_long => 3 + (self.bytes[1] as usize) * 256 + (self.bytes[2] as usize),
@@ -254,7 +255,7 @@ impl RLPListWitness {
 
     /// Returns the length of the list (excluding RLP bytes)
     pub(crate) fn len(&self) -> usize {
-        matchw! {
+        matchw! {"RLPListWitness::len";
             self.is_short() => get_len_list_short::value(self.bytes[0]),
             self.is_long() => self.bytes[1] as usize,
         }
@@ -274,7 +275,7 @@ impl RLPListWitness {
 
     /// Returns the rlc of the RLP bytes
     pub(crate) fn rlc_rlp_only<F: Field>(&self, r: F) -> (F, F) {
-        matchw! {
+        matchw! {"RLPListWitness::rlc_rlp_only";
             self.is_short() => (self.bytes[..1].rlc_value(r), r),
             self.is_long() => (self.bytes[..2].rlc_value(r), r*r),
             self.is_very_long() => (self.bytes[..3].rlc_value(r), r*r*r),
@@ -322,6 +323,10 @@ pub(crate) struct RLPValueGadget<F> {
     pub(crate) is_very_long: Cell<F>,
     pub(crate) is_list: Cell<F>,
     pub(crate) bytes: Vec<Expression<F>>,
+    // Values longer than `RLP_SHORT_MAX_LEN` need `max_len > RLP_SHORT_MAX_LEN`
+    max_len: usize,
+    // RLP_SHORT_MAX_LEN < len <= max_len for very long values
+    len_range: Option<[LtGadget<F, 1>; 2]>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -335,6 +340,18 @@ pub(crate) struct RLPValueWitness {
 
 impl<F: Field> RLPValueGadget<F> {
     pub(crate) fn construct(cb: &mut MPTConstraintBuilder<F>, bytes: &[Expression<F>]) -> Self {
+        Self::construct_with_max_len(cb, bytes, RLP_SHORT_MAX_LEN)
+    }
+
+    /// Decodes strings of up to `max_len` bytes. Strings longer than
+    /// `RLP_SHORT_MAX_LEN` store their length in the single byte following
+    /// the RLP byte, so `max_len` can be at most 255 and `bytes` needs to
+    /// contain the 2 RLP bytes followed by `max_len` value bytes.
+    pub(crate) fn construct_with_max_len(
+        cb: &mut MPTConstraintBuilder<F>,
+        bytes: &[Expression<F>],
+        max_len: usize,
+    ) -> Self {
         circuit!([meta, cb], {
             let is_short = cb.query_cell();
             let is_long = cb.query_cell();
@@ -352,12 +369,36 @@ impl<F: Field> RLPValueGadget<F> {
                 ) =>> @FIXED
             );
 
+            let len_range = if max_len > RLP_SHORT_MAX_LEN {
+                assert!(
+                    max_len <= u8::MAX as usize,
+                    "max_len needs to fit in a byte"
+                );
+                assert!(bytes.len() >= 2 + max_len, "not enough bytes for max_len");
+                let len_range = [
+                    LtGadget::construct(&mut cb.base, RLP_SHORT_MAX_LEN.expr(), bytes[1].expr()),
+                    LtGadget::construct(&mut cb.base, bytes[1].expr(), (max_len + 1).expr()),
+                ];
+                ifx! {is_very_long => {
+                    // Only a single length byte is supported
+                    require!(bytes[0] => RLP_LONG + 1);
+                    // The length is canonical and fits in the bytes
+                    require!(len_range[0].expr() => true);
+                    require!(len_range[1].expr() => true);
+                }}
+                Some(len_range)
+            } else {
+                None
+            };
+
             RLPValueGadget {
                 is_short,
                 is_long,
                 is_very_long,
                 is_list,
                 bytes: bytes.to_vec(),
+                max_len,
+                len_range,
             }
         })
     }
@@ -376,6 +417,12 @@ impl<F: Field> RLPValueGadget<F> {
             .assign(region, offset, is_very_long.scalar())?;
         self.is_list.assign(region, offset, is_list.scalar())?;
 
+        if let Some(len_range) = &self.len_range {
+            let len = bytes.get(1).copied().unwrap_or(0);
+            len_range[0].assign(region, offset, RLP_SHORT_MAX_LEN.scalar(), len.scalar())?;
+            len_range[1].assign(region, offset, len.scalar(), (self.max_len + 1).scalar())?;
+        }
+
         Ok(RLPValueWitness {
             is_short,
             is_long,
@@ -432,8 +479,12 @@ impl<F: Field> RLPValueGadget<F> {
                 self.is_short() => 1.expr(),
                 self.is_long() => get_num_bytes_short::expr(self.bytes[0].expr()),
                 self.is_very_long() => {
-                    unreachablex!();
-                    0.expr()
+                    if self.supports_very_long() {
+                        2.expr() + self.bytes[1].expr()
+                    } else {
+                        unreachablex!();
+                        0.expr()
+                    }
                 },
             )}
         })
@@ -446,8 +497,12 @@ impl<F: Field> RLPValueGadget<F> {
                 self.is_short() => 1.expr(),
                 self.is_long() => get_len_short::expr(self.bytes[0].expr()),
                 self.is_very_long() => {
-                    unreachablex!();
-                    0.expr()
+                    if self.supports_very_long() {
+                        self.bytes[1].expr()
+                    } else {
+                        unreachablex!();
+                        0.expr()
+                    }
                 },
             )}
         })
@@ -467,8 +522,12 @@ impl<F: Field> RLPValueGadget<F> {
                 self.is_short() => (self.bytes[..1].rlc_rev(r), pow::expr(r.expr(), 1)),
                 self.is_long() => (self.bytes[..1].rlc_rev(r), pow::expr(r.expr(), 1)),
                 self.is_very_long() => {
-                    unreachablex!();
-                    (0.expr(), 0.expr())
+                    if self.supports_very_long() {
+                        (self.bytes[..2].rlc_rev(r), pow::expr(r.expr(), 2))
+                    } else {
+                        unreachablex!();
+                        (0.expr(), 0.expr())
+                    }
                 },
             )}
         })
@@ -484,12 +543,21 @@ impl<F: Field> RLPValueGadget<F> {
                     self.bytes[1..].rlc(r)
                 },
                 self.is_very_long() => {
-                    unreachablex!();
-                    0.expr()
+                    if self.supports_very_long() {
+                        self.bytes[2..].rlc(r)
+                    } else {
+                        unreachablex!();
+                        0.expr()
+                    }
                 },
             )}
         })
     }
+
+    // Strings with the length stored in a separate byte
+    fn supports_very_long(&self) -> bool {
+        self.max_len > RLP_SHORT_MAX_LEN
+    }
 }
 
 impl RLPValueWitness {
@@ -515,7 +583,7 @@ impl RLPValueWitness {
 
     /// Number of RLP bytes
     pub(crate) fn num_rlp_bytes(&self) -> usize {
-        matchw! {
+        matchw! {"RLPValueWitness::num_rlp_bytes";
             self.is_short() => 0,
             self.is_long() => 1,
             self.is_very_long() => 2,
@@ -524,19 +592,19 @@ impl RLPValueWitness {
 
     /// Number of bytes in total (including RLP bytes)
     pub(crate) fn num_bytes(&self) -> usize {
-        matchw! {
+        matchw! {"RLPValueWitness::num_bytes";
             self.is_short() => 1,
             self.is_long() => get_num_bytes_short::value(self.bytes[0]),
-            self.is_very_long() => unreachable!(),
+            self.is_very_long() => 2 + self.bytes[1] as usize,
         }
     }
 
     /// Length of the value (excluding RLP bytes)
     pub(crate) fn len(&self) -> usize {
-        matchw! {
+        matchw! {"RLPValueWitness::len";
             self.is_short() => 1,
             self.is_long() => get_len_short::value(self.bytes[0]),
-            self.is_very_long() => unreachable!(),
+            self.is_very_long() => self.bytes[1] as usize,
         }
     }
 
@@ -545,7 +613,7 @@ impl RLPValueWitness {
     }
 
     pub(crate) fn rlc_value<F: Field>(&self, r: F) -> F {
-        matchw! {
+        matchw! {"RLPValueWitness::rlc_value";
             self.is_short() => {
                 self.bytes[0].scalar()
             },
@@ -553,7 +621,7 @@ impl RLPValueWitness {
                 self.bytes[1..].rlc_value(r)
             },
             self.is_very_long() => {
-                unreachable!();
+                self.bytes[2..].rlc_value(r)
             },
         }
     }
@@ -569,11 +637,6 @@ pub(crate) fn get_ext_odd_nibble<F: Field>(byte: Expression<F>) -> Expression<F>
     byte - KEY_PREFIX_ODD.expr()
 }
 
-pub(crate) fn get_ext_odd_nibble_value<F: Field>(byte: F) -> F {
-    // The odd nible is stored in the same byte as the prefix
-    byte - F::from(KEY_PREFIX_ODD as u64)
-}
-
 // A single RLP byte
 pub(crate) mod get_len_short {
     use crate::mpt_circuit::param::RLP_SHORT;
@@ -662,6 +725,7 @@ impl<F: Field> RLPItemGadget<F> {
     ) -> Result<RLPItemWitness, Error> {
         let value_witness = self.value.assign(region, offset, bytes)?;
         let list_witness = self.list.assign(region, offset, bytes)?;
+        #[cfg(not(feature = "fast_assign"))]
         assert!(!(value_witness.is_string() && list_witness.is_list()));
         Ok(RLPItemWitness {
             value: value_witness,
@@ -771,7 +835,7 @@ impl<F: Field> RLPItemGadget<F> {
 impl RLPItemWitness {
     /// Number of bytes in total (including RLP bytes)
     pub(crate) fn num_bytes(&self) -> usize {
-        matchw! {
+        matchw! {"RLPItemWitness::num_bytes";
             self.value.is_string() => self.value.num_bytes(),
             self.list.is_list() => self.list.num_bytes(),
         }
@@ -779,7 +843,7 @@ impl RLPItemWitness {
 
     /// Number of bytes in total (including RLP bytes)
     pub(crate) fn num_rlp_bytes(&self) -> usize {
-        matchw! {
+        matchw! {"RLPItemWitness::num_rlp_bytes";
             self.value.is_string() => self.value.num_rlp_bytes(),
             self.list.is_list() => self.list.num_rlp_bytes(),
         }
@@ -787,7 +851,7 @@ impl RLPItemWitness {
 
     /// Length of the value (excluding RLP bytes)
     pub(crate) fn len(&self) -> usize {
-        matchw! {
+        matchw! {"RLPItemWitness::len";
             self.value.is_string() => self.value.len(),
             self.list.is_list() => self.list.len(),
         }
@@ -802,21 +866,21 @@ impl RLPItemWitness {
     }
 
     pub(crate) fn is_short(&self) -> bool {
-        matchw! {
+        matchw! {"RLPItemWitness::is_short";
             self.value.is_string() => self.value.is_short(),
             self.list.is_list() => self.list.is_short(),
         }
     }
 
     pub(crate) fn is_long(&self) -> bool {
-        matchw! {
+        matchw! {"RLPItemWitness::is_long";
             self.value.is_string() => self.value.is_long(),
             self.list.is_list() => self.list.is_long(),
         }
     }
 
     pub(crate) fn rlc_content<F: Field>(&self, r: F) -> F {
-        matchw! {
+        matchw! {"RLPItemWitness::rlc_content";
             self.value.is_string() => self.value.rlc_value(r),
             self.list.is_list() => self.list.rlc_rlp(r),
         }
@@ -827,69 +891,20 @@ impl RLPItemWitness {
         let mult_inv = pow::value(r, 34 - self.num_bytes())
             .invert()
             .unwrap_or(F::ZERO);
-        matchw! {
+        matchw! {"RLPItemWitness::rlc_rlp_rev";
             self.value.is_string() => self.value.rlc_rlp_rev(r) * mult_inv,
             self.list.is_list() => self.list.rlc_rlp_rev(r) * mult_inv,
         }
     }
 
     pub(crate) fn word<F: Field>(&self) -> word::Word<F> {
-        // word::Word::from(Word::from_big_endian(&self.bytes[1..33]))
-        let (lo, hi) = if self.is_string() {
-            if self.is_short() {
-                let lo: F = self.bytes[0].scalar();
-                let hi: F = 0.scalar();
-                (lo, hi)
-            } else {
-                let mut bytes = self.bytes[1..].to_vec();
-                let mut len = self.len();
-                while len < 33 {
-                    bytes.insert(0, 0);
-                    len += 1;
-                }
-                let lo = from_bytes::value(
-                    bytes[17..33]
-                        .iter()
-                        .cloned()
-                        .rev()
-                        .collect::<Vec<u8>>()
-                        .as_slice(),
-                );
-                let hi = from_bytes::value(
-                    bytes[1..17]
-                        .iter()
-                        .cloned()
-                        .rev()
-                        .collect::<Vec<u8>>()
-                        .as_slice(),
-                );
-                (lo, hi)
-            }
-        } else {
-            let mut bytes = self.bytes[1..].to_vec();
-            let mut len = self.len();
-            while len < 33 {
-                bytes.insert(0, 0);
-                len += 1;
-            }
-            let lo = from_bytes::value(
-                bytes[17..33]
-                    .iter()
-                    .cloned()
-                    .rev()
-                    .collect::<Vec<u8>>()
-                    .as_slice(),
-            );
-            let hi = from_bytes::value(
-                bytes[1..17]
-                    .iter()
-                    .cloned()
-                    .rev()
-                    .collect::<Vec<u8>>()
-                    .as_slice(),
-            );
-            (lo, hi)
-        };
-        word::Word::new([lo, hi])
+        if self.is_string() && self.is_short() {
+            return word::Word::new([self.bytes[0].scalar(), 0.scalar()]);
+        }
+        // The bytes after the first RLP byte, only the last 32 of them when
+        // they don't fit in a word
+        let value = &self.bytes[1..1 + self.len().min(N_BYTES_WORD + 1)];
+        let value = &value[value.len().saturating_sub(N_BYTES_WORD)..];
+        word::Word::new(U256::from_big_endian(value).to_lo_hi())
     }
 }
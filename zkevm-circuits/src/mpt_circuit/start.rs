@@ -13,6 +13,7 @@ use crate::{
         },
         MPTConfig, MPTContext, MptMemory, RlpItemType,
     },
+    table::MPTProofType,
     util::word::Word,
 };
 use eth_types::Field;
@@ -22,6 +23,8 @@ use halo2_proofs::plonk::{Error, VirtualCells};
 #[derive(Clone, Debug, Default)]
 pub(crate) struct StartConfig<F> {
     proof_type: Cell<F>,
+    /// Set on the end node, a start node without a proof
+    is_end: Cell<F>,
 }
 
 impl<F: Field> StartConfig<F> {
@@ -39,6 +42,11 @@ impl<F: Field> StartConfig<F> {
             ];
 
             config.proof_type = cb.query_cell();
+            config.is_end = cb.query_cell();
+            require!(config.is_end => bool);
+            ifx! {config.is_end => {
+                require!(config.proof_type => MPTProofType::Disabled.expr());
+            }}
 
             let mut root = vec![Word::new([0.expr(), 0.expr()]); 2];
             for is_s in [true, false] {
@@ -56,6 +64,7 @@ impl<F: Field> StartConfig<F> {
                     root[true.idx()].hi().expr(),
                     root[false.idx()].lo().expr(),
                     root[false.idx()].hi().expr(),
+                    config.is_end.expr(),
                 ],
             );
 
@@ -95,6 +104,8 @@ impl<F: Field> StartConfig<F> {
 
         self.proof_type
             .assign(region, offset, start.proof_type.scalar())?;
+        let is_end = start.proof_type == MPTProofType::Disabled;
+        self.is_end.assign(region, offset, is_end.scalar())?;
 
         let mut root = vec![Word::new([0.scalar(), 0.scalar()]); 2];
         for is_s in [true, false] {
@@ -110,6 +121,7 @@ impl<F: Field> StartConfig<F> {
             0.scalar(),
             root[true.idx()],
             root[false.idx()],
+            is_end,
         )?;
 
         for is_s in [true, false] {
@@ -1,5 +1,5 @@
 use eth_types::Field;
-use gadgets::util::Scalar;
+use gadgets::util::{not, Expr, Scalar};
 use halo2_proofs::{
     circuit::Value,
     plonk::{Error, Expression, VirtualCells},
@@ -12,16 +12,17 @@ use crate::{
         cached_region::CachedRegion,
         cell_manager::Cell,
         constraint_builder::{RLCChainableRev, RLCable},
-        gadgets::{IsEqualGadget, LtGadget},
+        gadgets::LtGadget,
     },
+    ifw, matchw,
     mpt_circuit::{
         helpers::{
             key_memory, main_memory, num_nibbles, parent_memory, DriftedGadget,
             IsPlaceholderLeafGadget, KeyData, MPTConstraintBuilder, MainData, ParentData,
-            ParentDataWitness, KECCAK,
+            ParentDataWitness, ProofTypeGadget, KECCAK,
         },
         param::KEY_LEN_IN_NIBBLES,
-        MPTConfig, MPTContext, MptMemory, RlpItemType,
+        KeyDerivation, MPTConfig, MPTContext, MptMemory, RlpItemType,
     },
     table::MPTProofType,
     util::word::{self, Word},
@@ -47,8 +48,7 @@ pub(crate) struct StorageLeafConfig<F> {
     is_placeholder_leaf: [IsPlaceholderLeafGadget<F>; 2],
     drifted: DriftedGadget<F>,
     wrong: WrongGadget<F>,
-    is_storage_mod_proof: IsEqualGadget<F>,
-    is_non_existing_storage_proof: IsEqualGadget<F>,
+    proof_type: ProofTypeGadget<F>,
 }
 
 impl<F: Field> StorageLeafConfig<F> {
@@ -128,7 +128,7 @@ impl<F: Field> StorageLeafConfig<F> {
                 // `value` here containing a single stored value) the stored
                 // value is either stored directly in the RLP encoded string if short, or stored
                 // wrapped inside another RLP encoded string if long.
-                let rlp_value = config.rlp_value[is_s.idx()].rlc_value(&cb.key_r);
+                let rlp_value = config.rlp_value[is_s.idx()].rlc_value(&cb.keccak_r);
                 let rlp_value_rlc_mult =
                     config.rlp_value[is_s.idx()].rlc_rlp_only_rev(&cb.keccak_r);
                 let value_lo;
@@ -147,14 +147,21 @@ impl<F: Field> StorageLeafConfig<F> {
                     (value.lo(), value.hi(), value_rlp_rlc, rlp_value_rlc_mult.1 * value_item[is_s.idx()].mult())
                 }};
                 value_word[is_s.idx()] = Word::<Expression<F>>::new([value_lo, value_hi]);
+                // Also used for the drifted leaf, so only expand them once
+                value_rlp_rlc[is_s.idx()] =
+                    cb.cached_expr("value_rlp_rlc", value_rlp_rlc[is_s.idx()].expr());
+                value_rlp_rlc_mult[is_s.idx()] =
+                    cb.cached_expr("value_rlp_rlc_mult", value_rlp_rlc_mult[is_s.idx()].expr());
 
                 let leaf_rlc = rlp_key.rlc2(&cb.keccak_r).rlc_chain_rev((
                     value_rlp_rlc[is_s.idx()].expr(),
                     value_rlp_rlc_mult[is_s.idx()].expr(),
                 ));
+                let leaf_rlc = cb.cached_expr("leaf_rlc", leaf_rlc);
+                let leaf_num_bytes = cb.cached_expr("leaf_num_bytes", rlp_key.rlp_list.num_bytes());
 
                 // Key
-                key_rlc[is_s.idx()] = key_data.rlc.expr()
+                let leaf_key_rlc = key_data.rlc.expr()
                     + rlp_key.key.expr(
                         cb,
                         rlp_key.key_value.clone(),
@@ -162,6 +169,7 @@ impl<F: Field> StorageLeafConfig<F> {
                         key_data.is_odd.expr(),
                         &cb.key_r.expr(),
                     );
+                key_rlc[is_s.idx()] = cb.cached_expr("key_rlc", leaf_key_rlc);
                 // Total number of nibbles needs to be KEY_LEN_IN_NIBBLES
                 let num_nibbles =
                     num_nibbles::expr(rlp_key.key_value.len(), key_data.is_odd.expr());
@@ -179,11 +187,11 @@ impl<F: Field> StorageLeafConfig<F> {
                 // Check if the account is in its parent.
                 // Check is skipped for placeholder leaves which are dummy leaves
                 ifx! {not!(is_placeholder_leaf) => {
-                    config.is_not_hashed[is_s.idx()] = LtGadget::construct(&mut cb.base, rlp_key.rlp_list.num_bytes(), 32.expr());
+                    config.is_not_hashed[is_s.idx()] = LtGadget::construct(&mut cb.base, leaf_num_bytes.expr(), 32.expr());
                     ifx!{or::expr(&[parent_data.is_root.expr(), not!(config.is_not_hashed[is_s.idx()])]) => {
                         // Hashed branch hash in parent branch
                         let hash = parent_data.hash.expr();
-                        require!((1.expr(), leaf_rlc.expr(), rlp_key.rlp_list.num_bytes(), hash.lo(), hash.hi()) =>> @KECCAK);
+                        require!((1.expr(), leaf_rlc.expr(), leaf_num_bytes.expr(), hash.lo(), hash.hi()) =>> @KECCAK);
                     } elsex {
                         // Non-hashed branch hash in parent branch
                         require!(leaf_rlc => parent_data.rlc.expr());
@@ -205,16 +213,10 @@ impl<F: Field> StorageLeafConfig<F> {
             }
 
             // Proof types
-            config.is_storage_mod_proof = IsEqualGadget::construct(
-                &mut cb.base,
-                config.main_data.proof_type.expr(),
-                MPTProofType::StorageChanged.expr(),
-            );
-            config.is_non_existing_storage_proof = IsEqualGadget::construct(
-                &mut cb.base,
-                config.main_data.proof_type.expr(),
-                MPTProofType::StorageDoesNotExist.expr(),
-            );
+            config.proof_type = ProofTypeGadget::construct(cb, config.main_data.proof_type.expr());
+            let is_storage_mod_proof = config.proof_type.is(MPTProofType::StorageChanged);
+            let is_non_existing_storage_proof =
+                config.proof_type.is(MPTProofType::StorageDoesNotExist);
 
             // Drifted leaf handling
             config.drifted = DriftedGadget::construct(
@@ -237,7 +239,7 @@ impl<F: Field> StorageLeafConfig<F> {
             config.wrong = WrongGadget::construct(
                 cb,
                 key_item.hash_rlc(),
-                config.is_non_existing_storage_proof.expr(),
+                is_non_existing_storage_proof.expr(),
                 &config.rlp_key[true.idx()].key_value,
                 &key_rlc[true.idx()],
                 &expected_item,
@@ -259,22 +261,23 @@ impl<F: Field> StorageLeafConfig<F> {
                     0.expr(),
                     0.expr(),
                     0.expr(),
+                    false.expr(),
                 ],
             );
 
             // For non-existing proofs the tree needs to remain the same
-            ifx! {config.is_non_existing_storage_proof => {
+            ifx! {is_non_existing_storage_proof => {
                 require!(config.main_data.new_root => config.main_data.old_root);
                 require!(key_rlc[true.idx()] => key_rlc[false.idx()]);
             }}
 
             // Put the data in the lookup table
             let proof_type = matchx! {(
-                config.is_storage_mod_proof => MPTProofType::StorageChanged.expr(),
-                config.is_non_existing_storage_proof => MPTProofType::StorageDoesNotExist.expr(),
+                is_storage_mod_proof => MPTProofType::StorageChanged.expr(),
+                is_non_existing_storage_proof => MPTProofType::StorageDoesNotExist.expr(),
                 _ => MPTProofType::Disabled.expr(),
             )};
-            ifx! {not!(config.is_non_existing_storage_proof) => {
+            ifx! {not!(is_non_existing_storage_proof) => {
                 let key_rlc = ifx!{not!(config.parent_data[true.idx()].is_placeholder) => {
                     key_rlc[true.idx()].expr()
                 } elsex {
@@ -285,7 +288,15 @@ impl<F: Field> StorageLeafConfig<F> {
                 // Check if the key is correct for the given address
                 if ctx.params.is_preimage_check_enabled() {
                     let key = key_item.word();
-                    require!((1.expr(), address_item.bytes_le()[1..33].rlc(&cb.keccak_r), 32.expr(), key.lo(), key.hi()) =>> @KECCAK);
+                    match ctx.params.key_derivation {
+                        KeyDerivation::Keccak => {
+                            require!((1.expr(), address_item.bytes_le()[1..33].rlc(&cb.keccak_r), 32.expr(), key.lo(), key.hi()) =>> @KECCAK);
+                        }
+                        KeyDerivation::Identity => {
+                            require!(key.lo() => address_item.word().lo());
+                            require!(key.hi() => address_item.word().hi());
+                        }
+                    }
                 }
             }};
 
@@ -300,6 +311,7 @@ impl<F: Field> StorageLeafConfig<F> {
                     config.main_data.old_root.expr(),
                     value_word[false.idx()].clone(),
                     value_word[true.idx()].clone(),
+                    config.main_data.address.expr(),
                 );
             } elsex {
                 ctx.mpt_table.constrain(
@@ -312,8 +324,14 @@ impl<F: Field> StorageLeafConfig<F> {
                     config.main_data.old_root.expr(),
                     Word::<Expression<F>>::new([0.expr(), 0.expr()]),
                     value_word[true.idx()].clone(),
+                    config.main_data.address.expr(),
                 );
             }};
+
+            // Storage updates leave the balance unchanged
+            if let Some(balance_delta) = ctx.mpt_table.balance_delta {
+                require!([a!(balance_delta.lo()), a!(balance_delta.hi())] => [0.expr(), 0.expr()]);
+            }
         });
 
         config
@@ -412,11 +430,11 @@ impl<F: Field> StorageLeafConfig<F> {
                 offset,
                 &storage.value_rlp_bytes[is_s.idx()],
             )?;
-            value_word[is_s.idx()] = if value_witness.is_short() {
-                Word::<F>::new([value_witness.rlc_value(region.key_r), 0.scalar()])
-            } else {
+            value_word[is_s.idx()] = ifw! {region, offset; self.rlp_value[is_s.idx()].is_short() => {
+                Word::<F>::new([value_witness.rlc_value(region.keccak_r), 0.scalar()])
+            } elsex {
                 value_item[is_s.idx()].word()
-            };
+            }};
 
             ParentData::witness_store(
                 region,
@@ -436,18 +454,11 @@ impl<F: Field> StorageLeafConfig<F> {
             )?;
         }
 
-        let is_storage_mod_proof = self.is_storage_mod_proof.assign(
-            region,
-            offset,
-            main_data.proof_type.scalar(),
-            MPTProofType::StorageChanged.scalar(),
-        )? == true.scalar();
-        let is_non_existing_proof = self.is_non_existing_storage_proof.assign(
-            region,
-            offset,
-            main_data.proof_type.scalar(),
-            MPTProofType::StorageDoesNotExist.scalar(),
-        )? == true.scalar();
+        let proof_type = self
+            .proof_type
+            .assign(region, offset, main_data.proof_type)?;
+        let is_storage_mod_proof = proof_type == MPTProofType::StorageChanged;
+        let is_non_existing_proof = proof_type == MPTProofType::StorageDoesNotExist;
 
         // Drifted leaf handling
         self.drifted.assign(
@@ -482,21 +493,20 @@ impl<F: Field> StorageLeafConfig<F> {
             F::ZERO,
             Word::new([F::ZERO, F::ZERO]),
             Word::new([F::ZERO, F::ZERO]),
+            false,
         )?;
 
         // Put the data in the lookup table
-        let proof_type = if is_storage_mod_proof {
-            MPTProofType::StorageChanged
-        } else if is_non_existing_proof {
-            MPTProofType::StorageDoesNotExist
-        } else {
-            MPTProofType::Disabled
+        let proof_type = matchw! {"StorageLeafConfig::proof_type";
+            is_storage_mod_proof => MPTProofType::StorageChanged,
+            is_non_existing_proof => MPTProofType::StorageDoesNotExist,
+            _ => MPTProofType::Disabled,
         };
-
-        let mut new_value = value_word[false.idx()];
-        if parent_data[false.idx()].is_placeholder {
-            new_value = word::Word::<F>::new([0.scalar(), 0.scalar()]);
-        }
+        let new_value = ifw! {region, offset; not::expr(self.parent_data[false.idx()].is_placeholder.expr()) => {
+            value_word[false.idx()]
+        } elsex {
+            word::Word::<F>::new([0.scalar(), 0.scalar()])
+        }};
         mpt_config.mpt_table.assign_cached(
             region,
             offset,
@@ -508,8 +518,14 @@ impl<F: Field> StorageLeafConfig<F> {
                 old_root: main_data.old_root.into_value(),
                 new_value: new_value.into_value(),
                 old_value: value_word[true.idx()].into_value(),
+                trie_id: Value::known(main_data.address),
             },
         )?;
+        mpt_config.mpt_table.assign_balance_delta(
+            region,
+            offset,
+            word::Word::<F>::new([0.scalar(), 0.scalar()]).into_value(),
+        )?;
 
         Ok(())
     }
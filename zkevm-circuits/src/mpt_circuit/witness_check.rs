@@ -0,0 +1,364 @@
+//! Checks of an MPT witness outside of the circuit.
+//!
+//! Replays part of the MPT circuit constraints directly on the witness nodes:
+//! the order of the nodes, the hash of every branch against the reference of
+//! its parent, and the key path leading to every leaf. This runs in
+//! milliseconds, so a witness generator gets feedback without running the
+//! `MockProver` on a large circuit. A witness passing these checks can still
+//! fail in the circuit.
+
+use super::{
+    param::HASH_WIDTH,
+    witness_row::{AccountRowType, ExtensionBranchRowType, Node, StartRowType, StorageRowType},
+};
+use crate::table::MPTProofType;
+use eth_types::{keccak256, KeyPath, H256};
+use std::fmt;
+
+/// Inconsistency found in an MPT witness
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitnessError {
+    /// Index of the faulty node
+    pub node: usize,
+    /// What is wrong with the node
+    pub message: String,
+}
+
+impl fmt::Display for WitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "node {}: {}", self.node, self.message)
+    }
+}
+
+impl std::error::Error for WitnessError {}
+
+// State of the S or C trie while walking down a proof
+#[derive(Default)]
+struct Side {
+    // Reference of the parent to the next node, `None` when it is not checked
+    parent: Option<Vec<u8>>,
+    // Nibbles of the key taken from the root of the trie
+    path: Vec<u8>,
+    // The trie has a placeholder branch on the path, so its leaf has another key
+    has_placeholder: bool,
+}
+
+impl Side {
+    fn new(parent: &[u8]) -> Result<Self, String> {
+        Ok(Self {
+            parent: Some(rlp_item(parent)?.to_vec()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Checks the witness of the MPT circuit, as returned by `parse_proof`, and
+/// returns the first inconsistency found.
+pub fn check_witness(nodes: &[Node]) -> Result<(), WitnessError> {
+    let error = |node: usize| move |message: String| WitnessError { node, message };
+
+    let mut sides = [Side::default(), Side::default()];
+    let mut proof_type = MPTProofType::Disabled;
+    let mut has_account = false;
+    // The last proof ended with its end node
+    let mut ended = true;
+    let mut padded = false;
+    if nodes.first().map_or(true, |node| node.start.is_none()) {
+        return Err(error(0)(
+            "the witness does not begin with a start node".to_string(),
+        ));
+    }
+    for (idx, node) in nodes.iter().enumerate() {
+        let kinds = [
+            node.start.is_some(),
+            node.extension_branch.is_some(),
+            node.account.is_some(),
+            node.storage.is_some(),
+            node.padding,
+        ];
+        if kinds.iter().filter(|kind| **kind).count() != 1 {
+            return Err(error(idx)("not exactly one node type".to_string()));
+        }
+        if node.padding {
+            if !ended {
+                return Err(error(idx)("padding before the end node".to_string()));
+            }
+            padded = true;
+            continue;
+        }
+        if padded {
+            return Err(error(idx)("node after the padding".to_string()));
+        }
+        if ended && node.start.is_none() {
+            return Err(error(idx)(
+                "the proof does not begin with a start node".to_string(),
+            ));
+        }
+        let num_values = if node.start.is_some() {
+            StartRowType::Count as usize
+        } else if node.extension_branch.is_some() {
+            ExtensionBranchRowType::Count as usize
+        } else if node.account.is_some() {
+            AccountRowType::Count as usize
+        } else {
+            StorageRowType::Count as usize
+        };
+        if node.values.len() != num_values {
+            return Err(error(idx)(format!(
+                "{} values instead of {}",
+                node.values.len(),
+                num_values
+            )));
+        }
+
+        if let Some(start) = &node.start {
+            proof_type = start.proof_type;
+            has_account = false;
+            // Every proof ends with a disabled start node
+            ended = proof_type == MPTProofType::Disabled;
+            sides = [
+                Side::new(&node.values[StartRowType::RootS as usize]).map_err(error(idx))?,
+                Side::new(&node.values[StartRowType::RootC as usize]).map_err(error(idx))?,
+            ];
+        } else if let Some(extension_branch) = &node.extension_branch {
+            let branch = &extension_branch.branch;
+            let modified_index = branch.modified_index;
+            if modified_index >= 16 || branch.drifted_index >= 16 {
+                return Err(error(idx)("branch index out of range".to_string()));
+            }
+            let children = node.values[1..17]
+                .iter()
+                .map(|value| rlp_item(value))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(error(idx))?;
+            for (is_s, side) in [true, false].into_iter().zip(sides.iter_mut()) {
+                let side_idx = if is_s { 0 } else { 1 };
+                if extension_branch.is_placeholder[side_idx] {
+                    side.has_placeholder = true;
+                    continue;
+                }
+                // All the S children are in the witness, only the modified
+                // child of C is stored, in the first row
+                let mut data = branch.list_rlp_bytes[side_idx].to_vec();
+                for (child_idx, child) in children.iter().enumerate() {
+                    if !is_s && child_idx == modified_index {
+                        data.extend(rlp_item(&node.values[0]).map_err(error(idx))?);
+                    } else {
+                        data.extend(*child);
+                    }
+                }
+                // Empty value of the branch
+                data.push(0x80);
+                if rlp_item(&data).map_err(error(idx))?.len() != data.len() {
+                    return Err(error(idx)(format!(
+                        "{} branch length does not match its RLP",
+                        if is_s { "S" } else { "C" }
+                    )));
+                }
+                let parent = if extension_branch.is_extension {
+                    let row = if is_s {
+                        ExtensionBranchRowType::ValueS
+                    } else {
+                        ExtensionBranchRowType::ValueC
+                    };
+                    Some(
+                        rlp_item(&node.values[row as usize])
+                            .map_err(error(idx))?
+                            .to_vec(),
+                    )
+                } else {
+                    side.parent.clone()
+                };
+                if let Some(parent) = parent {
+                    check_reference(&parent, &data).map_err(|message| {
+                        error(idx)(format!(
+                            "{} branch {}",
+                            if is_s { "S" } else { "C" },
+                            message
+                        ))
+                    })?;
+                }
+            }
+            for (is_s, side) in [true, false].into_iter().zip(sides.iter_mut()) {
+                if extension_branch.is_extension {
+                    let key = rlp_item(&node.values[ExtensionBranchRowType::KeyS as usize])
+                        .map_err(error(idx))?;
+                    side.path.extend(key_nibbles(key));
+                }
+                side.path.push(modified_index as u8);
+                let side_idx = if is_s { 0 } else { 1 };
+                side.parent = if extension_branch.is_placeholder[side_idx] {
+                    None
+                } else if is_s {
+                    Some(children[modified_index].to_vec())
+                } else {
+                    Some(rlp_item(&node.values[0]).map_err(error(idx))?.to_vec())
+                };
+            }
+        } else if node.account.is_some() {
+            if has_account {
+                return Err(error(idx)("second account leaf in the proof".to_string()));
+            }
+            has_account = true;
+            let key_rows = [AccountRowType::KeyS as usize, AccountRowType::KeyC as usize];
+            if proof_type != MPTProofType::AccountDoesNotExist {
+                check_key(&sides, node, key_rows, AccountRowType::Key as usize)
+                    .map_err(error(idx))?;
+            }
+            // The storage trie starts below the account
+            sides = [
+                Side::new(&node.values[AccountRowType::StorageS as usize]).map_err(error(idx))?,
+                Side::new(&node.values[AccountRowType::StorageC as usize]).map_err(error(idx))?,
+            ];
+        } else {
+            if !has_account {
+                return Err(error(idx)(
+                    "storage leaf without an account leaf".to_string(),
+                ));
+            }
+            let key_rows = [StorageRowType::KeyS as usize, StorageRowType::KeyC as usize];
+            if proof_type != MPTProofType::StorageDoesNotExist {
+                check_key(&sides, node, key_rows, StorageRowType::Key as usize)
+                    .map_err(error(idx))?;
+            }
+            sides = [Side::default(), Side::default()];
+        }
+    }
+    if !ended {
+        return Err(error(nodes.len())(
+            "the witness does not end with the end node".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Checks that the key path of the trie followed by the key of the leaf is the
+// key of the leaf, on the sides without a placeholder branch
+fn check_key(
+    sides: &[Side; 2],
+    node: &Node,
+    key_rows: [usize; 2],
+    key_row: usize,
+) -> Result<(), String> {
+    // The key is added to the values by `parse_proof`, after an RLP prefix
+    let key = &node.values[key_row][1..];
+    if key.len() != HASH_WIDTH {
+        return Err("key is not 32 bytes long".to_string());
+    }
+    let key: Vec<u8> = KeyPath::new(H256::from_slice(key)).collect();
+    for ((side, row), name) in sides.iter().zip(key_rows).zip(["S", "C"]) {
+        if side.has_placeholder {
+            continue;
+        }
+        let mut path = side.path.clone();
+        path.extend(key_nibbles(rlp_item(&node.values[row])?));
+        if path != key {
+            return Err(format!("{} leaf is not at its key", name));
+        }
+    }
+    Ok(())
+}
+
+// Checks the reference of a parent to a child node: the hash of the child, or
+// the child itself when it is shorter than a hash
+fn check_reference(reference: &[u8], child: &[u8]) -> Result<(), String> {
+    let matches = if reference.len() == HASH_WIDTH + 1 && reference[0] == 0xa0 {
+        keccak256(child)[..] == reference[1..]
+    } else {
+        reference == child
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err("does not match the reference of its parent".to_string())
+    }
+}
+
+// Returns the RLP item at the start of `bytes`, its length is read from the
+// RLP prefix
+pub(super) fn rlp_item(bytes: &[u8]) -> Result<&[u8], String> {
+    let (header_len, payload_len) = rlp_header(bytes)?;
+    header_len
+        .checked_add(payload_len)
+        .and_then(|len| bytes.get(..len))
+        .ok_or_else(|| "RLP item longer than its value".to_string())
+}
+
+// Returns the length of the RLP prefix and of the payload
+fn rlp_header(bytes: &[u8]) -> Result<(usize, usize), String> {
+    let first = *bytes.first().ok_or_else(|| "empty RLP item".to_string())?;
+    let long_length = |offset: u8| {
+        let len_len = (first - offset) as usize;
+        let len_bytes = bytes
+            .get(1..1 + len_len)
+            .ok_or_else(|| "RLP length longer than its value".to_string())?;
+        let len = len_bytes
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        Ok::<_, String>((1 + len_len, len))
+    };
+    match first {
+        0x00..=0x7f => Ok((0, 1)),
+        0x80..=0xb7 => Ok((1, (first - 0x80) as usize)),
+        0xb8..=0xbf => long_length(0xb7),
+        0xc0..=0xf7 => Ok((1, (first - 0xc0) as usize)),
+        0xf8..=0xff => long_length(0xf7),
+    }
+}
+
+// Nibbles of the key in compact encoding of a leaf or extension
+fn key_nibbles(item: &[u8]) -> Vec<u8> {
+    let header_len = rlp_header(item).map_or(0, |(header_len, _)| header_len);
+    KeyPath::compact_nibbles(&item[header_len..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpt_circuit::load_proof;
+    use std::fs;
+
+    fn load_vector(name: &str) -> Vec<Node> {
+        load_proof(&format!("src/mpt_circuit/tests/{}.json", name))
+    }
+
+    #[test]
+    fn check_test_vectors() {
+        let files = fs::read_dir("src/mpt_circuit/tests").unwrap();
+        for file in files.filter_map(Result::ok) {
+            let path = file.path();
+            if path.extension().map_or(false, |e| e == "json") {
+                let nodes = load_proof(path.to_str().unwrap());
+                assert_eq!(check_witness(&nodes), Ok(()), "{:?}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn check_broken_witness() {
+        let nodes = load_vector("AddBranch");
+
+        // The first branch does not hash to the root
+        let mut broken = nodes.clone();
+        let mut root = broken[0].values[0].to_vec();
+        root[1] ^= 1;
+        broken[0].values[0] = root.into();
+        assert_eq!(check_witness(&broken).unwrap_err().node, 1);
+
+        // The storage leaf is not at the end of the path to its key
+        let mut broken = nodes.clone();
+        let leaf = broken
+            .iter()
+            .position(|node| node.storage.is_some())
+            .unwrap();
+        let mut key = broken[leaf].values[StorageRowType::Key as usize].to_vec();
+        key[HASH_WIDTH] ^= 1;
+        broken[leaf].values[StorageRowType::Key as usize] = key.into();
+        assert_eq!(check_witness(&broken).unwrap_err().node, leaf);
+
+        // Missing end node
+        let mut broken = nodes;
+        broken.pop();
+        assert!(check_witness(&broken).is_err());
+    }
+}
@@ -4,8 +4,13 @@ use crate::table::MPTProofType;
 
 use serde::{Deserialize, Serialize};
 
-use super::RlpItemType;
+use super::{param::RLP_UNIT_NUM_BYTES, RlpItemType};
 
+/// Rows of a storage leaf, one RLP item per row. Every row decodes its item
+/// with `MainRLPGadget` in the 80 cell columns of the RLP region, so packing
+/// two or three items per row would add 80 or 160 columns to all the rows of
+/// the circuit, while the 8 rows of a leaf come with 21 rows for every branch
+/// above it.
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum StorageRowType {
     KeyS,
@@ -71,6 +76,12 @@ pub(crate) enum StartRowType {
     Count,
 }
 
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum PaddingRowType {
+    Padding,
+    Count,
+}
+
 /// Serde for hex
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(transparent)]
@@ -138,8 +149,13 @@ pub struct ExtensionBranchNode {
 pub struct AccountNode {
     /// TODO Doc.
     pub address: Hex,
-    /// TODO Doc.
-    pub key: Hex,
+    /// Keccak hash of the address, the key in the trie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hashed_key: Option<Hex>,
+    /// Unhashed key, used in the trie instead of `hashed_key` when the
+    /// preimage check is disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<Hex>,
     /// TODO Doc.
     pub list_rlp_bytes: [Hex; 2],
     /// TODO Doc.
@@ -150,6 +166,11 @@ pub struct AccountNode {
     pub drifted_rlp_bytes: Hex,
     /// TODO Doc.
     pub wrong_rlp_bytes: Hex,
+    /// Size of the code of the account (S, C). Only used when the code
+    /// hashes are looked up in a bytecode table, filled from the code of the
+    /// block by the caller.
+    #[serde(default)]
+    pub code_size: [usize; 2],
 }
 
 /// MPT storage node
@@ -157,8 +178,13 @@ pub struct AccountNode {
 pub struct StorageNode {
     /// TODO Doc.
     pub address: Hex,
-    /// TODO Doc.
-    pub key: Hex,
+    /// Keccak hash of the slot, the key in the trie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hashed_key: Option<Hex>,
+    /// Unhashed key, used in the trie instead of `hashed_key` when the
+    /// preimage check is disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<Hex>,
     /// TODO Doc.
     pub list_rlp_bytes: [Hex; 2],
     /// TODO Doc.
@@ -169,6 +195,32 @@ pub struct StorageNode {
     pub wrong_rlp_bytes: Hex,
 }
 
+impl AccountNode {
+    /// The key in the trie, `None` if the witness does not contain it
+    pub fn key(&self, disable_preimage_check: bool) -> Option<&Hex> {
+        select_key(&self.hashed_key, &self.preimage, disable_preimage_check)
+    }
+}
+
+impl StorageNode {
+    /// The key in the trie, `None` if the witness does not contain it
+    pub fn key(&self, disable_preimage_check: bool) -> Option<&Hex> {
+        select_key(&self.hashed_key, &self.preimage, disable_preimage_check)
+    }
+}
+
+fn select_key<'a>(
+    hashed_key: &'a Option<Hex>,
+    preimage: &'a Option<Hex>,
+    disable_preimage_check: bool,
+) -> Option<&'a Hex> {
+    if disable_preimage_check {
+        preimage.as_ref()
+    } else {
+        hashed_key.as_ref()
+    }
+}
+
 /// MPT node
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Node {
@@ -180,12 +232,73 @@ pub struct Node {
     pub account: Option<AccountNode>,
     /// TODO Doc.
     pub storage: Option<StorageNode>,
-    /// MPT node values
+    /// Padding after the last proof, without values
+    #[serde(default)]
+    pub padding: bool,
+    /// MPT node values, one per row. Every value is RLP_UNIT_NUM_BYTES long, the
+    /// trailing zeros are left out when serialized (see `compact_values`).
+    #[serde(with = "compact_values")]
     pub values: Vec<Hex>,
     /// MPT keccak data
     pub keccak_data: Vec<Hex>,
 }
 
+impl Node {
+    /// Padding node, filling the circuit after the end node of the last proof
+    pub fn padding() -> Self {
+        Node {
+            padding: true,
+            ..Default::default()
+        }
+    }
+
+    /// Number of rows taken by the node in the circuit
+    pub fn num_rows(&self) -> usize {
+        if self.padding {
+            PaddingRowType::Count as usize
+        } else {
+            self.values.len()
+        }
+    }
+}
+
+/// Serde for the node values. Most values are zero padded to
+/// `RLP_UNIT_NUM_BYTES`, and the empty children of a branch (`0x80`) are only
+/// padding, so the trailing zeros are dropped when serializing and added back
+/// when deserializing. Full width values are still accepted.
+mod compact_values {
+    use super::{Hex, RLP_UNIT_NUM_BYTES};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(values: &[Hex], serializer: S) -> Result<S::Ok, S::Error> {
+        values
+            .iter()
+            .map(|value| {
+                let len = value
+                    .iter()
+                    .rposition(|byte| *byte != 0)
+                    .map_or(0, |pos| pos + 1);
+                Hex::from(value[..len].to_vec())
+            })
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Hex>, D::Error> {
+        let values = Vec::<Hex>::deserialize(deserializer)?;
+        Ok(values
+            .into_iter()
+            .map(|value| {
+                let mut bytes = value.bytes;
+                if bytes.len() < RLP_UNIT_NUM_BYTES {
+                    bytes.resize(RLP_UNIT_NUM_BYTES, 0);
+                }
+                Hex::from(bytes)
+            })
+            .collect())
+    }
+}
+
 /// RLP types start
 pub const NODE_RLP_TYPES_START: [RlpItemType; StartRowType::Count as usize] =
     [RlpItemType::Hash, RlpItemType::Hash];
@@ -1,6 +1,7 @@
 use crate::{
     root_circuit::{compile, Config, Gwc, PoseidonTranscript, RootCircuit},
     super_circuit::{test::block_1tx, SuperCircuit},
+    util::Randomness,
 };
 use bus_mapping::circuit_input_builder::FixedCParams;
 use halo2_proofs::{
@@ -21,7 +22,6 @@ use rand::rngs::OsRng;
 fn test_root_circuit() {
     let (params, protocol, proof, instance) = {
         // Preprocess
-        const TEST_MOCK_RANDOMNESS: u64 = 0x100;
         let circuits_params = FixedCParams {
             max_txs: 1,
             max_withdrawals: 5,
@@ -34,7 +34,7 @@ fn test_root_circuit() {
             max_keccak_rows: 0,
         };
         let (k, circuit, instance, _) =
-            SuperCircuit::<_>::build(block_1tx(), circuits_params, TEST_MOCK_RANDOMNESS.into())
+            SuperCircuit::<_>::build(block_1tx(), circuits_params, Randomness::challenge())
                 .unwrap();
         let params = ParamsKZG::<Bn256>::setup(k, OsRng);
         let pk = keygen_pk(&params, keygen_vk(&params, &circuit).unwrap(), &circuit).unwrap();
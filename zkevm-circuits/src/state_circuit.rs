@@ -525,8 +525,8 @@ fn queries<F: Field>(meta: &mut VirtualCells<'_, F>, c: &StateCircuitConfig<F>)
     let first_different_limb = c.lexicographic_ordering.first_different_limb;
     let final_bits_sum = meta.query_advice(first_different_limb.bits[3], Rotation::cur())
         + meta.query_advice(first_different_limb.bits[4], Rotation::cur());
-    let mpt_update_table_expressions = c.mpt_table.table_exprs(meta);
-    assert_eq!(mpt_update_table_expressions.len(), 12);
+    let mpt_update_table_expressions = c.mpt_table.lookup_exprs(meta);
+    assert_eq!(mpt_update_table_expressions.len(), 14);
 
     let meta_query_word =
         |metap: &mut VirtualCells<'_, F>, word_column: word::Word<Column<Advice>>, at: Rotation| {
@@ -556,28 +556,30 @@ fn queries<F: Field>(meta: &mut VirtualCells<'_, F>, c: &StateCircuitConfig<F>)
         },
         // TODO: clean this up
         mpt_update_table: MptUpdateTableQueries {
-            address: mpt_update_table_expressions[0].clone(),
+            q_lookup: mpt_update_table_expressions[0].clone(),
+            address: mpt_update_table_expressions[1].clone(),
             storage_key: word::Word::new([
-                mpt_update_table_expressions[1].clone(),
                 mpt_update_table_expressions[2].clone(),
+                mpt_update_table_expressions[3].clone(),
             ]),
-            proof_type: mpt_update_table_expressions[3].clone(),
+            proof_type: mpt_update_table_expressions[4].clone(),
             new_root: word::Word::new([
-                mpt_update_table_expressions[4].clone(),
                 mpt_update_table_expressions[5].clone(),
+                mpt_update_table_expressions[6].clone(),
             ]),
             old_root: word::Word::new([
-                mpt_update_table_expressions[6].clone(),
                 mpt_update_table_expressions[7].clone(),
+                mpt_update_table_expressions[8].clone(),
             ]),
             new_value: word::Word::new([
-                mpt_update_table_expressions[8].clone(),
                 mpt_update_table_expressions[9].clone(),
+                mpt_update_table_expressions[10].clone(),
             ]),
             old_value: word::Word::new([
-                mpt_update_table_expressions[10].clone(),
                 mpt_update_table_expressions[11].clone(),
+                mpt_update_table_expressions[12].clone(),
             ]),
+            trie_id: mpt_update_table_expressions[13].clone(),
         },
         lexicographic_ordering_selector: meta
             .query_fixed(c.lexicographic_ordering.selector, Rotation::cur()),
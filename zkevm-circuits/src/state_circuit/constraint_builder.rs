@@ -31,6 +31,8 @@ pub struct RwTableQueries<F: Field> {
 
 #[derive(Clone)]
 pub struct MptUpdateTableQueries<F: Field> {
+    /// One on the rows of the MPT circuit holding an update
+    pub q_lookup: Expression<F>,
     pub address: Expression<F>,
     pub storage_key: word::Word<Expression<F>>,
     pub proof_type: Expression<F>,
@@ -38,6 +40,7 @@ pub struct MptUpdateTableQueries<F: Field> {
     pub old_root: word::Word<Expression<F>>,
     pub new_value: word::Word<Expression<F>>,
     pub old_value: word::Word<Expression<F>>,
+    pub trie_id: Expression<F>,
 }
 
 #[derive(Clone)]
@@ -321,17 +324,10 @@ impl<F: Field> ConstraintBuilder<F> {
 
         // ref. spec 4.1. MPT lookup for last access to (address, storage_key)
         self.condition(q.last_access(), |cb| {
-            cb.add_lookup(
+            cb.add_mpt_update_lookup(
                 "mpt_update exists in mpt circuit for AccountStorage last access",
-                LookupBuilder::new()
-                    .add(&q.rw_table.address, &q.mpt_update_table.address)
-                    .add_word(&q.rw_table.storage_key, &q.mpt_update_table.storage_key)
-                    .add(&q.mpt_proof_type(), &q.mpt_update_table.proof_type)
-                    .add_word(&q.state_root(), &q.mpt_update_table.new_root)
-                    .add_word(&q.state_root_prev(), &q.mpt_update_table.old_root)
-                    .add_word(&q.value(), &q.mpt_update_table.new_value)
-                    .add_word(&q.initial_value(), &q.mpt_update_table.old_value)
-                    .build(),
+                q,
+                q.rw_table.address.clone(),
             );
         });
 
@@ -463,17 +459,10 @@ impl<F: Field> ConstraintBuilder<F> {
 
         // last_access degree = 1
         self.condition(q.last_access(), |cb| {
-            cb.add_lookup(
+            cb.add_mpt_update_lookup(
                 "mpt_update exists in mpt circuit for Account last access",
-                LookupBuilder::new()
-                    .add(&q.rw_table.address, &q.mpt_update_table.address)
-                    .add_word(&q.rw_table.storage_key, &q.mpt_update_table.storage_key)
-                    .add(&q.mpt_proof_type(), &q.mpt_update_table.proof_type)
-                    .add_word(&q.state_root(), &q.mpt_update_table.new_root)
-                    .add_word(&q.state_root_prev(), &q.mpt_update_table.old_root)
-                    .add_word(&q.value(), &q.mpt_update_table.new_value)
-                    .add_word(&q.initial_value(), &q.mpt_update_table.old_value)
-                    .build(),
+                q,
+                0.expr(),
             );
         });
 
@@ -573,6 +562,37 @@ impl<F: Field> ConstraintBuilder<F> {
         );
     }
 
+    /// Looks up the initial value and the last written value of a key as the
+    /// old and new value of an MPT update, which ties the accesses to the state
+    /// trie. Without the `state-mpt-lookup` feature only the consistency of the
+    /// accesses within the block is checked. `trie_id` is the account address
+    /// for storage and zero for accounts. Only the rows of the MPT table with
+    /// `q_lookup` set hold an update, see `MptTable::lookup_exprs`.
+    fn add_mpt_update_lookup(
+        &mut self,
+        name: &'static str,
+        q: &Queries<F>,
+        trie_id: Expression<F>,
+    ) {
+        if !cfg!(feature = "state-mpt-lookup") {
+            return;
+        }
+        self.add_lookup(
+            name,
+            LookupBuilder::new()
+                .add(&1.expr(), &q.mpt_update_table.q_lookup)
+                .add(&q.rw_table.address, &q.mpt_update_table.address)
+                .add_word(&q.rw_table.storage_key, &q.mpt_update_table.storage_key)
+                .add(&q.mpt_proof_type(), &q.mpt_update_table.proof_type)
+                .add_word(&q.state_root(), &q.mpt_update_table.new_root)
+                .add_word(&q.state_root_prev(), &q.mpt_update_table.old_root)
+                .add_word(&q.value(), &q.mpt_update_table.new_value)
+                .add_word(&q.initial_value(), &q.mpt_update_table.old_value)
+                .add(&trie_id, &q.mpt_update_table.trie_id)
+                .build(),
+        );
+    }
+
     fn add_lookup(&mut self, name: &'static str, lookup: Vec<(Expression<F>, Expression<F>)>) {
         let mut lookup = lookup;
         for (expression, _) in lookup.iter_mut() {
@@ -10,7 +10,7 @@ use bus_mapping::operation::{
 use eth_types::{
     address,
     evm_types::{MemoryAddress, StackAddress},
-    Address, ToAddress, Word, U256,
+    Address, ToAddress, ToScalar, Word, U256,
 };
 use gadgets::binary_number::AsBits;
 use halo2_proofs::{
@@ -392,6 +392,41 @@ fn address_limb_out_of_range() {
     assert_error_matches(result, "mpi limb fits into u16");
 }
 
+#[test]
+fn mpt_updates_trie_id() {
+    let account_address = address!("0x00000000000000000000000000000000000cafe1");
+    let rows = vec![
+        Rw::Account {
+            rw_counter: 1,
+            is_write: true,
+            account_address,
+            field_tag: AccountFieldTag::Balance,
+            value: U256::from(10),
+            value_prev: U256::zero(),
+        },
+        Rw::AccountStorage {
+            rw_counter: 2,
+            is_write: true,
+            account_address,
+            storage_key: U256::from(6),
+            value: U256::from(34),
+            value_prev: U256::zero(),
+            tx_id: 1,
+            committed_value: U256::zero(),
+        },
+    ];
+
+    // The account trie has id zero, a storage trie the address of its account
+    let updates = MptUpdates::mock_from(&rows).table_assignments::<Fr>();
+    assert_eq!(updates.len(), 2);
+    updates[0]
+        .trie_id
+        .assert_if_known(|trie_id| *trie_id == Fr::ZERO);
+    updates[1]
+        .trie_id
+        .assert_if_known(|trie_id| *trie_id == account_address.to_scalar().unwrap());
+}
+
 #[test]
 fn storage_key_mismatch() {
     let rows = vec![Rw::AccountStorage {
@@ -904,6 +939,40 @@ fn bad_initial_tx_log_value() {
     );
 }
 
+#[test]
+fn storage_value_not_in_mpt() {
+    let rows = vec![Rw::AccountStorage {
+        rw_counter: 1,
+        is_write: false,
+        account_address: Address::default(),
+        storage_key: U256::from(6),
+        value: U256::from(34),
+        value_prev: U256::from(34),
+        tx_id: 4,
+        committed_value: U256::from(34),
+    }];
+
+    // Consistent within the block, but the MPT update has initial value 34
+    let v = Fr::from(35);
+    let overrides = HashMap::from([
+        ((AdviceColumn::ValueLo, 0), v),
+        ((AdviceColumn::ValuePrevLo, 0), v),
+        ((AdviceColumn::InitialValueLo, 0), v),
+        ((AdviceColumn::NonEmptyWitness, 0), v.invert().unwrap()),
+    ]);
+
+    let result = verify_with_overrides(rows, overrides);
+
+    if cfg!(feature = "state-mpt-lookup") {
+        assert_error_matches(
+            result,
+            "mpt_update exists in mpt circuit for AccountStorage last access",
+        );
+    } else {
+        assert_eq!(result, Ok(()));
+    }
+}
+
 #[test]
 fn variadic_size_check() {
     let mut rows = vec![
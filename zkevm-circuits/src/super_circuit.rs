@@ -50,9 +50,12 @@
 //!   - [x] Tx Circuit
 //!   - [ ] MPT Circuit
 
+mod builder;
 #[cfg(test)]
 pub(crate) mod test;
 
+pub use builder::{CapacityReport, SuperCircuitBuilder};
+
 use crate::{
     bytecode_circuit::{BytecodeCircuit, BytecodeCircuitConfig, BytecodeCircuitConfigArgs},
     copy_circuit::{CopyCircuit, CopyCircuitConfig, CopyCircuitConfigArgs},
@@ -66,7 +69,7 @@ use crate::{
         UXTable, WdTable,
     },
     tx_circuit::{TxCircuit, TxCircuitConfig, TxCircuitConfigArgs},
-    util::{log2_ceil, Challenges, SubCircuit, SubCircuitConfig},
+    util::{log2_ceil, Challenges, Randomness, SubCircuit, SubCircuitConfig},
     witness::{block_convert, Block, MptUpdates},
 };
 use bus_mapping::{
@@ -79,11 +82,11 @@ use halo2_proofs::{
     plonk::{Circuit, ConstraintSystem, Error, Expression},
 };
 
-use std::array;
-
 /// Configuration of the Super Circuit
 #[derive(Clone)]
 pub struct SuperCircuitConfig<F: Field> {
+    // None with mock randomness
+    challenges: Option<Challenges>,
     block_table: BlockTable,
     mpt_table: MptTable,
     u8_table: UXTable<8>,
@@ -107,8 +110,8 @@ pub struct SuperCircuitConfigArgs<F: Field> {
     pub max_withdrawals: usize,
     /// Max calldata
     pub max_calldata: usize,
-    /// Mock randomness
-    pub mock_randomness: F,
+    /// Randomness of the random linear combinations
+    pub randomness: Randomness<F>,
 }
 
 impl<F: Field> SubCircuitConfig<F> for SuperCircuitConfig<F> {
@@ -121,7 +124,7 @@ impl<F: Field> SubCircuitConfig<F> for SuperCircuitConfig<F> {
             max_txs,
             max_withdrawals,
             max_calldata,
-            mock_randomness,
+            randomness,
         }: Self::ConfigArgs,
     ) -> Self {
         let tx_table = TxTable::construct(meta);
@@ -138,15 +141,24 @@ impl<F: Field> SubCircuitConfig<F> for SuperCircuitConfig<F> {
         let u10_table = UXTable::construct(meta);
         let u16_table = UXTable::construct(meta);
 
-        // Use a mock randomness instead of the randomness derived from the challange
-        // (either from mock or real prover) to help debugging assignments.
-        let power_of_randomness: [Expression<F>; 31] =
-            array::from_fn(|i| Expression::Constant(mock_randomness.pow([1 + i as u64, 0, 0, 0])));
-
-        let challenges = Challenges::mock(
-            power_of_randomness[0].clone(),
-            power_of_randomness[0].clone(),
-        );
+        let (challenges_config, challenges) = match randomness.mock_value() {
+            Some(mock_randomness) => {
+                // Keys get their own randomness, as with `construct_with_key_input`
+                let mock_key_randomness = Expression::Constant(mock_randomness + F::ONE);
+                let mock_randomness = Expression::Constant(mock_randomness);
+                let challenges = Challenges::mock(
+                    mock_randomness.clone(),
+                    mock_randomness,
+                    mock_key_randomness,
+                );
+                (None, challenges)
+            }
+            None => {
+                let challenges = Challenges::construct(meta);
+                let challenges_exprs = challenges.exprs(meta);
+                (Some(challenges), challenges_exprs)
+            }
+        };
 
         let keccak_circuit = KeccakCircuitConfig::new(
             meta,
@@ -182,6 +194,8 @@ impl<F: Field> SubCircuitConfig<F> for SuperCircuitConfig<F> {
             BytecodeCircuitConfigArgs {
                 bytecode_table: bytecode_table.clone(),
                 keccak_table: keccak_table.clone(),
+                // The bytecode table contains init code and the MPT table is mocked
+                mpt_table: None,
                 challenges: challenges.clone(),
             },
         );
@@ -225,6 +239,7 @@ impl<F: Field> SubCircuitConfig<F> for SuperCircuitConfig<F> {
         );
 
         Self {
+            challenges: challenges_config,
             block_table,
             mpt_table,
             u8_table,
@@ -263,8 +278,8 @@ pub struct SuperCircuit<F: Field> {
     pub keccak_circuit: KeccakCircuit<F>,
     /// Circuits Parameters
     pub circuits_params: FixedCParams,
-    /// Mock randomness
-    pub mock_randomness: F,
+    /// Randomness of the random linear combinations
+    pub randomness: Randomness<F>,
 }
 
 impl<F: Field> SuperCircuit<F> {
@@ -317,7 +332,7 @@ impl<F: Field> SubCircuit<F> for SuperCircuit<F> {
             exp_circuit,
             keccak_circuit,
             circuits_params: block.circuits_params,
-            mock_randomness: block.randomness,
+            randomness: Randomness::challenge(),
         }
     }
 
@@ -389,7 +404,7 @@ pub struct SuperCircuitParams<F: Field> {
     max_txs: usize,
     max_withdrawals: usize,
     max_calldata: usize,
-    mock_randomness: F,
+    randomness: Randomness<F>,
 }
 
 impl<F: Field> Circuit<F> for SuperCircuit<F> {
@@ -406,7 +421,7 @@ impl<F: Field> Circuit<F> for SuperCircuit<F> {
             max_txs: self.circuits_params.max_txs,
             max_withdrawals: self.circuits_params.max_withdrawals,
             max_calldata: self.circuits_params.max_calldata,
-            mock_randomness: self.mock_randomness,
+            randomness: self.randomness,
         }
     }
 
@@ -417,7 +432,7 @@ impl<F: Field> Circuit<F> for SuperCircuit<F> {
                 max_txs: params.max_txs,
                 max_withdrawals: params.max_withdrawals,
                 max_calldata: params.max_calldata,
-                mock_randomness: params.mock_randomness,
+                randomness: params.randomness,
             },
         )
     }
@@ -432,10 +447,17 @@ impl<F: Field> Circuit<F> for SuperCircuit<F> {
         mut layouter: impl Layouter<F>,
     ) -> Result<(), Error> {
         let block = self.evm_circuit.block.as_ref().unwrap();
-        let challenges = Challenges::mock(
-            Value::known(block.randomness),
-            Value::known(block.randomness),
-        );
+        let challenges = match self.randomness.mock_value() {
+            Some(mock_randomness) => Challenges::mock(
+                Value::known(mock_randomness),
+                Value::known(mock_randomness),
+                Value::known(mock_randomness + F::ONE),
+            ),
+            None => config
+                .challenges
+                .expect("challenges are configured without mock randomness")
+                .values(&mut layouter),
+        };
         let rws = &self.state_circuit.rows;
 
         config.block_table.load(&mut layouter, &block.context)?;
@@ -462,7 +484,7 @@ impl<F: Field> SuperCircuit<F> {
     pub fn build(
         geth_data: GethData,
         circuits_params: FixedCParams,
-        mock_randomness: F,
+        randomness: Randomness<F>,
     ) -> Result<(u32, Self, Vec<Vec<F>>, CircuitInputBuilder<FixedCParams>), bus_mapping::Error>
     {
         let block_data =
@@ -472,7 +494,7 @@ impl<F: Field> SuperCircuit<F> {
             .handle_block(&geth_data.eth_block, &geth_data.geth_traces)
             .expect("could not handle block tx");
 
-        let ret = Self::build_from_circuit_input_builder(&builder, mock_randomness)?;
+        let ret = Self::build_from_circuit_input_builder(&builder, randomness)?;
         Ok((ret.0, ret.1, ret.2, builder))
     }
 
@@ -483,16 +505,21 @@ impl<F: Field> SuperCircuit<F> {
     /// the Public Inputs needed.
     pub fn build_from_circuit_input_builder(
         builder: &CircuitInputBuilder<FixedCParams>,
-        mock_randomness: F,
+        randomness: Randomness<F>,
     ) -> Result<(u32, Self, Vec<Vec<F>>), bus_mapping::Error> {
         let mut block = block_convert(builder).unwrap();
-        block.randomness = mock_randomness;
+        if let Some(mock_randomness) = randomness.mock_value() {
+            block.randomness = mock_randomness;
+        }
 
         let (_, rows_needed) = Self::min_num_rows_block(&block);
         let k = log2_ceil(Self::unusable_rows() + rows_needed);
         log::debug!("super circuit uses k = {}", k);
 
-        let circuit = SuperCircuit::new_from_block(&block);
+        let circuit = SuperCircuit {
+            randomness,
+            ..SuperCircuit::new_from_block(&block)
+        };
 
         let instance = circuit.instance();
         Ok((k, circuit, instance))
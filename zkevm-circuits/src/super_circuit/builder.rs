@@ -0,0 +1,159 @@
+use super::SuperCircuit;
+use crate::{
+    bytecode_circuit::BytecodeCircuit,
+    copy_circuit::CopyCircuit,
+    evm_circuit::EvmCircuit,
+    exp_circuit::ExpCircuit,
+    instance::public_data_convert,
+    keccak_circuit::KeccakCircuit,
+    pi_circuit::PiCircuit,
+    state_circuit::StateCircuit,
+    tx_circuit::TxCircuit,
+    util::{log2_ceil, SubCircuit},
+    witness::Block,
+};
+use bus_mapping::circuit_input_builder::FixedCParams;
+use eth_types::Field;
+use std::fmt;
+
+/// Rows needed by each sub-circuit of a [`SuperCircuit`] for a degree `k`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityReport {
+    /// Circuit degree
+    pub k: u32,
+    /// Rows available to every sub-circuit
+    pub usable_rows: usize,
+    /// Sub-circuit names with the rows they need, padding included
+    pub circuits: Vec<(&'static str, usize)>,
+}
+
+impl CapacityReport {
+    /// True if every sub-circuit fits in `usable_rows`
+    pub fn fits(&self) -> bool {
+        self.circuits
+            .iter()
+            .all(|(_, rows)| *rows <= self.usable_rows)
+    }
+
+    /// Smallest degree that fits all the sub-circuits
+    pub fn min_k(&self) -> u32 {
+        let rows = self
+            .circuits
+            .iter()
+            .map(|(_, rows)| *rows)
+            .max()
+            .unwrap_or(0);
+        log2_ceil(SuperCircuit::<eth_types::Fr>::unusable_rows() + rows)
+    }
+}
+
+impl fmt::Display for CapacityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "k = {}: {} usable rows", self.k, self.usable_rows)?;
+        for (name, rows) in self.circuits.iter() {
+            let status = if *rows <= self.usable_rows {
+                "ok"
+            } else {
+                "insufficient"
+            };
+            write!(f, "\n  {:<10} {:>10} rows  {}", name, rows, status)?;
+        }
+        if !self.fits() {
+            write!(f, "\nthe witness needs k >= {}", self.min_k())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CapacityReport {}
+
+/// Builds a [`SuperCircuit`] with the capacity of every sub-circuit chosen
+/// from the witness, instead of the fixed [`FixedCParams`] of the block.
+///
+/// The capacities are the smallest that fit the witness, so the verifying key
+/// depends on the block. The EVM and Keccak circuits are sized dynamically.
+#[derive(Debug, Clone)]
+pub struct SuperCircuitBuilder<F: Field> {
+    block: Block<F>,
+    k: Option<u32>,
+}
+
+impl<F: Field> SuperCircuitBuilder<F> {
+    /// Sizes the sub-circuits for `block`, as returned by `block_convert`
+    pub fn from_block(mut block: Block<F>) -> Self {
+        let params = FixedCParams {
+            // One Start row is always needed
+            max_rws: StateCircuit::min_num_rows_block(&block).0,
+            // Padding txs and withdrawals keep the tables non-empty
+            max_txs: block.txs.len().max(1),
+            max_withdrawals: block.withdrawals().len().max(1),
+            max_calldata: block.txs.iter().map(|tx| tx.call_data.len()).sum(),
+            max_copy_rows: CopyCircuit::min_num_rows_block(&block).0,
+            max_exp_steps: block.exp_events.iter().map(|e| e.steps.len()).sum(),
+            // The last row of the bytecode circuit must be a padding row
+            max_bytecode: BytecodeCircuit::min_num_rows_block(&block).0 + 1,
+            max_evm_rows: 0,
+            max_keccak_rows: 0,
+        };
+        block.circuits_params = params;
+        // The public input bytes hashed by the PI circuit, last keccak input
+        // added by `block_convert`, depend on the capacities
+        let rpi_bytes = public_data_convert(&block).get_pi_bytes(
+            params.max_txs,
+            params.max_withdrawals,
+            params.max_calldata,
+        );
+        if let Some(last) = block.keccak_inputs.last_mut() {
+            *last = rpi_bytes;
+        }
+        Self { block, k: None }
+    }
+
+    /// Build the circuit for degree `k` instead of the smallest one
+    pub fn degree(mut self, k: u32) -> Self {
+        self.k = Some(k);
+        self
+    }
+
+    /// Capacities chosen for the block
+    pub fn circuits_params(&self) -> FixedCParams {
+        self.block.circuits_params
+    }
+
+    /// Rows needed by each sub-circuit for degree `k`
+    pub fn report(&self, k: u32) -> CapacityReport {
+        let block = &self.block;
+        let rows = |(rows, padded): (usize, usize)| rows.max(padded);
+        CapacityReport {
+            k,
+            usable_rows: (1 << k) - SuperCircuit::<F>::unusable_rows(),
+            circuits: vec![
+                ("evm", rows(EvmCircuit::min_num_rows_block(block))),
+                ("state", rows(StateCircuit::min_num_rows_block(block))),
+                ("tx", rows(TxCircuit::min_num_rows_block(block))),
+                ("pi", rows(PiCircuit::min_num_rows_block(block))),
+                ("bytecode", rows(BytecodeCircuit::min_num_rows_block(block))),
+                ("copy", rows(CopyCircuit::min_num_rows_block(block))),
+                ("exp", rows(ExpCircuit::min_num_rows_block(block))),
+                ("keccak", rows(KeccakCircuit::min_num_rows_block(block))),
+            ],
+        }
+    }
+
+    /// Returns the degree, the circuit and its public inputs. Fails with the
+    /// capacity report when a sub-circuit does not fit in the chosen degree.
+    pub fn build(self) -> Result<(u32, SuperCircuit<F>, Vec<Vec<F>>), CapacityReport> {
+        let k = match self.k {
+            Some(k) => k,
+            None => self.report(0).min_k(),
+        };
+        let report = self.report(k);
+        if !report.fits() {
+            return Err(report);
+        }
+        log::debug!("super circuit capacities: {}", report);
+        let circuit = SuperCircuit::new_from_block(&self.block);
+        let instance = circuit.instance();
+        Ok((k, circuit, instance))
+    }
+}
@@ -16,17 +16,29 @@ fn super_circuit_degree() {
         max_txs: 1,
         max_withdrawals: 5,
         max_calldata: 32,
-        mock_randomness: Fr::from(0x100),
+        randomness: Randomness::mock(Fr::from(0x100)),
     };
     SuperCircuit::configure_with_params(&mut cs, params);
     log::info!("super circuit degree: {}", cs.degree());
     log::info!("super circuit minimum_rows: {}", cs.minimum_rows());
     assert!(cs.degree() <= 10);
+    assert_eq!(cs.num_challenges(), 0);
+
+    let mut cs = ConstraintSystem::<Fr>::default();
+    let params = SuperCircuitParams {
+        max_txs: 1,
+        max_withdrawals: 5,
+        max_calldata: 32,
+        randomness: Randomness::challenge(),
+    };
+    SuperCircuit::configure_with_params(&mut cs, params);
+    assert!(cs.degree() <= 10);
+    assert_eq!(cs.num_challenges(), 2);
 }
 
-fn test_super_circuit(block: GethData, circuits_params: FixedCParams, mock_randomness: Fr) {
+fn test_super_circuit(block: GethData, circuits_params: FixedCParams, randomness: Randomness<Fr>) {
     let (k, circuit, instance, _) =
-        SuperCircuit::<Fr>::build(block, circuits_params, mock_randomness).unwrap();
+        SuperCircuit::<Fr>::build(block, circuits_params, randomness).unwrap();
     let prover = MockProver::run(k, &circuit, instance).unwrap();
     let res = prover.verify_par();
     if let Err(err) = res {
@@ -140,7 +152,11 @@ fn serial_test_super_circuit_1tx_1max_tx() {
         max_evm_rows: 0,
         max_keccak_rows: 0,
     };
-    test_super_circuit(block, circuits_params, Fr::from(TEST_MOCK_RANDOMNESS));
+    test_super_circuit(
+        block,
+        circuits_params,
+        Randomness::mock(Fr::from(TEST_MOCK_RANDOMNESS)),
+    );
 }
 #[ignore]
 #[test]
@@ -157,7 +173,11 @@ fn serial_test_super_circuit_1tx_2max_tx() {
         max_evm_rows: 0,
         max_keccak_rows: 0,
     };
-    test_super_circuit(block, circuits_params, Fr::from(TEST_MOCK_RANDOMNESS));
+    test_super_circuit(
+        block,
+        circuits_params,
+        Randomness::mock(Fr::from(TEST_MOCK_RANDOMNESS)),
+    );
 }
 #[ignore]
 #[test]
@@ -174,5 +194,58 @@ fn serial_test_super_circuit_2tx_2max_tx() {
         max_evm_rows: 0,
         max_keccak_rows: 0,
     };
-    test_super_circuit(block, circuits_params, Fr::from(TEST_MOCK_RANDOMNESS));
+    test_super_circuit(
+        block,
+        circuits_params,
+        Randomness::mock(Fr::from(TEST_MOCK_RANDOMNESS)),
+    );
+}
+
+fn block_witness(block: GethData, mock_randomness: Fr) -> Block<Fr> {
+    let circuits_params = FixedCParams {
+        max_txs: 4,
+        max_withdrawals: 5,
+        max_calldata: 256,
+        max_rws: 1024,
+        max_copy_rows: 1024,
+        max_exp_steps: 256,
+        max_bytecode: 1024,
+        max_evm_rows: 0,
+        max_keccak_rows: 0,
+    };
+    let block_data = BlockData::new_from_geth_data_with_params(block.clone(), circuits_params);
+    let mut builder = block_data.new_circuit_input_builder();
+    builder
+        .handle_block(&block.eth_block, &block.geth_traces)
+        .unwrap();
+    let mut block = block_convert(&builder).unwrap();
+    block.randomness = mock_randomness;
+    block
+}
+
+#[test]
+fn super_circuit_builder_capacities() {
+    let block = block_witness(block_1tx(), Fr::from(TEST_MOCK_RANDOMNESS));
+    let num_rws = block.rws.0.values().flatten().count();
+    let builder = SuperCircuitBuilder::from_block(block);
+
+    let params = builder.circuits_params();
+    assert_eq!(params.max_txs, 1);
+    assert_eq!(params.max_rws, num_rws + 1);
+
+    let k = builder.report(0).min_k();
+    assert!(builder.report(k).fits());
+    let report = builder.report(k - 1);
+    assert!(!report.fits());
+    assert!(report.to_string().contains("insufficient"));
+    assert_eq!(builder.clone().degree(k - 1).build().unwrap_err(), report);
+}
+
+#[ignore]
+#[test]
+fn serial_test_super_circuit_builder_1tx() {
+    let block = block_witness(block_1tx(), Fr::from(TEST_MOCK_RANDOMNESS));
+    let (k, circuit, instance) = SuperCircuitBuilder::from_block(block).build().unwrap();
+    let prover = MockProver::run(k, &circuit, instance).unwrap();
+    prover.assert_satisfied_par();
 }
@@ -49,7 +49,7 @@ pub(crate) mod ux_table;
 pub(crate) mod wd_table;
 
 pub(crate) use block_table::{BlockContextFieldTag, BlockTable};
-pub(crate) use bytecode_table::{BytecodeFieldTag, BytecodeTable};
+pub use bytecode_table::{BytecodeFieldTag, BytecodeTable};
 pub(crate) use copy_table::CopyTable;
 pub(crate) use exp_table::ExpTable;
 pub use keccak_table::KeccakTable;
@@ -8,7 +8,7 @@ use crate::{
 use serde::{Deserialize, Serialize};
 
 /// The types of proofs in the MPT table
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, EnumIter)]
 pub enum MPTProofType {
     /// Disabled
     Disabled,
@@ -57,27 +57,28 @@ pub struct MptTable {
     pub new_value: word::Word<Column<Advice>>,
     /// Old value
     pub old_value: word::Word<Column<Advice>>,
+    /// Trie holding the updated key: the account address for a storage trie,
+    /// zero for the account trie
+    pub trie_id: Column<Advice>,
+    /// Enabled on the rows holding an MPT update, zero on all the other rows
+    /// of the MPT circuit
+    pub q_lookup: Column<Advice>,
+    /// `new_value - old_value` modulo 2^256 on the BalanceChanged rows, zero on
+    /// the other updates. Only present when enabled in the MPT circuit.
+    pub balance_delta: Option<word::Word<Column<Advice>>>,
 }
 
 impl<F: Field> LookupTable<F> for MptTable {
     fn columns(&self) -> Vec<Column<Any>> {
-        vec![
-            self.address,
-            self.storage_key.lo(),
-            self.storage_key.hi(),
-            self.proof_type,
-            self.new_root.lo(),
-            self.new_root.hi(),
-            self.old_root.lo(),
-            self.old_root.hi(),
-            self.new_value.lo(),
-            self.new_value.hi(),
-            self.old_value.lo(),
-            self.old_value.hi(),
-        ]
-        .into_iter()
-        .map(|col| col.into())
-        .collect::<Vec<Column<Any>>>()
+        self.update_columns()
+            .into_iter()
+            .chain(
+                self.balance_delta
+                    .iter()
+                    .flat_map(|delta| [delta.lo(), delta.hi()]),
+            )
+            .map(|col| col.into())
+            .collect::<Vec<Column<Any>>>()
     }
 
     fn annotations(&self) -> Vec<String> {
@@ -94,7 +95,16 @@ impl<F: Field> LookupTable<F> for MptTable {
             String::from("new_value_hi"),
             String::from("old_value_lo"),
             String::from("old_value_hi"),
+            String::from("trie_id"),
         ]
+        .into_iter()
+        .chain(self.balance_delta.iter().flat_map(|_| {
+            [
+                String::from("balance_delta_lo"),
+                String::from("balance_delta_hi"),
+            ]
+        }))
+        .collect()
     }
 }
 
@@ -109,9 +119,51 @@ impl MptTable {
             old_root: word::Word::new([meta.advice_column(), meta.advice_column()]),
             new_value: word::Word::new([meta.advice_column(), meta.advice_column()]),
             old_value: word::Word::new([meta.advice_column(), meta.advice_column()]),
+            trie_id: meta.advice_column(),
+            q_lookup: meta.advice_column(),
+            balance_delta: None,
         }
     }
 
+    /// Construct a new MptTable with the balance delta columns
+    pub(crate) fn construct_with_balance_delta<F: Field>(meta: &mut ConstraintSystem<F>) -> Self {
+        let mut table = Self::construct(meta);
+        table.balance_delta = Some(word::Word::new([
+            meta.advice_column(),
+            meta.advice_column(),
+        ]));
+        table
+    }
+
+    /// The columns holding the values of an `MptUpdateRow`
+    fn update_columns(&self) -> [Column<Advice>; 13] {
+        [
+            self.address,
+            self.storage_key.lo(),
+            self.storage_key.hi(),
+            self.proof_type,
+            self.new_root.lo(),
+            self.new_root.hi(),
+            self.old_root.lo(),
+            self.old_root.hi(),
+            self.new_value.lo(),
+            self.new_value.hi(),
+            self.old_value.lo(),
+            self.old_value.hi(),
+            self.trie_id,
+        ]
+    }
+
+    /// Returns the table expressions prefixed by `q_lookup`. External circuits
+    /// looking up an update with a leading `1` only match the rows holding an
+    /// MPT update, not the intermediate rows of the MPT circuit.
+    pub fn lookup_exprs<F: Field>(&self, meta: &mut VirtualCells<F>) -> Vec<Expression<F>> {
+        [meta.query_advice(self.q_lookup, Rotation::cur())]
+            .into_iter()
+            .chain(LookupTable::<F>::table_exprs(self, meta))
+            .collect()
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn constrain<F: Field, C: CellType>(
         &self,
@@ -124,6 +176,7 @@ impl MptTable {
         old_root: word::Word<Expression<F>>,
         new_value: word::Word<Expression<F>>,
         old_value: word::Word<Expression<F>>,
+        trie_id: Expression<F>,
     ) {
         circuit!([meta, cb], {
             require!(a!(self.address) => address);
@@ -133,6 +186,7 @@ impl MptTable {
             require!([a!(self.old_root.lo()), a!(self.old_root.hi())] => old_root);
             require!([a!(self.new_value.lo()), a!(self.new_value.hi())] => new_value);
             require!([a!(self.old_value.lo()), a!(self.old_value.hi())] => old_value);
+            require!(a!(self.trie_id) => trie_id);
         })
     }
 
@@ -142,12 +196,15 @@ impl MptTable {
         offset: usize,
         row: &MptUpdateRow<Value<F>>,
     ) -> Result<(), Error> {
-        for (column, value) in <MptTable as LookupTable<F>>::advice_columns(self)
-            .iter()
-            .zip_eq(row.values())
-        {
+        for (column, value) in self.update_columns().iter().zip_eq(row.values()) {
             region.assign_advice(|| "assign mpt table row value", *column, offset, || value)?;
         }
+        region.assign_advice(
+            || "assign mpt table q_lookup",
+            self.q_lookup,
+            offset,
+            || Value::known(F::ONE),
+        )?;
         Ok(())
     }
 
@@ -157,12 +214,37 @@ impl MptTable {
         offset: usize,
         row: &MptUpdateRow<Value<F>>,
     ) -> Result<(), Error> {
-        for (column, value) in <MptTable as LookupTable<F>>::advice_columns(self)
-            .iter()
-            .zip_eq(row.values())
-        {
+        for (column, value) in self.update_columns().iter().zip_eq(row.values()) {
             region.assign_advice(|| "assign mpt table row value", *column, offset, || value)?;
         }
+        region.assign_advice(
+            || "assign mpt table q_lookup",
+            self.q_lookup,
+            offset,
+            || Value::known(F::ONE),
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn assign_balance_delta<F: Field>(
+        &self,
+        region: &mut CachedRegion<'_, '_, F>,
+        offset: usize,
+        delta: word::Word<Value<F>>,
+    ) -> Result<(), Error> {
+        if let Some(columns) = self.balance_delta {
+            for (column, value) in [columns.lo(), columns.hi()]
+                .iter()
+                .zip([delta.lo(), delta.hi()])
+            {
+                region.assign_advice(
+                    || "assign mpt table balance delta",
+                    *column,
+                    offset,
+                    || value,
+                )?;
+            }
+        }
         Ok(())
     }
 
@@ -15,9 +15,23 @@ use thiserror::Error;
 use crate::util::log2_ceil;
 use halo2_proofs::{
     dev::{MockProver, VerifyFailure},
-    halo2curves::bn256::Fr,
+    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    plonk::{create_proof, keygen_pk, keygen_vk, verify_proof, Circuit as Halo2Circuit},
+    poly::{
+        commitment::ParamsProver,
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG},
+            multiopen::{ProverSHPLONK, VerifierSHPLONK},
+            strategy::SingleStrategy,
+        },
+    },
+    transcript::{
+        Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
+    },
 };
 use mock::TestContext;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
 
 #[cfg(test)]
 #[ctor::ctor]
@@ -294,3 +308,82 @@ impl CircuitTestError {
         }
     }
 }
+
+/// Outcome of a circuit checked both by the MockProver and by the real prover
+#[derive(Debug)]
+pub struct DualVerification {
+    /// MockProver synthesis or verification failure
+    pub mock: Result<(), String>,
+    /// Failures reported by the MockProver verification, empty when it passes
+    /// or when the synthesis fails
+    pub mock_failures: Vec<VerifyFailure>,
+    /// Key generation, proving or verification failure
+    pub real: Result<(), String>,
+}
+
+impl DualVerification {
+    /// Checks `circuit` with the MockProver, then generates the keys, proves and
+    /// verifies it on KZG parameters of degree `k`. Some bugs only show up in
+    /// one of the two paths: the MockProver rejects reads of unassigned cells
+    /// that the real prover takes as zero, while only the real prover uses
+    /// random blinding rows and random challenges in the later phases.
+    pub fn run<C: Halo2Circuit<Fr>>(k: u32, circuit: &C, instances: Vec<Vec<Fr>>) -> Self {
+        let mut mock_failures = vec![];
+        let mock = MockProver::<Fr>::run(k, circuit, instances.clone())
+            .map_err(|err| format!("{err:?}"))
+            .and_then(|prover| {
+                prover.verify_par().map_err(|failures| {
+                    let err = format!("{failures:?}");
+                    mock_failures = failures;
+                    err
+                })
+            });
+        let real = Self::prove_and_verify(k, circuit, &instances).map_err(|err| format!("{err:?}"));
+        Self {
+            mock,
+            mock_failures,
+            real,
+        }
+    }
+
+    fn prove_and_verify<C: Halo2Circuit<Fr>>(
+        k: u32,
+        circuit: &C,
+        instances: &[Vec<Fr>],
+    ) -> Result<(), halo2_proofs::plonk::Error> {
+        let mut rng = ChaCha20Rng::seed_from_u64(2);
+        let params = ParamsKZG::<Bn256>::setup(k, &mut rng);
+        let vk = keygen_vk(&params, circuit)?;
+        let pk = keygen_pk(&params, vk, circuit)?;
+
+        let instances: Vec<&[Fr]> = instances.iter().map(|column| column.as_slice()).collect();
+        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+        create_proof::<KZGCommitmentScheme<Bn256>, ProverSHPLONK<'_, Bn256>, _, _, _, _>(
+            &params,
+            &pk,
+            std::slice::from_ref(circuit),
+            &[&instances],
+            rng,
+            &mut transcript,
+        )?;
+        let proof = transcript.finalize();
+
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+        verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<'_, Bn256>, _, _, _>(
+            params.verifier_params(),
+            pk.get_vk(),
+            SingleStrategy::new(&params),
+            &[&instances],
+            &mut transcript,
+        )
+    }
+
+    /// Panics when only one of the provers accepts the circuit
+    pub fn assert_agree(&self) {
+        assert_eq!(
+            self.mock.is_ok(),
+            self.real.is_ok(),
+            "the MockProver and the real prover disagree: {self:?}"
+        );
+    }
+}
@@ -138,7 +138,9 @@ impl<F: Field> TxCircuitConfig<F> {
 /// Tx Circuit for verifying transaction signatures
 #[derive(Clone, Default, Debug)]
 pub struct TxCircuit<F: Field> {
-    /// Max number of supported transactions
+    /// Max number of supported transactions, also the number of signatures
+    /// verified. The ECDSA verifications use most of the rows, so this should be
+    /// close to the number of transactions of the proven blocks.
     pub max_txs: usize,
     /// Max number of supported calldata bytes
     pub max_calldata: usize,
@@ -162,6 +164,13 @@ impl<F: Field> TxCircuit<F> {
         }
     }
 
+    /// Use `window_size` for the scalar multiplications of the ECDSA
+    /// verifications, see `SignVerifyChip::window_size`
+    pub fn with_ecdsa_window_size(mut self, window_size: usize) -> Self {
+        self.sign_verify = self.sign_verify.with_window_size(window_size);
+        self
+    }
+
     /// Return the minimum number of rows required to prove an input of a
     /// particular size.
     pub fn min_num_rows(txs_len: usize, call_data_len: usize) -> usize {
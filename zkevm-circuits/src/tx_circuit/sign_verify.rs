@@ -44,13 +44,17 @@ use maingate::{
 use num::Integer;
 use std::{iter, marker::PhantomData};
 
+/// Default window size of the scalar multiplications
+pub const DEFAULT_WINDOW_SIZE: usize = 4;
+
 /// Auxiliary Gadget to verify a that a message hash is signed by the public
 /// key corresponding to an Ethereum Address.
 #[derive(Clone, Debug)]
 pub struct SignVerifyChip<F: Field> {
     /// Aux generator for EccChip
     pub aux_generator: Secp256k1Affine,
-    /// Window size for EccChip
+    /// Window size for EccChip. Larger windows need fewer additions per scalar
+    /// multiplication but a table of `2^window_size` points per base.
     pub window_size: usize,
     /// Max number of verifications
     pub max_verif: usize,
@@ -70,14 +74,22 @@ impl<F: Field> SignVerifyChip<F> {
             <Secp256k1Affine as CurveAffine>::CurveExt::random(&mut rng).to_affine();
         Self {
             aux_generator,
-            window_size: 4,
+            window_size: DEFAULT_WINDOW_SIZE,
             max_verif,
             _marker: PhantomData,
         }
     }
 
+    /// Use `window_size` for the scalar multiplications, only the assignment
+    /// changes so the circuit configuration is the same for all window sizes
+    pub fn with_window_size(mut self, window_size: usize) -> Self {
+        assert!(window_size > 0, "window size must be at least 1");
+        self.window_size = window_size;
+        self
+    }
+
     /// Return the minimum number of rows required to prove an input of a
-    /// particular size.
+    /// particular size, with the default window size.
     pub fn min_num_rows(num_verif: usize) -> usize {
         // The values rows_ecc_chip_aux, rows_ecdsa_chip_verification and
         // rows_ecdsa_chip_verification have been obtained from log debugs while running
@@ -103,7 +115,7 @@ impl<F: Field> Default for SignVerifyChip<F> {
     fn default() -> Self {
         Self {
             aux_generator: Secp256k1Affine::default(),
-            window_size: 4,
+            window_size: DEFAULT_WINDOW_SIZE,
             max_verif: 0,
             _marker: PhantomData::default(),
         }
@@ -797,7 +809,7 @@ mod sign_verify_tests {
         }
     }
 
-    fn run<F: Field>(k: u32, max_verif: usize, signatures: Vec<SignData>) {
+    fn run<F: Field>(k: u32, max_verif: usize, window_size: usize, signatures: Vec<SignData>) {
         let mut rng = XorShiftRng::seed_from_u64(2);
         let aux_generator =
             <Secp256k1Affine as CurveAffine>::CurveExt::random(&mut rng).to_affine();
@@ -806,7 +818,7 @@ mod sign_verify_tests {
         let circuit = TestCircuitSignVerify::<F> {
             sign_verify: SignVerifyChip {
                 aux_generator,
-                window_size: 4,
+                window_size,
                 max_verif,
                 _marker: PhantomData,
             },
@@ -872,6 +884,7 @@ mod sign_verify_tests {
         }
 
         let k = 19;
-        run::<Fr>(k, MAX_VERIF, signatures);
+        run::<Fr>(k, MAX_VERIF, DEFAULT_WINDOW_SIZE, signatures.clone());
+        run::<Fr>(k, MAX_VERIF, 3, signatures);
     }
 }
@@ -6,7 +6,8 @@ use bus_mapping::evm::OpcodeId;
 use halo2_proofs::{
     circuit::{Layouter, Value},
     plonk::{
-        Challenge, ConstraintSystem, Error, Expression, FirstPhase, SecondPhase, VirtualCells,
+        Challenge, Circuit, ConstraintSystem, Error, Expression, FirstPhase, SecondPhase,
+        VirtualCells,
     },
 };
 
@@ -33,11 +34,40 @@ pub fn query_expression<F: Field, T>(
     expr.unwrap()
 }
 
+/// Randomness of the random linear combinations of a `SuperCircuit`: either the
+/// challenges sampled by the prover, or a mock value fixed in advance to help
+/// debugging assignments.
+///
+/// A proof using mock randomness is unsound since the witness can be chosen
+/// knowing it, so the mock value can only be set in tests or with the
+/// `mock-randomness` feature.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Randomness<F>(Option<F>);
+
+impl<F: Copy> Randomness<F> {
+    /// Challenges sampled from the transcript
+    pub fn challenge() -> Self {
+        Self(None)
+    }
+
+    /// Fixed randomness, for the `MockProver` only
+    #[cfg(any(test, feature = "mock-randomness"))]
+    pub fn mock(randomness: F) -> Self {
+        Self(Some(randomness))
+    }
+
+    /// Returns the mock value, `None` for the challenges
+    pub fn mock_value(&self) -> Option<F> {
+        self.0
+    }
+}
+
 /// All challenges used in `SuperCircuit`.
 #[derive(Default, Clone, Copy, Debug)]
 pub struct Challenges<T = Challenge> {
     keccak_input: T,
     lookup_input: T,
+    key_input: T,
 }
 
 impl Challenges {
@@ -50,20 +80,36 @@ impl Challenges {
         #[cfg(any(test, feature = "test-circuits"))]
         let _dummy_cols = [meta.advice_column(), meta.advice_column_in(SecondPhase)];
 
+        let keccak_input = meta.challenge_usable_after(FirstPhase);
         Self {
-            keccak_input: meta.challenge_usable_after(FirstPhase),
+            keccak_input,
             lookup_input: meta.challenge_usable_after(SecondPhase),
+            // Only allocated by the circuits RLCing keys, see
+            // `construct_with_key_input`
+            key_input: keccak_input,
+        }
+    }
+
+    /// Construct `Challenges` with a separate `key_input` challenge, for the
+    /// circuits accumulating trie keys and node data in separate RLCs.
+    pub fn construct_with_key_input<F: Field>(meta: &mut ConstraintSystem<F>) -> Self {
+        let challenges = Self::construct(meta);
+        Self {
+            key_input: meta.challenge_usable_after(FirstPhase),
+            ..challenges
         }
     }
 
     /// Returns `Expression` of challenges from `ConstraintSystem`.
     pub fn exprs<F: Field>(&self, meta: &mut ConstraintSystem<F>) -> Challenges<Expression<F>> {
-        let [keccak_input, lookup_input] = query_expression(meta, |meta| {
-            [self.keccak_input, self.lookup_input].map(|challenge| meta.query_challenge(challenge))
+        let [keccak_input, lookup_input, key_input] = query_expression(meta, |meta| {
+            [self.keccak_input, self.lookup_input, self.key_input]
+                .map(|challenge| meta.query_challenge(challenge))
         });
         Challenges {
             keccak_input,
             lookup_input,
+            key_input,
         }
     }
 
@@ -72,6 +118,7 @@ impl Challenges {
         Challenges {
             keccak_input: layouter.get_challenge(self.keccak_input),
             lookup_input: layouter.get_challenge(self.lookup_input),
+            key_input: layouter.get_challenge(self.key_input),
         }
     }
 }
@@ -87,15 +134,23 @@ impl<T: Clone> Challenges<T> {
         self.lookup_input.clone()
     }
 
+    /// Returns challenge of `key_input`, used for the RLC of trie keys so that
+    /// keys and node data are accumulated in separate domains. It is
+    /// `keccak_input` unless constructed with `construct_with_key_input`.
+    pub fn key_input(&self) -> T {
+        self.key_input.clone()
+    }
+
     /// Returns the challenges indexed by the challenge index
-    pub fn indexed(&self) -> [&T; 2] {
-        [&self.keccak_input, &self.lookup_input]
+    pub fn indexed(&self) -> [&T; 3] {
+        [&self.keccak_input, &self.lookup_input, &self.key_input]
     }
 
-    pub(crate) fn mock(keccak_input: T, lookup_input: T) -> Self {
+    pub(crate) fn mock(keccak_input: T, lookup_input: T, key_input: T) -> Self {
         Self {
             keccak_input,
             lookup_input,
+            key_input,
         }
     }
 }
@@ -202,8 +257,16 @@ pub(crate) fn get_push_size(byte: u8) -> u64 {
     }
 }
 
-#[cfg(test)]
-use halo2_proofs::plonk::Circuit;
+/// Returns a fingerprint of the structure of the Circuit configured with the
+/// given params: its columns, gates, lookups and permutation. Two binaries
+/// producing the same fingerprint build the same constraint system, so the
+/// fingerprint can be used to detect a prover/verifier mismatch before
+/// attempting to verify a proof.
+pub fn circuit_fingerprint<F: Field, C: Circuit<F>>(params: C::Params) -> [u8; 32] {
+    let mut cs = ConstraintSystem::default();
+    C::configure_with_params(&mut cs, params);
+    keccak256(format!("{:?}", cs.pinned()))
+}
 
 #[cfg(test)]
 /// Returns number of unusable rows of the Circuit.
@@ -4,7 +4,7 @@
 //   limb is 256/4 = 64 bits
 
 use bus_mapping::state_db::CodeDB;
-use eth_types::{Field, ToLittleEndian, H160, H256};
+use eth_types::{Field, ToLittleEndian, ToWord, H160, H256};
 use gadgets::util::{not, or, Expr};
 use halo2_proofs::{
     circuit::{AssignedCell, Region, Value},
@@ -255,26 +255,14 @@ impl<T: Clone + PartialEq> PartialEq for Word<T> {
 impl<F: Field> From<eth_types::Word> for Word<F> {
     /// Construct the word from u256
     fn from(value: eth_types::Word) -> Self {
-        let bytes = value.to_le_bytes();
-        Word::new([
-            from_bytes::value(&bytes[..N_BYTES_HALF_WORD]),
-            from_bytes::value(&bytes[N_BYTES_HALF_WORD..]),
-        ])
+        Word::new(value.to_lo_hi())
     }
 }
 
 impl<F: Field> From<H256> for Word<F> {
     /// Construct the word from H256
     fn from(h: H256) -> Self {
-        let le_bytes = {
-            let mut b = h.to_fixed_bytes();
-            b.reverse();
-            b
-        };
-        Word::new([
-            from_bytes::value(&le_bytes[..N_BYTES_HALF_WORD]),
-            from_bytes::value(&le_bytes[N_BYTES_HALF_WORD..]),
-        ])
+        Word::new(h.to_lo_hi())
     }
 }
 
@@ -302,12 +290,7 @@ impl<F: Field> From<bool> for Word<F> {
 impl<F: Field> From<H160> for Word<F> {
     /// Construct the word from h160
     fn from(value: H160) -> Self {
-        let mut bytes = *value.as_fixed_bytes();
-        bytes.reverse();
-        Word::new([
-            from_bytes::value(&bytes[..N_BYTES_HALF_WORD]),
-            from_bytes::value(&bytes[N_BYTES_HALF_WORD..]),
-        ])
+        Word::new(value.to_lo_hi())
     }
 }
 
@@ -1,6 +1,6 @@
 use super::{ExecStep, Rw, RwMap, Transaction};
 use crate::{
-    evm_circuit::{detect_fixed_table_tags, EvmCircuit},
+    evm_circuit::{detect_fixed_table_tags, unsupported_opcodes, EvmCircuit},
     exp_circuit::param::OFFSET_INCREMENT,
     instance::public_data_convert,
     table::BlockContextFieldTag,
@@ -293,6 +293,21 @@ pub fn block_convert<F: Field>(
         keccak_inputs: circuit_input_builder::keccak_inputs(block, code_db)?,
         eth_block: block.eth_block.clone(),
     };
+    let unsupported = unsupported_opcodes(
+        block
+            .txs
+            .iter()
+            .flat_map(|tx| tx.steps())
+            .filter(|step| step.error.is_none())
+            .filter_map(|step| step.opcode()),
+    );
+    if !unsupported.is_empty() {
+        log::warn!(
+            "block {} executes opcodes not supported by the evm circuit: {:?}",
+            block.context.number,
+            unsupported
+        );
+    }
     let public_data = public_data_convert(&block);
     let rpi_bytes = public_data.get_pi_bytes(
         block.circuits_params.max_txs,
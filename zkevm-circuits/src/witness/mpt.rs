@@ -51,6 +51,7 @@ pub struct MptUpdateRow<F: Clone> {
     pub(crate) old_root: word::Word<F>,
     pub(crate) new_value: word::Word<F>,
     pub(crate) old_value: word::Word<F>,
+    pub(crate) trie_id: F,
 }
 
 impl MptUpdates {
@@ -107,6 +108,7 @@ impl MptUpdates {
                     old_root: word::Word::<F>::from(old_root).into_value(),
                     new_value: word::Word::<F>::from(new_value).into_value(),
                     old_value: word::Word::<F>::from(old_value).into_value(),
+                    trie_id: Value::known(update.key.trie_id().to_scalar().unwrap()),
                 }
             })
             .collect()
@@ -174,6 +176,13 @@ impl Key {
             Self::Account { address, .. } | Self::AccountStorage { address, .. } => *address,
         }
     }
+    // The account address for storage tries, zero for the account trie.
+    fn trie_id(&self) -> Address {
+        match self {
+            Self::Account { .. } => Address::zero(),
+            Self::AccountStorage { address, .. } => *address,
+        }
+    }
     fn storage_key(&self) -> Word {
         match self {
             Self::Account { .. } => Word::zero(),
@@ -185,7 +194,7 @@ impl Key {
 impl<F: Clone> MptUpdateRow<F> {
     /// The individual values of the row, in the column order used by the
     /// MptTable
-    pub fn values(&self) -> [F; 12] {
+    pub fn values(&self) -> [F; 13] {
         [
             self.address.clone(),
             self.storage_key.lo(),
@@ -199,6 +208,7 @@ impl<F: Clone> MptUpdateRow<F> {
             self.new_value.hi(),
             self.old_value.lo(),
             self.old_value.hi(),
+            self.trie_id.clone(),
         ]
     }
 }